@@ -104,7 +104,18 @@ CREATE TABLE IF NOT EXISTS msg.message
     version     smallint                 NOT NULL,
     extension   character varying(86)    COLLATE pg_catalog."default",
     payload     character varying(5462)  COLLATE pg_catalog."default",
-    CONSTRAINT message_pkey PRIMARY KEY (id)
+    -- "<smaller id>-<bigger id>" for chats, "<group id>-<group id>" for groups.
+    conversation_id character varying(32) NOT NULL,
+    -- number of edits, the replaced contents are kept in msg.message_revision.
+    revision    integer                  NOT NULL DEFAULT 0,
+    edited_at   timestamp with time zone,
+    -- the message quoted and the root of the thread replied in, both in the same conversation.
+    quote_seq   bigint,
+    thread_root bigint,
+    -- purged with its revisions and reactions after this, see msg.conversation_ttl.
+    expire_at   timestamp with time zone,
+    CONSTRAINT message_pkey PRIMARY KEY (id),
+    CONSTRAINT conversation_id_seq_num UNIQUE (conversation_id, seq_num)
 )
     TABLESPACE pg_default;
 
@@ -136,6 +147,189 @@ CREATE INDEX IF NOT EXISTS sender_index
 CREATE INDEX IF NOT EXISTS msg_history_index
     ON msg.message (sender, receiver, seq_num);
 
+-- Index: thread_index
+
+-- DROP INDEX IF EXISTS msg.thread_index;
+
+CREATE INDEX IF NOT EXISTS thread_index
+    ON msg.message (conversation_id, thread_root, seq_num)
+    WHERE thread_root IS NOT NULL;
+
+-- Index: expire_index
+
+-- DROP INDEX IF EXISTS msg.expire_index;
+
+CREATE INDEX IF NOT EXISTS expire_index
+    ON msg.message (expire_at)
+    WHERE expire_at IS NOT NULL;
+
+-- Table: msg.message_revision
+
+-- DROP TABLE IF EXISTS msg.message_revision;
+
+CREATE TABLE IF NOT EXISTS msg.message_revision
+(
+    id              bigserial,
+    conversation_id character varying(32)    NOT NULL,
+    seq_num         bigint                   NOT NULL,
+    -- the content replaced by the n-th edit, starts from 1.
+    revision        integer                  NOT NULL,
+    payload         character varying(5462)  COLLATE pg_catalog."default",
+    editor          bigint                   NOT NULL,
+    edited_at       timestamp with time zone NOT NULL,
+    CONSTRAINT message_revision_pkey PRIMARY KEY (id),
+    CONSTRAINT conversation_id_seq_num_revision UNIQUE (conversation_id, seq_num, revision)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS msg.message_revision
+    OWNER to prim;
+
+-- Table: msg.message_pending_edit
+
+-- DROP TABLE IF EXISTS msg.message_pending_edit;
+
+-- edits saved before the message they change, applied once it is saved.
+CREATE TABLE IF NOT EXISTS msg.message_pending_edit
+(
+    id              bigserial,
+    conversation_id character varying(32)    NOT NULL,
+    seq_num         bigint                   NOT NULL,
+    payload         character varying(5462)  COLLATE pg_catalog."default",
+    editor          bigint                   NOT NULL,
+    edited_at       timestamp with time zone NOT NULL,
+    CONSTRAINT message_pending_edit_pkey PRIMARY KEY (id),
+    CONSTRAINT conversation_id_seq_num_edited_at UNIQUE (conversation_id, seq_num, edited_at)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS msg.message_pending_edit
+    OWNER to prim;
+
+-- Table: msg.message_reaction
+
+-- DROP TABLE IF EXISTS msg.message_reaction;
+
+CREATE TABLE IF NOT EXISTS msg.message_reaction
+(
+    id              bigserial,
+    conversation_id character varying(32)    NOT NULL,
+    seq_num         bigint                   NOT NULL,
+    user_id         bigint                   NOT NULL,
+    emoji           character varying(64)    COLLATE pg_catalog."default" NOT NULL,
+    -- reacting again takes the reaction back, the row is kept for the toggles to stay ordered.
+    active          boolean                  NOT NULL,
+    updated_at      timestamp with time zone NOT NULL,
+    CONSTRAINT message_reaction_pkey PRIMARY KEY (id),
+    CONSTRAINT conversation_id_seq_num_user_id_emoji UNIQUE (conversation_id, seq_num, user_id, emoji)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS msg.message_reaction
+    OWNER to prim;
+
+-- Table: msg.conversation_ttl
+
+-- DROP TABLE IF EXISTS msg.conversation_ttl;
+
+CREATE TABLE IF NOT EXISTS msg.conversation_ttl
+(
+    conversation_id character varying(32)    NOT NULL,
+    -- in seconds, for messages sent after it's set.
+    ttl             integer                  NOT NULL,
+    set_by          bigint                   NOT NULL,
+    update_at       timestamp with time zone NOT NULL,
+    CONSTRAINT conversation_ttl_pkey PRIMARY KEY (conversation_id)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS msg.conversation_ttl
+    OWNER to prim;
+
+-- Table: api.scheduled_message
+
+-- DROP TABLE IF EXISTS api.scheduled_message;
+
+CREATE TABLE IF NOT EXISTS api.scheduled_message
+(
+    id          bigserial,
+    sender      bigint                   NOT NULL,
+    receiver    bigint                   NOT NULL,
+    type        smallint                 NOT NULL,
+    extension   character varying(64)    COLLATE pg_catalog."default" NOT NULL,
+    payload     character varying(4096)  COLLATE pg_catalog."default" NOT NULL,
+    deliver_at  timestamp with time zone NOT NULL,
+    create_at   timestamp with time zone NOT NULL,
+    -- set while an api instance sends it, a claim left by a crashed instance is taken over.
+    claimed_at  timestamp with time zone,
+    -- sends failed so far, the next one is tried from retry_at.
+    attempt     integer                  NOT NULL DEFAULT 0,
+    retry_at    timestamp with time zone,
+    CONSTRAINT scheduled_message_pkey PRIMARY KEY (id)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS api.scheduled_message
+    OWNER to prim;
+
+-- Index: deliver_at_index
+
+-- DROP INDEX IF EXISTS api.deliver_at_index;
+
+CREATE INDEX IF NOT EXISTS deliver_at_index
+    ON api.scheduled_message USING btree
+    (deliver_at ASC NULLS LAST)
+    TABLESPACE pg_default;
+
+-- Index: scheduled_sender_index
+
+-- DROP INDEX IF EXISTS api.scheduled_sender_index;
+
+CREATE INDEX IF NOT EXISTS scheduled_sender_index
+    ON api.scheduled_message USING btree
+    (sender ASC NULLS LAST)
+    TABLESPACE pg_default;
+
+-- Table: api.prekey_bundle
+
+-- DROP TABLE IF EXISTS api.prekey_bundle;
+
+CREATE TABLE IF NOT EXISTS api.prekey_bundle
+(
+    user_id          bigint                   NOT NULL,
+    -- url safe base64 of the public keys, see the e2ee crate.
+    identity_key     character varying(64)    COLLATE pg_catalog."default" NOT NULL,
+    signing_key      character varying(64)    COLLATE pg_catalog."default" NOT NULL,
+    signed_prekey_id bigint                   NOT NULL,
+    signed_prekey    character varying(64)    COLLATE pg_catalog."default" NOT NULL,
+    signature        character varying(128)   COLLATE pg_catalog."default" NOT NULL,
+    update_at        timestamp with time zone NOT NULL,
+    CONSTRAINT prekey_bundle_pkey PRIMARY KEY (user_id)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS api.prekey_bundle
+    OWNER to prim;
+
+-- Table: api.one_time_prekey
+
+-- DROP TABLE IF EXISTS api.one_time_prekey;
+
+CREATE TABLE IF NOT EXISTS api.one_time_prekey
+(
+    id        bigserial,
+    user_id   bigint                   NOT NULL,
+    -- handed out once, deleted as it is.
+    prekey_id bigint                   NOT NULL,
+    prekey    character varying(64)    COLLATE pg_catalog."default" NOT NULL,
+    CONSTRAINT one_time_prekey_pkey PRIMARY KEY (id),
+    CONSTRAINT user_id_prekey_id UNIQUE (user_id, prekey_id)
+)
+    TABLESPACE pg_default;
+
+ALTER TABLE IF EXISTS api.one_time_prekey
+    OWNER to prim;
+
 -- Type: user_relationship_status
 
 -- DROP TYPE IF EXISTS api.user_relationship_status;
@@ -26,6 +26,39 @@ pub(crate) static JOIN_GROUP: &str = "JOIN_GROUP_";
 pub(crate) static CHECK_CODE : &str = "CHECK_CODE_";
 pub(crate) static LAST_ONLINE_TIME: &str = "LAST_ONLINE_TIME_";
 pub(crate) static LAST_READ: &str = "LAST_READ_";
+/// hash of peer id to the number of messages from the peer the user hasn't read.
+pub(crate) static UNREAD_COUNT: &str = "UNREAD_COUNT_";
 pub(crate) static USER_INBOX: &str = "USER_INBOX_";
 pub(crate) static MSG_CACHE: &str = "MSG_CACHE_";
+/// reaction toggles of a conversation not surely saved by `msgprocessor` yet, as
+/// `<seqnum>:<user id>:<time>:<emoji in base64>` scored by the time, so the reactions read from
+/// the database are brought up to date.
+pub(crate) static PENDING_REACTION: &str = "PENDING_REACTION_";
+/// peers and groups a user has talked in, scored by the time of the latest message.
+pub(crate) static USER_CONVERSATION: &str = "USER_CONVERSATION_";
+/// conversations a user pinned to the top.
+pub(crate) static CONVERSATION_PIN_SET: &str = "CONVERSATION_PIN_SET_";
+/// `SyncEvent` of a user in json, scored by `USER_SYNC_SEQ_` in the order they are written.
+pub(crate) static USER_SYNC: &str = "USER_SYNC_";
+/// the counter scoring `USER_SYNC_` of a user, see `sync_seq_key`.
+pub(crate) static USER_SYNC_SEQ: &str = "USER_SYNC_SEQ_";
+/// `<user id>:<score>` of the `USER_SYNC_` events holding messages with a time to live, scored by
+/// the time they expire, the api removes the events then.
+pub(crate) static SYNC_EXPIRE: &str = "SYNC_EXPIRE";
+/// seconds the messages sent to the conversation live, mirrors `msg.conversation_ttl` for the
+/// message node to write it in the extension.
+pub(crate) static CONVERSATION_TTL: &str = "CONVERSATION_TTL_";
 pub(crate) static ADD_FRIEND: &str = "ADD_FRIEND_";
+/// `<platform>:<token>` of every device a user wants pushes on.
+pub(crate) static PUSH_DEVICE_SET: &str = "PUSH_DEVICE_SET_";
+/// users and groups a user doesn't want pushes from.
+pub(crate) static PUSH_MUTE_SET: &str = "PUSH_MUTE_SET_";
+/// prekey bundles a user got lately, expires with the window it counts.
+pub(crate) static BUNDLE_RATE: &str = "BUNDLE_RATE_";
+
+/// `USER_SYNC_SEQ_{USER_SYNC_<user id>}`, hash tagged to be in the slot of `USER_SYNC_` of the
+/// user, they are written by one script.
+#[inline]
+pub(crate) fn sync_seq_key(user_id: u64) -> String {
+    format!("{}{{{}{}}}", USER_SYNC_SEQ, USER_SYNC, user_id)
+}
@@ -1,4 +1,4 @@
-use std::{fs, net::{ToSocketAddrs, SocketAddr}, path::PathBuf};
+use std::{fs, net::{ToSocketAddrs, SocketAddr}, path::PathBuf, time::Duration};
 
 use anyhow::Context;
 use tracing::Level;
@@ -10,6 +10,7 @@ struct Config0 {
     redis: Option<Redis0>,
     rpc: Option<Rpc0>,
     sql: Option<Sql0>,
+    message: Option<Message0>,
 }
 
 #[derive(Debug)]
@@ -19,6 +20,7 @@ pub(crate) struct Config {
     pub(crate) redis: Redis,
     pub(crate) rpc: Rpc,
     pub(crate) sql: Sql,
+    pub(crate) message: Message,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -95,6 +97,26 @@ pub(crate) struct Sql {
     pub(crate) max_connections: u32,
 }
 
+#[derive(serde::Deserialize, Debug)]
+struct Message0 {
+    edit_window: Option<u64>,
+    withdraw_window: Option<u64>,
+    schedule_horizon: Option<u64>,
+    schedule_limit: Option<u32>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Message {
+    /// how long the sender may edit a message after sending it.
+    pub(crate) edit_window: Duration,
+    /// how long the sender may withdraw a message, group admins may withdraw any time.
+    pub(crate) withdraw_window: Duration,
+    /// how far ahead a message may be scheduled.
+    pub(crate) schedule_horizon: Duration,
+    /// scheduled messages a user may have pending at once.
+    pub(crate) schedule_limit: u32,
+}
+
 impl Config {
     fn from_config0(config0: Config0) -> Config {
         let log_level = match config0.log_level.unwrap().as_str() {
@@ -111,6 +133,12 @@ impl Config {
             redis: Redis::from_redis0(config0.redis.unwrap()),
             rpc: Rpc::from_rpc0(config0.rpc.unwrap()),
             sql: Sql::from_sql0(config0.sql.unwrap()),
+            message: Message::from_message0(config0.message.unwrap_or(Message0 {
+                edit_window: None,
+                withdraw_window: None,
+                schedule_horizon: None,
+                schedule_limit: None,
+            })),
         }
     }
 }
@@ -196,6 +224,17 @@ impl Sql {
     }
 }
 
+impl Message {
+    fn from_message0(message0: Message0) -> Self {
+        Message {
+            edit_window: Duration::from_secs(message0.edit_window.unwrap_or(900)),
+            withdraw_window: Duration::from_secs(message0.withdraw_window.unwrap_or(120)),
+            schedule_horizon: Duration::from_secs(message0.schedule_horizon.unwrap_or(2592000)),
+            schedule_limit: message0.schedule_limit.unwrap_or(100),
+        }
+    }
+}
+
 pub(crate) fn load_config(config_path: &str) {
     let toml_str = fs::read_to_string(config_path).unwrap();
     let config0: Config0 = toml::from_str(&toml_str).unwrap();
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Local;
+use lib::{entity::Msg, util::conversation_id};
+use salvo::handler;
+use tracing::error;
+
+use crate::{
+    cache::{
+        get_redis_ops, CONVERSATION_PIN_SET, MSG_CACHE, PUSH_MUTE_SET, UNREAD_COUNT,
+        USER_CONVERSATION,
+    },
+    error::HandlerError,
+    model::{msg::Message, relationship::UserRelationship},
+};
+
+use super::{verify_user, HandlerResult, ResponseResult};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ConversationResp {
+    /// a user id or a group id.
+    peer_id: u64,
+    /// time of the latest message, 0 for a group nobody has talked in.
+    last_active: u64,
+    last_msg: Option<Msg>,
+    unread_count: u64,
+    muted: bool,
+    pinned: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ConversationPage {
+    list: Vec<ConversationResp>,
+    /// pass as `cursor` for the next page, none when this is the last one.
+    next_cursor: Option<String>,
+}
+
+/// where a page ends: `last_active` of the last entry got, and how many entries with exactly
+/// that `last_active` are got so far, so conversations active at the same time are neither
+/// skipped nor repeated. written as `<last_active>:<count>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cursor {
+    last_active: u64,
+    count: usize,
+}
+
+impl Cursor {
+    /// a bare `last_active`, as older clients send, continues after all entries active then.
+    fn parse(cursor: &str) -> Option<Self> {
+        match cursor.split_once(':') {
+            Some((last_active, count)) => Some(Self {
+                last_active: last_active.parse().ok()?,
+                count: count.parse().ok()?,
+            }),
+            None => Some(Self {
+                last_active: cursor.parse::<u64>().ok()?.checked_sub(1)?,
+                count: 0,
+            }),
+        }
+    }
+
+    /// the cursor after the page got from `cursor`, whose `last_active`s are `page`.
+    fn next(cursor: Option<Self>, page: &[u64]) -> Option<Self> {
+        let last_active = *page.last()?;
+        let mut count = page.iter().rev().take_while(|t| **t == last_active).count();
+        if count == page.len() {
+            // the whole page shares the last_active the previous one ended with.
+            if let Some(cursor) = cursor.filter(|cursor| cursor.last_active == last_active) {
+                count += cursor.count;
+            }
+        }
+        Some(Self { last_active, count })
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.last_active, self.count)
+    }
+}
+
+/// all conversations of the user, the most recently active first.
+///
+/// `cursor` is the `next_cursor` of the previous page, groups nobody has talked in are listed
+/// at the end of the last page.
+#[handler]
+pub(crate) async fn conversation_list(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ConversationPage> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let limit = req
+        .query::<usize>("limit")
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = match req.query::<String>("cursor") {
+        Some(cursor) => match Cursor::parse(&cursor) {
+            Some(cursor) => Some(cursor),
+            None => {
+                return Err(HandlerError::RequestMismatch(
+                    400,
+                    "invalid cursor".to_string(),
+                ))
+            }
+        },
+        None => None,
+    };
+    let (offset, to) = match cursor {
+        Some(cursor) => (cursor.count, cursor.last_active as f64),
+        None => (0, f64::MAX),
+    };
+    let conversation_key = format!("{}{}", USER_CONVERSATION, user_id);
+    let active_list = match redis_ops
+        .peek_sort_queue_more_with_score::<u64>(
+            &conversation_key,
+            offset,
+            limit,
+            f64::MIN,
+            to,
+            false,
+        )
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("get conversation list failed: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    let unread_map = redis_ops
+        .peek_hash::<HashMap<u64, i64>>(&format!("{}{}", UNREAD_COUNT, user_id))
+        .await
+        .unwrap_or_default();
+    let mute_set = redis_ops
+        .peek_set::<HashSet<u64>>(&format!("{}{}", PUSH_MUTE_SET, user_id))
+        .await
+        .unwrap_or_default();
+    let pin_set = redis_ops
+        .peek_set::<HashSet<u64>>(&format!("{}{}", CONVERSATION_PIN_SET, user_id))
+        .await
+        .unwrap_or_default();
+    let is_last_page = active_list.len() < limit;
+    let mut entry_list = active_list
+        .into_iter()
+        .map(|(peer_id, score)| (peer_id, score as u64))
+        .collect::<Vec<(u64, u64)>>();
+    let next_cursor = if is_last_page {
+        None
+    } else {
+        let page = entry_list
+            .iter()
+            .map(|(_, last_active)| *last_active)
+            .collect::<Vec<u64>>();
+        Cursor::next(cursor, &page).map(|cursor| cursor.to_string())
+    };
+    if is_last_page {
+        match UserRelationship::get_group_id_list(user_id as i64).await {
+            Ok(group_id_list) => {
+                for group_id in group_id_list {
+                    let group_id = group_id as u64;
+                    let score = redis_ops
+                        .get_sort_queue_score(&conversation_key, &group_id)
+                        .await
+                        .unwrap_or(None);
+                    if score.is_none() {
+                        entry_list.push((group_id, 0));
+                    }
+                }
+            }
+            Err(e) => error!("get group list of {} failed: {}", user_id, e),
+        }
+    }
+    let mut list = Vec::with_capacity(entry_list.len());
+    for (peer_id, last_active) in entry_list {
+        let id = conversation_id(user_id, peer_id);
+        let last_msg = match redis_ops
+            .peek_sort_queue::<Vec<Msg>>(&format!("{}{}", MSG_CACHE, id))
+            .await
+        {
+            Ok(mut cache_list) if !cache_list.is_empty() => cache_list.pop(),
+            // not cached any more, or never.
+            _ if last_active > 0 => match Message::get_latest(&id).await {
+                Ok(message) => message.as_ref().map(|message| message.into()),
+                Err(e) => {
+                    error!("get latest message of {} failed: {}", id, e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        list.push(ConversationResp {
+            peer_id,
+            last_active,
+            last_msg,
+            unread_count: unread_map.get(&peer_id).copied().unwrap_or(0).max(0) as u64,
+            muted: mute_set.contains(&peer_id),
+            pinned: pin_set.contains(&peer_id),
+        });
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: ConversationPage { list, next_cursor },
+    })
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct PinReq {
+    /// a user id or a group id.
+    peer_id: u64,
+    pinned: bool,
+}
+
+#[handler]
+pub(crate) async fn set_pin(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<PinReq>().await {
+        Ok(form) => form,
+        Err(_err) => {
+            return Err(HandlerError::ParameterMismatch(
+                "peer id and pinned are required.".to_string(),
+            ))
+        }
+    };
+    if form.peer_id == 0 || form.peer_id == user_id {
+        return Err(HandlerError::ParameterMismatch(
+            "invalid peer id.".to_string(),
+        ));
+    }
+    let key = format!("{}{}", CONVERSATION_PIN_SET, user_id);
+    let res = if form.pinned {
+        redis_ops.push_set(&key, &form.peer_id).await
+    } else {
+        redis_ops.remove_set(&key, &form.peer_id).await
+    };
+    if let Err(e) = res {
+        error!("set pin of {} failed: {}", user_id, e);
+        return Err(HandlerError::InternalError("internal error".to_string()));
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+
+    #[test]
+    fn test_cursor() {
+        assert_eq!(
+            Cursor::parse("100:2"),
+            Some(Cursor {
+                last_active: 100,
+                count: 2
+            })
+        );
+        // older clients.
+        assert_eq!(
+            Cursor::parse("100"),
+            Some(Cursor {
+                last_active: 99,
+                count: 0
+            })
+        );
+        assert_eq!(Cursor::parse("0"), None);
+        assert_eq!(Cursor::parse("a:1"), None);
+        assert_eq!(Cursor::parse(""), None);
+        let cursor = Cursor::parse("7:3").unwrap();
+        assert_eq!(Cursor::parse(&cursor.to_string()), Some(cursor));
+    }
+
+    #[test]
+    fn test_next_cursor() {
+        // ties at the end of a page are counted.
+        let first = Cursor::next(None, &[9, 8, 5, 5]).unwrap();
+        assert_eq!(
+            first,
+            Cursor {
+                last_active: 5,
+                count: 2
+            }
+        );
+        // a page of ties only adds up.
+        let second = Cursor::next(Some(first), &[5, 5, 5, 5]).unwrap();
+        assert_eq!(second.count, 6);
+        // and starts over once the last_active changes.
+        let third = Cursor::next(Some(second), &[5, 3]).unwrap();
+        assert_eq!(
+            third,
+            Cursor {
+                last_active: 3,
+                count: 1
+            }
+        );
+        assert_eq!(Cursor::next(Some(third), &[]), None);
+    }
+}
@@ -0,0 +1,272 @@
+use std::time::Duration;
+
+use base64::Engine;
+use chrono::Local;
+use lib::entity::GROUP_ID_THRESHOLD;
+use salvo::handler;
+use tracing::error;
+
+use crate::{
+    cache::{get_redis_ops, BUNDLE_RATE},
+    error::HandlerError,
+    model::e2ee::{OneTimePrekey, PrekeyBundle},
+};
+
+use super::{msg::check_relationship, verify_user, HandlerResult, ResponseResult};
+
+/// one-time prekeys a user may have published and not handed out yet.
+const MAX_ONE_TIME_PREKEY: i64 = 1000;
+/// bundles a user may get in `BUNDLE_RATE_WINDOW`.
+const MAX_BUNDLE_PER_WINDOW: u64 = 30;
+const BUNDLE_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct OneTimePrekeyReq {
+    id: u32,
+    key: String,
+}
+
+/// the keys are url safe base64 without padding, as the e2ee crate writes them.
+#[derive(Debug, serde::Deserialize)]
+struct PublishReq {
+    identity_key: String,
+    signing_key: String,
+    signed_prekey_id: u32,
+    signed_prekey: String,
+    signature: String,
+    #[serde(default)]
+    one_time_prekey_list: Vec<OneTimePrekeyReq>,
+    /// take the place of another identity key, that of another device or one lost, sessions
+    /// others started with it break.
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct BundleResp {
+    identity_key: String,
+    signing_key: String,
+    signed_prekey_id: u32,
+    signed_prekey: String,
+    signature: String,
+    /// none once the user runs out of them, the session is started without it.
+    one_time_prekey: Option<OneTimePrekeyReq>,
+}
+
+/// whether the key decodes to `len` bytes, the api never checks the signature as clients do.
+fn is_key(key: &str, len: usize) -> bool {
+    let engine = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    engine.decode(key).is_ok_and(|bytes| bytes.len() == len)
+}
+
+/// publish the keys others start encrypted conversations with, the one-time prekeys are added
+/// to those not handed out yet unless the identity key changed. a user has one identity key,
+/// another one only takes its place if asked to, so a second device doesn't break the first.
+#[handler]
+pub(crate) async fn publish_bundle(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<PublishReq>().await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::ParameterMismatch(
+                "identity_key, signing_key, signed_prekey_id, signed_prekey and signature are required.".to_string(),
+            ))
+        }
+    };
+    if !is_key(&form.identity_key, 32)
+        || !is_key(&form.signing_key, 32)
+        || !is_key(&form.signed_prekey, 32)
+        || !is_key(&form.signature, 64)
+        || !form
+            .one_time_prekey_list
+            .iter()
+            .all(|prekey| prekey.id != 0 && is_key(&prekey.key, 32))
+    {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "invalid key.".to_string(),
+        ));
+    }
+    match PrekeyBundle::count_one_time_prekey(user_id as i64).await {
+        Ok(count) if count + form.one_time_prekey_list.len() as i64 > MAX_ONE_TIME_PREKEY => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "too many one-time prekeys.".to_string(),
+            ))
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    }
+    let bundle = PrekeyBundle {
+        user_id: user_id as i64,
+        identity_key: form.identity_key,
+        signing_key: form.signing_key,
+        signed_prekey_id: form.signed_prekey_id as i64,
+        signed_prekey: form.signed_prekey,
+        signature: form.signature,
+        update_at: Local::now(),
+    };
+    let one_time_prekey_list = form
+        .one_time_prekey_list
+        .into_iter()
+        .map(|prekey| OneTimePrekey {
+            prekey_id: prekey.id as i64,
+            prekey: prekey.key,
+        })
+        .collect::<Vec<OneTimePrekey>>();
+    match bundle.publish(&one_time_prekey_list, form.replace).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(HandlerError::RequestMismatch(
+                409,
+                "another identity key is published, set replace to take its place.".to_string(),
+            ))
+        }
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// the bundle of a friend to start an encrypted conversation with, each one-time prekey is
+/// handed out once, so a user gets a few in a while only, groups have none.
+#[handler]
+pub(crate) async fn get_bundle(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, BundleResp> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let peer_id = match req.query::<u64>("peer_id") {
+        Some(v) if v < GROUP_ID_THRESHOLD => v,
+        Some(_) => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "only 1:1 conversations can be encrypted.".to_string(),
+            ))
+        }
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "peer id is required.".to_string(),
+            ))
+        }
+    };
+    check_relationship(user_id, peer_id).await?;
+    let key = format!("{}{}", BUNDLE_RATE, user_id);
+    let count = match redis_ops.atomic_increment(&key).await {
+        Ok(count) => count,
+        Err(e) => {
+            error!("redis error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    if count == 1 {
+        _ = redis_ops.expire(&key, BUNDLE_RATE_WINDOW).await;
+    }
+    if count > MAX_BUNDLE_PER_WINDOW {
+        return Err(HandlerError::RequestMismatch(
+            429,
+            "too many bundles requested, try again later.".to_string(),
+        ));
+    }
+    let bundle = match PrekeyBundle::get(peer_id as i64).await {
+        Ok(Some(bundle)) => bundle,
+        Ok(None) => {
+            return Err(HandlerError::RequestMismatch(
+                404,
+                "peer has no prekey bundle.".to_string(),
+            ))
+        }
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    let one_time_prekey = match PrekeyBundle::take_one_time_prekey(peer_id as i64).await {
+        Ok(prekey) => prekey.map(|prekey| OneTimePrekeyReq {
+            id: prekey.prekey_id as u32,
+            key: prekey.prekey,
+        }),
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: BundleResp {
+            identity_key: bundle.identity_key,
+            signing_key: bundle.signing_key,
+            signed_prekey_id: bundle.signed_prekey_id as u32,
+            signed_prekey: bundle.signed_prekey,
+            signature: bundle.signature,
+            one_time_prekey,
+        },
+    })
+}
+
+/// one-time prekeys of the user not handed out yet, clients publish more when it runs low.
+#[handler]
+pub(crate) async fn prekey_count(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, u64> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let count = match PrekeyBundle::count_one_time_prekey(user_id as i64).await {
+        Ok(count) => count as u64,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: count,
+    })
+}
@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use chrono::Local;
+use lib::{
+    cache::redis_ops::RedisOps,
+    entity::{Msg, Type, GROUP_ID_THRESHOLD, MAX_MSG_TTL},
+    util::{conversation_id, timestamp},
+};
+use salvo::handler;
+use tracing::error;
+
+use crate::{
+    cache::{get_redis_ops, CONVERSATION_TTL, MSG_CACHE, SYNC_EXPIRE, USER_SYNC},
+    error::HandlerError,
+    model::{
+        msg::{ConversationTtl, Message},
+        relationship::UserRelationship,
+    },
+    rpc::get_rpc_client,
+};
+
+use super::{
+    msg::{check_member, sender_of},
+    verify_user, HandlerResult, ResponseResult,
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+const SWEEP_BATCH_SIZE: i64 = 200;
+
+#[derive(Debug, serde::Deserialize)]
+struct TtlReq {
+    peer_id: u64,
+    /// in seconds, 0 takes the time to live off.
+    ttl: u64,
+}
+
+/// set the time to live of the messages sent to the conversation from now on, in a group only
+/// admins may set it.
+#[handler]
+pub(crate) async fn set_ttl(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<TtlReq>().await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::ParameterMismatch(
+                "peer_id and ttl are required.".to_string(),
+            ))
+        }
+    };
+    if form.ttl > MAX_MSG_TTL {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "ttl out of range.".to_string(),
+        ));
+    }
+    if form.peer_id >= GROUP_ID_THRESHOLD {
+        let is_admin =
+            match UserRelationship::get_user_id_peer_id(user_id as i64, form.peer_id as i64).await
+            {
+                Ok(relationship) => {
+                    relationship.info.get("role").and_then(|role| role.as_str()) == Some("admin")
+                }
+                Err(_) => {
+                    return Err(HandlerError::RequestMismatch(
+                        403,
+                        "user not in this group.".to_string(),
+                    ))
+                }
+            };
+        if !is_admin {
+            return Err(HandlerError::RequestMismatch(
+                403,
+                "only group admins can set the ttl.".to_string(),
+            ));
+        }
+    }
+    let conversation = conversation_id(user_id, form.peer_id);
+    let res = if form.ttl == 0 {
+        ConversationTtl::delete(&conversation).await
+    } else {
+        ConversationTtl {
+            conversation_id: conversation,
+            ttl: form.ttl as i32,
+            set_by: user_id as i64,
+            update_at: Local::now(),
+        }
+        .upsert()
+        .await
+    };
+    if let Err(e) = res {
+        error!("db error: {}", e);
+        return Err(HandlerError::InternalError("internal error".to_string()));
+    }
+    let key = format!(
+        "{}{}",
+        CONVERSATION_TTL,
+        conversation_id(user_id, form.peer_id)
+    );
+    let res = if form.ttl == 0 {
+        redis_ops.del(&key).await
+    } else {
+        redis_ops.set(&key, &form.ttl).await
+    };
+    if let Err(e) = res {
+        error!("redis error: {}", e);
+        return Err(HandlerError::InternalError("internal error".to_string()));
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// the time to live of the conversation in seconds, 0 if there is none.
+#[handler]
+pub(crate) async fn get_ttl(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, u64> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let peer_id = match req.query::<u64>("peer_id") {
+        Some(v) => v,
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "peer id is required.".to_string(),
+            ))
+        }
+    };
+    check_member(user_id, peer_id).await?;
+    let ttl = match ConversationTtl::get(&conversation_id(user_id, peer_id)).await {
+        Ok(ttl) => ttl.map(|ttl| ttl.ttl as u64).unwrap_or(0),
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: ttl,
+    })
+}
+
+/// purge the messages expired from the database and the cache, and the sync events holding them,
+/// and tell the conversation so clients delete them too.
+///
+/// every api instance runs it, a message is purged by one of them only, and only after the
+/// node accepted its tombstone, one refused or failed to push is tried again by the next sweep.
+pub(crate) async fn sweep() {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut redis_ops = get_redis_ops().await;
+        if let Err(e) = sweep_sync(&mut redis_ops).await {
+            error!("remove expired sync events error: {}", e);
+        }
+        let (tx, list) = match Message::lock_expired(SWEEP_BATCH_SIZE).await {
+            Ok(res) => res,
+            Err(e) => {
+                error!("lock expired messages error: {}", e);
+                continue;
+            }
+        };
+        if list.is_empty() {
+            continue;
+        }
+        let mut rpc_client = get_rpc_client().await;
+        let mut purge_list = Vec::with_capacity(list.len());
+        for message in list.into_iter() {
+            let msg: Msg = (&message).into();
+            let sender = sender_of(&msg).unwrap_or(msg.sender());
+            let mut tombstone = Msg::raw2(sender, msg.receiver(), 0, &[], &[]);
+            tombstone.set_type(Type::Expire);
+            tombstone.set_seqnum(msg.seqnum());
+            match rpc_client.call_push_msg(&tombstone).await {
+                Ok(_) => purge_list.push(message),
+                Err(e) => error!("rpc call push msg error: {}", e),
+            }
+        }
+        if let Err(e) = Message::purge(tx, &purge_list).await {
+            error!("purge expired messages error: {}", e);
+            continue;
+        }
+        for message in purge_list.iter() {
+            let key = format!(
+                "{}{}",
+                MSG_CACHE,
+                conversation_id(message.sender as u64, message.receiver as u64)
+            );
+            if let Err(e) = redis_ops
+                .remove_sort_queue_data(&key, message.seq_num as f64)
+                .await
+            {
+                error!("remove cached message error: {}", e);
+            }
+        }
+    }
+}
+
+/// remove the `USER_SYNC_` events of messages expired, as the message node indexed them.
+async fn sweep_sync(redis_ops: &mut RedisOps) -> lib::Result<()> {
+    let list = redis_ops
+        .peek_sort_queue_more::<String>(
+            SYNC_EXPIRE,
+            0,
+            SWEEP_BATCH_SIZE as usize,
+            f64::MIN,
+            timestamp() as f64,
+            true,
+        )
+        .await?;
+    for entry in list.iter() {
+        if let Some((user_id, score)) = parse_sync_expire(entry) {
+            redis_ops
+                .remove_sort_queue_data(&format!("{}{}", USER_SYNC, user_id), score as f64)
+                .await?;
+        }
+        redis_ops
+            .remove_sort_queue_member(SYNC_EXPIRE, entry)
+            .await?;
+    }
+    Ok(())
+}
+
+/// `<user id>:<score>`.
+fn parse_sync_expire(entry: &str) -> Option<(u64, u64)> {
+    let (user_id, score) = entry.split_once(':')?;
+    Some((user_id.parse().ok()?, score.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_sync_expire;
+
+    #[test]
+    fn test_parse_sync_expire() {
+        assert_eq!(parse_sync_expire("7:42"), Some((7, 42)));
+        assert_eq!(parse_sync_expire("7"), None);
+        assert_eq!(parse_sync_expire("x:42"), None);
+    }
+}
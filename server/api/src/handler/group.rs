@@ -1,13 +1,13 @@
 use std::time::Duration;
 
 use chrono::Local;
-use lib::entity::{Msg, Type, GROUP_ID_THRESHOLD};
+use lib::entity::{Msg, SyncEvent, Type, GROUP_ID_THRESHOLD};
 use salvo::{handler, Request, Response};
 use serde_json::json;
 use tracing::error;
 
 use crate::{
-    cache::{get_redis_ops, CHECK_CODE, JOIN_GROUP},
+    cache::{get_redis_ops, CHECK_CODE, JOIN_GROUP, USER_CONVERSATION},
     error::HandlerError,
     model::{
         group::{Group, GroupStatus},
@@ -18,7 +18,7 @@ use crate::{
     sql::DELETE_AT,
 };
 
-use super::{verify_user, HandlerResult, ResponseResult};
+use super::{sync::push_sync_event, verify_user, HandlerResult, ResponseResult};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct JoinGroupReq {
@@ -184,6 +184,10 @@ pub(crate) async fn leave_group(
         };
     }
     _ = user_relationship.delete().await;
+    _ = redis_ops
+        .remove_sort_queue_member(&format!("{}{}", USER_CONVERSATION, user_id), &group_id)
+        .await;
+    _ = push_sync_event(&mut redis_ops, user_id, &SyncEvent::LeaveGroup { group_id }).await;
     let user_role = user_relationship
         .info
         .as_object()
@@ -322,6 +326,7 @@ pub(crate) async fn create_group(
             "internal server error.".to_string(),
         ));
     }
+    _ = push_sync_event(&mut redis_ops, user_id, &SyncEvent::JoinGroup { group_id }).await;
     Ok(ResponseResult {
         code: 200,
         message: "ok.",
@@ -672,6 +677,10 @@ pub(crate) async fn remove_member(
     });
     _ = group.update().await;
     _ = peer_group_list.delete().await;
+    _ = redis_ops
+        .remove_sort_queue_member(&format!("{}{}", USER_CONVERSATION, peer_id), &group_id)
+        .await;
+    _ = push_sync_event(&mut redis_ops, peer_id, &SyncEvent::LeaveGroup { group_id }).await;
     let mut msg = Msg::raw(
         user_id,
         peer_id,
@@ -781,6 +790,10 @@ pub(crate) async fn approve_join(
                 }));
                 group.member_list = member_list;
                 _ = group.update().await;
+                let event = SyncEvent::JoinGroup {
+                    group_id: form.group_id,
+                };
+                _ = push_sync_event(&mut redis_ops, form.peer_id, &event).await;
                 res
             }
             Err(e) => {
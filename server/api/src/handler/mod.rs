@@ -9,10 +9,17 @@ use salvo::{writing::Json, Piece, Request, Response};
 
 use crate::{cache::USER_TOKEN, error::HandlerError};
 
+pub(crate) mod conversation;
+pub(crate) mod e2ee;
+pub(crate) mod expire;
 pub(crate) mod file;
 pub(crate) mod group;
 pub(crate) mod msg;
+pub(crate) mod push;
 pub(crate) mod relationship;
+pub(crate) mod schedule;
+pub(crate) mod sync;
+pub(crate) mod thread;
 pub(crate) mod user;
 
 pub(crate) type HandlerResult<'a, T> = std::result::Result<ResponseResult<'a, T>, HandlerError>;
@@ -1,21 +1,31 @@
+use std::{collections::HashMap, time::Duration};
+
 use base64::Engine;
 use chrono::Local;
 use lib::{
-    entity::{Msg, Type, GROUP_ID_THRESHOLD},
-    util::{timestamp, who_we_are},
+    cache::redis_ops::RedisOps,
+    entity::{Msg, MsgExtension, SyncEvent, Type, GROUP_ID_THRESHOLD},
+    util::{conversation_id, timestamp, who_we_are},
     Result,
 };
 use salvo::handler;
 use tracing::error;
 
 use crate::{
-    cache::{get_redis_ops, LAST_ONLINE_TIME, LAST_READ, MSG_CACHE, USER_INBOX},
+    cache::{
+        get_redis_ops, LAST_ONLINE_TIME, LAST_READ, MSG_CACHE, PENDING_REACTION, UNREAD_COUNT,
+        USER_INBOX,
+    },
+    config::config,
     error::HandlerError,
-    model::msg::Message,
+    model::{
+        msg::{EditState, Message, ReactionCount, ThreadState, UserReaction},
+        relationship::UserRelationship,
+    },
     rpc::get_rpc_client,
 };
 
-use super::{verify_user, HandlerResult, ResponseResult};
+use super::{sync::push_sync_event, verify_user, HandlerResult, ResponseResult};
 
 /// depends on certain client.
 /// this method will return all users who have sent message to this user when the user is offline.
@@ -150,6 +160,21 @@ pub(crate) async fn update_unread(
         error!("update unread failed.");
         return Err(HandlerError::InternalError("internal error".to_string()));
     }
+    // the same as a read receipt, so all devices see the conversation read.
+    if let Err(e) = redis_ops
+        .remove_hash(&format!("{}{}", UNREAD_COUNT, user_id), &peer_id)
+        .await
+    {
+        error!("reset unread count failed: {}", e);
+        return Err(HandlerError::InternalError("internal error".to_string()));
+    }
+    let event = SyncEvent::Read {
+        peer_id,
+        seqnum: last_read_seq,
+    };
+    if let Err(e) = push_sync_event(&mut redis_ops, user_id, &event).await {
+        error!("push sync event failed: {}", e);
+    }
     Ok(ResponseResult {
         code: 200,
         message: "ok.",
@@ -158,6 +183,313 @@ pub(crate) async fn update_unread(
     })
 }
 
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct UnreadResp {
+    peer_id: u64,
+    unread_count: u64,
+    last_read_seq: u64,
+}
+
+/// every conversation with unread messages, counted by the server as messages are delivered.
+#[handler]
+pub(crate) async fn unread_list(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, Vec<UnreadResp>> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let count_map = match redis_ops
+        .peek_hash::<HashMap<u64, i64>>(&format!("{}{}", UNREAD_COUNT, user_id))
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("get unread count failed: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    let mut list = vec![];
+    for (peer_id, unread_count) in count_map {
+        if unread_count <= 0 {
+            continue;
+        }
+        let last_read_seq = redis_ops
+            .get::<u64>(&format!("{}{}-{}", LAST_READ, user_id, peer_id))
+            .await
+            .unwrap_or(0);
+        list.push(UnreadResp {
+            peer_id,
+            unread_count: unread_count as u64,
+            last_read_seq,
+        });
+    }
+    list.sort_by_key(|resp| std::cmp::Reverse(resp.unread_count));
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: list,
+    })
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct HistoryMsg {
+    msg: Msg,
+    /// number of edits, see `revision_list` for the contents replaced.
+    revision: u32,
+    /// time of the latest edit in milliseconds.
+    edited_at: Option<u64>,
+    /// replies in the thread rooted at this message, counted from the stored ones.
+    reply_count: u32,
+    /// time of the latest reply in milliseconds.
+    last_reply_at: Option<u64>,
+    reaction_list: Vec<ReactionResp>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ReactionResp {
+    emoji: String,
+    count: u32,
+    /// the user asking reacted with it too, reacting again takes it back.
+    reacted: bool,
+}
+
+impl From<ReactionCount> for ReactionResp {
+    fn from(reaction: ReactionCount) -> Self {
+        let engine = base64::engine::GeneralPurpose::new(
+            &base64::alphabet::URL_SAFE,
+            base64::engine::general_purpose::NO_PAD,
+        );
+        Self {
+            emoji: String::from_utf8_lossy(
+                &engine.decode(reaction.emoji.as_bytes()).unwrap_or_default(),
+            )
+            .to_string(),
+            count: reaction.count as u32,
+            reacted: reaction.reacted,
+        }
+    }
+}
+
+/// reactions on messages of a conversation as seen by the user, by seqnum, the toggles
+/// `msgprocessor` may not have saved yet are applied to those read from the database.
+pub(super) async fn reactions_by_seq(
+    conversation: &str,
+    user_id: u64,
+    seq_num_list: &[i64],
+) -> Result<HashMap<u64, Vec<ReactionResp>>> {
+    let mut map: HashMap<u64, Vec<ReactionResp>> = HashMap::new();
+    if seq_num_list.is_empty() {
+        return Ok(map);
+    }
+    for reaction in Message::get_reaction_list(conversation, seq_num_list, user_id as i64).await? {
+        map.entry(reaction.seq_num as u64)
+            .or_default()
+            .push(reaction.into());
+    }
+    let mut redis_ops = get_redis_ops().await;
+    // the latest ones, older toggles are the most likely saved.
+    let pending_list = redis_ops
+        .peek_sort_queue_more::<String>(
+            &format!("{}{}", PENDING_REACTION, conversation),
+            0,
+            MAX_PENDING_REACTION,
+            f64::MIN,
+            f64::MAX,
+            false,
+        )
+        .await?
+        .iter()
+        .filter_map(|pending| PendingReaction::parse(pending))
+        .filter(|pending| seq_num_list.contains(&(pending.seq_num as i64)))
+        .collect::<Vec<PendingReaction>>();
+    if pending_list.is_empty() {
+        return Ok(map);
+    }
+    let mut user_list = pending_list
+        .iter()
+        .map(|pending| pending.user_id as i64)
+        .collect::<Vec<i64>>();
+    user_list.sort();
+    user_list.dedup();
+    let saved_list =
+        Message::get_user_reaction_list(conversation, seq_num_list, &user_list).await?;
+    apply_pending(&mut map, user_id, &saved_list, pending_list);
+    Ok(map)
+}
+
+const MAX_PENDING_REACTION: usize = 1000;
+
+/// a toggle in `PENDING_REACTION_`, see `message`.
+#[derive(Debug, Clone, PartialEq)]
+struct PendingReaction {
+    seq_num: u64,
+    user_id: u64,
+    timestamp: u64,
+    /// in base64, as saved.
+    emoji: String,
+}
+
+impl PendingReaction {
+    fn parse(pending: &str) -> Option<Self> {
+        let mut split = pending.splitn(4, ':');
+        Some(Self {
+            seq_num: split.next()?.parse().ok()?,
+            user_id: split.next()?.parse().ok()?,
+            timestamp: split.next()?.parse().ok()?,
+            emoji: split.next()?.to_string(),
+        })
+    }
+}
+
+/// apply the toggles to the reactions read, a toggle not newer than the saved state of its
+/// user and emoji is saved already.
+fn apply_pending(
+    map: &mut HashMap<u64, Vec<ReactionResp>>,
+    user_id: u64,
+    saved_list: &[UserReaction],
+    mut pending_list: Vec<PendingReaction>,
+) {
+    let saved_map = saved_list
+        .iter()
+        .map(|saved| {
+            (
+                (
+                    saved.seq_num as u64,
+                    saved.user_id as u64,
+                    saved.emoji.clone(),
+                ),
+                (saved.active, saved.updated_at.timestamp_millis() as u64),
+            )
+        })
+        .collect::<HashMap<(u64, u64, String), (bool, u64)>>();
+    let mut state_map = saved_map.clone();
+    let mut key_list = vec![];
+    pending_list.sort_by_key(|pending| pending.timestamp);
+    for pending in pending_list.into_iter() {
+        let key = (pending.seq_num, pending.user_id, pending.emoji);
+        let state = state_map.entry(key.clone()).or_insert((false, 0));
+        if state.1 >= pending.timestamp {
+            continue;
+        }
+        *state = (!state.0, pending.timestamp);
+        if !key_list.contains(&key) {
+            key_list.push(key);
+        }
+    }
+    let engine = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    for key in key_list.into_iter() {
+        let active = state_map[&key].0;
+        if active == saved_map.get(&key).map(|state| state.0).unwrap_or(false) {
+            continue;
+        }
+        let (seq_num, reactor, emoji) = key;
+        let emoji = String::from_utf8_lossy(&engine.decode(emoji.as_bytes()).unwrap_or_default())
+            .to_string();
+        let list = map.entry(seq_num).or_default();
+        let index = match list.iter().position(|reaction| reaction.emoji == emoji) {
+            Some(index) => index,
+            None => {
+                list.push(ReactionResp {
+                    emoji,
+                    count: 0,
+                    reacted: false,
+                });
+                list.len() - 1
+            }
+        };
+        let reaction = &mut list[index];
+        if active {
+            reaction.count += 1;
+        } else {
+            reaction.count = reaction.count.saturating_sub(1);
+        }
+        if reactor == user_id {
+            reaction.reacted = active;
+        }
+        if reaction.count == 0 {
+            list.remove(index);
+        }
+    }
+    map.retain(|_, list| !list.is_empty());
+}
+
+/// thread replies are fetched by `thread`, the main timeline goes without them.
+pub(super) fn off_thread(mut list: Vec<Msg>) -> Vec<Msg> {
+    list.retain(|msg| MsgExtension::parse(msg.extension()).thread_root.is_none());
+    list
+}
+
+/// attach the edit, thread and reaction state to messages of a conversation.
+pub(super) async fn with_state(
+    conversation: &str,
+    user_id: u64,
+    list: Vec<Msg>,
+) -> Result<Vec<HistoryMsg>> {
+    // an edited message is cached as an edit, but stored with its own type.
+    let seq_num_list = list
+        .iter()
+        .filter(|msg| msg.typ() == Type::Edit || msg.typ() == Type::Text)
+        .map(|msg| msg.seqnum() as i64)
+        .collect::<Vec<i64>>();
+    let edit_map = if seq_num_list.is_empty() {
+        HashMap::new()
+    } else {
+        Message::get_edit_state_list(conversation, &seq_num_list)
+            .await?
+            .into_iter()
+            .map(|state| (state.seq_num as u64, state))
+            .collect::<HashMap<u64, EditState>>()
+    };
+    let root_list = list
+        .iter()
+        .filter(|msg| msg.typ() != Type::Withdraw)
+        .map(|msg| msg.seqnum() as i64)
+        .collect::<Vec<i64>>();
+    let thread_map = if root_list.is_empty() {
+        HashMap::new()
+    } else {
+        Message::get_thread_state_list(conversation, &root_list)
+            .await?
+            .into_iter()
+            .map(|state| (state.thread_root as u64, state))
+            .collect::<HashMap<u64, ThreadState>>()
+    };
+    let mut reaction_map = reactions_by_seq(conversation, user_id, &root_list).await?;
+    Ok(list
+        .into_iter()
+        .map(|msg| {
+            let edit_state = edit_map.get(&msg.seqnum());
+            let thread_state = thread_map.get(&msg.seqnum());
+            HistoryMsg {
+                revision: edit_state.map(|state| state.revision as u32).unwrap_or(0),
+                edited_at: edit_state
+                    .and_then(|state| state.edited_at)
+                    .map(|edited_at| edited_at.timestamp_millis() as u64),
+                reply_count: thread_state
+                    .map(|state| state.reply_count as u32)
+                    .unwrap_or(0),
+                last_reply_at: thread_state
+                    .and_then(|state| state.last_reply_at)
+                    .map(|last_reply_at| last_reply_at.timestamp_millis() as u64),
+                reaction_list: reaction_map.remove(&msg.seqnum()).unwrap_or_default(),
+                msg,
+            }
+        })
+        .collect())
+}
+
 /// to_seq_num == 0: client don't know the newest seq_num, but it will provide it's local latest seq_num.
 ///
 /// to_seq_num != 0: client have synchronized the msg list and wants more msgs.
@@ -168,11 +500,13 @@ pub(crate) async fn update_unread(
 ///
 /// - get all new msg from cache, if the oldest seq_num match the parameter, returned.
 /// - try to get remained msgs from db.
+///
+/// messages come the newest first, thread replies are left out, see `thread`.
 #[handler]
 pub(crate) async fn history_msg(
     req: &mut salvo::Request,
     _resp: &mut salvo::Response,
-) -> HandlerResult<'static, Vec<Msg>> {
+) -> HandlerResult<'static, Vec<HistoryMsg>> {
     let mut redis_ops = get_redis_ops().await;
     let user_id = match verify_user(req, &mut redis_ops).await {
         Ok(v) => v,
@@ -223,61 +557,73 @@ pub(crate) async fn history_msg(
     } else {
         who_we_are(user_id, peer_id)
     };
-    let cache_from_seq_num = from_seq_num as f64;
-    let mut cache_to_seq_num = to_seq_num as f64;
-    let mut db_from_seq_num = from_seq_num as i64;
-    let mut db_to_seq_num = to_seq_num as i64;
-    if to_seq_num == 0 {
-        cache_to_seq_num = f64::MAX;
-        db_to_seq_num = i64::MAX;
-    }
-    let cache_list = redis_ops
-        .peek_sort_queue_more::<Msg>(
-            &format!("{}{}", MSG_CACHE, id_key),
-            0,
-            expected_size,
-            cache_from_seq_num,
-            cache_to_seq_num - 1.0,
-            false,
-        )
-        .await;
-    if cache_list.is_err() {
-        error!("redis error: {}", cache_list.err().unwrap());
-        return Err(HandlerError::InternalError("internal error".to_string()));
-    }
-    let cache_list = cache_list.unwrap();
-    if cache_list.len() == expected_size {
-        return Ok(ResponseResult {
-            code: 200,
-            message: "ok.",
-            timestamp: Local::now(),
-            data: cache_list,
-        });
-    }
-    if cache_list.len() > 0 {
-        db_to_seq_num = cache_list[0].seqnum() as i64;
-        if to_seq_num == 0 {
-            db_from_seq_num = db_to_seq_num - ((expected_size - cache_list.len()) as i64);
-        }
+    let key = format!("{}{}", MSG_CACHE, id_key);
+    let cache_to_seq_num = if to_seq_num == 0 {
+        f64::MAX
+    } else {
+        to_seq_num as f64 - 1.0
+    };
+    // thread replies are skipped while reading, so a page is as long as expected whenever the
+    // main timeline has enough messages, the cache holds the latest ones in a row.
+    let mut list = vec![];
+    let mut db_to_seq_num = if to_seq_num == 0 {
+        i64::MAX
     } else {
-        if to_seq_num == 0 {
-            db_from_seq_num = db_to_seq_num - expected_size as i64;
+        to_seq_num as i64
+    };
+    let mut offset = 0;
+    while list.len() < expected_size {
+        let cache_list = match redis_ops
+            .peek_sort_queue_more::<Msg>(
+                &key,
+                offset,
+                expected_size,
+                from_seq_num as f64,
+                cache_to_seq_num,
+                false,
+            )
+            .await
+        {
+            Ok(cache_list) => cache_list,
+            Err(e) => {
+                error!("redis error: {}", e);
+                return Err(HandlerError::InternalError("internal error".to_string()));
+            }
+        };
+        offset += cache_list.len();
+        let exhausted = cache_list.len() < expected_size;
+        if let Some(msg) = cache_list.last() {
+            db_to_seq_num = msg.seqnum() as i64;
+        }
+        list.extend(off_thread(cache_list));
+        if exhausted {
+            break;
         }
     }
-    let db_list = Message::get_by_user_and_peer(
-        user_id as i64,
-        peer_id as i64,
-        db_from_seq_num,
-        db_to_seq_num,
-    )
-    .await;
-    if db_list.is_err() {
-        error!("db error: {}", db_list.err().unwrap());
-        return Err(HandlerError::InternalError("internal error".to_string()));
+    list.truncate(expected_size);
+    if list.len() < expected_size && db_to_seq_num > from_seq_num as i64 {
+        match Message::get_main_timeline(
+            &id_key,
+            from_seq_num as i64,
+            db_to_seq_num,
+            (expected_size - list.len()) as i64,
+        )
+        .await
+        {
+            Ok(db_list) => list.extend(db_list.iter().map(|x| x.into())),
+            Err(e) => {
+                error!("db error: {}", e);
+                return Err(HandlerError::InternalError("internal error".to_string()));
+            }
+        }
     }
-    let db_list = db_list.unwrap();
-    let mut list = db_list.iter().map(|x| x.into()).collect::<Vec<Msg>>();
-    list.extend(cache_list);
+    let list = match with_state(&id_key, user_id, list).await {
+        Ok(list) => list,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
     Ok(ResponseResult {
         code: 200,
         message: "ok.",
@@ -286,6 +632,238 @@ pub(crate) async fn history_msg(
     })
 }
 
+/// who sent the message, a cached group message carries its real sender in the extension.
+pub(super) fn sender_of(msg: &Msg) -> Option<u64> {
+    if msg.receiver() >= GROUP_ID_THRESHOLD && msg.sender() == msg.receiver() {
+        MsgExtension::parse(msg.extension()).sender
+    } else {
+        Some(msg.sender())
+    }
+}
+
+/// a user can read a chat of its own, and a group it's in.
+pub(super) async fn check_member(
+    user_id: u64,
+    peer_id: u64,
+) -> std::result::Result<(), HandlerError> {
+    if peer_id >= GROUP_ID_THRESHOLD {
+        return check_relationship(user_id, peer_id).await;
+    }
+    Ok(())
+}
+
+/// the user is in the group, or has the user as a friend.
+pub(super) async fn check_relationship(
+    user_id: u64,
+    peer_id: u64,
+) -> std::result::Result<(), HandlerError> {
+    if UserRelationship::get_user_id_peer_id(user_id as i64, peer_id as i64)
+        .await
+        .is_err()
+    {
+        let cause = if peer_id >= GROUP_ID_THRESHOLD {
+            "user not in this group."
+        } else {
+            "user not a friend of the peer."
+        };
+        return Err(HandlerError::RequestMismatch(403, cause.to_string()));
+    }
+    Ok(())
+}
+
+/// the message with the seqnum in the conversation of the user and the peer, and whether it's
+/// cached, the cache is looked up first.
+pub(super) async fn find_msg(
+    redis_ops: &mut RedisOps,
+    user_id: u64,
+    peer_id: u64,
+    seq_num: u64,
+) -> std::result::Result<(Msg, bool), HandlerError> {
+    let conversation = conversation_id(user_id, peer_id);
+    let cached: Result<Vec<Msg>> = redis_ops
+        .peek_sort_queue_more(
+            &format!("{}{}", MSG_CACHE, conversation),
+            0,
+            1,
+            seq_num as f64,
+            seq_num as f64,
+            true,
+        )
+        .await;
+    if let Some(msg) = cached.ok().and_then(|mut list| list.pop()) {
+        return Ok((msg, true));
+    }
+    stored_msg(Message::get_by_seq(&conversation, seq_num as i64).await)
+}
+
+/// those of the seqnums whose messages are in the conversation, stored or only cached yet.
+pub(crate) async fn exist_seq_num_list(
+    redis_ops: &mut RedisOps,
+    conversation_id: &str,
+    seq_num_list: &[u64],
+) -> Result<Vec<u64>> {
+    let mut exist_list = Message::get_seq_num_list(
+        conversation_id,
+        &seq_num_list
+            .iter()
+            .map(|seq_num| *seq_num as i64)
+            .collect::<Vec<i64>>(),
+    )
+    .await?
+    .into_iter()
+    .map(|seq_num| seq_num as u64)
+    .collect::<Vec<u64>>();
+    for seq_num in seq_num_list.iter() {
+        if exist_list.contains(seq_num) {
+            continue;
+        }
+        let cached: Vec<Msg> = redis_ops
+            .peek_sort_queue_more(
+                &format!("{}{}", MSG_CACHE, conversation_id),
+                0,
+                1,
+                *seq_num as f64,
+                *seq_num as f64,
+                true,
+            )
+            .await?;
+        if !cached.is_empty() {
+            exist_list.push(*seq_num);
+        }
+    }
+    Ok(exist_list)
+}
+
+/// the message found in the database, when it's not cached.
+fn stored_msg(res: Result<Option<Message>>) -> std::result::Result<(Msg, bool), HandlerError> {
+    match res {
+        Ok(Some(message)) => Ok(((&message).into(), false)),
+        Ok(None) => Err(HandlerError::RequestMismatch(
+            404,
+            "message not found.".to_string(),
+        )),
+        Err(e) => {
+            error!("db error: {}", e);
+            Err(HandlerError::InternalError("internal error".to_string()))
+        }
+    }
+}
+
+/// whether the message is sent no longer than the window before `now`.
+fn within(msg: &Msg, window: Duration, now: u64) -> bool {
+    now.saturating_sub(msg.timestamp()) <= window.as_millis() as u64
+}
+
+/// the sender may withdraw within the window, a group admin any time, `role` is empty out of
+/// groups.
+fn check_withdraw(
+    msg: &Msg,
+    user_id: u64,
+    role: &str,
+    window: Duration,
+    now: u64,
+) -> std::result::Result<(), HandlerError> {
+    if msg.typ() == Type::Withdraw {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "message already withdrawn.".to_string(),
+        ));
+    }
+    if sender_of(msg) == Some(user_id) {
+        if !within(msg, window, now) {
+            return Err(HandlerError::RequestMismatch(
+                403,
+                "withdraw window passed.".to_string(),
+            ));
+        }
+    } else if role != "admin" {
+        return Err(HandlerError::RequestMismatch(
+            403,
+            "only the sender or a group admin can withdraw.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// only the sender may edit a text message, within the window.
+fn check_edit(
+    msg: &Msg,
+    user_id: u64,
+    window: Duration,
+    now: u64,
+) -> std::result::Result<(), HandlerError> {
+    if sender_of(msg) != Some(user_id) {
+        return Err(HandlerError::RequestMismatch(
+            403,
+            "only the sender can edit.".to_string(),
+        ));
+    }
+    if msg.typ() != Type::Text && msg.typ() != Type::Edit {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "only text message can be edited.".to_string(),
+        ));
+    }
+    if !within(msg, window, now) {
+        return Err(HandlerError::RequestMismatch(
+            403,
+            "edit window passed.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// replace the cached message with its changed version, so history reads it changed, the
+/// database is changed by `msgprocessor` with the message pushed.
+async fn replace_cached(
+    redis_ops: &mut RedisOps,
+    msg: &Msg,
+    typ: Type,
+    payload: &[u8],
+) -> Result<()> {
+    let key = format!(
+        "{}{}",
+        MSG_CACHE,
+        conversation_id(msg.sender(), msg.receiver())
+    );
+    let mut new_msg = Msg::raw2(
+        msg.sender(),
+        msg.receiver(),
+        msg.node_id(),
+        payload,
+        msg.extension(),
+    );
+    new_msg.set_type(typ);
+    new_msg.set_seqnum(msg.seqnum());
+    new_msg.set_timestamp(msg.timestamp());
+    new_msg.set_encrypted(typ == Type::Edit && msg.encrypted());
+    redis_ops
+        .remove_sort_queue_data(&key, msg.seqnum() as f64)
+        .await?;
+    redis_ops
+        .push_sort_queue(&key, &new_msg, new_msg.seqnum() as f64)
+        .await
+}
+
+/// push the change to the conversation, group members get it through the group as usual.
+async fn push_change(
+    user_id: u64,
+    peer_id: u64,
+    msg: &Msg,
+    typ: Type,
+    payload: &[u8],
+) -> Result<()> {
+    let mut change = Msg::raw2(user_id, peer_id, 0, payload, msg.extension());
+    change.set_type(typ);
+    change.set_seqnum(msg.seqnum());
+    // the new text of an encrypted message is encrypted by the sender as well.
+    change.set_encrypted(typ == Type::Edit && msg.encrypted());
+    let mut rpc_client = get_rpc_client().await;
+    rpc_client.call_push_msg(&change).await
+}
+
+/// the sender may withdraw a message within `withdraw_window`, and a group admin may withdraw
+/// any message of the group.
 #[handler]
 pub(crate) async fn withdraw(
     req: &mut salvo::Request,
@@ -317,54 +895,39 @@ pub(crate) async fn withdraw(
             ))
         }
     };
-    let id_key = who_we_are(user_id, peer_id);
-    let user_peer_key = format!("{}{}", MSG_CACHE, id_key);
-    let res: Result<Vec<Msg>> = redis_ops
-        .peek_sort_queue_more(&user_peer_key, 0, 1, seq_num as f64, seq_num as f64, true)
-        .await;
-    if let Ok(res) = res {
-        if res.len() > 0 {
-            let msg = &res[0];
-            let mut new_msg = Msg::raw(msg.sender(), msg.receiver(), msg.node_id(), &[]);
-            new_msg.set_type(Type::Withdraw);
-            new_msg.set_seqnum(msg.seqnum());
-            _ = redis_ops
-                .remove_sort_queue_data(&user_peer_key, msg.seqnum() as f64)
-                .await;
-            _ = redis_ops
-                .push_sort_queue(&user_peer_key, &new_msg, new_msg.seqnum() as f64)
-                .await;
-            return Ok(ResponseResult {
-                code: 200,
-                message: "ok.",
-                timestamp: Local::now(),
-                data: (),
-            });
-        }
-    }
-    let message_list = Message::get_by_user_and_peer(
-        user_id as i64,
-        peer_id as i64,
-        seq_num as i64,
-        seq_num as i64,
-    )
-    .await;
-    if let Ok(mut message_list) = message_list {
-        if message_list.len() > 0 {
-            let message = &mut message_list[0];
-            message.typ = Type::Withdraw;
-            message.payload = "".to_string();
-            message.extension = "".to_string();
-            _ = message.update().await;
-        } else {
-            // todo
+    let role = if peer_id >= GROUP_ID_THRESHOLD {
+        match UserRelationship::get_user_id_peer_id(user_id as i64, peer_id as i64).await {
+            Ok(relationship) => relationship
+                .info
+                .get("role")
+                .and_then(|role| role.as_str())
+                .unwrap_or("member")
+                .to_string(),
+            Err(_) => {
+                return Err(HandlerError::RequestMismatch(
+                    403,
+                    "user not in this group.".to_string(),
+                ))
+            }
+        }
+    } else {
+        "".to_string()
+    };
+    let (msg, cached) = find_msg(&mut redis_ops, user_id, peer_id, seq_num).await?;
+    check_withdraw(
+        &msg,
+        user_id,
+        &role,
+        config().message.withdraw_window,
+        timestamp(),
+    )?;
+    if cached {
+        if let Err(e) = replace_cached(&mut redis_ops, &msg, Type::Withdraw, &[]).await {
+            error!("replace cached message error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
         }
     }
-    let mut rpc_client = get_rpc_client().await;
-    let mut msg = Msg::raw(user_id, peer_id, 0, &[]);
-    msg.set_seqnum(seq_num);
-    msg.set_type(Type::Withdraw);
-    if let Err(e) = rpc_client.call_push_msg(&msg).await {
+    if let Err(e) = push_change(user_id, peer_id, &msg, Type::Withdraw, &[]).await {
         error!("rpc call push msg error: {}", e);
         return Err(HandlerError::InternalError("internal error".to_string()));
     }
@@ -383,7 +946,7 @@ struct EditReq {
     new_text: String,
 }
 
-/// only allow message (type == text) to be edited.
+/// only the sender may edit a text message, within `edit_window`.
 #[handler]
 pub(crate) async fn edit(
     req: &mut salvo::Request,
@@ -407,68 +970,17 @@ pub(crate) async fn edit(
             ))
         }
     };
-    let id_key = who_we_are(user_id, edit_req.peer_id);
-    let user_peer_key = format!("{}{}", MSG_CACHE, id_key);
-    let res: Result<Vec<Msg>> = redis_ops
-        .peek_sort_queue_more(
-            &user_peer_key,
-            0,
-            1,
-            edit_req.seq_num as f64,
-            edit_req.seq_num as f64,
-            true,
-        )
-        .await;
-    if let Ok(res) = res {
-        if res.len() > 0 {
-            let msg = &res[0];
-            let mut new_msg = Msg::text(
-                msg.sender(),
-                msg.receiver(),
-                msg.node_id(),
-                &edit_req.new_text,
-            );
-            new_msg.set_type(Type::Edit);
-            new_msg.set_seqnum(msg.seqnum());
-            _ = redis_ops
-                .remove_sort_queue_data(&user_peer_key, msg.seqnum() as f64)
-                .await;
-            _ = redis_ops
-                .push_sort_queue(&user_peer_key, &new_msg, new_msg.seqnum() as f64)
-                .await;
-            return Ok(ResponseResult {
-                code: 200,
-                message: "ok.",
-                timestamp: Local::now(),
-                data: (),
-            });
-        }
-    }
-    let message_list = Message::get_by_user_and_peer(
-        user_id as i64,
-        edit_req.peer_id as i64,
-        edit_req.seq_num as i64,
-        edit_req.seq_num as i64,
-    )
-    .await;
-    if let Ok(mut message_list) = message_list {
-        if message_list.len() > 0 {
-            let message = &mut message_list[0];
-            message.typ = Type::Edit;
-            let engine = base64::engine::GeneralPurpose::new(
-                &base64::alphabet::URL_SAFE,
-                base64::engine::general_purpose::PAD,
-            );
-            message.payload = engine.encode(&edit_req.new_text);
-            message.extension = "".to_string();
-            _ = message.update().await;
+    let (msg, cached) =
+        find_msg(&mut redis_ops, user_id, edit_req.peer_id, edit_req.seq_num).await?;
+    check_edit(&msg, user_id, config().message.edit_window, timestamp())?;
+    let payload = edit_req.new_text.as_bytes();
+    if cached {
+        if let Err(e) = replace_cached(&mut redis_ops, &msg, Type::Edit, payload).await {
+            error!("replace cached message error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
         }
     }
-    let mut rpc_client = get_rpc_client().await;
-    let mut msg = Msg::text(user_id, edit_req.seq_num, 0, &edit_req.new_text);
-    msg.set_seqnum(edit_req.seq_num);
-    msg.set_type(Type::Edit);
-    if let Err(e) = rpc_client.call_push_msg(&msg).await {
+    if let Err(e) = push_change(user_id, edit_req.peer_id, &msg, Type::Edit, payload).await {
         error!("rpc call push msg error: {}", e);
         return Err(HandlerError::InternalError("internal error".to_string()));
     }
@@ -480,9 +992,222 @@ pub(crate) async fn edit(
     })
 }
 
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct RevisionResp {
+    revision: u32,
+    /// the content replaced by this edit.
+    text: String,
+    editor: u64,
+    edited_at: u64,
+}
+
+/// contents a message had before each edit, the oldest first, none for a withdrawn one.
+#[handler]
+pub(crate) async fn revision_list(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, Vec<RevisionResp>> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let peer_id = match req.query::<u64>("peer_id") {
+        Some(v) => v,
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "peer id is required.".to_string(),
+            ))
+        }
+    };
+    let seq_num = match req.query::<u64>("seq_num") {
+        Some(v) => v,
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "seq num is required.".to_string(),
+            ))
+        }
+    };
+    check_member(user_id, peer_id).await?;
+    let (msg, _) = find_msg(&mut redis_ops, user_id, peer_id, seq_num).await?;
+    // the keys of an encrypted message's revisions are gone, the clients keep what they read.
+    if msg.typ() == Type::Withdraw || msg.encrypted() {
+        return Ok(ResponseResult {
+            code: 200,
+            message: "ok.",
+            timestamp: Local::now(),
+            data: vec![],
+        });
+    }
+    let list = match Message::get_revision_list(&conversation_id(user_id, peer_id), seq_num as i64)
+        .await
+    {
+        Ok(list) => list,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    let engine = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    let list = list
+        .into_iter()
+        .map(|revision| RevisionResp {
+            revision: revision.revision as u32,
+            text: String::from_utf8_lossy(
+                &engine
+                    .decode(revision.payload.as_bytes())
+                    .unwrap_or_default(),
+            )
+            .to_string(),
+            editor: revision.editor as u64,
+            edited_at: revision.edited_at.timestamp_millis() as u64,
+        })
+        .collect::<Vec<RevisionResp>>();
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: list,
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, time::Duration};
+
+    use chrono::{Local, TimeZone};
+    use lib::entity::{Msg, MsgExtension, Type, GROUP_ID_THRESHOLD};
+
+    use super::{
+        apply_pending, check_edit, check_withdraw, sender_of, stored_msg, within, PendingReaction,
+        ReactionResp,
+    };
+    use crate::{
+        error::HandlerError,
+        model::msg::{Message, UserReaction},
+    };
+
+    const WINDOW: Duration = Duration::from_secs(120);
+    const GROUP_ID: u64 = GROUP_ID_THRESHOLD + 1;
+
+    fn text(sender: u64, receiver: u64, timestamp: u64) -> Msg {
+        let mut msg = Msg::text(sender, receiver, 0, "hello");
+        msg.set_timestamp(timestamp);
+        msg.set_seqnum(7);
+        msg
+    }
+
+    /// a group message as cached, sent by the group with the real sender in the extension.
+    fn group_text(sender: u64, timestamp: u64) -> Msg {
+        let extension = MsgExtension {
+            sender: Some(sender),
+            ..Default::default()
+        }
+        .to_string();
+        let mut msg = Msg::raw2(GROUP_ID, GROUP_ID, 0, b"hello", extension.as_bytes());
+        msg.set_type(Type::Text);
+        msg.set_timestamp(timestamp);
+        msg
+    }
+
+    fn status(res: std::result::Result<(), HandlerError>) -> u32 {
+        match res {
+            Ok(_) => 200,
+            Err(HandlerError::RequestMismatch(code, _)) => code,
+            Err(HandlerError::ParameterMismatch(_)) => 400,
+            Err(HandlerError::InternalError(_)) => 500,
+        }
+    }
+
+    #[test]
+    fn test_within() {
+        let msg = text(1, 2, 1_000_000);
+        assert!(within(&msg, WINDOW, 1_000_000));
+        assert!(within(&msg, WINDOW, 1_000_000 + 120_000));
+        assert!(!within(&msg, WINDOW, 1_000_000 + 120_001));
+        // a clock behind the sender's node.
+        assert!(within(&msg, WINDOW, 999_000));
+    }
+
+    #[test]
+    fn test_sender_of() {
+        assert_eq!(sender_of(&text(1, 2, 0)), Some(1));
+        // a group message of the database keeps its real sender.
+        assert_eq!(sender_of(&text(1, GROUP_ID, 0)), Some(1));
+        assert_eq!(sender_of(&group_text(3, 0)), Some(3));
+        let mut msg = group_text(3, 0);
+        msg.replace_extension(b"");
+        assert_eq!(sender_of(&msg), None);
+    }
+
+    #[test]
+    fn test_check_withdraw() {
+        let now = 10_000_000;
+        let msg = text(1, 2, now - 120_000);
+        assert_eq!(status(check_withdraw(&msg, 1, "", WINDOW, now)), 200);
+        assert_eq!(status(check_withdraw(&msg, 1, "", WINDOW, now + 1)), 403);
+        // the peer of a chat is not the sender.
+        assert_eq!(status(check_withdraw(&msg, 2, "", WINDOW, now)), 403);
+        let msg = group_text(1, now - 3_600_000);
+        assert_eq!(status(check_withdraw(&msg, 1, "member", WINDOW, now)), 403);
+        assert_eq!(status(check_withdraw(&msg, 3, "member", WINDOW, now)), 403);
+        // an admin withdraws others' messages at any time, its own only within the window.
+        assert_eq!(status(check_withdraw(&msg, 3, "admin", WINDOW, now)), 200);
+        assert_eq!(status(check_withdraw(&msg, 1, "admin", WINDOW, now)), 403);
+        let mut msg = group_text(1, now);
+        msg.set_type(Type::Withdraw);
+        assert_eq!(status(check_withdraw(&msg, 3, "admin", WINDOW, now)), 400);
+    }
+
+    #[test]
+    fn test_check_edit() {
+        let now = 10_000_000;
+        let msg = text(1, 2, now - 120_000);
+        assert_eq!(status(check_edit(&msg, 1, WINDOW, now)), 200);
+        assert_eq!(status(check_edit(&msg, 1, WINDOW, now + 1)), 403);
+        assert_eq!(status(check_edit(&msg, 2, WINDOW, now)), 403);
+        // an admin can't edit others' messages.
+        assert_eq!(status(check_edit(&group_text(1, now), 3, WINDOW, now)), 403);
+        assert_eq!(status(check_edit(&group_text(1, now), 1, WINDOW, now)), 200);
+        let mut msg = text(1, 2, now);
+        msg.set_type(Type::Image);
+        assert_eq!(status(check_edit(&msg, 1, WINDOW, now)), 400);
+    }
+
+    #[test]
+    fn test_stored_msg() {
+        let message = Message {
+            id: 1,
+            sender: 1,
+            receiver: 2,
+            timestamp: Local.timestamp_millis_opt(1_000_000).unwrap(),
+            seq_num: 7,
+            typ: Type::Text,
+            version: 1,
+            extension: "".to_string(),
+            payload: "aGVsbG8".to_string(),
+        };
+        let (msg, cached) = stored_msg(Ok(Some(message))).ok().unwrap();
+        assert!(!cached);
+        assert_eq!(msg.seqnum(), 7);
+        assert_eq!(sender_of(&msg), Some(1));
+        assert!(matches!(
+            stored_msg(Ok(None)),
+            Err(HandlerError::RequestMismatch(404, _))
+        ));
+        assert!(matches!(
+            stored_msg(Err(anyhow::anyhow!("closed"))),
+            Err(HandlerError::InternalError(_))
+        ));
+    }
 
     #[test]
     fn test() {
@@ -491,4 +1216,70 @@ mod tests {
         map.insert("a", val);
         println!("{}", serde_json::to_string(&map).unwrap());
     }
+
+    #[test]
+    fn test_pending_reaction() {
+        assert_eq!(
+            PendingReaction::parse("7:1:1000:8J-RjQ"),
+            Some(PendingReaction {
+                seq_num: 7,
+                user_id: 1,
+                timestamp: 1000,
+                emoji: "8J-RjQ".to_string(),
+            })
+        );
+        assert_eq!(PendingReaction::parse("7:1:1000"), None);
+        assert_eq!(PendingReaction::parse("7:x:1000:8J-RjQ"), None);
+    }
+
+    #[test]
+    fn test_apply_pending() {
+        // "+1" in base64.
+        let emoji = "KzE".to_string();
+        let pending = |seq_num: u64, user_id: u64, timestamp: u64| PendingReaction {
+            seq_num,
+            user_id,
+            timestamp,
+            emoji: emoji.clone(),
+        };
+        let saved = |user_id: i64, active: bool, updated_at: i64| UserReaction {
+            seq_num: 7,
+            user_id,
+            emoji: emoji.clone(),
+            active,
+            updated_at: Local.timestamp_millis_opt(updated_at).unwrap(),
+        };
+        let mut map = HashMap::new();
+        map.insert(
+            7,
+            vec![ReactionResp {
+                emoji: "+1".to_string(),
+                count: 1,
+                reacted: false,
+            }],
+        );
+        // user 2 reacted and is saved, user 1 reacts and takes it back, then reacts again, user
+        // 2 takes it back, only the last toggle of user 2 is not saved yet.
+        let saved_list = vec![saved(2, true, 100)];
+        let pending_list = vec![
+            pending(7, 1, 200),
+            pending(7, 1, 300),
+            pending(7, 1, 400),
+            pending(7, 2, 100),
+            pending(7, 2, 500),
+        ];
+        apply_pending(&mut map, 1, &saved_list, pending_list);
+        assert_eq!(map[&7].len(), 1);
+        assert_eq!(map[&7][0].count, 1);
+        assert!(map[&7][0].reacted);
+        // the last reaction taken back.
+        let saved_list = vec![saved(1, true, 400), saved(2, false, 500)];
+        apply_pending(&mut map, 1, &saved_list, vec![pending(7, 1, 600)]);
+        assert!(map.is_empty());
+        // a new emoji on another message.
+        apply_pending(&mut map, 1, &[], vec![pending(8, 3, 700)]);
+        assert_eq!(map[&8][0].emoji, "+1");
+        assert_eq!(map[&8][0].count, 1);
+        assert!(!map[&8][0].reacted);
+    }
 }
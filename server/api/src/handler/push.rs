@@ -0,0 +1,206 @@
+use chrono::Local;
+use salvo::handler;
+use tracing::error;
+
+use crate::{
+    cache::{get_redis_ops, PUSH_DEVICE_SET, PUSH_MUTE_SET},
+    error::HandlerError,
+};
+
+use super::{verify_user, HandlerResult, ResponseResult};
+
+/// platforms msgprocessor has a push provider for.
+const PLATFORM_LIST: [&str; 3] = ["apns", "fcm", "http"];
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct DeviceReq {
+    /// one of `apns`, `fcm` and `http`.
+    platform: String,
+    token: String,
+}
+
+#[handler]
+pub(crate) async fn register_device(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<DeviceReq>().await {
+        Ok(form) => form,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "request parameter missing or invalid.".to_string(),
+            ))
+        }
+    };
+    if !PLATFORM_LIST.contains(&form.platform.as_str()) || form.token.is_empty() {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "unsupported platform or empty token.".to_string(),
+        ));
+    }
+    if let Err(e) = redis_ops
+        .push_set(
+            &format!("{}{}", PUSH_DEVICE_SET, user_id),
+            &format!("{}:{}", form.platform, form.token),
+        )
+        .await
+    {
+        error!("register device of {} error: {}", user_id, e);
+        return Err(HandlerError::RequestMismatch(
+            500,
+            "internal server error.".to_string(),
+        ));
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// called on logout, so the device stops receiving pushes of the user.
+#[handler]
+pub(crate) async fn unregister_device(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<DeviceReq>().await {
+        Ok(form) => form,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "request parameter missing or invalid.".to_string(),
+            ))
+        }
+    };
+    if let Err(e) = redis_ops
+        .remove_set(
+            &format!("{}{}", PUSH_DEVICE_SET, user_id),
+            &format!("{}:{}", form.platform, form.token),
+        )
+        .await
+    {
+        error!("unregister device of {} error: {}", user_id, e);
+        return Err(HandlerError::RequestMismatch(
+            500,
+            "internal server error.".to_string(),
+        ));
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct MuteReq {
+    /// a user id or a group id.
+    peer_id: u64,
+    muted: bool,
+}
+
+/// messages from a muted peer are stored and delivered as usual, but never pushed.
+#[handler]
+pub(crate) async fn set_mute(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<MuteReq>().await {
+        Ok(form) => form,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "request parameter missing or invalid.".to_string(),
+            ))
+        }
+    };
+    let key = format!("{}{}", PUSH_MUTE_SET, user_id);
+    let res = if form.muted {
+        redis_ops.push_set(&key, &form.peer_id).await
+    } else {
+        redis_ops.remove_set(&key, &form.peer_id).await
+    };
+    if let Err(e) = res {
+        error!("set mute of {} error: {}", user_id, e);
+        return Err(HandlerError::RequestMismatch(
+            500,
+            "internal server error.".to_string(),
+        ));
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+#[handler]
+pub(crate) async fn get_mute_list(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, Vec<u64>> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(user_id) => user_id,
+        Err(_err) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized.".to_string(),
+            ))
+        }
+    };
+    let mute_list = match redis_ops
+        .peek_set::<Vec<u64>>(&format!("{}{}", PUSH_MUTE_SET, user_id))
+        .await
+    {
+        Ok(mute_list) => mute_list,
+        Err(e) => {
+            error!("get mute list of {} error: {}", user_id, e);
+            return Err(HandlerError::RequestMismatch(
+                500,
+                "internal server error.".to_string(),
+            ));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: mute_list,
+    })
+}
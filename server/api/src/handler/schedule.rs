@@ -0,0 +1,543 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+use lib::{
+    entity::{Msg, MsgExtension, Type, GROUP_ID_THRESHOLD},
+    error::HandlerError as NodeError,
+    util::{conversation_id, timestamp},
+};
+use salvo::handler;
+use tracing::{error, warn};
+
+use crate::{
+    cache::get_redis_ops,
+    config::config,
+    error::HandlerError,
+    model::{group::Group, schedule::ScheduledMessage},
+    rpc::get_rpc_client,
+};
+
+use super::{
+    msg::{check_member, exist_seq_num_list},
+    verify_user, HandlerResult, ResponseResult,
+};
+
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+const DISPATCH_BATCH_SIZE: i64 = 100;
+/// in seconds, a claim older than this is left by an api instance stopped while sending. the claim
+/// is refreshed right before each message is sent, so it only has to outlast one send.
+const CLAIM_TIMEOUT: i64 = 60;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// a message failing to be sent this many times is dropped.
+const MAX_SEND_ATTEMPT: i32 = 8;
+/// in seconds, the delay before the first retry, doubled by each failure after.
+const RETRY_DELAY: i64 = 2;
+const MAX_PAYLOAD_LENGTH: usize = 4096;
+
+#[derive(Debug, serde::Deserialize)]
+struct ScheduleReq {
+    receiver: u64,
+    /// Text if not given.
+    typ: Option<u16>,
+    payload: String,
+    #[serde(default)]
+    extension: String,
+    /// in milliseconds.
+    deliver_at: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ScheduledResp {
+    id: u64,
+    receiver: u64,
+    typ: u16,
+    payload: String,
+    extension: String,
+    deliver_at: u64,
+    create_at: u64,
+}
+
+impl From<ScheduledMessage> for ScheduledResp {
+    fn from(scheduled: ScheduledMessage) -> Self {
+        Self {
+            id: scheduled.id as u64,
+            receiver: scheduled.receiver as u64,
+            typ: scheduled.typ.value(),
+            payload: scheduled.payload,
+            extension: scheduled.extension,
+            deliver_at: scheduled.deliver_at.timestamp_millis() as u64,
+            create_at: scheduled.create_at.timestamp_millis() as u64,
+        }
+    }
+}
+
+/// the rules the message node applies to messages sent by clients, checked when a message is
+/// scheduled as the node takes the api's messages as they are: mentions follow
+/// `MsgExtension::check_mention`, quoted messages and thread roots are in the conversation.
+async fn check_extension(
+    user_id: u64,
+    receiver: u64,
+    extension: &str,
+) -> std::result::Result<(), HandlerError> {
+    let extension = MsgExtension::parse(extension.as_bytes());
+    if extension.has_mention() {
+        let (user_list, admin_list) = if receiver >= GROUP_ID_THRESHOLD {
+            match Group::get_group_id(receiver as i64).await {
+                Ok(group) => group_member_list(&group),
+                Err(e) => {
+                    error!("db error: {}", e);
+                    return Err(HandlerError::InternalError("internal error".to_string()));
+                }
+            }
+        } else {
+            (vec![], vec![])
+        };
+        if let Err(cause) = extension.check_mention(user_id, receiver, &user_list, &admin_list) {
+            return Err(HandlerError::RequestMismatch(400, cause));
+        }
+    }
+    let seq_num_list = extension
+        .quote
+        .into_iter()
+        .chain(extension.thread_root)
+        .collect::<Vec<u64>>();
+    if seq_num_list.is_empty() {
+        return Ok(());
+    }
+    let mut redis_ops = get_redis_ops().await;
+    let exist_list = match exist_seq_num_list(
+        &mut redis_ops,
+        &conversation_id(user_id, receiver),
+        &seq_num_list,
+    )
+    .await
+    {
+        Ok(list) => list,
+        Err(e) => {
+            error!("get seq num list error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    if !seq_num_list
+        .iter()
+        .all(|seq_num| exist_list.contains(seq_num))
+    {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "invalid quote or thread root.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// all members, admins included, and the admins, as the message node gets them.
+fn group_member_list(group: &Group) -> (Vec<u64>, Vec<u64>) {
+    let id_of = |user: &serde_json::Value| user.get("user_id").and_then(|id| id.as_u64());
+    let admin_list = group
+        .admin_list
+        .iter()
+        .filter_map(id_of)
+        .collect::<Vec<u64>>();
+    let user_list = group
+        .member_list
+        .iter()
+        .filter_map(id_of)
+        .chain(admin_list.iter().copied())
+        .collect::<Vec<u64>>();
+    (user_list, admin_list)
+}
+
+/// later than now and within the horizon, in milliseconds.
+fn in_range(deliver_at: u64, now: u64, horizon: Duration) -> bool {
+    deliver_at > now && deliver_at - now <= horizon.as_millis() as u64
+}
+
+/// whether a running api instance is sending the message, a claim left by a stopped one isn't.
+fn claimed(claimed_at: Option<DateTime<Local>>, now: DateTime<Local>) -> bool {
+    claimed_at
+        .is_some_and(|claimed_at| claimed_at >= now - chrono::Duration::seconds(CLAIM_TIMEOUT))
+}
+
+/// only the sender cancels, and only before it's being sent.
+fn check_cancel(
+    scheduled: Option<&ScheduledMessage>,
+    user_id: u64,
+    now: DateTime<Local>,
+) -> std::result::Result<(), HandlerError> {
+    match scheduled {
+        Some(scheduled) if scheduled.sender as u64 == user_id => {
+            if claimed(scheduled.claimed_at, now) {
+                return Err(HandlerError::RequestMismatch(
+                    409,
+                    "scheduled message being sent.".to_string(),
+                ));
+            }
+            Ok(())
+        }
+        _ => Err(HandlerError::RequestMismatch(
+            404,
+            "scheduled message not found.".to_string(),
+        )),
+    }
+}
+
+/// in seconds, the delay before sending again a message failed `attempt` times, none once it
+/// failed too many times.
+fn retry_delay(attempt: i32) -> Option<i64> {
+    if attempt >= MAX_SEND_ATTEMPT {
+        return None;
+    }
+    Some(RETRY_DELAY << (attempt - 1).max(0))
+}
+
+/// keep a message to be sent at `deliver_at`, it gets its seqnum then, the id returned is for
+/// listing and cancelling it.
+#[handler]
+pub(crate) async fn schedule(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, u64> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let form = match req.parse_json::<ScheduleReq>().await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::ParameterMismatch(
+                "receiver, payload and deliver_at are required.".to_string(),
+            ))
+        }
+    };
+    let typ = Type::from(form.typ.unwrap_or(Type::Text.value()));
+    if !(Type::Text.value()..Type::Edit.value()).contains(&typ.value()) {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "only content messages can be scheduled.".to_string(),
+        ));
+    }
+    if form.payload.is_empty()
+        || form.payload.len() > MAX_PAYLOAD_LENGTH
+        || form.extension.len() >= 1 << 6
+    {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "payload or extension too long.".to_string(),
+        ));
+    }
+    if !in_range(
+        form.deliver_at,
+        timestamp(),
+        config().message.schedule_horizon,
+    ) {
+        return Err(HandlerError::RequestMismatch(
+            400,
+            "deliver_at out of range.".to_string(),
+        ));
+    }
+    check_member(user_id, form.receiver).await?;
+    check_extension(user_id, form.receiver, &form.extension).await?;
+    match ScheduledMessage::count_by_sender(user_id as i64).await {
+        Ok(count) if count >= config().message.schedule_limit as i64 => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "too many scheduled messages.".to_string(),
+            ))
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    }
+    let scheduled = ScheduledMessage {
+        id: 0,
+        sender: user_id as i64,
+        receiver: form.receiver as i64,
+        typ,
+        extension: form.extension,
+        payload: form.payload,
+        deliver_at: Local.timestamp_millis_opt(form.deliver_at as i64).unwrap(),
+        create_at: Local::now(),
+        claimed_at: None,
+        attempt: 0,
+    };
+    let id = match scheduled.insert().await {
+        Ok(id) => id,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: id as u64,
+    })
+}
+
+/// messages of the user not sent yet, the earliest to send first.
+#[handler]
+pub(crate) async fn schedule_list(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, Vec<ScheduledResp>> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let list = match ScheduledMessage::get_by_sender(user_id as i64).await {
+        Ok(list) => list,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: list.into_iter().map(|scheduled| scheduled.into()).collect(),
+    })
+}
+
+/// a message being sent can't be cancelled.
+#[handler]
+pub(crate) async fn cancel_schedule(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ()> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let id = match req.query::<u64>("id") {
+        Some(v) => v,
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "id is required.".to_string(),
+            ))
+        }
+    };
+    let scheduled = match ScheduledMessage::get(id as i64).await {
+        Ok(scheduled) => scheduled,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    check_cancel(scheduled.as_ref(), user_id, Local::now())?;
+    // claimed since it was read.
+    match ScheduledMessage::cancel(id as i64, user_id as i64, CLAIM_TIMEOUT).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(HandlerError::RequestMismatch(
+                409,
+                "scheduled message being sent.".to_string(),
+            ))
+        }
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: (),
+    })
+}
+
+/// send the scheduled messages due through the scheduler, as if the sender sent them now.
+///
+/// every api instance runs it, a message failed to send is tried again later and dropped after
+/// `MAX_SEND_ATTEMPT` failures, one whose sender left the group is dropped. a message is sent
+/// only if its claim is refreshed right before, so an instance taking over a stale claim doesn't
+/// send it twice.
+pub(crate) async fn dispatch() {
+    let mut interval = tokio::time::interval(DISPATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let list = match ScheduledMessage::claim_due(DISPATCH_BATCH_SIZE, CLAIM_TIMEOUT).await {
+            Ok(list) => list,
+            Err(e) => {
+                error!("claim scheduled messages error: {}", e);
+                continue;
+            }
+        };
+        if list.is_empty() {
+            continue;
+        }
+        let mut rpc_client = get_rpc_client().await;
+        for mut scheduled in list {
+            let (sender, receiver) = (scheduled.sender as u64, scheduled.receiver as u64);
+            if check_member(sender, receiver).await.is_err() {
+                warn!("{} left {}, scheduled message dropped", sender, receiver);
+                if let Err(e) = ScheduledMessage::delete(scheduled.id).await {
+                    error!("db error: {}", e);
+                }
+                continue;
+            }
+            match scheduled.refresh_claim().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(
+                        "scheduled message {} claimed by another instance",
+                        scheduled.id
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    error!("db error: {}", e);
+                    continue;
+                }
+            }
+            let mut msg = Msg::raw2(
+                sender,
+                receiver,
+                0,
+                scheduled.payload.as_bytes(),
+                scheduled.extension.as_bytes(),
+            );
+            msg.set_type(scheduled.typ);
+            msg.set_timestamp(timestamp());
+            let res = match tokio::time::timeout(SEND_TIMEOUT, rpc_client.call_push_msg(&msg)).await
+            {
+                Ok(Ok(_)) => ScheduledMessage::delete(scheduled.id).await,
+                Ok(Err(e)) => match e.downcast_ref::<NodeError>() {
+                    // a bad mention, a stale quote and so on, it will be refused again.
+                    Some(NodeError::Parse(cause)) => {
+                        error!(
+                            "scheduled message {} refused: {}, dropped",
+                            scheduled.id, cause
+                        );
+                        ScheduledMessage::delete(scheduled.id).await
+                    }
+                    _ => {
+                        error!("send scheduled message {} error: {}", scheduled.id, e);
+                        retry(&scheduled).await
+                    }
+                },
+                Err(_) => {
+                    error!("send scheduled message {} timeout", scheduled.id);
+                    retry(&scheduled).await
+                }
+            };
+            if let Err(e) = res {
+                error!("db error: {}", e);
+            }
+        }
+    }
+}
+
+async fn retry(scheduled: &ScheduledMessage) -> lib::Result<()> {
+    match retry_delay(scheduled.attempt + 1) {
+        Some(delay) => ScheduledMessage::release(scheduled.id, delay).await,
+        None => {
+            error!(
+                "scheduled message {} failed {} times, dropped",
+                scheduled.id, MAX_SEND_ATTEMPT
+            );
+            ScheduledMessage::delete(scheduled.id).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{Local, TimeZone};
+    use lib::entity::Type;
+
+    use crate::{error::HandlerError, model::schedule::ScheduledMessage};
+
+    use super::{check_cancel, claimed, in_range, retry_delay, CLAIM_TIMEOUT, MAX_SEND_ATTEMPT};
+
+    fn scheduled(sender: i64, claimed_at: Option<i64>) -> ScheduledMessage {
+        ScheduledMessage {
+            id: 1,
+            sender,
+            receiver: 2,
+            typ: Type::Text,
+            extension: "".to_string(),
+            payload: "text".to_string(),
+            deliver_at: Local.timestamp_millis_opt(10_000_000).unwrap(),
+            create_at: Local.timestamp_millis_opt(0).unwrap(),
+            claimed_at: claimed_at.map(|millis| Local.timestamp_millis_opt(millis).unwrap()),
+            attempt: 0,
+        }
+    }
+
+    #[test]
+    fn test_in_range() {
+        let horizon = Duration::from_secs(60);
+        assert!(in_range(1_001, 1_000, horizon));
+        assert!(in_range(61_000, 1_000, horizon));
+        assert!(!in_range(1_000, 1_000, horizon));
+        assert!(!in_range(999, 1_000, horizon));
+        assert!(!in_range(61_001, 1_000, horizon));
+    }
+
+    #[test]
+    fn test_claimed() {
+        let now = Local.timestamp_millis_opt(10_000_000).unwrap();
+        let timeout = CLAIM_TIMEOUT * 1000;
+        assert!(!claimed(None, now));
+        let at = |millis: i64| Some(Local.timestamp_millis_opt(millis).unwrap());
+        assert!(claimed(at(10_000_000), now));
+        assert!(claimed(at(10_000_000 - timeout), now));
+        assert!(!claimed(at(10_000_000 - timeout - 1), now));
+    }
+
+    #[test]
+    fn test_check_cancel() {
+        let now = Local.timestamp_millis_opt(10_000_000).unwrap();
+        let code = |res: Result<(), HandlerError>| match res {
+            Ok(_) => 200,
+            Err(HandlerError::RequestMismatch(code, _)) => code,
+            Err(_) => 500,
+        };
+        assert_eq!(code(check_cancel(Some(&scheduled(1, None)), 1, now)), 200);
+        assert_eq!(code(check_cancel(None, 1, now)), 404);
+        assert_eq!(code(check_cancel(Some(&scheduled(3, None)), 1, now)), 404);
+        let claimed = scheduled(1, Some(10_000_000 - 1_000));
+        assert_eq!(code(check_cancel(Some(&claimed), 1, now)), 409);
+        // left by a stopped instance.
+        let stale = scheduled(1, Some(10_000_000 - CLAIM_TIMEOUT * 1000 - 1));
+        assert_eq!(code(check_cancel(Some(&stale), 1, now)), 200);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Some(2));
+        assert_eq!(retry_delay(2), Some(4));
+        assert_eq!(
+            retry_delay(MAX_SEND_ATTEMPT - 1),
+            Some(2 << (MAX_SEND_ATTEMPT - 2))
+        );
+        assert_eq!(retry_delay(MAX_SEND_ATTEMPT), None);
+        let total = (1..MAX_SEND_ATTEMPT).filter_map(retry_delay).sum::<i64>();
+        assert!(total < 24 * 60 * 60);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use chrono::Local;
+use lib::{
+    cache::redis_ops::RedisOps,
+    entity::{SyncEvent, Type},
+    util::conversation_id,
+    Result,
+};
+use salvo::handler;
+use tracing::error;
+
+use crate::{
+    cache::{get_redis_ops, sync_seq_key, USER_SYNC},
+    error::HandlerError,
+};
+
+use super::{
+    msg::{reactions_by_seq, ReactionResp},
+    verify_user, HandlerResult, ResponseResult,
+};
+
+const DEFAULT_SYNC_SIZE: usize = 500;
+const MAX_SYNC_SIZE: usize = 1000;
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct SyncResp {
+    events: Vec<SyncEvent>,
+    /// pass as `token` for the next call, it never goes back.
+    token: String,
+    has_more: bool,
+    /// events after the token are partly trimmed, the client should reload its conversations
+    /// and continue syncing with the new token.
+    truncated: bool,
+    /// reactions now on the messages reacted to by the events, to replace the local ones.
+    reaction_list: Vec<ReactionState>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ReactionState {
+    peer_id: u64,
+    seq_num: u64,
+    reaction_list: Vec<ReactionResp>,
+}
+
+/// record a change for all devices of the user.
+pub(crate) async fn push_sync_event(
+    redis_ops: &mut RedisOps,
+    user_id: u64,
+    event: &SyncEvent,
+) -> Result<()> {
+    let event = serde_json::to_string(event)?;
+    redis_ops
+        .push_sort_queue_seq(
+            &format!("{}{}", USER_SYNC, user_id),
+            &sync_seq_key(user_id),
+            &[event],
+        )
+        .await?;
+    Ok(())
+}
+
+/// the token is the score of the last event got, scores count the events of the user, so an
+/// event written late is never behind a token given out. tokens of the older `<time>-<skip>`
+/// form start over from the earliest event kept.
+fn parse_token(token: &str) -> Option<Option<u64>> {
+    if token.is_empty() || token.contains('-') {
+        return Some(None);
+    }
+    token.parse::<u64>().ok().map(Some)
+}
+
+/// changes of the user since the token, the oldest first, an empty token starts from the
+/// earliest one kept, which is always truncated.
+#[handler]
+pub(crate) async fn sync(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, SyncResp> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let limit = req
+        .query::<usize>("limit")
+        .unwrap_or(DEFAULT_SYNC_SIZE)
+        .clamp(1, MAX_SYNC_SIZE);
+    let after = match parse_token(&req.query::<String>("token").unwrap_or_default()) {
+        Some(after) => after,
+        None => {
+            return Err(HandlerError::RequestMismatch(
+                400,
+                "invalid sync token.".to_string(),
+            ))
+        }
+    };
+    let from = match after {
+        Some(after) => (after + 1) as f64,
+        None => f64::MIN,
+    };
+    let list = match redis_ops
+        .peek_sort_queue_more_with_score::<String>(
+            &format!("{}{}", USER_SYNC, user_id),
+            0,
+            limit,
+            from,
+            f64::MAX,
+            true,
+        )
+        .await
+    {
+        Ok(list) => list,
+        Err(e) => {
+            error!("get sync events failed: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    let has_more = list.len() == limit;
+    let token = match list.last() {
+        Some((_, last)) => *last as u64,
+        None => after.unwrap_or(0),
+    }
+    .to_string();
+    let mut truncated = false;
+    let mut events = Vec::with_capacity(list.len());
+    for (event, _) in list.into_iter() {
+        match serde_json::from_str::<SyncEvent>(&event) {
+            Ok(SyncEvent::Floor) => truncated = true,
+            Ok(event) => events.push(event),
+            Err(e) => error!("parse sync event failed: {}", e),
+        }
+    }
+    // the toggles themselves don't tell the count, the stored state of the messages does.
+    let mut reacted_map: HashMap<u64, Vec<u64>> = HashMap::new();
+    for event in events.iter() {
+        if let SyncEvent::Msg { msg } = event {
+            if msg.typ() != Type::Reaction {
+                continue;
+            }
+            let peer_id = if msg.sender() == user_id {
+                msg.receiver()
+            } else {
+                msg.sender()
+            };
+            let seq_num_list = reacted_map.entry(peer_id).or_default();
+            if !seq_num_list.contains(&msg.seqnum()) {
+                seq_num_list.push(msg.seqnum());
+            }
+        }
+    }
+    let mut reaction_list = vec![];
+    for (peer_id, seq_num_list) in reacted_map.into_iter() {
+        let mut reaction_map = match reactions_by_seq(
+            &conversation_id(user_id, peer_id),
+            user_id,
+            &seq_num_list
+                .iter()
+                .map(|seq_num| *seq_num as i64)
+                .collect::<Vec<i64>>(),
+        )
+        .await
+        {
+            Ok(map) => map,
+            Err(e) => {
+                error!("db error: {}", e);
+                return Err(HandlerError::InternalError("internal error".to_string()));
+            }
+        };
+        for seq_num in seq_num_list.into_iter() {
+            reaction_list.push(ReactionState {
+                peer_id,
+                seq_num,
+                reaction_list: reaction_map.remove(&seq_num).unwrap_or_default(),
+            });
+        }
+    }
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: SyncResp {
+            events,
+            token,
+            has_more,
+            truncated,
+            reaction_list,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_token;
+
+    #[test]
+    fn test_parse_token() {
+        assert_eq!(parse_token(""), Some(None));
+        assert_eq!(parse_token("42"), Some(Some(42)));
+        // tokens of older versions start over.
+        assert_eq!(parse_token("1700000000000-3"), Some(None));
+        assert_eq!(parse_token("abc"), None);
+    }
+}
@@ -0,0 +1,141 @@
+use chrono::Local;
+use lib::{
+    entity::{Msg, MsgExtension},
+    util::conversation_id,
+};
+use salvo::handler;
+use tracing::error;
+
+use crate::{
+    cache::{get_redis_ops, MSG_CACHE},
+    error::HandlerError,
+    model::msg::Message,
+};
+
+use super::{
+    msg::{check_member, find_msg, with_state, HistoryMsg},
+    verify_user, HandlerResult, ResponseResult,
+};
+
+const DEFAULT_THREAD_SIZE: usize = 50;
+const MAX_THREAD_SIZE: usize = 100;
+/// replies not stored yet are found among this many of the newest cached messages.
+const CACHE_SCAN_SIZE: usize = 1000;
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ThreadResp {
+    /// with the number of replies and the time of the latest one.
+    root: HistoryMsg,
+    /// replies after `from_seq_num`, the oldest first.
+    list: Vec<HistoryMsg>,
+    has_more: bool,
+}
+
+/// a thread is the messages replying in it, see `lib::entity::MsgExtension`, they stay off the
+/// main timeline so a discussion doesn't flood it.
+#[handler]
+pub(crate) async fn thread(
+    req: &mut salvo::Request,
+    _resp: &mut salvo::Response,
+) -> HandlerResult<'static, ThreadResp> {
+    let mut redis_ops = get_redis_ops().await;
+    let user_id = match verify_user(req, &mut redis_ops).await {
+        Ok(v) => v,
+        Err(_e) => {
+            return Err(HandlerError::RequestMismatch(
+                401,
+                "unauthorized".to_string(),
+            ))
+        }
+    };
+    let peer_id = match req.query::<u64>("peer_id") {
+        Some(v) => v,
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "peer id is required.".to_string(),
+            ))
+        }
+    };
+    let root_seq = match req.query::<u64>("root_seq") {
+        Some(v) => v,
+        None => {
+            return Err(HandlerError::ParameterMismatch(
+                "root seq is required.".to_string(),
+            ))
+        }
+    };
+    let from_seq_num = req.query::<u64>("from_seq_num").unwrap_or(root_seq);
+    let limit = req
+        .query::<usize>("limit")
+        .unwrap_or(DEFAULT_THREAD_SIZE)
+        .clamp(1, MAX_THREAD_SIZE);
+    check_member(user_id, peer_id).await?;
+    let (root, _) = find_msg(&mut redis_ops, user_id, peer_id, root_seq).await?;
+    let conversation = conversation_id(user_id, peer_id);
+    let mut list = match Message::get_thread(
+        &conversation,
+        root_seq as i64,
+        from_seq_num as i64,
+        limit as i64,
+    )
+    .await
+    {
+        Ok(list) => list.iter().map(|x| x.into()).collect::<Vec<Msg>>(),
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    // the newest replies may be cached only.
+    if list.len() < limit {
+        let cache_from = list.last().map(|msg| msg.seqnum()).unwrap_or(from_seq_num);
+        let cache_list = match redis_ops
+            .peek_sort_queue_more::<Msg>(
+                &format!("{}{}", MSG_CACHE, conversation),
+                0,
+                CACHE_SCAN_SIZE,
+                (cache_from + 1) as f64,
+                f64::MAX,
+                true,
+            )
+            .await
+        {
+            Ok(list) => list,
+            Err(e) => {
+                error!("redis error: {}", e);
+                return Err(HandlerError::InternalError("internal error".to_string()));
+            }
+        };
+        list.extend(
+            cache_list
+                .into_iter()
+                .filter(|msg| MsgExtension::parse(msg.extension()).thread_root == Some(root_seq))
+                .take(limit - list.len()),
+        );
+    }
+    let has_more = list.len() == limit;
+    let mut root = match with_state(&conversation, user_id, vec![root]).await {
+        Ok(root) => root,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    let list = match with_state(&conversation, user_id, list).await {
+        Ok(list) => list,
+        Err(e) => {
+            error!("db error: {}", e);
+            return Err(HandlerError::InternalError("internal error".to_string()));
+        }
+    };
+    Ok(ResponseResult {
+        code: 200,
+        message: "ok.",
+        timestamp: Local::now(),
+        data: ThreadResp {
+            root: root.pop().unwrap(),
+            list,
+            has_more,
+        },
+    })
+}
@@ -56,6 +56,8 @@ async fn main() -> Result<()> {
             tracing::error!("rpc server error: {}", e);
         }
     });
+    tokio::spawn(handler::schedule::dispatch());
+    tokio::spawn(handler::expire::sweep());
     let cors = Cors::new()
         .allow_methods(vec![
             Method::GET,
@@ -185,10 +187,82 @@ async fn main() -> Result<()> {
                         .put(handler::msg::update_unread)
                         .options(salvo::prelude::handler::empty()),
                 )
+                .push(
+                    Router::with_path("/unread/list")
+                        .get(handler::msg::unread_list)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/conversations")
+                        .get(handler::conversation::conversation_list)
+                        .options(salvo::prelude::handler::empty())
+                        .push(
+                            Router::with_path("/pin")
+                                .put(handler::conversation::set_pin)
+                                .options(salvo::prelude::handler::empty()),
+                        ),
+                )
                 .push(
                     Router::with_path("/history")
                         .get(handler::msg::history_msg)
                         .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/schedule")
+                        .post(handler::schedule::schedule)
+                        .get(handler::schedule::schedule_list)
+                        .delete(handler::schedule::cancel_schedule)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/ttl")
+                        .put(handler::expire::set_ttl)
+                        .get(handler::expire::get_ttl)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/thread")
+                        .get(handler::thread::thread)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/revisions")
+                        .get(handler::msg::revision_list)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/sync")
+                        .get(handler::sync::sync)
+                        .options(salvo::prelude::handler::empty()),
+                ),
+        )
+        .push(
+            Router::with_path("/e2ee")
+                .push(
+                    Router::with_path("/bundle")
+                        .put(handler::e2ee::publish_bundle)
+                        .get(handler::e2ee::get_bundle)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/prekey/count")
+                        .get(handler::e2ee::prekey_count)
+                        .options(salvo::prelude::handler::empty()),
+                ),
+        )
+        .push(
+            Router::with_path("/push")
+                .push(
+                    Router::with_path("/device")
+                        .post(handler::push::register_device)
+                        .delete(handler::push::unregister_device)
+                        .options(salvo::prelude::handler::empty()),
+                )
+                .push(
+                    Router::with_path("/mute")
+                        .put(handler::push::set_mute)
+                        .get(handler::push::get_mute_list)
+                        .options(salvo::prelude::handler::empty()),
                 ),
         )
         .push(
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Local};
+use lib::Result;
+
+use crate::sql::get_sql_pool;
+
+/// the long term keys a user publishes for others to start encrypted conversations with, kept
+/// in `api.prekey_bundle`, the one-time prekeys are kept aside.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct PrekeyBundle {
+    pub(crate) user_id: i64,
+    pub(crate) identity_key: String,
+    pub(crate) signing_key: String,
+    pub(crate) signed_prekey_id: i64,
+    pub(crate) signed_prekey: String,
+    pub(crate) signature: String,
+    pub(crate) update_at: DateTime<Local>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct OneTimePrekey {
+    pub(crate) prekey_id: i64,
+    pub(crate) prekey: String,
+}
+
+impl PrekeyBundle {
+    /// replace the bundle and add the one-time prekeys, those of a former identity key are
+    /// dropped as nobody can use them any more. false if another identity key is published and
+    /// `replace` isn't set, nothing is changed then.
+    pub(crate) async fn publish(
+        &self,
+        one_time_prekey_list: &[OneTimePrekey],
+        replace: bool,
+    ) -> Result<bool> {
+        let mut tx = get_sql_pool().await.begin().await?;
+        sqlx::query("DELETE FROM api.one_time_prekey WHERE user_id = $1 AND EXISTS (SELECT 1 FROM api.prekey_bundle WHERE user_id = $1 AND identity_key <> $2)")
+            .bind(self.user_id)
+            .bind(&self.identity_key)
+            .execute(&mut *tx)
+            .await?;
+        let res = sqlx::query("INSERT INTO api.prekey_bundle (user_id, identity_key, signing_key, signed_prekey_id, signed_prekey, signature, update_at) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (user_id) DO UPDATE SET identity_key = $2, signing_key = $3, signed_prekey_id = $4, signed_prekey = $5, signature = $6, update_at = $7 WHERE api.prekey_bundle.identity_key = $2 OR $8")
+            .bind(self.user_id)
+            .bind(&self.identity_key)
+            .bind(&self.signing_key)
+            .bind(self.signed_prekey_id)
+            .bind(&self.signed_prekey)
+            .bind(&self.signature)
+            .bind(self.update_at)
+            .bind(replace)
+            .execute(&mut *tx)
+            .await?;
+        // rolled back as the transaction is dropped.
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+        if !one_time_prekey_list.is_empty() {
+            let mut batch_inserter: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new(
+                "INSERT INTO api.one_time_prekey (user_id, prekey_id, prekey) ",
+            );
+            batch_inserter.push_values(one_time_prekey_list, |mut binder, prekey| {
+                binder.push_bind(self.user_id);
+                binder.push_bind(prekey.prekey_id);
+                binder.push_bind(&prekey.prekey);
+            });
+            batch_inserter.push(" ON CONFLICT (user_id, prekey_id) DO NOTHING");
+            batch_inserter.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub(crate) async fn get(user_id: i64) -> Result<Option<Self>> {
+        let bundle = sqlx::query_as("SELECT user_id, identity_key, signing_key, signed_prekey_id, signed_prekey, signature, update_at FROM api.prekey_bundle WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        Ok(bundle)
+    }
+
+    /// take the oldest one-time prekey of the user, concurrent requests never get the same one.
+    pub(crate) async fn take_one_time_prekey(user_id: i64) -> Result<Option<OneTimePrekey>> {
+        let prekey = sqlx::query_as("DELETE FROM api.one_time_prekey WHERE id = (SELECT id FROM api.one_time_prekey WHERE user_id = $1 ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED) RETURNING prekey_id, prekey")
+            .bind(user_id)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        Ok(prekey)
+    }
+
+    pub(crate) async fn count_one_time_prekey(user_id: i64) -> Result<i64> {
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT count(*) FROM api.one_time_prekey WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(get_sql_pool().await)
+                .await?;
+        Ok(count)
+    }
+}
@@ -1,4 +1,6 @@
 pub(crate) mod msg;
 pub(crate) mod user;
 pub(crate) mod group;
-pub(crate) mod relationship;
\ No newline at end of file
+pub(crate) mod relationship;
+pub(crate) mod schedule;
+pub(crate) mod e2ee;
\ No newline at end of file
@@ -8,6 +8,7 @@ use base64::Engine;
 use chrono::{DateTime, Local};
 use lib::{
     entity::{Msg, Type},
+    util::conversation_id,
     Result,
 };
 use sqlx::Postgres;
@@ -28,6 +29,91 @@ pub(crate) struct Message {
     pub(crate) payload: String,
 }
 
+/// a content replaced by an edit, see `msgprocessor`.
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, sqlx::FromRow)]
+pub(crate) struct MessageRevision {
+    pub(crate) revision: i32,
+    pub(crate) payload: String,
+    pub(crate) editor: i64,
+    pub(crate) edited_at: DateTime<Local>,
+}
+
+/// replies of a thread, see `lib::entity::MsgExtension`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct ThreadState {
+    pub(crate) thread_root: i64,
+    pub(crate) reply_count: i64,
+    pub(crate) last_reply_at: Option<DateTime<Local>>,
+}
+
+/// reactions with the same emoji on a message, the ones taken back not counted.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct ReactionCount {
+    pub(crate) seq_num: i64,
+    pub(crate) emoji: String,
+    pub(crate) count: i64,
+    /// the user asking reacted with it too.
+    pub(crate) reacted: bool,
+}
+
+/// the reaction of a user with an emoji on a message, taken back or not.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct UserReaction {
+    pub(crate) seq_num: i64,
+    pub(crate) user_id: i64,
+    pub(crate) emoji: String,
+    pub(crate) active: bool,
+    pub(crate) updated_at: DateTime<Local>,
+}
+
+/// time to live of the messages sent to a conversation after it's set, see `msgprocessor`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct ConversationTtl {
+    pub(crate) conversation_id: String,
+    /// in seconds.
+    pub(crate) ttl: i32,
+    pub(crate) set_by: i64,
+    pub(crate) update_at: DateTime<Local>,
+}
+
+impl ConversationTtl {
+    pub(crate) async fn get(conversation_id: &str) -> Result<Option<Self>> {
+        let ttl = sqlx::query_as("SELECT conversation_id, ttl, set_by, update_at FROM msg.conversation_ttl WHERE conversation_id = $1")
+            .bind(conversation_id)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        Ok(ttl)
+    }
+
+    pub(crate) async fn upsert(&self) -> Result<()> {
+        sqlx::query("INSERT INTO msg.conversation_ttl (conversation_id, ttl, set_by, update_at) VALUES ($1, $2, $3, $4) ON CONFLICT (conversation_id) DO UPDATE SET ttl = $2, set_by = $3, update_at = $4")
+            .bind(&self.conversation_id)
+            .bind(self.ttl)
+            .bind(self.set_by)
+            .bind(self.update_at)
+            .execute(get_sql_pool().await)
+            .await?;
+        Ok(())
+    }
+
+    /// messages sent after it live as long as their own time to live says.
+    pub(crate) async fn delete(conversation_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM msg.conversation_ttl WHERE conversation_id = $1")
+            .bind(conversation_id)
+            .execute(get_sql_pool().await)
+            .await?;
+        Ok(())
+    }
+}
+
+/// edit state of a message, for the ones edited only.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct EditState {
+    pub(crate) seq_num: i64,
+    pub(crate) revision: i32,
+    pub(crate) edited_at: Option<DateTime<Local>>,
+}
+
 impl From<&Msg> for Message {
     fn from(msg: &Msg) -> Self {
         let t: DateTime<Local> = DateTime::from(
@@ -115,7 +201,7 @@ impl Display for Message {
 impl Message {
     #[allow(unused)]
     pub(crate) async fn insert(&self) -> Result<()> {
-        sqlx::query("INSERT INTO msg.message (sender, receiver, timestamp, seq_num, type, version, extension, payload) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+        sqlx::query("INSERT INTO msg.message (sender, receiver, timestamp, seq_num, type, version, extension, payload, conversation_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
             .bind(self.sender)
             .bind(self.receiver)
             .bind(self.timestamp)
@@ -124,13 +210,14 @@ impl Message {
             .bind(self.version)
             .bind(&self.extension)
             .bind(&self.payload)
+            .bind(conversation_id(self.sender as u64, self.receiver as u64))
             .execute(get_sql_pool().await).await?;
         Ok(())
     }
 
     #[allow(unused)]
     pub(crate) async fn update(&self) -> Result<()> {
-        sqlx::query("UPDATE msg.message SET sender = $1, receiver = $2, timestamp = $3, seq_num = $4, type = $5, version = $6, extension = $7, payload = $8, where id = $9")
+        sqlx::query("UPDATE msg.message SET sender = $1, receiver = $2, timestamp = $3, seq_num = $4, type = $5, version = $6, extension = $7, payload = $8 WHERE id = $9")
             .bind(&self.sender)
             .bind(&self.receiver)
             .bind(&self.timestamp)
@@ -189,9 +276,191 @@ impl Message {
         }
     }
 
+    /// those of the seqnums stored in a conversation.
+    pub(crate) async fn get_seq_num_list(
+        conversation_id: &str,
+        seq_num_list: &[i64],
+    ) -> Result<Vec<i64>> {
+        let list: Vec<(i64,)> = sqlx::query_as(
+            "SELECT seq_num FROM msg.message WHERE conversation_id = $1 AND seq_num = ANY($2)",
+        )
+        .bind(conversation_id)
+        .bind(seq_num_list)
+        .fetch_all(get_sql_pool().await)
+        .await?;
+        Ok(list.into_iter().map(|(seq_num,)| seq_num).collect())
+    }
+
+    /// messages of a conversation in `[from_seq, to_seq)` but thread replies, the newest first.
+    pub(crate) async fn get_main_timeline(
+        conversation_id: &str,
+        from_seq: i64,
+        to_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let list = sqlx::query_as("SELECT id, sender, receiver, timestamp, seq_num, type, version, extension, payload FROM msg.message WHERE conversation_id = $1 AND thread_root IS NULL AND seq_num >= $2 AND seq_num < $3 ORDER BY seq_num DESC LIMIT $4")
+            .bind(conversation_id)
+            .bind(from_seq)
+            .bind(to_seq)
+            .bind(limit)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// the message with the seqnum in a conversation, see `lib::util::conversation_id`.
+    pub(crate) async fn get_by_seq(conversation_id: &str, seq_num: i64) -> Result<Option<Self>> {
+        let msg = sqlx::query_as("SELECT id, sender, receiver, timestamp, seq_num, type, version, extension, payload FROM msg.message WHERE conversation_id = $1 AND seq_num = $2")
+            .bind(conversation_id)
+            .bind(seq_num)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        Ok(msg)
+    }
+
+    /// edit state of the messages with the seqnums in a conversation, unedited ones are left out.
+    pub(crate) async fn get_edit_state_list(
+        conversation_id: &str,
+        seq_num_list: &[i64],
+    ) -> Result<Vec<EditState>> {
+        let list = sqlx::query_as("SELECT seq_num, revision, edited_at FROM msg.message WHERE conversation_id = $1 AND seq_num = ANY($2) AND revision > 0")
+            .bind(conversation_id)
+            .bind(seq_num_list)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// the newest message of a conversation, see `lib::util::conversation_id`.
+    pub(crate) async fn get_latest(conversation_id: &str) -> Result<Option<Self>> {
+        let msg = sqlx::query_as("SELECT id, sender, receiver, timestamp, seq_num, type, version, extension, payload FROM msg.message WHERE conversation_id = $1 ORDER BY seq_num DESC LIMIT 1")
+            .bind(conversation_id)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        Ok(msg)
+    }
+
+    /// replies of the threads rooted at the seqnums in a conversation, roots without any are
+    /// left out.
+    pub(crate) async fn get_thread_state_list(
+        conversation_id: &str,
+        root_list: &[i64],
+    ) -> Result<Vec<ThreadState>> {
+        let list = sqlx::query_as("SELECT thread_root, count(*) AS reply_count, max(timestamp) AS last_reply_at FROM msg.message WHERE conversation_id = $1 AND thread_root = ANY($2) GROUP BY thread_root")
+            .bind(conversation_id)
+            .bind(root_list)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// reaction counts of the messages, each message's earliest emoji first.
+    pub(crate) async fn get_reaction_list(
+        conversation_id: &str,
+        seq_num_list: &[i64],
+        user_id: i64,
+    ) -> Result<Vec<ReactionCount>> {
+        let list = sqlx::query_as("SELECT seq_num, emoji, count(*) AS count, bool_or(user_id = $3) AS reacted FROM msg.message_reaction WHERE conversation_id = $1 AND seq_num = ANY($2) AND active GROUP BY seq_num, emoji ORDER BY seq_num, min(updated_at)")
+            .bind(conversation_id)
+            .bind(seq_num_list)
+            .bind(user_id)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// reactions of the users on the messages, including those taken back.
+    pub(crate) async fn get_user_reaction_list(
+        conversation_id: &str,
+        seq_num_list: &[i64],
+        user_list: &[i64],
+    ) -> Result<Vec<UserReaction>> {
+        let list = sqlx::query_as("SELECT seq_num, user_id, emoji, active, updated_at FROM msg.message_reaction WHERE conversation_id = $1 AND seq_num = ANY($2) AND user_id = ANY($3)")
+            .bind(conversation_id)
+            .bind(seq_num_list)
+            .bind(user_list)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// replies of the thread after the seqnum, the oldest first.
+    pub(crate) async fn get_thread(
+        conversation_id: &str,
+        thread_root: i64,
+        from_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let list = sqlx::query_as("SELECT id, sender, receiver, timestamp, seq_num, type, version, extension, payload FROM msg.message WHERE conversation_id = $1 AND thread_root = $2 AND seq_num > $3 ORDER BY seq_num LIMIT $4")
+            .bind(conversation_id)
+            .bind(thread_root)
+            .bind(from_seq)
+            .bind(limit)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// contents the message had before each edit, the oldest first.
+    pub(crate) async fn get_revision_list(
+        conversation_id: &str,
+        seq_num: i64,
+    ) -> Result<Vec<MessageRevision>> {
+        let list = sqlx::query_as("SELECT revision, payload, editor, edited_at FROM msg.message_revision WHERE conversation_id = $1 AND seq_num = $2 ORDER BY revision")
+            .bind(conversation_id)
+            .bind(seq_num)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// at most `limit` messages expired, locked until the transaction is given to `purge`, so
+    /// concurrent api instances never take the same message.
+    pub(crate) async fn lock_expired(
+        limit: i64,
+    ) -> Result<(sqlx::Transaction<'static, Postgres>, Vec<Self>)> {
+        let mut tx = get_sql_pool().await.begin().await?;
+        let list: Vec<Self> = sqlx::query_as("SELECT id, sender, receiver, timestamp, seq_num, type, version, extension, payload FROM msg.message WHERE expire_at <= now() ORDER BY expire_at LIMIT $1 FOR UPDATE SKIP LOCKED")
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await?;
+        Ok((tx, list))
+    }
+
+    /// delete the messages locked by `lock_expired` with their revisions and reactions, those
+    /// locked but not given are left for later.
+    pub(crate) async fn purge(
+        mut tx: sqlx::Transaction<'static, Postgres>,
+        list: &[Self],
+    ) -> Result<()> {
+        if list.is_empty() {
+            tx.commit().await?;
+            return Ok(());
+        }
+        let id_list = list.iter().map(|msg| msg.id).collect::<Vec<i64>>();
+        sqlx::query("DELETE FROM msg.message WHERE id = ANY($1)")
+            .bind(&id_list)
+            .execute(&mut *tx)
+            .await?;
+        let conversation_list = list
+            .iter()
+            .map(|msg| conversation_id(msg.sender as u64, msg.receiver as u64))
+            .collect::<Vec<String>>();
+        let seq_num_list = list.iter().map(|msg| msg.seq_num).collect::<Vec<i64>>();
+        for table in ["msg.message_revision", "msg.message_reaction"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE (conversation_id, seq_num) IN (SELECT * FROM unnest($1::varchar[], $2::bigint[]))", table))
+                .bind(&conversation_list)
+                .bind(&seq_num_list)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
     #[allow(unused)]
     pub(crate) async fn insert_batch(msg_list: Vec<Message>) -> Result<()> {
-        let mut batch_inserter: sqlx::QueryBuilder<Postgres> = sqlx::QueryBuilder::new("INSERT INTO msg.message (sender, receiver, timestamp, seq_num, type, version, extension, payload) ");
+        let mut batch_inserter: sqlx::QueryBuilder<Postgres> = sqlx::QueryBuilder::new("INSERT INTO msg.message (sender, receiver, timestamp, seq_num, type, version, extension, payload, conversation_id) ");
         batch_inserter.push_values(msg_list, |mut binder, msg| {
             binder.push_bind(msg.sender);
             binder.push_bind(msg.receiver);
@@ -201,6 +470,7 @@ impl Message {
             binder.push_bind(msg.version);
             binder.push_bind(msg.extension);
             binder.push_bind(msg.payload);
+            binder.push_bind(conversation_id(msg.sender as u64, msg.receiver as u64));
         });
         let query = batch_inserter.build();
         query.execute(get_sql_pool().await).await?;
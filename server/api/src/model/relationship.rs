@@ -1,5 +1,5 @@
 use chrono::{DateTime, Local};
-use lib::Result;
+use lib::{entity::GROUP_ID_THRESHOLD, Result};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
@@ -85,6 +85,17 @@ impl UserRelationship {
         Ok(user)
     }
 
+    /// groups are peers with ids from `GROUP_ID_THRESHOLD`.
+    pub(crate) async fn get_group_id_list(user_id: i64) -> Result<Vec<i64>> {
+        let list: Vec<(i64,)> = sqlx::query_as("SELECT peer_id FROM api.user_relationship WHERE user_id = $1 AND peer_id >= $2 AND delete_at = $3")
+            .bind(user_id)
+            .bind(GROUP_ID_THRESHOLD as i64)
+            .bind(&*crate::DELETE_AT)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list.into_iter().map(|(peer_id,)| peer_id).collect())
+    }
+
     #[allow(unused)]
     pub(crate) async fn insert(&self) -> Result<()> {
         sqlx::query("INSERT INTO api.user_relationship (user_id, peer_id, remark, status, classification, tag_list, info, create_at, update_at, delete_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)")
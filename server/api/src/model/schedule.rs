@@ -0,0 +1,125 @@
+use chrono::{DateTime, Local};
+use lib::{entity::Type, Result};
+
+use crate::sql::get_sql_pool;
+
+/// a message kept in `api.scheduled_message` until it's sent at `deliver_at`, the extension and
+/// payload are kept as they are.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub(crate) struct ScheduledMessage {
+    pub(crate) id: i64,
+    pub(crate) sender: i64,
+    pub(crate) receiver: i64,
+    #[sqlx(rename = "type", try_from = "i16")]
+    pub(crate) typ: Type,
+    pub(crate) extension: String,
+    pub(crate) payload: String,
+    pub(crate) deliver_at: DateTime<Local>,
+    pub(crate) create_at: DateTime<Local>,
+    /// set while an api instance sends it.
+    pub(crate) claimed_at: Option<DateTime<Local>>,
+    /// sends failed so far.
+    pub(crate) attempt: i32,
+}
+
+impl ScheduledMessage {
+    /// the id of the message inserted.
+    pub(crate) async fn insert(&self) -> Result<i64> {
+        let (id,): (i64,) = sqlx::query_as("INSERT INTO api.scheduled_message (sender, receiver, type, extension, payload, deliver_at, create_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id")
+            .bind(self.sender)
+            .bind(self.receiver)
+            .bind(self.typ.value() as i16)
+            .bind(&self.extension)
+            .bind(&self.payload)
+            .bind(self.deliver_at)
+            .bind(self.create_at)
+            .fetch_one(get_sql_pool().await)
+            .await?;
+        Ok(id)
+    }
+
+    pub(crate) async fn count_by_sender(sender: i64) -> Result<i64> {
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT count(*) FROM api.scheduled_message WHERE sender = $1")
+                .bind(sender)
+                .fetch_one(get_sql_pool().await)
+                .await?;
+        Ok(count)
+    }
+
+    /// pending messages of the sender, the earliest to send first.
+    pub(crate) async fn get_by_sender(sender: i64) -> Result<Vec<Self>> {
+        let list = sqlx::query_as("SELECT id, sender, receiver, type, extension, payload, deliver_at, create_at, claimed_at, attempt FROM api.scheduled_message WHERE sender = $1 ORDER BY deliver_at, id")
+            .bind(sender)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    pub(crate) async fn get(id: i64) -> Result<Option<Self>> {
+        let scheduled = sqlx::query_as("SELECT id, sender, receiver, type, extension, payload, deliver_at, create_at, claimed_at, attempt FROM api.scheduled_message WHERE id = $1")
+            .bind(id)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        Ok(scheduled)
+    }
+
+    /// false if there is no such message, or it's claimed by a running api instance, which takes
+    /// the same claim as `claim_due` does.
+    pub(crate) async fn cancel(id: i64, sender: i64, stale_secs: i64) -> Result<bool> {
+        let res = sqlx::query("DELETE FROM api.scheduled_message WHERE id = $1 AND sender = $2 AND (claimed_at IS NULL OR claimed_at < now() - make_interval(secs => $3))")
+            .bind(id)
+            .bind(sender)
+            .bind(stale_secs as f64)
+            .execute(get_sql_pool().await)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// claim at most `limit` messages due and not waiting for a retry, those claimed longer than
+    /// `stale_secs` ago are claimed again, concurrent api instances never claim the same message.
+    pub(crate) async fn claim_due(limit: i64, stale_secs: i64) -> Result<Vec<Self>> {
+        let list = sqlx::query_as("UPDATE api.scheduled_message SET claimed_at = now() WHERE id IN (SELECT id FROM api.scheduled_message WHERE deliver_at <= now() AND (retry_at IS NULL OR retry_at <= now()) AND (claimed_at IS NULL OR claimed_at < now() - make_interval(secs => $2)) ORDER BY deliver_at LIMIT $1 FOR UPDATE SKIP LOCKED) RETURNING id, sender, receiver, type, extension, payload, deliver_at, create_at, claimed_at, attempt")
+            .bind(limit)
+            .bind(stale_secs as f64)
+            .fetch_all(get_sql_pool().await)
+            .await?;
+        Ok(list)
+    }
+
+    /// take the claim for another `stale_secs` right before sending, false if it's not the claim
+    /// got anymore, another instance took it over as this one took too long.
+    pub(crate) async fn refresh_claim(&mut self) -> Result<bool> {
+        let claimed_at: Option<(DateTime<Local>,)> = sqlx::query_as("UPDATE api.scheduled_message SET claimed_at = clock_timestamp() WHERE id = $1 AND claimed_at = $2 RETURNING claimed_at")
+            .bind(self.id)
+            .bind(self.claimed_at)
+            .fetch_optional(get_sql_pool().await)
+            .await?;
+        match claimed_at {
+            Some((claimed_at,)) => {
+                self.claimed_at = Some(claimed_at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// the message is sent.
+    pub(crate) async fn delete(id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM api.scheduled_message WHERE id = $1")
+            .bind(id)
+            .execute(get_sql_pool().await)
+            .await?;
+        Ok(())
+    }
+
+    /// the message failed to be sent, it's tried again `delay_secs` later.
+    pub(crate) async fn release(id: i64, delay_secs: i64) -> Result<()> {
+        sqlx::query("UPDATE api.scheduled_message SET claimed_at = NULL, attempt = attempt + 1, retry_at = now() + make_interval(secs => $2) WHERE id = $1")
+            .bind(id)
+            .bind(delay_secs as f64)
+            .execute(get_sql_pool().await)
+            .await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use base64::Engine;
-use lib::{entity::Msg, Result};
+use lib::{entity::Msg, error::HandlerError, Result};
 use tonic::{
     transport::{Channel, ClientTlsConfig, Server, ServerTlsConfig},
     Request, Response, Status,
@@ -10,10 +10,13 @@ use tracing::{error, info};
 use super::node_proto::{
     api_server::{Api, ApiServer},
     scheduler_client::SchedulerClient,
-    GroupUserListReq, GroupUserListResp, PushMsgReq, WhichNodeReq,
+    GroupUserListReq, GroupUserListResp, MsgExistListReq, MsgExistListResp, PushMsgReq,
+    WhichNodeReq,
 };
 use crate::rpc::node_proto::WhichToConnectReq;
-use crate::{config::config, model::group::Group};
+use crate::{
+    cache::get_redis_ops, config::config, handler::msg::exist_seq_num_list, model::group::Group,
+};
 
 #[derive(Clone)]
 pub(crate) struct Client {
@@ -41,6 +44,7 @@ impl Client {
         Ok(response.into_inner().node_id)
     }
 
+    /// a message the node refused fails with `HandlerError::Parse`, sending it again won't help.
     #[allow(unused)]
     pub(crate) async fn call_push_msg(&mut self, msg: &Msg) -> Result<()> {
         let engine = base64::engine::GeneralPurpose::new(
@@ -55,13 +59,14 @@ impl Client {
             r#type: msg.typ().value() as u32,
             payload: engine.encode(msg.payload()),
             extension: engine.encode(msg.extension()),
+            seqnum: msg.seqnum(),
         });
         let response = self.scheduler_client.push_msg(request).await?;
         let resp = response.into_inner();
         if resp.success {
             Ok(())
         } else {
-            return Err(anyhow::anyhow!(resp.err_msg));
+            Err(anyhow::anyhow!(HandlerError::Parse(resp.err_msg)))
         }
     }
 
@@ -100,6 +105,7 @@ impl Api for RpcServer {
         let res = match Group::get_group_id(group_id as i64).await {
             Ok(group) => {
                 let mut user_list = vec![];
+                let mut admin_list = vec![];
                 for user in group.member_list.iter() {
                     if let Some(map) = user.as_object() {
                         let user_id = map.get("user_id").unwrap();
@@ -112,9 +118,13 @@ impl Api for RpcServer {
                         let user_id = map.get("user_id").unwrap();
                         let user_id = user_id.as_i64().unwrap() as u64;
                         user_list.push(user_id);
+                        admin_list.push(user_id);
                     }
                 }
-                Ok(Response::new(GroupUserListResp { user_list }))
+                Ok(Response::new(GroupUserListResp {
+                    user_list,
+                    admin_list,
+                }))
             }
             Err(e) => {
                 error!("get group by group_id error: {}", e);
@@ -124,4 +134,27 @@ impl Api for RpcServer {
         info!("group_user_list: {:?}", res);
         res
     }
+
+    /// stored messages and those only cached yet count.
+    async fn msg_exist_list(
+        &self,
+        request: Request<MsgExistListReq>,
+    ) -> std::result::Result<Response<MsgExistListResp>, Status> {
+        let request_inner = request.into_inner();
+        let mut redis_ops = get_redis_ops().await;
+        let seq_num_list = match exist_seq_num_list(
+            &mut redis_ops,
+            &request_inner.conversation_id,
+            &request_inner.seq_num_list,
+        )
+        .await
+        {
+            Ok(list) => list,
+            Err(e) => {
+                error!("get seq num list error: {}", e);
+                return Err(Status::internal(e.to_string()));
+            }
+        };
+        Ok(Response::new(MsgExistListResp { seq_num_list }))
+    }
 }
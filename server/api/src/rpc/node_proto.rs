@@ -36,6 +36,9 @@ pub struct PushMsgReq {
     pub payload: ::prost::alloc::string::String,
     #[prost(string, tag = "7")]
     pub extension: ::prost::alloc::string::String,
+    /// the message changed by an edit or withdraw.
+    #[prost(uint64, tag = "8")]
+    pub seqnum: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PushMsgResp {
@@ -72,6 +75,23 @@ pub struct GroupUserListReq {
 pub struct GroupUserListResp {
     #[prost(uint64, repeated, tag = "1")]
     pub user_list: ::prost::alloc::vec::Vec<u64>,
+    /// the admins, who are in user_list too.
+    #[prost(uint64, repeated, tag = "2")]
+    pub admin_list: ::prost::alloc::vec::Vec<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MsgExistListReq {
+    /// see `lib::util::conversation_id`.
+    #[prost(string, tag = "1")]
+    pub conversation_id: ::prost::alloc::string::String,
+    #[prost(uint64, repeated, tag = "2")]
+    pub seq_num_list: ::prost::alloc::vec::Vec<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MsgExistListResp {
+    /// those of seq_num_list in the conversation.
+    #[prost(uint64, repeated, tag = "1")]
+    pub seq_num_list: ::prost::alloc::vec::Vec<u64>,
 }
 /// Generated client implementations.
 pub mod scheduler_client {
@@ -327,6 +347,25 @@ pub mod api_client {
             );
             self.inner.unary(request.into_request(), path, codec).await
         }
+        pub async fn msg_exist_list(
+            &mut self,
+            request: impl tonic::IntoRequest<super::MsgExistListReq>,
+        ) -> Result<tonic::Response<super::MsgExistListResp>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/node_proto.API/MsgExistList",
+            );
+            self.inner.unary(request.into_request(), path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -658,6 +697,10 @@ pub mod api_server {
             &self,
             request: tonic::Request<super::GroupUserListReq>,
         ) -> Result<tonic::Response<super::GroupUserListResp>, tonic::Status>;
+        async fn msg_exist_list(
+            &self,
+            request: tonic::Request<super::MsgExistListReq>,
+        ) -> Result<tonic::Response<super::MsgExistListResp>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct ApiServer<T: Api> {
@@ -756,6 +799,44 @@ pub mod api_server {
                     };
                     Box::pin(fut)
                 }
+                "/node_proto.API/MsgExistList" => {
+                    #[allow(non_camel_case_types)]
+                    struct MsgExistListSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::MsgExistListReq>
+                    for MsgExistListSvc<T> {
+                        type Response = super::MsgExistListResp;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::MsgExistListReq>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).msg_exist_list(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = MsgExistListSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
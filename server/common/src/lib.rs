@@ -1,3 +1,5 @@
+pub mod load;
+pub mod mq;
 pub mod scheduler;
 
 #[cfg(test)]
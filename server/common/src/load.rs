@@ -0,0 +1,117 @@
+use std::time::Instant;
+
+use lib::entity::ServerLoad;
+use sysinfo::{CpuExt, DiskExt, NetworkExt, NetworksExt, Pid, ProcessExt, System, SystemExt};
+
+const MIB: u64 = 1 << 20;
+const GIB: u64 = 1 << 30;
+
+/// sampler of the current node's load.
+///
+/// cpu usage and io rates are computed between two refreshes, so keep one sampler
+/// and call `sample()` periodically instead of creating a new one each time.
+pub struct LoadSampler {
+    sys: System,
+    pid: Option<Pid>,
+    last_sample: Instant,
+}
+
+impl LoadSampler {
+    pub fn new() -> Self {
+        let mut sys = System::new_all();
+        sys.refresh_all();
+        Self {
+            sys,
+            pid: sysinfo::get_current_pid().ok(),
+            last_sample: Instant::now(),
+        }
+    }
+
+    pub fn sample(&mut self) -> ServerLoad {
+        self.sys.refresh_cpu();
+        self.sys.refresh_memory();
+        self.sys.refresh_disks();
+        self.sys.refresh_networks();
+        self.sys.refresh_processes();
+        let elapsed = self.last_sample.elapsed().as_secs_f32().max(0.001);
+        self.last_sample = Instant::now();
+
+        let total_mem = self.sys.total_memory();
+        let (total_disk, available_disk) =
+            self.sys
+                .disks()
+                .iter()
+                .fold((0, 0), |(total, available), disk| {
+                    (
+                        total + disk.total_space(),
+                        available + disk.available_space(),
+                    )
+                });
+        let (net_read, net_write) = self
+            .sys
+            .networks()
+            .iter()
+            .fold((0, 0), |(read, write), (_, data)| {
+                (read + data.received(), write + data.transmitted())
+            });
+        let net_read = (net_read as f32 / 1024.0 / elapsed) as u32;
+        let net_write = (net_write as f32 / 1024.0 / elapsed) as u32;
+
+        let mut load = ServerLoad {
+            cpu: (
+                self.sys.cpus().len() as u32,
+                self.sys.global_cpu_info().cpu_usage(),
+            ),
+            mem: (
+                (total_mem / MIB) as u32,
+                percent(self.sys.used_memory(), total_mem),
+            ),
+            net: (
+                self.sys.networks().iter().count() as u32,
+                (net_read + net_write) as f32,
+            ),
+            disk: (
+                (total_disk / GIB) as u32,
+                percent(total_disk - available_disk, total_disk),
+            ),
+            thread_num: thread_num(),
+            process_num: self.sys.processes().len() as u32,
+            swap_disk: percent(self.sys.used_swap(), self.sys.total_swap()),
+            net_read,
+            net_write,
+            ..Default::default()
+        };
+        if let Some(process) = self.pid.and_then(|pid| self.sys.process(pid)) {
+            let disk_usage = process.disk_usage();
+            load.physical_mem = (process.memory() / MIB) as f32;
+            load.virtual_mem = (process.virtual_memory() / MIB) as f32;
+            load.disk_read = (disk_usage.read_bytes as f32 / 1024.0 / elapsed) as u32;
+            load.disk_write = (disk_usage.written_bytes as f32 / 1024.0 / elapsed) as u32;
+        }
+        load
+    }
+}
+
+impl Default for LoadSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[inline]
+fn percent(used: u64, total: u64) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        used as f32 * 100.0 / total as f32
+    }
+}
+
+/// sysinfo doesn't expose threads of a process on every platform, so count them by procfs.
+#[inline]
+fn thread_num() -> u32 {
+    match std::fs::read_dir("/proc/self/task") {
+        Ok(dir) => dir.count() as u32,
+        Err(_) => 0,
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use ahash::AHashMap;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use byteorder::{BigEndian, ByteOrder};
+use lib::Result;
+use tokio::sync::Mutex;
+
+use super::{MQConsumer, MQProducer, Record};
+
+/// key length(4 bytes) + payload length(4 bytes) + timestamp(8 bytes).
+const HEAD_LEN: usize = 16;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// every topic is a single log file `<topic>.log` under `dir`, offset of a record is its position
+/// in the file.
+///
+/// appends are only serialized inside this producer, the file is not locked, so `dir` must be
+/// written by one process: concurrent appends from other processes may interleave, and
+/// truncation may drop their records. consumers in other processes can read it.
+pub struct FileProducer {
+    dir: PathBuf,
+    file_map: Mutex<AHashMap<String, File>>,
+}
+
+impl FileProducer {
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            file_map: Mutex::new(AHashMap::new()),
+        })
+    }
+}
+
+#[async_trait]
+impl MQProducer for FileProducer {
+    async fn ensure_topic(&self, topic: &str, _partitions: i32) -> Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(format!("{}.log", topic)))?;
+        Ok(())
+    }
+
+    async fn send(&self, topic: &str, key: &[u8], payload: &[u8], timestamp: i64) -> Result<()> {
+        let mut bytes = vec![0u8; HEAD_LEN];
+        BigEndian::write_u32(&mut bytes[0..4], key.len() as u32);
+        BigEndian::write_u32(&mut bytes[4..8], payload.len() as u32);
+        BigEndian::write_i64(&mut bytes[8..16], timestamp);
+        bytes.extend_from_slice(key);
+        bytes.extend_from_slice(payload);
+        let mut file_map = self.file_map.lock().await;
+        if !file_map.contains_key(topic) {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(format!("{}.log", topic)))?;
+            file_map.insert(topic.to_owned(), file);
+        }
+        let file = file_map.get_mut(topic).unwrap();
+        file.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+impl FileProducer {
+    /// empty the log of `topic` if `cond` holds, no record is appended while `cond` is checked.
+    pub(super) async fn truncate_if(
+        &self,
+        topic: &str,
+        cond: impl FnOnce() -> bool,
+    ) -> Result<bool> {
+        let _file_map = self.file_map.lock().await;
+        if !cond() {
+            return Ok(false);
+        }
+        match OpenOptions::new()
+            .write(true)
+            .open(self.dir.join(format!("{}.log", topic)))
+        {
+            Ok(file) => file.set_len(0)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(true)
+    }
+}
+
+/// the committed offset of a group is kept in `<topic>.<group>.offset`, so only one consumer
+/// of a group is supported.
+pub struct FileConsumer {
+    topic: String,
+    log_path: PathBuf,
+    offset_path: PathBuf,
+    file: Option<File>,
+    position: u64,
+}
+
+impl FileConsumer {
+    pub fn new(dir: PathBuf, topic: &str, group: &str) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let offset_path = dir.join(format!("{}.{}.offset", topic, group));
+        let position = match fs::read_to_string(&offset_path) {
+            Ok(offset) => offset.trim().parse::<u64>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            topic: topic.to_owned(),
+            log_path: dir.join(format!("{}.log", topic)),
+            offset_path,
+            file: None,
+            position,
+        })
+    }
+
+    /// read the record at current position, None if it's not completely written yet.
+    pub(super) fn try_recv(&mut self) -> Result<Option<Record>> {
+        if self.file.is_none() {
+            match File::open(&self.log_path) {
+                Ok(file) => self.file = Some(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let file = self.file.as_mut().unwrap();
+        let len = file.metadata()?.len();
+        if len < self.position + HEAD_LEN as u64 {
+            return Ok(None);
+        }
+        let mut head = [0u8; HEAD_LEN];
+        file.seek(SeekFrom::Start(self.position))?;
+        file.read_exact(&mut head)?;
+        let key_len = BigEndian::read_u32(&head[0..4]) as usize;
+        let payload_len = BigEndian::read_u32(&head[4..8]) as usize;
+        if len < self.position + (HEAD_LEN + key_len + payload_len) as u64 {
+            return Ok(None);
+        }
+        let mut key = vec![0u8; key_len];
+        file.read_exact(&mut key)?;
+        let mut payload = vec![0u8; payload_len];
+        file.read_exact(&mut payload)?;
+        let record = Record {
+            topic: self.topic.clone(),
+            partition: 0,
+            offset: self.position as i64,
+            key,
+            payload,
+            timestamp: BigEndian::read_i64(&head[8..16]),
+        };
+        self.position += (HEAD_LEN + key_len + payload_len) as u64;
+        Ok(Some(record))
+    }
+}
+
+#[async_trait]
+impl MQConsumer for FileConsumer {
+    async fn recv(&mut self) -> Result<Record> {
+        loop {
+            if let Some(record) = self.try_recv()? {
+                return Ok(record);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn commit(&mut self, record: &Record) -> Result<()> {
+        if record.topic != self.topic {
+            return Err(anyhow!("record of topic {} is not mine", record.topic));
+        }
+        let next =
+            record.offset as u64 + (HEAD_LEN + record.key.len() + record.payload.len()) as u64;
+        self.save_offset(next)
+    }
+
+    async fn drained(&mut self) -> Result<bool> {
+        let committed = match fs::read_to_string(&self.offset_path) {
+            Ok(offset) => offset.trim().parse::<u64>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        match fs::metadata(&self.log_path) {
+            Ok(metadata) => Ok(committed >= metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl FileConsumer {
+    /// start over from the beginning, used after the log is truncated.
+    pub(super) fn rewind(&mut self) -> Result<()> {
+        self.position = 0;
+        self.save_offset(0)
+    }
+
+    fn save_offset(&self, next: u64) -> Result<()> {
+        let mut tmp_path = self.offset_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(next.to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.offset_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{FileConsumer, FileProducer};
+    use crate::mq::{MQConsumer, MQProducer};
+
+    #[tokio::test]
+    async fn test_file_log() {
+        let dir = std::env::temp_dir().join(format!("prim-mq-{}", std::process::id()));
+        let producer = FileProducer::new(dir.clone()).unwrap();
+        producer.ensure_topic("msg-test", 1).await.unwrap();
+        for i in 0..3u8 {
+            producer
+                .send("msg-test", &[i], &[i; 10], i as i64)
+                .await
+                .unwrap();
+        }
+        let mut consumer = FileConsumer::new(dir.clone(), "msg-test", "default").unwrap();
+        let first = consumer.recv().await.unwrap();
+        assert_eq!(first.payload, vec![0u8; 10]);
+        consumer.commit(&first).await.unwrap();
+        let second = consumer.recv().await.unwrap();
+        assert_eq!(second.key, vec![1u8]);
+        assert_eq!(second.timestamp, 1);
+        // read is not committed.
+        assert!(!consumer.drained().await.unwrap());
+
+        // the uncommitted record is delivered again after restart.
+        let mut consumer = FileConsumer::new(dir.clone(), "msg-test", "default").unwrap();
+        assert_eq!(consumer.recv().await.unwrap(), second);
+        let third = consumer.recv().await.unwrap();
+        consumer.commit(&third).await.unwrap();
+        assert!(consumer.drained().await.unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recv_batch() {
+        let dir = std::env::temp_dir().join(format!("prim-mq-batch-{}", std::process::id()));
+        let producer = FileProducer::new(dir.clone()).unwrap();
+        producer.ensure_topic("msg-test", 1).await.unwrap();
+        for i in 0..5u8 {
+            producer
+                .send("msg-test", &[i], &[i], i as i64)
+                .await
+                .unwrap();
+        }
+        let mut consumer = FileConsumer::new(dir.clone(), "msg-test", "default").unwrap();
+        let wait = Duration::from_millis(300);
+        // no more than `max`.
+        let batch = consumer.recv_batch(3, wait).await.unwrap();
+        assert_eq!(
+            batch.iter().map(|r| r.key[0]).collect::<Vec<u8>>(),
+            vec![0, 1, 2]
+        );
+        // what arrived within `wait`.
+        let batch = consumer.recv_batch(10, wait).await.unwrap();
+        assert_eq!(batch.len(), 2);
+
+        // nothing arrives.
+        assert!(consumer.recv_batch(10, wait).await.unwrap().is_empty());
+        let producer = Arc::new(producer);
+        let sender = producer.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(wait / 3).await;
+            sender.send("msg-test", &[5], &[5], 5).await.unwrap();
+        });
+        let batch = consumer.recv_batch(10, wait).await.unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].key, vec![5u8]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
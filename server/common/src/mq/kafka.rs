@@ -0,0 +1,176 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lib::Result;
+use rdkafka::{
+    admin::{AdminClient, AdminOptions, NewTopic, TopicReplication},
+    client::DefaultClientContext,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    producer::{FutureProducer, FutureRecord},
+    types::RDKafkaErrorCode,
+    util::Timeout,
+    ClientConfig, Message, Offset, TopicPartitionList,
+};
+
+use super::{MQConsumer, MQProducer, Record};
+
+pub struct KafkaProducer {
+    address: String,
+    producer: FutureProducer,
+}
+
+impl KafkaProducer {
+    /// the producer is idempotent, so retries inside it never duplicate or reorder records of
+    /// a partition. a record republished from the spool may still show up twice, consumers
+    /// must tolerate that.
+    pub fn new(address: &str) -> Result<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", address)
+            .set("message.timeout.ms", "3000")
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .set("max.in.flight.requests.per.connection", "5")
+            // the same key always goes to the same partition.
+            .set("partitioner", "murmur2_random")
+            .create()?;
+        Ok(Self {
+            address: address.to_owned(),
+            producer,
+        })
+    }
+}
+
+#[async_trait]
+impl MQProducer for KafkaProducer {
+    async fn ensure_topic(&self, topic: &str, partitions: i32) -> Result<()> {
+        let admin_client: AdminClient<DefaultClientContext> = ClientConfig::new()
+            .set("bootstrap.servers", &self.address)
+            .create()?;
+        let admin_options = AdminOptions::new().operation_timeout(Some(Duration::from_secs(5)));
+        let res = admin_client
+            .create_topics(
+                [&NewTopic::new(
+                    topic,
+                    partitions,
+                    TopicReplication::Fixed(1),
+                )],
+                &admin_options,
+            )
+            .await?;
+        match &res[0] {
+            Ok(_) | Err((_, RDKafkaErrorCode::TopicAlreadyExists)) => Ok(()),
+            Err((_, code)) => Err(anyhow!("create topic {} error: {}", topic, code)),
+        }
+    }
+
+    async fn send(&self, topic: &str, key: &[u8], payload: &[u8], timestamp: i64) -> Result<()> {
+        match self
+            .producer
+            .send(
+                FutureRecord::to(topic)
+                    .key(key)
+                    .timestamp(timestamp)
+                    .payload(payload),
+                Timeout::After(Duration::from_millis(3000)),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err((e, _)) => Err(anyhow!(e.to_string())),
+        }
+    }
+}
+
+const METADATA_TIMEOUT: Duration = Duration::from_millis(3000);
+
+/// commits and metadata requests of `consumer` block, they run on the blocking threads.
+pub struct KafkaConsumer {
+    consumer: Arc<StreamConsumer>,
+    topic: String,
+}
+
+impl KafkaConsumer {
+    pub fn new(address: &str, topic: &str, group: &str) -> Result<Self> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("group.id", group)
+            .set("bootstrap.servers", address)
+            .set("enable.partition.eof", "false")
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(Self {
+            consumer: Arc::new(consumer),
+            topic: topic.to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl MQConsumer for KafkaConsumer {
+    async fn recv(&mut self) -> Result<Record> {
+        loop {
+            let msg = self.consumer.recv().await?;
+            let payload = match msg.payload() {
+                Some(payload) => payload.to_owned(),
+                None => {
+                    // nothing to process, but it should not be delivered again.
+                    self.consumer.commit_message(&msg, CommitMode::Async)?;
+                    continue;
+                }
+            };
+            return Ok(Record {
+                topic: msg.topic().to_owned(),
+                partition: msg.partition(),
+                offset: msg.offset(),
+                key: msg.key().map(|key| key.to_owned()).unwrap_or_default(),
+                payload,
+                timestamp: msg.timestamp().to_millis().unwrap_or_default(),
+            });
+        }
+    }
+
+    async fn commit(&mut self, record: &Record) -> Result<()> {
+        let mut list = TopicPartitionList::new();
+        list.add_partition_offset(
+            &record.topic,
+            record.partition,
+            Offset::Offset(record.offset + 1),
+        )?;
+        let consumer = self.consumer.clone();
+        tokio::task::spawn_blocking(move || consumer.commit(&list, CommitMode::Sync)).await??;
+        Ok(())
+    }
+
+    /// every partition counts, not only those assigned to this consumer.
+    async fn drained(&mut self) -> Result<bool> {
+        let consumer = self.consumer.clone();
+        let topic = self.topic.clone();
+        tokio::task::spawn_blocking(move || drained(&consumer, &topic)).await?
+    }
+}
+
+fn drained(consumer: &StreamConsumer, topic: &str) -> Result<bool> {
+    let metadata = consumer.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+    let mut list = TopicPartitionList::new();
+    for topic in metadata.topics() {
+        for partition in topic.partitions() {
+            list.add_partition(topic.name(), partition.id());
+        }
+    }
+    let committed = consumer.committed_offsets(list, METADATA_TIMEOUT)?;
+    for elem in committed.elements() {
+        let (low, high) =
+            consumer.fetch_watermarks(elem.topic(), elem.partition(), METADATA_TIMEOUT)?;
+        let next = match elem.offset() {
+            Offset::Offset(offset) => offset,
+            // nothing committed yet.
+            _ => low,
+        };
+        if next < high {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,117 @@
+//! message queue between message nodes and msgprocessors.
+//!
+//! `Kafka` is the backend for production, `Redis` reuses the redis cluster every deployment
+//! already has, and `File` keeps an append-only log on local disk, which is enough for a single
+//! host deployment and for integration tests.
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use lib::Result;
+
+pub mod file;
+pub mod kafka;
+pub mod redis;
+pub mod spool;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub topic: String,
+    /// always 0 for backends without partitions.
+    pub partition: i32,
+    /// position of the record in its partition, used to commit.
+    pub offset: i64,
+    pub key: Vec<u8>,
+    pub payload: Vec<u8>,
+    /// in milliseconds.
+    pub timestamp: i64,
+}
+
+#[async_trait]
+pub trait MQProducer: Send + Sync + 'static {
+    /// create the topic if not exists, `partitions` is ignored by backends without partitions.
+    async fn ensure_topic(&self, topic: &str, partitions: i32) -> Result<()>;
+
+    async fn send(&self, topic: &str, key: &[u8], payload: &[u8], timestamp: i64) -> Result<()>;
+}
+
+/// consumers of the same group share the records of a topic, every record is delivered to
+/// one of them, and delivered again after restart until it's committed.
+#[async_trait]
+pub trait MQConsumer: Send + 'static {
+    async fn recv(&mut self) -> Result<Record>;
+
+    /// take what arrives within `wait`, `max` at most, empty if nothing arrives, so callers
+    /// get the chance to stop between batches without cancelling a read.
+    ///
+    /// the default one relies on `recv` being cancel safe.
+    async fn recv_batch(&mut self, max: usize, wait: Duration) -> Result<Vec<Record>> {
+        let mut list = vec![];
+        let deadline = tokio::time::Instant::now() + wait;
+        while list.len() < max {
+            match tokio::time::timeout_at(deadline, self.recv()).await {
+                Ok(Ok(record)) => list.push(record),
+                Ok(Err(e)) if list.is_empty() => return Err(e),
+                // the error shows up again on the next call, keep what we have got.
+                Ok(Err(_)) | Err(_) => break,
+            }
+        }
+        Ok(list)
+    }
+
+    /// mark the record and all records before it in the same partition as consumed.
+    async fn commit(&mut self, record: &Record) -> Result<()>;
+
+    /// whether the group has committed every record of the topic so far, by any consumer.
+    async fn drained(&mut self) -> Result<bool>;
+}
+
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// `address` is the comma separated broker list.
+    Kafka {
+        address: String,
+    },
+    Redis {
+        addresses: Vec<SocketAddr>,
+        passwords: Vec<String>,
+    },
+    File {
+        dir: PathBuf,
+    },
+}
+
+impl Backend {
+    pub async fn producer(&self) -> Result<Arc<dyn MQProducer>> {
+        Ok(match self {
+            Backend::Kafka { address } => Arc::new(kafka::KafkaProducer::new(address)?),
+            Backend::Redis {
+                addresses,
+                passwords,
+            } => Arc::new(redis::RedisProducer::new(addresses.clone(), passwords.clone()).await?),
+            Backend::File { dir } => Arc::new(file::FileProducer::new(dir.clone())?),
+        })
+    }
+
+    /// `name` identifies the consumer inside its group, it should be stable across restarts.
+    pub async fn consumer(
+        &self,
+        topic: &str,
+        group: &str,
+        name: &str,
+    ) -> Result<Box<dyn MQConsumer>> {
+        Ok(match self {
+            Backend::Kafka { address } => {
+                Box::new(kafka::KafkaConsumer::new(address, topic, group)?)
+            }
+            Backend::Redis {
+                addresses,
+                passwords,
+            } => Box::new(
+                redis::RedisConsumer::new(addresses.clone(), passwords.clone(), topic, group, name)
+                    .await?,
+            ),
+            Backend::File { dir } => Box::new(file::FileConsumer::new(dir.clone(), topic, group)?),
+        })
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::BTreeMap,
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use byteorder::{BigEndian, ByteOrder};
+use lib::{cache::redis_ops::RedisOps, Result};
+
+use super::{MQConsumer, MQProducer, Record};
+
+/// streams are trimmed to about this length, records not consumed before are lost.
+const STREAM_MAX_LEN: usize = 1_000_000;
+const READ_BATCH: usize = 128;
+const READ_BLOCK: Duration = Duration::from_millis(1000);
+/// entries pending on another consumer of the group for so long are taken over, their
+/// consumer is gone, or stuck.
+const CLAIM_MIN_IDLE: Duration = Duration::from_secs(60);
+
+#[inline]
+fn stream_key(topic: &str) -> String {
+    format!("MQ_STREAM_{}", topic)
+}
+
+pub struct RedisProducer {
+    redis_ops: RedisOps,
+}
+
+impl RedisProducer {
+    pub async fn new(addresses: Vec<SocketAddr>, passwords: Vec<String>) -> Result<Self> {
+        Ok(Self {
+            redis_ops: RedisOps::connect(addresses, Some(passwords)).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl MQProducer for RedisProducer {
+    /// streams are created on first write.
+    async fn ensure_topic(&self, _topic: &str, _partitions: i32) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send(&self, topic: &str, key: &[u8], payload: &[u8], timestamp: i64) -> Result<()> {
+        let mut ts = [0u8; 8];
+        BigEndian::write_i64(&mut ts, timestamp);
+        self.redis_ops
+            .clone()
+            .stream_add(
+                &stream_key(topic),
+                &[("k", key), ("p", payload), ("t", &ts)],
+                STREAM_MAX_LEN,
+            )
+            .await
+    }
+}
+
+/// stream entry ids are not numbers, so records are numbered locally and mapped back
+/// to entry ids when committed.
+pub struct RedisConsumer {
+    /// a dedicated connection, blocking reads would stall other commands on a shared one.
+    redis_ops: RedisOps,
+    topic: String,
+    key: String,
+    group: String,
+    name: String,
+    /// id of the last pending entry read, entries left pending by the previous run, or claimed
+    /// from other consumers, are consumed first, then `>` for new entries.
+    cursor: String,
+    last_claim: Option<Instant>,
+    buffer: VecDeque<Record>,
+    next_offset: i64,
+    uncommitted: BTreeMap<i64, String>,
+}
+
+impl RedisConsumer {
+    pub async fn new(
+        addresses: Vec<SocketAddr>,
+        passwords: Vec<String>,
+        topic: &str,
+        group: &str,
+        name: &str,
+    ) -> Result<Self> {
+        let mut redis_ops = RedisOps::connect(addresses, Some(passwords)).await?;
+        let key = stream_key(topic);
+        redis_ops.stream_create_group(&key, group).await?;
+        Ok(Self {
+            redis_ops,
+            topic: topic.to_owned(),
+            key,
+            group: group.to_owned(),
+            name: name.to_owned(),
+            cursor: "0".to_owned(),
+            last_claim: None,
+            buffer: VecDeque::new(),
+            next_offset: 0,
+            uncommitted: BTreeMap::new(),
+        })
+    }
+
+    /// take over entries other consumers of the group left pending, at start and then every
+    /// `CLAIM_MIN_IDLE`, they are read again from the pending ones of this consumer.
+    async fn claim(&mut self) -> Result<()> {
+        if self
+            .last_claim
+            .is_some_and(|last_claim| last_claim.elapsed() < CLAIM_MIN_IDLE)
+        {
+            return Ok(());
+        }
+        let mut start = "0-0".to_owned();
+        let mut claimed = 0;
+        loop {
+            let (next, id_list) = self
+                .redis_ops
+                .stream_auto_claim(
+                    &self.key,
+                    &self.group,
+                    &self.name,
+                    CLAIM_MIN_IDLE,
+                    &start,
+                    READ_BATCH,
+                )
+                .await?;
+            claimed += id_list.len();
+            if next == "0-0" {
+                break;
+            }
+            start = next;
+        }
+        self.last_claim = Some(Instant::now());
+        if claimed > 0 {
+            self.cursor = "0".to_owned();
+        }
+        Ok(())
+    }
+
+    async fn fill(&mut self, count: usize, block: Duration) -> Result<()> {
+        self.claim().await?;
+        let entries = self
+            .redis_ops
+            .stream_read_group(
+                &self.key,
+                &self.group,
+                &self.name,
+                &self.cursor,
+                count,
+                block,
+            )
+            .await?;
+        if self.cursor != ">" {
+            match entries.last() {
+                Some((id, _)) => self.cursor = id.clone(),
+                None => self.cursor = ">".to_owned(),
+            }
+        }
+        for (id, fields) in entries {
+            let mut record = Record {
+                topic: self.topic.clone(),
+                partition: 0,
+                offset: self.next_offset,
+                key: vec![],
+                payload: vec![],
+                timestamp: 0,
+            };
+            for pair in fields.chunks_exact(2) {
+                match pair[0].as_slice() {
+                    b"k" => record.key = pair[1].clone(),
+                    b"p" => record.payload = pair[1].clone(),
+                    b"t" if pair[1].len() == 8 => record.timestamp = BigEndian::read_i64(&pair[1]),
+                    _ => {}
+                }
+            }
+            self.next_offset += 1;
+            self.uncommitted.insert(record.offset, id);
+            // an entry trimmed while pending comes back without fields.
+            if fields.is_empty() {
+                continue;
+            }
+            self.buffer.push_back(record);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MQConsumer for RedisConsumer {
+    async fn recv(&mut self) -> Result<Record> {
+        loop {
+            if let Some(record) = self.buffer.pop_front() {
+                return Ok(record);
+            }
+            self.fill(READ_BATCH, READ_BLOCK).await?;
+        }
+    }
+
+    /// a blocking read dropped halfway loses the entries it has read, so it's bounded by `wait`
+    /// instead of being cancelled.
+    async fn recv_batch(&mut self, max: usize, wait: Duration) -> Result<Vec<Record>> {
+        if self.buffer.is_empty() {
+            self.fill(max, wait).await?;
+        }
+        let len = self.buffer.len().min(max);
+        Ok(self.buffer.drain(..len).collect())
+    }
+
+    async fn commit(&mut self, record: &Record) -> Result<()> {
+        if record.topic != self.topic {
+            return Err(anyhow!("record of topic {} is not mine", record.topic));
+        }
+        let rest = self.uncommitted.split_off(&(record.offset + 1));
+        let id_list = std::mem::replace(&mut self.uncommitted, rest)
+            .into_values()
+            .collect::<Vec<String>>();
+        if id_list.is_empty() {
+            return Ok(());
+        }
+        self.redis_ops
+            .stream_ack(&self.key, &self.group, &id_list)
+            .await
+    }
+
+    async fn drained(&mut self) -> Result<bool> {
+        match self
+            .redis_ops
+            .stream_group_backlog(&self.key, &self.group)
+            .await?
+        {
+            Some(backlog) => Ok(backlog == 0),
+            None => Err(anyhow!("backlog of {} is unknown", self.key)),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use byteorder::{BigEndian, ByteOrder};
+use lib::Result;
+use tracing::{error, info, warn};
+
+use super::{
+    file::{FileConsumer, FileProducer},
+    MQConsumer, MQProducer, Record,
+};
+
+/// all topics share one spool log, so records are republished in the order they were sent.
+const SPOOL_TOPIC: &str = "spool";
+const DRAINER_GROUP: &str = "drainer";
+const DEAD_LETTER_TOPIC: &str = "dead-letter";
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// counters of a spool, shared with whoever wants to report them.
+#[derive(Debug, Default)]
+pub struct SpoolMetrics {
+    /// records waiting in the spool.
+    pub depth: AtomicU64,
+    pub spooled: AtomicU64,
+    pub drained: AtomicU64,
+    pub dead_lettered: AtomicU64,
+}
+
+/// a producer which never drops records: a failed send is written to a local spool, and a
+/// background drainer republishes spooled records in order once the backend recovers.
+///
+/// while the spool is not empty, new records are spooled too, so a record never overtakes
+/// one sent before it. a record keeps failing while the backend is healthy is moved to
+/// `<topic>-dlq`, or to the local dead-letter log if that fails as well.
+pub struct SpooledProducer {
+    inner: Arc<dyn MQProducer>,
+    /// also keeps the local dead-letter log.
+    spool: FileProducer,
+    metrics: Arc<SpoolMetrics>,
+}
+
+impl SpooledProducer {
+    /// `dead_letter_attempts` is how many times a record is republished before it's dead.
+    pub fn new(
+        inner: Arc<dyn MQProducer>,
+        dir: PathBuf,
+        dead_letter_attempts: u32,
+    ) -> Result<Arc<Self>> {
+        let mut consumer = FileConsumer::new(dir.clone(), SPOOL_TOPIC, DRAINER_GROUP)?;
+        let metrics = Arc::new(SpoolMetrics::default());
+        // records left by the previous run.
+        while consumer.try_recv()?.is_some() {
+            metrics.depth.fetch_add(1, Ordering::AcqRel);
+        }
+        let consumer = FileConsumer::new(dir.clone(), SPOOL_TOPIC, DRAINER_GROUP)?;
+        let producer = Arc::new(Self {
+            inner,
+            spool: FileProducer::new(dir)?,
+            metrics,
+        });
+        let drainer = producer.clone();
+        tokio::spawn(async move {
+            drainer.drain(consumer, dead_letter_attempts).await;
+        });
+        let reporter = producer.clone();
+        tokio::spawn(async move {
+            reporter.report().await;
+        });
+        Ok(producer)
+    }
+
+    pub fn metrics(&self) -> Arc<SpoolMetrics> {
+        self.metrics.clone()
+    }
+
+    async fn drain(&self, mut consumer: FileConsumer, dead_letter_attempts: u32) {
+        loop {
+            let record = match consumer.recv().await {
+                Ok(record) => record,
+                Err(e) => {
+                    error!("read spool error: {}", e);
+                    tokio::time::sleep(MAX_BACKOFF).await;
+                    continue;
+                }
+            };
+            let (topic, key) = match decode_key(&record.key) {
+                Some(topic_key) => topic_key,
+                None => {
+                    error!("broken spool record at {}, skipped", record.offset);
+                    self.consumed(&mut consumer, &record).await;
+                    continue;
+                }
+            };
+            self.republish(&topic, key, &record, dead_letter_attempts)
+                .await;
+            self.consumed(&mut consumer, &record).await;
+        }
+    }
+
+    /// send until succeeded or dead, failures while the backend is down are not counted.
+    async fn republish(&self, topic: &str, key: &[u8], record: &Record, dead_letter_attempts: u32) {
+        let mut attempts = 0;
+        let mut backoff = Duration::from_millis(100);
+        loop {
+            let e = match self
+                .inner
+                .send(topic, key, &record.payload, record.timestamp)
+                .await
+            {
+                Ok(_) => {
+                    self.metrics.drained.fetch_add(1, Ordering::AcqRel);
+                    return;
+                }
+                Err(e) => e,
+            };
+            // the topic exists already, so this only probes whether the backend is healthy.
+            if self.inner.ensure_topic(topic, 1).await.is_ok() {
+                attempts += 1;
+                if attempts >= dead_letter_attempts {
+                    warn!(
+                        "record of {} keeps failing: {}, moved to dead letter",
+                        topic, e
+                    );
+                    self.bury(topic, key, record).await;
+                    return;
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    async fn bury(&self, topic: &str, key: &[u8], record: &Record) {
+        self.metrics.dead_lettered.fetch_add(1, Ordering::AcqRel);
+        let dead_letter_topic = format!("{}-dlq", topic);
+        if self.inner.ensure_topic(&dead_letter_topic, 1).await.is_ok()
+            && self
+                .inner
+                .send(&dead_letter_topic, key, &record.payload, record.timestamp)
+                .await
+                .is_ok()
+        {
+            return;
+        }
+        if let Err(e) = self
+            .spool
+            .send(
+                DEAD_LETTER_TOPIC,
+                &record.key,
+                &record.payload,
+                record.timestamp,
+            )
+            .await
+        {
+            error!("write dead letter of {} error: {}, record lost", topic, e);
+        }
+    }
+
+    async fn consumed(&self, consumer: &mut FileConsumer, record: &Record) {
+        if let Err(e) = consumer.commit(record).await {
+            error!("commit spool error: {}", e);
+        }
+        self.metrics.depth.fetch_sub(1, Ordering::AcqRel);
+        // reclaim the disk once everything is drained.
+        let depth = &self.metrics.depth;
+        match self
+            .spool
+            .truncate_if(SPOOL_TOPIC, || depth.load(Ordering::Acquire) == 0)
+            .await
+        {
+            Ok(true) => {
+                if let Err(e) = consumer.rewind() {
+                    error!("rewind spool error: {}", e);
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!("truncate spool error: {}", e),
+        }
+    }
+
+    async fn report(&self) {
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let depth = self.metrics.depth.load(Ordering::Acquire);
+            if depth == 0 {
+                continue;
+            }
+            info!(
+                "mq spool depth: {}, spooled: {}, drained: {}, dead lettered: {}",
+                depth,
+                self.metrics.spooled.load(Ordering::Acquire),
+                self.metrics.drained.load(Ordering::Acquire),
+                self.metrics.dead_lettered.load(Ordering::Acquire)
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl MQProducer for SpooledProducer {
+    async fn ensure_topic(&self, topic: &str, partitions: i32) -> Result<()> {
+        self.inner.ensure_topic(topic, partitions).await
+    }
+
+    /// `Ok` once the record is sent or spooled.
+    async fn send(&self, topic: &str, key: &[u8], payload: &[u8], timestamp: i64) -> Result<()> {
+        if self.metrics.depth.load(Ordering::Acquire) == 0 {
+            match self.inner.send(topic, key, payload, timestamp).await {
+                Ok(_) => return Ok(()),
+                Err(e) => warn!("send to {} failed: {}, spooled", topic, e),
+            }
+        }
+        // counted before written, so the spool is never truncated under a pending record.
+        self.metrics.depth.fetch_add(1, Ordering::AcqRel);
+        if let Err(e) = self
+            .spool
+            .send(SPOOL_TOPIC, &encode_key(topic, key), payload, timestamp)
+            .await
+        {
+            self.metrics.depth.fetch_sub(1, Ordering::AcqRel);
+            return Err(anyhow!("spool record of {} error: {}", topic, e));
+        }
+        self.metrics.spooled.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+}
+
+/// topic length(2 bytes) + topic + key.
+#[inline]
+fn encode_key(topic: &str, key: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0u8; 2];
+    BigEndian::write_u16(&mut bytes, topic.len() as u16);
+    bytes.extend_from_slice(topic.as_bytes());
+    bytes.extend_from_slice(key);
+    bytes
+}
+
+#[inline]
+fn decode_key(bytes: &[u8]) -> Option<(String, &[u8])> {
+    if bytes.len() < 2 {
+        return None;
+    }
+    let topic_len = BigEndian::read_u16(bytes) as usize;
+    if bytes.len() < 2 + topic_len {
+        return None;
+    }
+    let topic = String::from_utf8(bytes[2..2 + topic_len].to_vec()).ok()?;
+    Some((topic, &bytes[2 + topic_len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use lib::Result;
+    use tokio::sync::Mutex;
+
+    use super::SpooledProducer;
+    use crate::mq::MQProducer;
+
+    #[derive(Default)]
+    struct Flaky {
+        down: AtomicBool,
+        sent: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl MQProducer for Flaky {
+        async fn ensure_topic(&self, _topic: &str, _partitions: i32) -> Result<()> {
+            Ok(())
+        }
+
+        async fn send(&self, _topic: &str, _key: &[u8], payload: &[u8], _ts: i64) -> Result<()> {
+            if self.down.load(Ordering::Acquire) {
+                return Err(anyhow!("broker down"));
+            }
+            self.sent.lock().await.push(payload.to_vec());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spool_in_order() {
+        let dir = std::env::temp_dir().join(format!("prim-spool-{}", std::process::id()));
+        let inner = Arc::new(Flaky::default());
+        let producer = SpooledProducer::new(inner.clone(), dir.clone(), u32::MAX).unwrap();
+        producer.send("msg", b"k", &[0], 0).await.unwrap();
+        inner.down.store(true, Ordering::Release);
+        producer.send("msg", b"k", &[1], 0).await.unwrap();
+        inner.down.store(false, Ordering::Release);
+        // spooled before, so this one waits in the spool too.
+        producer.send("msg", b"k", &[2], 0).await.unwrap();
+        for _ in 0..50 {
+            if producer.metrics().depth.load(Ordering::Acquire) == 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(*inner.sent.lock().await, vec![vec![0], vec![1], vec![2]]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,8 +1,14 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use anyhow::anyhow;
 use async_trait::async_trait;
 use lib::{
-    entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerType},
+    entity::{
+        NodeAuthInfo, ReqwestMsg, ReqwestResourceID, ServerInfo, ServerType, REGISTER_AGAIN,
+    },
     net::{client::ClientConfig, InnerStates},
     Result,
 };
@@ -12,13 +18,22 @@ use lib_net_tokio::net::{
 };
 
 use tokio::sync::mpsc;
-use tracing::error;
+use tracing::{debug, error, warn};
+
+use crate::load::LoadSampler;
+
+/// how often a node sends heartbeat to `scheduler`.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(1000);
+/// how often a node reports its load to `scheduler`, a load report is also a heartbeat.
+pub const LOAD_REPORT_INTERVAL: Duration = Duration::from_millis(5000);
 
+/// `join_token` is issued by `scheduler` for the kind(and optionally the id) of this node.
 pub async fn connect2scheduler(
     client_config: ClientConfig,
     timeout: Duration,
     handler_map: ReqwestHandlerMap,
     self_info: ServerInfo,
+    join_token: String,
     states_gen: Box<dyn Fn() -> InnerStates + Send + Sync + 'static>,
     reqwest_request_id: ReqwestResourceID,
 ) -> Result<ReqwestOperatorManager> {
@@ -83,13 +98,70 @@ pub async fn connect2scheduler(
     // for client, we only need the operator manager returned, so leak client for drop on exit.
     Box::leak(Box::new(client));
 
-    let mut auth_info = self_info.clone();
-    auth_info.typ = ServerType::SchedulerClient;
+    register(&operator, &self_info, &join_token, reqwest_request_id).await?;
+    Ok(operator)
+}
+
+/// authenticate the connection and register the node with `reqwest_request_id`.
+async fn register(
+    operator: &ReqwestOperatorManager,
+    self_info: &ServerInfo,
+    join_token: &str,
+    reqwest_request_id: ReqwestResourceID,
+) -> Result<()> {
+    let mut auth_info = NodeAuthInfo {
+        info: self_info.clone(),
+        token: join_token.to_owned(),
+    };
+    auth_info.info.typ = ServerType::SchedulerClient;
     let auth_msg =
         ReqwestMsg::with_resource_id_payload(ReqwestResourceID::NodeAuth, &auth_info.to_bytes());
-    let _resp = operator.call(auth_msg).await?;
+    let resp = operator.call(auth_msg).await?;
+    if resp.payload().is_empty() {
+        return Err(anyhow!("node {} is rejected by scheduler", self_info.id));
+    }
     let register_msg =
         ReqwestMsg::with_resource_id_payload(reqwest_request_id, &self_info.to_bytes());
     let _resp = operator.call(register_msg).await?;
-    Ok(operator)
+    Ok(())
+}
+
+/// send heartbeat to `scheduler` every `HEARTBEAT_INTERVAL`, and report load of current node
+/// instead every `LOAD_REPORT_INTERVAL`. never returns.
+///
+/// the node registers again with the same arguments as `connect2scheduler` once `scheduler`
+/// replies it doesn't know the node, e.g. the node has been treated as crashed.
+pub async fn keep_alive(
+    operator: &ReqwestOperatorManager,
+    self_info: ServerInfo,
+    join_token: String,
+    reqwest_request_id: ReqwestResourceID,
+) {
+    let mut sampler = LoadSampler::new();
+    let mut info = self_info.clone();
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_report = Instant::now();
+    loop {
+        interval.tick().await;
+        let msg = if last_report.elapsed() >= LOAD_REPORT_INTERVAL {
+            last_report = Instant::now();
+            info.load = Some(sampler.sample());
+            debug!("report load: {}", info.load.as_ref().unwrap());
+            ReqwestMsg::with_resource_id_payload(ReqwestResourceID::NodeLoadReport, &info.to_bytes())
+        } else {
+            ReqwestMsg::with_resource_id_payload(ReqwestResourceID::NodeHeartbeat, b"")
+        };
+        match operator.call(msg).await {
+            Ok(resp) if resp.payload() == REGISTER_AGAIN => {
+                warn!("scheduler asks node {} to register again", self_info.id);
+                if let Err(e) =
+                    register(operator, &self_info, &join_token, reqwest_request_id).await
+                {
+                    error!("register to scheduler again failed: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("keep alive with scheduler failed: {}", e),
+        }
+    }
 }
@@ -0,0 +1,228 @@
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{Error, Result};
+
+/// one-time prekeys published at once, the api hands out each of them once.
+pub const ONE_TIME_PREKEY_BATCH: usize = 100;
+
+const ENGINE: base64::engine::GeneralPurpose = base64::engine::GeneralPurpose::new(
+    &base64::alphabet::URL_SAFE,
+    base64::engine::general_purpose::NO_PAD,
+);
+
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    ENGINE.encode(bytes)
+}
+
+pub(crate) fn decode<const N: usize>(s: &str) -> Result<[u8; N]> {
+    ENGINE
+        .decode(s)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidKey(s.to_string()))
+}
+
+/// the long term keys of a device, the x25519 one takes part in X3DH and the ed25519 one signs
+/// the signed prekeys.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct IdentityKeyPair {
+    dh_secret: [u8; 32],
+    signing_secret: [u8; 32],
+}
+
+impl IdentityKeyPair {
+    pub fn generate() -> Self {
+        Self {
+            dh_secret: StaticSecret::random_from_rng(OsRng).to_bytes(),
+            signing_secret: SigningKey::generate(&mut OsRng).to_bytes(),
+        }
+    }
+
+    pub fn dh_public(&self) -> [u8; 32] {
+        PublicKey::from(&StaticSecret::from(self.dh_secret)).to_bytes()
+    }
+
+    pub fn signing_public(&self) -> [u8; 32] {
+        SigningKey::from_bytes(&self.signing_secret)
+            .verifying_key()
+            .to_bytes()
+    }
+
+    pub(crate) fn dh(&self, public: &[u8; 32]) -> [u8; 32] {
+        StaticSecret::from(self.dh_secret)
+            .diffie_hellman(&PublicKey::from(*public))
+            .to_bytes()
+    }
+
+    fn sign(&self, msg: &[u8]) -> [u8; 64] {
+        SigningKey::from_bytes(&self.signing_secret)
+            .sign(msg)
+            .to_bytes()
+    }
+}
+
+/// the signature covers the identity dh key too, so the api can't swap it.
+fn signed_content(identity_key: &[u8; 32], signed_prekey: &[u8; 32]) -> [u8; 64] {
+    let mut content = [0u8; 64];
+    content[..32].copy_from_slice(identity_key);
+    content[32..].copy_from_slice(signed_prekey);
+    content
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct PreKey {
+    id: u32,
+    secret: [u8; 32],
+}
+
+impl PreKey {
+    fn generate(id: u32) -> Self {
+        Self {
+            id,
+            secret: StaticSecret::random_from_rng(OsRng).to_bytes(),
+        }
+    }
+
+    fn public(&self) -> [u8; 32] {
+        PublicKey::from(&StaticSecret::from(self.secret)).to_bytes()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OneTimePreKeyPublic {
+    pub id: u32,
+    pub key: String,
+}
+
+/// what a device publishes to the api, keys are url safe base64 without padding.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct PublishedKeys {
+    pub identity_key: String,
+    pub signing_key: String,
+    pub signed_prekey_id: u32,
+    pub signed_prekey: String,
+    pub signature: String,
+    pub one_time_prekey_list: Vec<OneTimePreKeyPublic>,
+}
+
+/// what the api hands out to start a session with its owner, the one-time prekey is gone once
+/// they run out.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct PreKeyBundle {
+    pub identity_key: String,
+    pub signing_key: String,
+    pub signed_prekey_id: u32,
+    pub signed_prekey: String,
+    pub signature: String,
+    pub one_time_prekey: Option<OneTimePreKeyPublic>,
+}
+
+impl PreKeyBundle {
+    /// the signed prekey, checked against the signing key of the owner.
+    pub(crate) fn verified_signed_prekey(&self) -> Result<[u8; 32]> {
+        let identity_key = decode::<32>(&self.identity_key)?;
+        let signed_prekey = decode::<32>(&self.signed_prekey)?;
+        let signing_key = VerifyingKey::from_bytes(&decode::<32>(&self.signing_key)?)
+            .map_err(|_| Error::InvalidKey(self.signing_key.clone()))?;
+        let signature = Signature::from_bytes(&decode::<64>(&self.signature)?);
+        signing_key
+            .verify(&signed_content(&identity_key, &signed_prekey), &signature)
+            .map_err(|_| Error::BadSignature)?;
+        Ok(signed_prekey)
+    }
+}
+
+/// the identity and prekeys of a device, kept by the client only.
+///
+/// the previous signed prekey is kept after a rotation, sessions started from it before the
+/// new one is published can still be answered.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct PreKeyStore {
+    identity: IdentityKeyPair,
+    signed_prekey: PreKey,
+    previous_signed_prekey: Option<PreKey>,
+    one_time_prekey_list: Vec<PreKey>,
+    next_id: u32,
+}
+
+impl PreKeyStore {
+    pub fn new(identity: IdentityKeyPair) -> Self {
+        Self {
+            identity,
+            signed_prekey: PreKey::generate(1),
+            previous_signed_prekey: None,
+            one_time_prekey_list: vec![],
+            next_id: 2,
+        }
+    }
+
+    pub fn identity(&self) -> &IdentityKeyPair {
+        &self.identity
+    }
+
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        id
+    }
+
+    pub fn rotate_signed_prekey(&mut self) {
+        let id = self.next_id();
+        let previous = std::mem::replace(&mut self.signed_prekey, PreKey::generate(id));
+        self.previous_signed_prekey = Some(previous);
+    }
+
+    /// new one-time prekeys, the ones published before are kept until they are used.
+    pub fn generate_one_time_prekeys(&mut self, n: usize) -> Vec<OneTimePreKeyPublic> {
+        (0..n)
+            .map(|_| {
+                let prekey = PreKey::generate(self.next_id());
+                let public = OneTimePreKeyPublic {
+                    id: prekey.id,
+                    key: encode(&prekey.public()),
+                };
+                self.one_time_prekey_list.push(prekey);
+                public
+            })
+            .collect()
+    }
+
+    /// the current signed prekey with the one-time prekeys given.
+    pub fn published_keys(&self, one_time_prekey_list: Vec<OneTimePreKeyPublic>) -> PublishedKeys {
+        let identity_key = self.identity.dh_public();
+        let signed_prekey = self.signed_prekey.public();
+        PublishedKeys {
+            identity_key: encode(&identity_key),
+            signing_key: encode(&self.identity.signing_public()),
+            signed_prekey_id: self.signed_prekey.id,
+            signed_prekey: encode(&signed_prekey),
+            signature: encode(
+                &self
+                    .identity
+                    .sign(&signed_content(&identity_key, &signed_prekey)),
+            ),
+            one_time_prekey_list,
+        }
+    }
+
+    pub(crate) fn signed_prekey_secret(&self, id: u32) -> Result<[u8; 32]> {
+        std::iter::once(&self.signed_prekey)
+            .chain(self.previous_signed_prekey.iter())
+            .find(|prekey| prekey.id == id)
+            .map(|prekey| prekey.secret)
+            .ok_or(Error::UnknownPreKey(id))
+    }
+
+    /// a one-time prekey is used once, it's removed as it's taken.
+    pub(crate) fn take_one_time_prekey(&mut self, id: u32) -> Result<[u8; 32]> {
+        let index = self
+            .one_time_prekey_list
+            .iter()
+            .position(|prekey| prekey.id == id)
+            .ok_or(Error::UnknownPreKey(id))?;
+        Ok(self.one_time_prekey_list.swap_remove(index).secret)
+    }
+}
@@ -0,0 +1,34 @@
+//! end-to-end encryption of 1:1 conversations, the servers only ever see the envelopes.
+//!
+//! a session starts with X3DH against the prekey bundle the peer published to the api, and
+//! goes on with a double ratchet, see `Session`.
+
+use thiserror::Error;
+
+pub mod identity;
+pub mod ratchet;
+pub mod session;
+pub mod x3dh;
+
+pub use identity::{
+    IdentityKeyPair, OneTimePreKeyPublic, PreKeyBundle, PreKeyStore, PublishedKeys,
+};
+pub use session::{Envelope, Session};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid key: `{0}`")]
+    InvalidKey(String),
+    #[error("signed prekey signature mismatch")]
+    BadSignature,
+    #[error("prekey `{0}` not found")]
+    UnknownPreKey(u32),
+    #[error("too many skipped messages")]
+    TooManySkipped,
+    #[error("decrypt failed")]
+    Decrypt,
+    #[error("malformed envelope: `{0}`")]
+    Malformed(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
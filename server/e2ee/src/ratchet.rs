@@ -0,0 +1,253 @@
+use chacha20poly1305::{aead::Aead, aead::Payload, ChaCha20Poly1305, KeyInit, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{Error, Result};
+
+/// message keys of a chain skipped at once, more than this is taken as an attack.
+const MAX_SKIP: u32 = 1000;
+/// skipped message keys kept, the oldest are dropped first.
+const MAX_SKIPPED_KEYS: usize = 2000;
+const ROOT_INFO: &[u8] = b"prim-ratchet";
+const MESSAGE_INFO: &[u8] = b"prim-message";
+
+/// sent in clear with every message, bound to the ciphertext as associated data.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// the current ratchet key of the sender.
+    pub dh: [u8; 32],
+    /// messages in the previous sending chain.
+    pub pn: u32,
+    /// of the message in the current sending chain.
+    pub n: u32,
+}
+
+impl Header {
+    fn as_bytes(&self) -> [u8; 40] {
+        let mut bytes = [0u8; 40];
+        bytes[..32].copy_from_slice(&self.dh);
+        bytes[32..36].copy_from_slice(&self.pn.to_be_bytes());
+        bytes[36..].copy_from_slice(&self.n.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct SkippedKey {
+    dh: [u8; 32],
+    n: u32,
+    key: [u8; 32],
+}
+
+/// the double ratchet, every message has its own key and a new ratchet key is taken each time
+/// the peer answers.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Ratchet {
+    dh_secret: [u8; 32],
+    dh_remote: Option<[u8; 32]>,
+    root_key: [u8; 32],
+    send_chain: Option<[u8; 32]>,
+    recv_chain: Option<[u8; 32]>,
+    send_n: u32,
+    recv_n: u32,
+    prev_send_n: u32,
+    skipped_list: Vec<SkippedKey>,
+}
+
+fn dh(secret: &[u8; 32], public: &[u8; 32]) -> [u8; 32] {
+    StaticSecret::from(*secret)
+        .diffie_hellman(&PublicKey::from(*public))
+        .to_bytes()
+}
+
+/// (root key, chain key)
+fn kdf_root(root_key: &[u8; 32], dh_out: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh_out)
+        .expand(ROOT_INFO, &mut okm)
+        .expect("64 bytes is a valid hkdf output length");
+    let (mut root, mut chain) = ([0u8; 32], [0u8; 32]);
+    root.copy_from_slice(&okm[..32]);
+    chain.copy_from_slice(&okm[32..]);
+    (root, chain)
+}
+
+/// (chain key, message key)
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hmac_of = |byte: u8| {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key)
+            .expect("hmac takes keys of any length");
+        mac.update(&[byte]);
+        let out: [u8; 32] = mac.finalize().into_bytes().into();
+        out
+    };
+    (hmac_of(2), hmac_of(1))
+}
+
+/// a message key is used once, so the nonce derived with the key never repeats.
+fn cipher_of(message_key: &[u8; 32]) -> (ChaCha20Poly1305, Nonce) {
+    let mut okm = [0u8; 44];
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(MESSAGE_INFO, &mut okm)
+        .expect("44 bytes is a valid hkdf output length");
+    let cipher = ChaCha20Poly1305::new_from_slice(&okm[..32]).expect("the key is 32 bytes");
+    (cipher, *Nonce::from_slice(&okm[32..]))
+}
+
+fn seal(message_key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    let (cipher, nonce) = cipher_of(message_key);
+    cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("chacha20poly1305 never fails to encrypt in memory")
+}
+
+fn open(message_key: &[u8; 32], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let (cipher, nonce) = cipher_of(message_key);
+    cipher
+        .decrypt(
+            &nonce,
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Decrypt)
+}
+
+impl Ratchet {
+    /// the initiator sends first, to the signed prekey of the responder.
+    pub fn initiator(secret: [u8; 32], remote_signed_prekey: [u8; 32]) -> Self {
+        let dh_secret = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let (root_key, send_chain) = kdf_root(&secret, &dh(&dh_secret, &remote_signed_prekey));
+        Self {
+            dh_secret,
+            dh_remote: Some(remote_signed_prekey),
+            root_key,
+            send_chain: Some(send_chain),
+            recv_chain: None,
+            send_n: 0,
+            recv_n: 0,
+            prev_send_n: 0,
+            skipped_list: vec![],
+        }
+    }
+
+    /// the responder's first ratchet key is its signed prekey.
+    pub fn responder(secret: [u8; 32], signed_prekey_secret: [u8; 32]) -> Self {
+        Self {
+            dh_secret: signed_prekey_secret,
+            dh_remote: None,
+            root_key: secret,
+            send_chain: None,
+            recv_chain: None,
+            send_n: 0,
+            recv_n: 0,
+            prev_send_n: 0,
+            skipped_list: vec![],
+        }
+    }
+
+    /// the responder can't send before the initiator's first message arrives.
+    pub fn can_send(&self) -> bool {
+        self.send_chain.is_some()
+    }
+
+    pub fn encrypt(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<(Header, Vec<u8>)> {
+        let send_chain = self
+            .send_chain
+            .ok_or_else(|| Error::Malformed("no sending chain yet".to_string()))?;
+        let (send_chain, message_key) = kdf_chain(&send_chain);
+        self.send_chain = Some(send_chain);
+        let header = Header {
+            dh: PublicKey::from(&StaticSecret::from(self.dh_secret)).to_bytes(),
+            pn: self.prev_send_n,
+            n: self.send_n,
+        };
+        self.send_n += 1;
+        let aad = [associated_data, header.as_bytes().as_slice()].concat();
+        Ok((header, seal(&message_key, plaintext, &aad)))
+    }
+
+    /// the state is left as it was if the message can't be decrypted.
+    pub fn decrypt(
+        &mut self,
+        header: &Header,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let aad = [associated_data, header.as_bytes().as_slice()].concat();
+        if let Some(index) = self
+            .skipped_list
+            .iter()
+            .position(|skipped| skipped.dh == header.dh && skipped.n == header.n)
+        {
+            let plaintext = open(&self.skipped_list[index].key, ciphertext, &aad)?;
+            self.skipped_list.remove(index);
+            return Ok(plaintext);
+        }
+        let mut next = self.clone();
+        if next.dh_remote != Some(header.dh) {
+            next.skip_until(header.pn)?;
+            next.step(header.dh);
+        }
+        next.skip_until(header.n)?;
+        let (recv_chain, message_key) = kdf_chain(&next.recv_chain.expect("set by the step"));
+        next.recv_chain = Some(recv_chain);
+        next.recv_n += 1;
+        let plaintext = open(&message_key, ciphertext, &aad)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn skip_until(&mut self, until: u32) -> Result<()> {
+        let (Some(mut recv_chain), Some(dh_remote)) = (self.recv_chain, self.dh_remote) else {
+            return Ok(());
+        };
+        if until > self.recv_n + MAX_SKIP {
+            return Err(Error::TooManySkipped);
+        }
+        while self.recv_n < until {
+            let (next_chain, message_key) = kdf_chain(&recv_chain);
+            self.skipped_list.push(SkippedKey {
+                dh: dh_remote,
+                n: self.recv_n,
+                key: message_key,
+            });
+            recv_chain = next_chain;
+            self.recv_n += 1;
+        }
+        self.recv_chain = Some(recv_chain);
+        if self.skipped_list.len() > MAX_SKIPPED_KEYS {
+            let overflow = self.skipped_list.len() - MAX_SKIPPED_KEYS;
+            self.skipped_list.drain(..overflow);
+        }
+        Ok(())
+    }
+
+    /// the peer took a new ratchet key, so do we.
+    fn step(&mut self, dh_remote: [u8; 32]) {
+        self.prev_send_n = self.send_n;
+        self.send_n = 0;
+        self.recv_n = 0;
+        self.dh_remote = Some(dh_remote);
+        let (root_key, recv_chain) = kdf_root(&self.root_key, &dh(&self.dh_secret, &dh_remote));
+        self.dh_secret = StaticSecret::random_from_rng(OsRng).to_bytes();
+        let (root_key, send_chain) = kdf_root(&root_key, &dh(&self.dh_secret, &dh_remote));
+        self.root_key = root_key;
+        self.recv_chain = Some(recv_chain);
+        self.send_chain = Some(send_chain);
+    }
+}
@@ -0,0 +1,279 @@
+use std::fmt::{Display, Formatter};
+
+use base64::Engine;
+
+use crate::{
+    identity::{IdentityKeyPair, PreKeyBundle, PreKeyStore},
+    ratchet::{Header, Ratchet},
+    x3dh::{self, PreKeyHeader},
+    Error, Result,
+};
+
+const ENVELOPE_VERSION: u8 = 1;
+const FLAG_PREKEY: u8 = 1;
+const PREKEY_HEADER_LEN: usize = 72;
+const HEADER_LEN: usize = 40;
+
+/// the payload of an encrypted message, url safe base64 of
+/// `version | flags | [prekey header] | header | ciphertext`, so it's stored as text as any
+/// other payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// set until the initiator hears back from the responder.
+    pub prekey_header: Option<PreKeyHeader>,
+    header: Header,
+    ciphertext: Vec<u8>,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().expect("4 bytes"))
+}
+
+fn read_key(bytes: &[u8]) -> [u8; 32] {
+    bytes[..32].try_into().expect("32 bytes")
+}
+
+impl Envelope {
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|e| Error::Malformed(e.to_string()))?;
+        if bytes.len() < 2 || bytes[0] != ENVELOPE_VERSION {
+            return Err(Error::Malformed("unknown version".to_string()));
+        }
+        let has_prekey = bytes[1] & FLAG_PREKEY != 0;
+        let head_len = 2 + if has_prekey { PREKEY_HEADER_LEN } else { 0 } + HEADER_LEN;
+        if bytes.len() < head_len {
+            return Err(Error::Malformed("too short".to_string()));
+        }
+        let mut rest = &bytes[2..];
+        let prekey_header = if has_prekey {
+            let one_time_prekey_id = read_u32(&rest[68..]);
+            let prekey_header = PreKeyHeader {
+                identity_key: read_key(rest),
+                ephemeral_key: read_key(&rest[32..]),
+                signed_prekey_id: read_u32(&rest[64..]),
+                // prekey ids start from 1.
+                one_time_prekey_id: (one_time_prekey_id != 0).then_some(one_time_prekey_id),
+            };
+            rest = &rest[PREKEY_HEADER_LEN..];
+            Some(prekey_header)
+        } else {
+            None
+        };
+        let header = Header {
+            dh: read_key(rest),
+            pn: read_u32(&rest[32..]),
+            n: read_u32(&rest[36..]),
+        };
+        Ok(Self {
+            prekey_header,
+            header,
+            ciphertext: rest[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+impl Display for Envelope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = vec![ENVELOPE_VERSION, 0];
+        if let Some(prekey_header) = self.prekey_header.as_ref() {
+            bytes[1] |= FLAG_PREKEY;
+            bytes.extend_from_slice(&prekey_header.identity_key);
+            bytes.extend_from_slice(&prekey_header.ephemeral_key);
+            bytes.extend_from_slice(&prekey_header.signed_prekey_id.to_be_bytes());
+            bytes.extend_from_slice(&prekey_header.one_time_prekey_id.unwrap_or(0).to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.header.dh);
+        bytes.extend_from_slice(&self.header.pn.to_be_bytes());
+        bytes.extend_from_slice(&self.header.n.to_be_bytes());
+        bytes.extend_from_slice(&self.ciphertext);
+        write!(
+            f,
+            "{}",
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+        )
+    }
+}
+
+/// a conversation with one device of the peer, kept by the client between messages.
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+pub struct Session {
+    ratchet: Ratchet,
+    associated_data: Vec<u8>,
+    prekey_header: Option<PreKeyHeader>,
+    /// the prekey header the session was agreed by, sent or received.
+    #[serde(default)]
+    origin: Option<PreKeyHeader>,
+}
+
+impl Session {
+    /// start a session with the owner of the bundle, it may send right away.
+    pub fn initiate(identity: &IdentityKeyPair, bundle: &PreKeyBundle) -> Result<Self> {
+        let (agreement, prekey_header, signed_prekey) = x3dh::initiate(identity, bundle)?;
+        Ok(Self {
+            ratchet: Ratchet::initiator(agreement.secret, signed_prekey),
+            associated_data: agreement.associated_data,
+            prekey_header: Some(prekey_header.clone()),
+            origin: Some(prekey_header),
+        })
+    }
+
+    /// answer the first message of a session started by the peer, the prekeys it used are taken
+    /// from the store only if the message decrypts.
+    pub fn respond(store: &mut PreKeyStore, envelope: &Envelope) -> Result<(Self, Vec<u8>)> {
+        let prekey_header = envelope
+            .prekey_header
+            .as_ref()
+            .ok_or_else(|| Error::Malformed("no prekey header".to_string()))?;
+        let mut next_store = store.clone();
+        let (agreement, signed_prekey) = x3dh::respond(&mut next_store, prekey_header)?;
+        let mut session = Self {
+            ratchet: Ratchet::responder(agreement.secret, signed_prekey),
+            associated_data: agreement.associated_data,
+            prekey_header: None,
+            origin: Some(prekey_header.clone()),
+        };
+        let plaintext = session.decrypt(envelope)?;
+        *store = next_store;
+        Ok((session, plaintext))
+    }
+
+    /// whether the session was agreed by the prekey header, a message with another one starts a
+    /// new session.
+    pub fn started_from(&self, prekey_header: &PreKeyHeader) -> bool {
+        self.origin.as_ref() == Some(prekey_header)
+    }
+
+    /// the payload to send, see `Envelope`.
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<String> {
+        let (header, ciphertext) = self.ratchet.encrypt(plaintext, &self.associated_data)?;
+        Ok(Envelope {
+            prekey_header: self.prekey_header.clone(),
+            header,
+            ciphertext,
+        }
+        .to_string())
+    }
+
+    pub fn decrypt(&mut self, envelope: &Envelope) -> Result<Vec<u8>> {
+        let plaintext = self.ratchet.decrypt(
+            &envelope.header,
+            &envelope.ciphertext,
+            &self.associated_data,
+        )?;
+        // the peer answered, so it has the session already.
+        self.prekey_header = None;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        identity::{IdentityKeyPair, PreKeyBundle, PreKeyStore},
+        Error,
+    };
+
+    use super::{Envelope, Session};
+
+    fn bundle_of(store: &mut PreKeyStore, with_one_time_prekey: bool) -> PreKeyBundle {
+        let one_time_prekey_list = store.generate_one_time_prekeys(1);
+        let published = store.published_keys(one_time_prekey_list);
+        PreKeyBundle {
+            identity_key: published.identity_key,
+            signing_key: published.signing_key,
+            signed_prekey_id: published.signed_prekey_id,
+            signed_prekey: published.signed_prekey,
+            signature: published.signature,
+            one_time_prekey: published
+                .one_time_prekey_list
+                .into_iter()
+                .next()
+                .filter(|_| with_one_time_prekey),
+        }
+    }
+
+    fn pair(with_one_time_prekey: bool) -> (Session, Session) {
+        let alice = IdentityKeyPair::generate();
+        let mut bob_store = PreKeyStore::new(IdentityKeyPair::generate());
+        let bundle = bundle_of(&mut bob_store, with_one_time_prekey);
+        let mut alice_session = Session::initiate(&alice, &bundle).unwrap();
+        let first = alice_session.encrypt(b"hello").unwrap();
+        let envelope = Envelope::parse(first.as_bytes()).unwrap();
+        let (bob_session, plaintext) = Session::respond(&mut bob_store, &envelope).unwrap();
+        assert_eq!(plaintext, b"hello");
+        (alice_session, bob_session)
+    }
+
+    fn send(from: &mut Session, to: &mut Session, text: &str) {
+        let payload = from.encrypt(text.as_bytes()).unwrap();
+        let plaintext = to
+            .decrypt(&Envelope::parse(payload.as_bytes()).unwrap())
+            .unwrap();
+        assert_eq!(plaintext, text.as_bytes());
+    }
+
+    #[test]
+    fn test_conversation() {
+        for with_one_time_prekey in [true, false] {
+            let (mut alice, mut bob) = pair(with_one_time_prekey);
+            send(&mut bob, &mut alice, "hi");
+            assert!(alice.prekey_header.is_none());
+            send(&mut alice, &mut bob, "how are you");
+            send(&mut alice, &mut bob, "still there?");
+            send(&mut bob, &mut alice, "yes");
+        }
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let (mut alice, mut bob) = pair(true);
+        let payload_list = (0..5)
+            .map(|i| alice.encrypt(format!("{}", i).as_bytes()).unwrap())
+            .collect::<Vec<String>>();
+        for i in [3, 0, 4, 2, 1] {
+            let plaintext = bob
+                .decrypt(&Envelope::parse(payload_list[i].as_bytes()).unwrap())
+                .unwrap();
+            assert_eq!(plaintext, format!("{}", i).as_bytes());
+        }
+        send(&mut bob, &mut alice, "all received");
+    }
+
+    #[test]
+    fn test_tampered() {
+        let (mut alice, mut bob) = pair(true);
+        let mut envelope = Envelope::parse(alice.encrypt(b"secret").unwrap().as_bytes()).unwrap();
+        let last = envelope.ciphertext.len() - 1;
+        envelope.ciphertext[last] ^= 1;
+        assert!(matches!(bob.decrypt(&envelope), Err(Error::Decrypt)));
+        // the failed one leaves the session usable.
+        send(&mut alice, &mut bob, "again");
+    }
+
+    #[test]
+    fn test_replay_and_forged_bundle() {
+        let alice = IdentityKeyPair::generate();
+        let mut bob_store = PreKeyStore::new(IdentityKeyPair::generate());
+        let bundle = bundle_of(&mut bob_store, true);
+        let mut forged = bundle.clone();
+        forged.identity_key = bundle_of(&mut PreKeyStore::new(alice.clone()), false).identity_key;
+        assert!(matches!(
+            Session::initiate(&alice, &forged),
+            Err(Error::BadSignature)
+        ));
+        let mut session = Session::initiate(&alice, &bundle).unwrap();
+        let envelope = Envelope::parse(session.encrypt(b"once").unwrap().as_bytes()).unwrap();
+        let (bob_session, _) = Session::respond(&mut bob_store, &envelope).unwrap();
+        let prekey_header = envelope.prekey_header.as_ref().unwrap();
+        assert!(bob_session.started_from(prekey_header) && session.started_from(prekey_header));
+        let other = Session::initiate(&alice, &bundle_of(&mut bob_store, false)).unwrap();
+        assert!(!bob_session.started_from(other.origin.as_ref().unwrap()));
+        // the one-time prekey is used up.
+        assert!(matches!(
+            Session::respond(&mut bob_store, &envelope),
+            Err(Error::UnknownPreKey(_))
+        ));
+    }
+}
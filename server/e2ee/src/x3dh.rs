@@ -0,0 +1,112 @@
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{
+    identity::{decode, IdentityKeyPair, PreKeyBundle, PreKeyStore},
+    Result,
+};
+
+const INFO: &[u8] = b"prim-x3dh";
+
+/// what the responder needs to derive the same secret, sent along with the first messages.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PreKeyHeader {
+    pub identity_key: [u8; 32],
+    pub ephemeral_key: [u8; 32],
+    pub signed_prekey_id: u32,
+    pub one_time_prekey_id: Option<u32>,
+}
+
+/// the shared secret and the associated data of both identities, initiator's first.
+pub struct Agreement {
+    pub secret: [u8; 32],
+    pub associated_data: Vec<u8>,
+}
+
+fn kdf(dh_list: &[[u8; 32]]) -> [u8; 32] {
+    let mut ikm = vec![0xFF; 32];
+    for dh in dh_list {
+        ikm.extend_from_slice(dh);
+    }
+    let mut secret = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32]), &ikm)
+        .expand(INFO, &mut secret)
+        .expect("32 bytes is a valid hkdf output length");
+    secret
+}
+
+fn associated_data(initiator: &[u8; 32], responder: &[u8; 32]) -> Vec<u8> {
+    [initiator.as_slice(), responder.as_slice()].concat()
+}
+
+/// agree on a secret with the owner of the bundle, the signed prekey of the bundle is the first
+/// ratchet key of the responder.
+pub fn initiate(
+    identity: &IdentityKeyPair,
+    bundle: &PreKeyBundle,
+) -> Result<(Agreement, PreKeyHeader, [u8; 32])> {
+    let signed_prekey = bundle.verified_signed_prekey()?;
+    let responder_identity = decode::<32>(&bundle.identity_key)?;
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let dh_with = |public: &[u8; 32]| {
+        ephemeral
+            .diffie_hellman(&PublicKey::from(*public))
+            .to_bytes()
+    };
+    let mut dh_list = vec![
+        identity.dh(&signed_prekey),
+        dh_with(&responder_identity),
+        dh_with(&signed_prekey),
+    ];
+    let one_time_prekey_id = match bundle.one_time_prekey.as_ref() {
+        Some(one_time_prekey) => {
+            dh_list.push(dh_with(&decode::<32>(&one_time_prekey.key)?));
+            Some(one_time_prekey.id)
+        }
+        None => None,
+    };
+    let identity_key = identity.dh_public();
+    let header = PreKeyHeader {
+        identity_key,
+        ephemeral_key: PublicKey::from(&ephemeral).to_bytes(),
+        signed_prekey_id: bundle.signed_prekey_id,
+        one_time_prekey_id,
+    };
+    let agreement = Agreement {
+        secret: kdf(&dh_list),
+        associated_data: associated_data(&identity_key, &responder_identity),
+    };
+    Ok((agreement, header, signed_prekey))
+}
+
+/// the secret the initiator agreed on, and the secret of the signed prekey it used.
+///
+/// the one-time prekey is taken from the store, the same header can't be answered twice.
+pub fn respond(store: &mut PreKeyStore, header: &PreKeyHeader) -> Result<(Agreement, [u8; 32])> {
+    let signed_prekey = store.signed_prekey_secret(header.signed_prekey_id)?;
+    let one_time_prekey = match header.one_time_prekey_id {
+        Some(id) => Some(store.take_one_time_prekey(id)?),
+        None => None,
+    };
+    let dh_of = |secret: &[u8; 32], public: &[u8; 32]| {
+        StaticSecret::from(*secret)
+            .diffie_hellman(&PublicKey::from(*public))
+            .to_bytes()
+    };
+    let identity = store.identity();
+    let mut dh_list = vec![
+        dh_of(&signed_prekey, &header.identity_key),
+        identity.dh(&header.ephemeral_key),
+        dh_of(&signed_prekey, &header.ephemeral_key),
+    ];
+    if let Some(one_time_prekey) = one_time_prekey {
+        dh_list.push(dh_of(&one_time_prekey, &header.ephemeral_key));
+    }
+    let agreement = Agreement {
+        secret: kdf(&dh_list),
+        associated_data: associated_data(&header.identity_key, &identity.dh_public()),
+    };
+    Ok((agreement, signed_prekey))
+}
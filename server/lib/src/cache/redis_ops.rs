@@ -1,7 +1,8 @@
-use std::{any::Any, net::SocketAddr};
+use std::{any::Any, collections::HashMap, net::SocketAddr};
 
 use crate::{net::GenericParameter, Result};
 
+use ahash::AHashMap;
 use anyhow::anyhow;
 use redis::{FromRedisValue, RedisResult, ToRedisArgs};
 use redis_cluster_async::{Client, Connection};
@@ -101,6 +102,114 @@ impl RedisOps {
         }
     }
 
+    /// add members scored by the counter at `seq_key` in order, both in one script, so once a
+    /// score is read all lower ones are written. a member already there keeps its score.
+    ///
+    /// the two keys must be in the same slot, return the score of the last member.
+    pub async fn push_sort_queue_seq<T: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        seq_key: &str,
+        val_list: &[T],
+    ) -> Result<u64> {
+        let res: RedisResult<u64> = redis::cmd("EVAL")
+            .arg(
+                "local seq = redis.call('INCRBY', KEYS[2], #ARGV) \
+                 for i, val in ipairs(ARGV) do \
+                     redis.call('ZADD', KEYS[1], 'NX', seq - #ARGV + i, val) \
+                 end \
+                 return seq",
+            )
+            .arg(2)
+            .arg(key)
+            .arg(seq_key)
+            .arg(val_list)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    /// add `(key, member, score)` entries in pipelines, one for each slot, so a batch costs
+    /// about one round trip no matter how many keys it touches.
+    pub async fn push_sort_queue_batch<T: ToRedisArgs>(
+        &mut self,
+        entry_list: &[(String, T, f64)],
+    ) -> Result<()> {
+        let mut pipe_map: AHashMap<u16, redis::Pipeline> = AHashMap::new();
+        for (key, val, score) in entry_list.iter() {
+            pipe_map
+                .entry(slot_of(key))
+                .or_insert_with(redis::pipe)
+                .cmd("ZADD")
+                .arg(key)
+                .arg(*score)
+                .arg(val)
+                .ignore();
+        }
+        self.query_by_slot(pipe_map).await
+    }
+
+    /// add `delta` to the `(key, field)` entries of hashes, pipelined like `push_sort_queue_batch`.
+    pub async fn increase_hash_batch<T: ToRedisArgs>(
+        &mut self,
+        entry_list: &[(String, T, i64)],
+    ) -> Result<()> {
+        let mut pipe_map: AHashMap<u16, redis::Pipeline> = AHashMap::new();
+        for (key, field, delta) in entry_list.iter() {
+            pipe_map
+                .entry(slot_of(key))
+                .or_insert_with(redis::pipe)
+                .cmd("HINCRBY")
+                .arg(key)
+                .arg(field)
+                .arg(*delta)
+                .ignore();
+        }
+        self.query_by_slot(pipe_map).await
+    }
+
+    async fn query_by_slot(&self, pipe_map: AHashMap<u16, redis::Pipeline>) -> Result<()> {
+        let task_list = pipe_map.into_values().map(|pipe| {
+            let mut connection = self.connection.clone();
+            async move {
+                let res: RedisResult<()> = pipe.query_async(&mut connection).await;
+                res
+            }
+        });
+        for res in futures::future::join_all(task_list).await {
+            if let Err(e) = res {
+                return Err(anyhow!(e.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn peek_hash<T: FromRedisValue>(&mut self, key: &str) -> Result<T> {
+        let res: RedisResult<T> = redis::cmd("HGETALL")
+            .arg(key)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    pub async fn remove_hash<T: ToRedisArgs>(&mut self, key: &str, field: &T) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("HDEL")
+            .arg(key)
+            .arg(field)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
     pub async fn peek_sort_queue<T: FromRedisValue>(&mut self, key: &str) -> Result<T> {
         let res: RedisResult<T> = redis::cmd("ZREVRANGEBYSCORE")
             .arg(key)
@@ -235,6 +344,32 @@ impl RedisOps {
         }
     }
 
+    /// keep the `keep` members with the highest scores.
+    pub async fn trim_sort_queue(&mut self, key: &str, keep: usize) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("ZREMRANGEBYRANK")
+            .arg(key)
+            .arg(0)
+            .arg(-(keep as i64) - 1)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    pub async fn expire(&mut self, key: &str, exp: std::time::Duration) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("PEXPIRE")
+            .arg(key)
+            .arg(exp.as_millis() as u64)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
     pub async fn remove_sort_queue_data(&mut self, key: &str, score: f64) -> Result<()> {
         let res: RedisResult<()> = redis::cmd("ZREMRANGEBYSCORE")
             .arg(key)
@@ -248,6 +383,35 @@ impl RedisOps {
         }
     }
 
+    /// none if the member is not in the sorted set.
+    pub async fn get_sort_queue_score<T: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        val: &T,
+    ) -> Result<Option<f64>> {
+        let res: RedisResult<Option<f64>> = redis::cmd("ZSCORE")
+            .arg(key)
+            .arg(val)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    pub async fn remove_sort_queue_member<T: ToRedisArgs>(&mut self, key: &str, val: &T) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("ZREM")
+            .arg(key)
+            .arg(val)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
     pub async fn push_set<T: ToRedisArgs>(&mut self, key: &str, val: &T) -> Result<()> {
         let res: RedisResult<()> = redis::cmd("SADD")
             .arg(key)
@@ -260,6 +424,29 @@ impl RedisOps {
         }
     }
 
+    pub async fn remove_set<T: ToRedisArgs>(&mut self, key: &str, val: &T) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("SREM")
+            .arg(key)
+            .arg(val)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    pub async fn peek_set<T: FromRedisValue>(&mut self, key: &str) -> Result<T> {
+        let res: RedisResult<T> = redis::cmd("SMEMBERS")
+            .arg(key)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(v) => Ok(v),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
     pub async fn clear_set(&mut self, key: &str) -> RedisResult<()> {
         let res: RedisResult<()> = redis::cmd("DEL")
             .arg(key)
@@ -271,6 +458,184 @@ impl RedisOps {
         }
     }
 
+    /// push to the head of a list and keep only the latest `max_len` elements.
+    pub async fn push_capped_list<T: ToRedisArgs>(
+        &mut self,
+        key: &str,
+        val: &T,
+        max_len: usize,
+    ) -> Result<()> {
+        let res: RedisResult<()> = redis::pipe()
+            .cmd("LPUSH")
+            .arg(key)
+            .arg(val)
+            .ignore()
+            .cmd("LTRIM")
+            .arg(key)
+            .arg(0)
+            .arg(max_len as i64 - 1)
+            .ignore()
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    /// append an entry to a stream, the stream is trimmed to about `max_len` entries.
+    pub async fn stream_add(
+        &mut self,
+        key: &str,
+        fields: &[(&str, &[u8])],
+        max_len: usize,
+    ) -> Result<()> {
+        let mut cmd = redis::cmd("XADD");
+        cmd.arg(key).arg("MAXLEN").arg("~").arg(max_len).arg("*");
+        for (field, value) in fields {
+            cmd.arg(*field).arg(*value);
+        }
+        let res: RedisResult<String> = cmd.query_async(&mut self.connection).await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    /// create the consumer group reading the stream from the beginning, it's fine if it exists.
+    pub async fn stream_create_group(&mut self, key: &str, group: &str) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(key)
+            .arg(group)
+            .arg("0")
+            .arg("MKSTREAM")
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) if e.code() == Some("BUSYGROUP") => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    /// read entries after `id` for the consumer, `>` for new entries and `0` for entries delivered
+    /// but not acknowledged. return pairs of the entry id and its flattened fields.
+    pub async fn stream_read_group(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        id: &str,
+        count: usize,
+        block: std::time::Duration,
+    ) -> Result<Vec<(String, Vec<Vec<u8>>)>> {
+        // nil on timeout, or pairs of the stream key and its entries.
+        type Reply = Option<Vec<(String, Vec<(String, Vec<Vec<u8>>)>)>>;
+        let res: RedisResult<Reply> = redis::cmd("XREADGROUP")
+            .arg("GROUP")
+            .arg(group)
+            .arg(consumer)
+            .arg("COUNT")
+            .arg(count)
+            .arg("BLOCK")
+            .arg(block.as_millis() as u64)
+            .arg("STREAMS")
+            .arg(key)
+            .arg(id)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(Some(mut streams)) => Ok(streams
+                .pop()
+                .map(|(_, entries)| entries)
+                .unwrap_or_default()),
+            Ok(None) => Ok(vec![]),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
+    /// claim entries pending on other consumers for at least `min_idle`, from `start` on,
+    /// return the id to continue from, `0-0` when all are scanned, and the ids claimed.
+    pub async fn stream_auto_claim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: std::time::Duration,
+        start: &str,
+        count: usize,
+    ) -> Result<(String, Vec<String>)> {
+        // the next start, the ids claimed and since redis 7.0 the ids of deleted entries.
+        let res: RedisResult<Vec<redis::Value>> = redis::cmd("XAUTOCLAIM")
+            .arg(key)
+            .arg(group)
+            .arg(consumer)
+            .arg(min_idle.as_millis() as u64)
+            .arg(start)
+            .arg("COUNT")
+            .arg(count)
+            .arg("JUSTID")
+            .query_async(&mut self.connection)
+            .await;
+        let reply = match res {
+            Ok(reply) => reply,
+            Err(e) => return Err(anyhow!(e.to_string())),
+        };
+        if reply.len() < 2 {
+            return Err(anyhow!("unexpected reply of XAUTOCLAIM"));
+        }
+        let next: String =
+            redis::from_redis_value(&reply[0]).map_err(|e| anyhow!(e.to_string()))?;
+        let id_list: Vec<String> =
+            redis::from_redis_value(&reply[1]).map_err(|e| anyhow!(e.to_string()))?;
+        Ok((next, id_list))
+    }
+
+    /// entries of the stream the group has not acknowledged, delivered or not, None if redis
+    /// can't tell, which is the case before redis 7.0 or after the stream is trimmed.
+    pub async fn stream_group_backlog(&mut self, key: &str, group: &str) -> Result<Option<u64>> {
+        let res: RedisResult<Vec<HashMap<String, redis::Value>>> = redis::cmd("XINFO")
+            .arg("GROUPS")
+            .arg(key)
+            .query_async(&mut self.connection)
+            .await;
+        let group_list = match res {
+            Ok(group_list) => group_list,
+            Err(e) => return Err(anyhow!(e.to_string())),
+        };
+        for info in group_list {
+            let name: Option<String> = info
+                .get("name")
+                .and_then(|name| redis::from_redis_value(name).ok());
+            if name.as_deref() != Some(group) {
+                continue;
+            }
+            let pending: Option<u64> = info
+                .get("pending")
+                .and_then(|pending| redis::from_redis_value(pending).ok());
+            // nil when unknown.
+            let lag: Option<u64> = info
+                .get("lag")
+                .and_then(|lag| redis::from_redis_value(lag).ok());
+            return Ok(pending.zip(lag).map(|(pending, lag)| pending + lag));
+        }
+        Err(anyhow!("group {} of {} not found", group, key))
+    }
+
+    pub async fn stream_ack(&mut self, key: &str, group: &str, id_list: &[String]) -> Result<()> {
+        let res: RedisResult<()> = redis::cmd("XACK")
+            .arg(key)
+            .arg(group)
+            .arg(id_list)
+            .query_async(&mut self.connection)
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(anyhow!(e.to_string())),
+        }
+    }
+
     pub async fn atomic_increment(&mut self, key: &str) -> Result<u64> {
         let res: RedisResult<u64> = redis::cmd("INCR")
             .arg(key)
@@ -327,9 +692,23 @@ impl GenericParameter for RedisOps {
     }
 }
 
+/// the cluster slot of a key, a pipeline is routed as a whole, so its keys must share one.
+fn slot_of(key: &str) -> u16 {
+    let key = key.as_bytes();
+    // only the part inside the first non-empty `{...}` is hashed.
+    let hashed = match key.iter().position(|b| *b == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|b| *b == b'}') {
+            Some(close) if close > 0 => &key[open + 1..open + 1 + close],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16::State::<crc16::XMODEM>::calculate(hashed) % 16384
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::cache::redis_ops::RedisOps;
+    use crate::cache::redis_ops::{slot_of, RedisOps};
     use crate::Result;
     use std::net::SocketAddr;
 
@@ -352,4 +731,12 @@ mod tests {
         println!("{:?}", res);
         Ok(())
     }
+
+    #[test]
+    fn test_slot_of() {
+        assert_eq!(slot_of("123456789"), 12739);
+        assert_eq!(slot_of("foo"), 12182);
+        assert_eq!(slot_of("{user1000}.following"), slot_of("user1000"));
+        assert_ne!(slot_of("{}foo"), slot_of("foo"));
+    }
 }
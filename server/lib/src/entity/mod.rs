@@ -8,6 +8,23 @@ pub const EXTENSION_THRESHOLD: usize = 1 << 6 - 1;
 pub const PAYLOAD_THRESHOLD: usize = 1 << 14 - 1;
 /// user_id lager than(also equal) this value is considered as a group
 pub const GROUP_ID_THRESHOLD: u64 = 1 << 36;
+/// in seconds, the longest time to live of a message or a conversation.
+pub const MAX_MSG_TTL: u64 = 30 * 24 * 60 * 60;
+/// the most users a message mentions, so the mentions, the real sender and a quote fit in an
+/// extension, which is shorter than 64 bytes, `m=all` mentions more.
+pub const MAX_MENTION: usize = 3;
+/// the payload `scheduler` replies a `NodeHeartbeat` or `NodeLoadReport` with when it doesn't
+/// know the node(anymore), the node should authenticate and register again.
+pub const REGISTER_AGAIN: &[u8] = b"register";
+/// set in the version of a message whose payload is end-to-end encrypted, servers carry it as it
+/// is. the highest bit kept by the `smallint` version of `msg.message`.
+pub const ENCRYPTED_FLAG: u32 = 1 << 14;
+/// the first byte of a `MessageForward` reply carrying the cause a message is invalid for,
+/// forwarding it again won't help. an accepted message is replied with an empty payload.
+pub const FORWARD_REFUSED: u8 = 1;
+/// the first byte of a `MessageForward` reply carrying the cause a message failed for, it may
+/// be forwarded again.
+pub const FORWARD_FAILED: u8 = 2;
 
 #[derive(
     serde::Serialize,
@@ -39,6 +56,14 @@ pub enum Type {
     /// control message part
     Edit = 64,
     Withdraw = 65,
+    /// read receipt, the seqnum is the last one read in the conversation with the receiver.
+    Read = 66,
+    /// the seqnum is the message reacted to and the payload the emoji, sending it again takes
+    /// the reaction back.
+    Reaction = 67,
+    /// the seqnum is the message expired by its time to live, clients delete it, sent by the
+    /// api only.
+    Expire = 68,
 
     /// the below types are used for user and server's communication.
     ///
@@ -119,6 +144,20 @@ pub enum ReqwestResourceID {
     MessageConfigHotReload = 16,
     AssignMQProcessor = 17,
     UnassignMQProcessor = 18,
+    /// use for nodes to report their load to `scheduler` periodically.
+    NodeLoadReport = 19,
+    /// use for `scheduler` to notify message nodes that a peer has crashed.
+    MessageNodeCrash = 20,
+    /// raft rpc between `scheduler` nodes, used for replicating the node registry.
+    RaftRequestVote = 21,
+    RaftAppendEntries = 22,
+    /// use for a `scheduler` follower to forward a registry change to the leader.
+    RaftPropose = 23,
+    /// use for nodes to tell `scheduler` they are alive, `Ping` is answered by the transport
+    /// itself and never reaches `scheduler`.
+    NodeHeartbeat = 24,
+    /// raft rpc from the leader `scheduler` to a follower lagging behind its compacted log.
+    RaftInstallSnapshot = 25,
 }
 
 /// a reqwest's layout may look like:
@@ -153,19 +192,31 @@ pub enum ServerType {
     MsgprocessorCluster,
 }
 
+/// the pairs are (capacity, usage), rates are measured between two samples.
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
 pub struct ServerLoad {
+    /// (cpu number, global usage in percent)
     pub cpu: (u32, f32),
+    /// (total memory in MiB, usage in percent)
     pub mem: (u32, f32),
+    /// (network interface number, throughput in KiB/s)
     pub net: (u32, f32),
+    /// (total disk space in GiB, usage in percent)
     pub disk: (u32, f32),
+    /// threads of the node process
     pub thread_num: u32,
+    /// processes of the host
     pub process_num: u32,
+    /// resident memory of the node process in MiB
     pub physical_mem: f32,
+    /// virtual memory of the node process in MiB
     pub virtual_mem: f32,
+    /// swap usage in percent
     pub swap_disk: f32,
+    /// in KiB/s, of the node process
     pub disk_write: u32,
     pub disk_read: u32,
+    /// in KiB/s, of the host
     pub net_write: u32,
     pub net_read: u32,
 }
@@ -181,3 +232,47 @@ pub struct ServerInfo {
     pub typ: ServerType,
     pub load: Option<ServerLoad>,
 }
+
+/// payload of `NodeAuth` sent to `scheduler`, the token is issued by `scheduler --issue_join_token`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeAuthInfo {
+    pub info: ServerInfo,
+    pub token: String,
+}
+
+/// the extension of Text, Image and File messages, `;` separated pairs of `s=<sender>`,
+/// `q=<seqnum>`, `t=<seqnum>`, `m=<user id>,<user id>` or `m=all` and `e=<seconds>`, a bare
+/// number is the sender alone as older clients write. mentions follow `check_mention`.
+///
+/// quoted messages and thread roots are always in the conversation of the message itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MsgExtension {
+    /// the real sender of a group message, which members receive as sent by the group.
+    pub sender: Option<u64>,
+    /// the message quoted, the reply is shown in the main timeline.
+    pub quote: Option<u64>,
+    /// root of the thread replied in, thread replies stay off the main timeline.
+    pub thread_root: Option<u64>,
+    /// group members mentioned, they are notified even if they mute the group.
+    pub mention_list: Vec<u64>,
+    /// `@all`, only group admins may mention everyone.
+    pub mention_all: bool,
+    /// seconds the message lives after it's sent, the message node writes that of the
+    /// conversation in if the sender gives none.
+    pub ttl: Option<u64>,
+}
+
+/// a change all devices of a user converge on, kept in `USER_SYNC_` in the order it is written.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SyncEvent {
+    /// a new, edited, withdrawn or expired message or a reaction, the others keep the seqnum
+    /// they change.
+    Msg { msg: Msg },
+    /// the conversation with the peer is read up to the seqnum.
+    Read { peer_id: u64, seqnum: u64 },
+    JoinGroup { group_id: u64 },
+    LeaveGroup { group_id: u64 },
+    /// events before this one are dropped, it's not a change itself.
+    Floor,
+}
@@ -12,7 +12,10 @@ use rusqlite::{types::ToSqlOutput, ToSql};
 use crate::{Result, util::timestamp};
 
 
-use super::{Head, Msg, ReqwestMsg, ReqwestResourceID, Type, HEAD_LEN};
+use super::{
+    Head, Msg, MsgExtension, ReqwestMsg, ReqwestResourceID, Type, ENCRYPTED_FLAG,
+    GROUP_ID_THRESHOLD, HEAD_LEN, MAX_MENTION,
+};
 
 pub(self) const BIT_MASK_LEFT_46: u64 = 0xFFFF_C000_0000_0000;
 pub(self) const BIT_MASK_RIGHT_46: u64 = 0x0000_3FFF_FFFF_FFFF;
@@ -74,6 +77,9 @@ impl Display for Type {
                 Type::Audio => "Audio",
                 Type::Edit => "Edit",
                 Type::Withdraw => "Withdraw",
+                Type::Read => "Read",
+                Type::Reaction => "Reaction",
+                Type::Expire => "Expire",
                 Type::Auth => "Auth",
                 Type::Ping => "Ping",
                 Type::Echo => "Echo",
@@ -525,6 +531,22 @@ impl Msg {
         Head::set_version(self.as_mut_slice(), version);
     }
 
+    /// the payload is an opaque ciphertext only the peer can read.
+    #[inline]
+    pub fn encrypted(&self) -> bool {
+        self.version() & ENCRYPTED_FLAG != 0
+    }
+
+    #[inline]
+    pub fn set_encrypted(&mut self, encrypted: bool) {
+        let version = if encrypted {
+            self.version() | ENCRYPTED_FLAG
+        } else {
+            self.version() & !ENCRYPTED_FLAG
+        };
+        self.set_version(version);
+    }
+
     #[inline]
     pub fn extension(&self) -> &[u8] {
         let extension_length = self.extension_length();
@@ -594,6 +616,18 @@ impl Msg {
         true
     }
 
+    /// unlike `set_extension`, the new extension can be of any length the head can hold.
+    pub fn replace_extension(&mut self, extension: &[u8]) -> bool {
+        if extension.len() >= 1 << 6 {
+            return false;
+        }
+        let payload_length = self.payload_length();
+        self.0.truncate(HEAD_LEN + payload_length);
+        self.0.extend_from_slice(extension);
+        self.set_extension_length(extension.len());
+        true
+    }
+
     #[inline]
     pub fn ping(sender: u64, receiver: u64, node_id: u32) -> Self {
         let inner_head = InnerHead {
@@ -1109,6 +1143,13 @@ impl Display for ReqwestResourceID {
                 ReqwestResourceID::MessageConfigHotReload => "MessageConfigHotReload",
                 ReqwestResourceID::AssignMQProcessor => "AssignMQProcessor",
                 ReqwestResourceID::UnassignMQProcessor => "UnassignMQProcessor",
+                ReqwestResourceID::NodeLoadReport => "NodeLoadReport",
+                ReqwestResourceID::MessageNodeCrash => "MessageNodeCrash",
+                ReqwestResourceID::RaftRequestVote => "RaftRequestVote",
+                ReqwestResourceID::RaftAppendEntries => "RaftAppendEntries",
+                ReqwestResourceID::RaftPropose => "RaftPropose",
+                ReqwestResourceID::NodeHeartbeat => "NodeHeartbeat",
+                ReqwestResourceID::RaftInstallSnapshot => "RaftInstallSnapshot",
             }
         )
     }
@@ -1121,11 +1162,107 @@ impl ReqwestResourceID {
     }
 }
 
+impl MsgExtension {
+    /// pairs unknown or broken are ignored.
+    pub fn parse(extension: &[u8]) -> Self {
+        let extension = String::from_utf8_lossy(extension);
+        let mut res = Self::default();
+        if let Ok(sender) = extension.parse::<u64>() {
+            res.sender = Some(sender);
+            return res;
+        }
+        for pair in extension.split(';') {
+            match pair.split_once('=') {
+                Some(("s", v)) => res.sender = v.parse().ok(),
+                Some(("q", v)) => res.quote = v.parse().ok(),
+                Some(("t", v)) => res.thread_root = v.parse().ok(),
+                Some(("e", v)) => res.ttl = v.parse().ok(),
+                Some(("m", "all")) => res.mention_all = true,
+                Some(("m", v)) => {
+                    res.mention_list = v.split(',').filter_map(|id| id.parse().ok()).collect()
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+
+    /// `@all` mentions every member.
+    pub fn mentions(&self, user_id: u64) -> bool {
+        self.mention_all || self.mention_list.contains(&user_id)
+    }
+
+    /// the members of the group are only needed to check mentions if there are some.
+    pub fn has_mention(&self) -> bool {
+        self.mention_all || !self.mention_list.is_empty()
+    }
+
+    /// the mentions `sender` may make in `receiver`: at most `MAX_MENTION` users, all members of
+    /// the group, and `@all` by admins only. `user_list` holds every member, admins included,
+    /// the error is the cause to show the sender.
+    pub fn check_mention(
+        &self,
+        sender: u64,
+        receiver: u64,
+        user_list: &[u64],
+        admin_list: &[u64],
+    ) -> std::result::Result<(), String> {
+        if !self.has_mention() {
+            return Ok(());
+        }
+        if self.mention_list.len() > MAX_MENTION {
+            return Err(format!(
+                "at most {} users can be mentioned, mention all instead.",
+                MAX_MENTION
+            ));
+        }
+        if receiver < GROUP_ID_THRESHOLD
+            || self.mention_all && !admin_list.contains(&sender)
+            || !self
+                .mention_list
+                .iter()
+                .all(|user_id| user_list.contains(user_id))
+        {
+            return Err("invalid mention.".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Display for MsgExtension {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut pair_list = vec![];
+        if let Some(sender) = self.sender {
+            pair_list.push(format!("s={}", sender));
+        }
+        if let Some(quote) = self.quote {
+            pair_list.push(format!("q={}", quote));
+        }
+        if let Some(thread_root) = self.thread_root {
+            pair_list.push(format!("t={}", thread_root));
+        }
+        if self.mention_all {
+            pair_list.push("m=all".to_string());
+        } else if !self.mention_list.is_empty() {
+            let id_list = self
+                .mention_list
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>();
+            pair_list.push(format!("m={}", id_list.join(",")));
+        }
+        if let Some(ttl) = self.ttl {
+            pair_list.push(format!("e={}", ttl));
+        }
+        write!(f, "{}", pair_list.join(";"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Read;
 
-    use crate::entity::{msg::InnerHead, Head, Msg, Type};
+    use crate::entity::{msg::InnerHead, Head, Msg, MsgExtension, Type};
 
     #[test]
     fn test() {
@@ -1177,4 +1314,55 @@ mod tests {
         let msg = Msg::text(1, 2, 3, "一只狗");
         println!("{:?}", msg.as_bytes());
     }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(MsgExtension::parse(b"123").sender, Some(123));
+        let extension = MsgExtension {
+            sender: Some(1 << 45),
+            quote: Some(1 << 49),
+            thread_root: Some(1 << 49),
+            mention_list: vec![8, 9],
+            mention_all: false,
+            ttl: Some(30),
+        };
+        let bytes = extension.to_string();
+        assert!(bytes.len() < 1 << 6);
+        assert_eq!(MsgExtension::parse(bytes.as_bytes()), extension);
+        assert!(extension.mentions(9) && !extension.mentions(1 << 45));
+        assert_eq!(MsgExtension::parse(b"x=1;q=2").quote, Some(2));
+        assert!(MsgExtension::parse(b"s=1;m=all").mentions(1 << 45));
+        let group = 1 << 40;
+        let mention = MsgExtension::parse(b"m=8,9");
+        assert!(mention.has_mention() && !MsgExtension::parse(b"s=1").has_mention());
+        assert!(mention.check_mention(1, group, &[1, 8, 9], &[]).is_ok());
+        assert!(mention.check_mention(1, group, &[1, 8], &[]).is_err());
+        assert!(mention.check_mention(1, 2, &[1, 8, 9], &[]).is_err());
+        assert!(MsgExtension::parse(b"s=1").check_mention(1, 2, &[], &[]).is_ok());
+        let mention_all = MsgExtension::parse(b"m=all");
+        assert!(mention_all.check_mention(1, group, &[1], &[1]).is_ok());
+        assert!(mention_all.check_mention(2, group, &[1, 2], &[1]).is_err());
+        let too_many = MsgExtension::parse(b"m=1,2,3,4");
+        assert!(too_many
+            .check_mention(1, group, &[1, 2, 3, 4], &[1])
+            .unwrap_err()
+            .starts_with("at most"));
+        let mut msg = Msg::text(1, 2, 3, "text");
+        assert!(msg.replace_extension(b"q=7"));
+        assert_eq!(msg.payload(), b"text");
+        assert_eq!(MsgExtension::parse(msg.extension()).quote, Some(7));
+        assert!(!msg.replace_extension(&[b'0'; 64]));
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let mut msg = Msg::text(1, 2, 3, "ciphertext");
+        msg.set_version(3);
+        assert!(!msg.encrypted());
+        msg.set_encrypted(true);
+        assert!(msg.encrypted());
+        assert_eq!(msg.version() as i16 as u32, msg.version());
+        msg.set_encrypted(false);
+        assert_eq!(msg.version(), 3);
+    }
 }
@@ -1,6 +1,6 @@
 use std::fmt::{Display, Formatter};
 use tracing::error;
-use crate::entity::{ServerInfo, ServerLoad, ServerStatus, ServerType};
+use crate::entity::{NodeAuthInfo, ServerInfo, ServerLoad, ServerStatus, ServerType};
 
 impl Display for ServerStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -106,6 +106,35 @@ impl ServerInfo {
     }
 }
 
+impl From<&[u8]> for NodeAuthInfo {
+    fn from(value: &[u8]) -> Self {
+        let res: serde_json::Result<NodeAuthInfo> = serde_json::from_slice(value);
+        match res {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to deserialize NodeAuthInfo from bytes: {}", e);
+                NodeAuthInfo {
+                    info: ServerInfo::default(),
+                    token: String::new(),
+                }
+            }
+        }
+    }
+}
+
+impl NodeAuthInfo {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let result = serde_json::to_vec(self);
+        match result {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to serialize NodeAuthInfo to bytes: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::entity::ServerInfo;
@@ -70,10 +70,66 @@ pub fn verify_token(token: &str, key: &[u8], audience: u64) -> anyhow::Result<()
     Ok(())
 }
 
+/// claims of a token allowing a node to join the cluster through `scheduler`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct JoinClaims {
+    /// Expiration time (as UTC timestamp in seconds)
+    exp: u64,
+    iat: u64,
+    iss: String,
+    /// kind of the node: `message`, `seqnum` or `msgprocessor`.
+    sub: String,
+    /// the only node id allowed to join by this token, 0 for any id of the kind.
+    node_id: u32,
+}
+
+/// issue a join token, signed by the secret shared with `scheduler`.
+#[inline]
+pub fn join_token(key: &[u8], kind: &str, node_id: u32, ttl_secs: u64) -> String {
+    let t = timestamp() / 1000;
+    encode(
+        &Header::default(),
+        &JoinClaims {
+            exp: t + ttl_secs,
+            iat: t,
+            iss: "PRIM".to_string(),
+            sub: kind.to_string(),
+            node_id,
+        },
+        &EncodingKey::from_secret(key),
+    )
+    .unwrap()
+}
+
+/// verify signature and expiration of a join token, return the node kind and node id it's bound to.
+#[inline]
+pub fn verify_join_token(token: &str, key: &[u8]) -> Result<(String, u32)> {
+    let res = decode::<JoinClaims>(token, &DecodingKey::from_secret(key), &Validation::default())?;
+    if res.claims.iss != "PRIM" {
+        return Err(anyhow!("invalid token"));
+    }
+    Ok((res.claims.sub, res.claims.node_id))
+}
+
 #[cfg(test)]
 mod tests {
     use base64::Engine;
 
+    use super::{join_token, verify_join_token};
+
+    #[test]
+    fn test_join_token() {
+        let token = join_token(b"secret", "message", 3, 60);
+        assert_eq!(
+            verify_join_token(&token, b"secret").unwrap(),
+            ("message".to_string(), 3)
+        );
+        assert!(verify_join_token(&token, b"another").is_err());
+        let mut tampered = token.clone();
+        tampered.push('x');
+        assert!(verify_join_token(&tampered, b"secret").is_err());
+    }
+
 
     #[test]
     fn test() {
@@ -1,8 +1,11 @@
 pub mod jwt;
 pub mod map;
+pub mod ring;
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::entity::GROUP_ID_THRESHOLD;
+
 #[allow(unused)]
 #[inline]
 pub fn timestamp() -> u64 {
@@ -24,6 +27,16 @@ pub fn who_we_are(id1: u64, id2: u64) -> String {
     }
 }
 
+/// the conversation a message belongs to, all members of a group share one conversation.
+#[inline]
+pub fn conversation_id(sender: u64, receiver: u64) -> String {
+    if receiver >= GROUP_ID_THRESHOLD {
+        who_we_are(receiver, receiver)
+    } else {
+        who_we_are(sender, receiver)
+    }
+}
+
 #[allow(unused)]
 #[inline]
 pub fn salt(length: usize) -> String {
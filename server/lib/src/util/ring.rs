@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// a consistent-hash ring with virtual nodes and weights.
+///
+/// every node owns `virtual_nodes * weight` points on the ring, a key belongs to the first point
+/// clockwise from its hash. the hash is deterministic so every scheduler computes the same placement,
+/// and a node joining or leaving only moves the keys adjacent to its own points.
+///
+/// points are keyed by `(hash, node_id)`, so two nodes hashed to the same point both keep it and
+/// the smaller node id comes first, whatever order they were inserted in.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    virtual_nodes: u32,
+    points: BTreeMap<(u64, u32), u32>,
+    weights: HashMap<u32, u32>,
+}
+
+impl HashRing {
+    pub fn new(virtual_nodes: u32) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            points: BTreeMap::new(),
+            weights: HashMap::new(),
+        }
+    }
+
+    /// add a node, or re-weight it if it is already on the ring.
+    pub fn insert(&mut self, node_id: u32, weight: u32) {
+        let weight = weight.max(1);
+        if self.weights.get(&node_id) == Some(&weight) {
+            return;
+        }
+        self.remove(node_id);
+        for i in 0..self.virtual_nodes * weight {
+            let point = hash(&(((node_id as u64) << 32) | i as u64).to_be_bytes());
+            self.points.insert((point, node_id), node_id);
+        }
+        self.weights.insert(node_id, weight);
+    }
+
+    pub fn remove(&mut self, node_id: u32) {
+        if self.weights.remove(&node_id).is_some() {
+            self.points.retain(|_, owner| *owner != node_id);
+        }
+    }
+
+    pub fn contains(&self, node_id: u32) -> bool {
+        self.weights.contains_key(&node_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.weights.keys().copied()
+    }
+
+    /// find the node which owns `key`.
+    pub fn get(&self, key: u64) -> Option<u32> {
+        self.get_filtered(key, |_| true)
+    }
+
+    /// find the first node clockwise from `key` that satisfies `filter`,
+    /// used for skipping nodes which can not accept new keys for now.
+    pub fn get_filtered<F: Fn(u32) -> bool>(&self, key: u64, filter: F) -> Option<u32> {
+        let point = hash(&key.to_be_bytes());
+        self.points
+            .range((point, 0)..)
+            .chain(self.points.range(..(point, 0)))
+            .map(|(_, node_id)| *node_id)
+            .find(|node_id| filter(*node_id))
+    }
+}
+
+/// fnv-1a with a final avalanche step, stable across processes and versions.
+#[inline]
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashRing;
+
+    #[test]
+    fn test_placement() {
+        let mut ring1 = HashRing::new(64);
+        let mut ring2 = HashRing::new(64);
+        for id in [1, 2, 3, 4] {
+            ring1.insert(id, 1);
+        }
+        for id in [4, 3, 2, 1] {
+            ring2.insert(id, 1);
+        }
+        for user_id in 0..10000u64 {
+            assert_eq!(ring1.get(user_id), ring2.get(user_id));
+        }
+        // only keys owned by the new node should move.
+        let before: Vec<u32> = (0..10000u64).map(|k| ring1.get(k).unwrap()).collect();
+        ring1.insert(5, 1);
+        for (k, node_id) in before.iter().enumerate() {
+            let now = ring1.get(k as u64).unwrap();
+            assert!(now == *node_id || now == 5);
+        }
+        ring1.remove(5);
+        let after: Vec<u32> = (0..10000u64).map(|k| ring1.get(k).unwrap()).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_weight() {
+        let mut ring = HashRing::new(128);
+        ring.insert(1, 1);
+        ring.insert(2, 3);
+        let heavy = (0..40000u64).filter(|k| ring.get(*k) == Some(2)).count();
+        assert!(heavy > 25000 && heavy < 35000);
+        assert_eq!(ring.get_filtered(7, |id| id != 2), Some(1));
+        assert_eq!(HashRing::new(8).get(7), None);
+    }
+
+    #[test]
+    fn test_collision() {
+        let point = super::hash(&7u64.to_be_bytes());
+        let mut ring1 = HashRing::new(1);
+        let mut ring2 = HashRing::new(1);
+        for id in [1, 2] {
+            ring1.points.insert((point, id), id);
+            ring1.weights.insert(id, 1);
+        }
+        for id in [2, 1] {
+            ring2.points.insert((point, id), id);
+            ring2.weights.insert(id, 1);
+        }
+        assert_eq!(ring1.get(7), Some(1));
+        assert_eq!(ring2.get(7), Some(1));
+        assert_eq!(ring1.get_filtered(7, |id| id != 1), Some(2));
+        ring1.remove(1);
+        assert_eq!(ring1.get(7), Some(2));
+    }
+}
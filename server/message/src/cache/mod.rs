@@ -27,5 +27,35 @@ pub(crate) static NODE_ID: &str = "NODE_ID_MESSAGE_";
 #[allow(unused)]
 pub(crate) static SEQ_NUM: &str = "SEQ_NUM_";
 pub(crate) static MSG_CACHE: &str = "MSG_CACHE_";
+/// reaction toggles of a conversation not surely saved by `msgprocessor` yet, as
+/// `<seqnum>:<user id>:<time>:<emoji in base64>` scored by the time, so the reactions read from
+/// the database are brought up to date.
+pub(crate) static PENDING_REACTION: &str = "PENDING_REACTION_";
 pub(crate) static LAST_ONLINE_TIME: &str = "LAST_ONLINE_TIME_";
 pub(crate) static USER_INBOX: &str = "USER_INBOX_";
+/// peers and groups a user has talked in, scored by the time of the latest message.
+pub(crate) static USER_CONVERSATION: &str = "USER_CONVERSATION_";
+/// `SyncEvent` of a user in json, scored by `USER_SYNC_SEQ_` in the order they are written.
+pub(crate) static USER_SYNC: &str = "USER_SYNC_";
+/// the counter scoring `USER_SYNC_` of a user, see `sync_seq_key`.
+pub(crate) static USER_SYNC_SEQ: &str = "USER_SYNC_SEQ_";
+/// the counter of `USER_SYNC_` at a time, scored by the time, to trim events by their age.
+pub(crate) static USER_SYNC_MARK: &str = "USER_SYNC_MARK_";
+/// `<user id>:<score>` of the `USER_SYNC_` events holding messages with a time to live, scored by
+/// the time they expire, the api removes the events then.
+pub(crate) static SYNC_EXPIRE: &str = "SYNC_EXPIRE";
+/// seconds the messages sent to the conversation live, mirrors `msg.conversation_ttl` for the
+/// message node to write it in the extension.
+pub(crate) static CONVERSATION_TTL: &str = "CONVERSATION_TTL_";
+pub(crate) static LAST_READ: &str = "LAST_READ_";
+/// hash of peer id to the number of messages from the peer the user hasn't read.
+pub(crate) static UNREAD_COUNT: &str = "UNREAD_COUNT_";
+/// the message node a user is connected to, removed when the connection closes.
+pub(crate) static USER_ONLINE: &str = "USER_ONLINE_";
+
+/// `USER_SYNC_SEQ_{USER_SYNC_<user id>}`, hash tagged to be in the slot of `USER_SYNC_` of the
+/// user, they are written by one script.
+#[inline]
+pub(crate) fn sync_seq_key(user_id: u64) -> String {
+    format!("{}{{{}{}}}", USER_SYNC_SEQ, USER_SYNC, user_id)
+}
@@ -2,7 +2,12 @@ use std::sync::Arc;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
-use lib::{entity::Msg, error::HandlerError, net::InnerStates, Result};
+use lib::{
+    entity::{Msg, Type},
+    error::HandlerError,
+    net::InnerStates,
+    Result,
+};
 use lib_net_tokio::net::Handler;
 use tracing::debug;
 
@@ -20,7 +25,8 @@ pub(crate) struct Text;
 impl Handler for Text {
     async fn run(&self, msg: &mut Arc<Msg>, inner_states: &mut InnerStates) -> Result<Msg> {
         let type_value = msg.typ().value();
-        if type_value < 32 || type_value >= 64 {
+        // reactions are delivered the same way, only they take no place in the timeline.
+        if (type_value < 32 || type_value >= 64) && msg.typ() != Type::Reaction {
             return Err(anyhow!(HandlerError::NotMine));
         }
         let client_map = inner_states
@@ -2,11 +2,11 @@ use std::{net::SocketAddr, sync::Arc};
 
 use dashmap::{mapref::one::Ref, DashMap};
 use lazy_static::lazy_static;
-use lib::{entity::Msg, net::GenericParameter, util::should_connect_to_peer, Result};
+use lib::{net::GenericParameter, util::should_connect_to_peer, Result};
 use lib_net_tokio::net::MsgSender;
 use tracing::warn;
 
-use crate::{cluster::client::Client, util::my_id};
+use crate::{cluster::client::Client, service::handler::reload_group_user_list, util::my_id};
 
 mod client;
 mod handler;
@@ -57,9 +57,13 @@ pub(crate) async fn node_offline(node_id: u32) -> Result<()> {
     Ok(())
 }
 
-#[allow(unused)]
-pub(crate) async fn node_crash(msg: Arc<Msg>) -> Result<()> {
-    todo!("node_crash");
+/// the users of crashed node are moved to alive nodes by scheduler, some of them may be moved here,
+/// so the group user list on this node should be reloaded.
+pub(crate) async fn node_crash(node_id: u32) -> Result<()> {
+    warn!("node[{}] crashed", node_id);
+    CLUSTER_CONNECTION_MAP.0.remove(&node_id);
+    reload_group_user_list().await;
+    Ok(())
 }
 
 #[allow(unused)]
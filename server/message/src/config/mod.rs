@@ -6,6 +6,7 @@ use std::{
 };
 
 use anyhow::Context;
+use common::mq::Backend;
 use tracing::Level;
 
 #[derive(serde::Deserialize, Debug)]
@@ -18,6 +19,8 @@ struct Config0 {
     rpc: Option<Rpc0>,
     seqnum: Option<Seqnum0>,
     message_queue: Option<MessageQueue0>,
+    retention: Option<Retention0>,
+    io_task: Option<IOTask0>,
 }
 
 #[derive(Debug)]
@@ -30,6 +33,8 @@ pub(crate) struct Config {
     pub(crate) rpc: Rpc,
     pub(crate) seqnum: Seqnum,
     pub(crate) message_queue: MessageQueue,
+    pub(crate) retention: Retention,
+    pub(crate) io_task: IOTask,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -91,6 +96,7 @@ struct Scheduler0 {
     address: Option<String>,
     domain: Option<String>,
     cert_path: Option<String>,
+    join_token: Option<String>,
 }
 
 #[derive(Debug)]
@@ -98,6 +104,8 @@ pub(crate) struct Scheduler {
     pub(crate) address: SocketAddr,
     pub(crate) domain: String,
     pub(crate) cert: rustls::Certificate,
+    /// issued by `scheduler --issue_join_token`, presented when connecting to it.
+    pub(crate) join_token: String,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -152,12 +160,65 @@ pub(crate) struct Seqnum {
 
 #[derive(serde::Deserialize, Debug)]
 struct MessageQueue0 {
+    backend: Option<String>,
     address: Option<String>,
+    dir: Option<String>,
+    partitions: Option<i32>,
+    spool_dir: Option<String>,
+    dead_letter_attempts: Option<u32>,
 }
 
 #[derive(Debug)]
 pub(crate) struct MessageQueue {
-    pub(crate) address: String,
+    pub(crate) backend: Backend,
+    /// partitions of the topic created by this node, only kafka has partitions.
+    pub(crate) partitions: i32,
+    /// where messages failed to send wait for republishing.
+    pub(crate) spool_dir: PathBuf,
+    /// a message failed so many times while the queue is healthy goes to the dead letter.
+    pub(crate) dead_letter_attempts: u32,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Retention0 {
+    msg_cache_size: Option<usize>,
+    msg_cache_ttl: Option<u64>,
+    inbox_ttl: Option<u64>,
+    sync_ttl: Option<u64>,
+    sweep_interval: Option<u64>,
+}
+
+/// how long messages stay in redis, they are always kept in database by msgprocessors.
+#[derive(Debug)]
+pub(crate) struct Retention {
+    /// latest messages kept for each conversation.
+    pub(crate) msg_cache_size: usize,
+    /// the cache of a conversation without new messages for such time is dropped.
+    pub(crate) msg_cache_ttl: Duration,
+    /// inbox entries older than this are removed.
+    pub(crate) inbox_ttl: Duration,
+    /// sync events older than this are removed, devices offline longer need a full sync.
+    pub(crate) sync_ttl: Duration,
+    pub(crate) sweep_interval: Duration,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct IOTask0 {
+    worker_number: Option<usize>,
+    queue_size: Option<usize>,
+    batch_size: Option<usize>,
+    batch_interval: Option<u64>,
+}
+
+/// messages are cached by workers sharded by conversation, each writes in batches.
+#[derive(Debug)]
+pub(crate) struct IOTask {
+    pub(crate) worker_number: usize,
+    /// capacity of each worker's queue, senders wait when it's full.
+    pub(crate) queue_size: usize,
+    pub(crate) batch_size: usize,
+    /// a batch is written once it's full or such time passed since its first message.
+    pub(crate) batch_interval: Duration,
 }
 
 impl Config {
@@ -170,15 +231,31 @@ impl Config {
             "error" => Level::ERROR,
             _ => Level::INFO,
         };
+        let redis = Redis::from_redis0(config0.redis.unwrap());
+        let message_queue =
+            MessageQueue::from_message_queue0(config0.message_queue.unwrap(), &redis);
         Config {
             log_level,
             server: Server::from_server0(config0.server.unwrap()),
             transport: Transport::from_transport0(config0.transport.unwrap()),
-            redis: Redis::from_redis0(config0.redis.unwrap()),
+            redis,
             scheduler: Scheduler::from_scheduler0(config0.scheduler.unwrap()),
             rpc: Rpc::from_rpc0(config0.rpc.unwrap()),
             seqnum: Seqnum::from_seqnum0(config0.seqnum.unwrap()),
-            message_queue: MessageQueue::from_message_queue0(config0.message_queue.unwrap()),
+            message_queue,
+            retention: Retention::from_retention0(config0.retention.unwrap_or(Retention0 {
+                msg_cache_size: None,
+                msg_cache_ttl: None,
+                inbox_ttl: None,
+                sync_ttl: None,
+                sweep_interval: None,
+            })),
+            io_task: IOTask::from_io_task0(config0.io_task.unwrap_or(IOTask0 {
+                worker_number: None,
+                queue_size: None,
+                batch_size: None,
+                batch_interval: None,
+            })),
         }
     }
 }
@@ -243,6 +320,7 @@ impl Scheduler {
                 .collect::<Vec<SocketAddr>>()[0],
             domain: scheduler0.domain.take().unwrap(),
             cert: rustls::Certificate(cert),
+            join_token: scheduler0.join_token.take().unwrap_or_default(),
         }
     }
 }
@@ -308,9 +386,61 @@ impl Seqnum {
 }
 
 impl MessageQueue {
-    fn from_message_queue0(message_queue0: MessageQueue0) -> Self {
+    /// the redis backend shares the redis cluster of this node.
+    fn from_message_queue0(mut message_queue0: MessageQueue0, redis: &Redis) -> Self {
+        let backend = match message_queue0.backend.as_deref().unwrap_or("kafka") {
+            "kafka" => Backend::Kafka {
+                address: message_queue0.address.take().unwrap(),
+            },
+            "redis" => Backend::Redis {
+                addresses: redis.addresses.clone(),
+                passwords: redis.passwords.clone(),
+            },
+            "file" => Backend::File {
+                dir: PathBuf::from(message_queue0.dir.take().unwrap_or("./message/mq".to_string())),
+            },
+            backend => panic!("unknown message queue backend: {}", backend),
+        };
+        let partitions = match &backend {
+            // one partition per broker by default.
+            Backend::Kafka { address } => message_queue0
+                .partitions
+                .unwrap_or(address.split(',').count() as i32),
+            _ => 1,
+        };
         MessageQueue {
-            address: message_queue0.address.unwrap(),
+            backend,
+            partitions,
+            spool_dir: PathBuf::from(
+                message_queue0
+                    .spool_dir
+                    .take()
+                    .unwrap_or("./message/spool".to_string()),
+            ),
+            dead_letter_attempts: message_queue0.dead_letter_attempts.unwrap_or(5),
+        }
+    }
+}
+
+impl Retention {
+    fn from_retention0(retention0: Retention0) -> Self {
+        Retention {
+            msg_cache_size: retention0.msg_cache_size.unwrap_or(1000),
+            msg_cache_ttl: Duration::from_secs(retention0.msg_cache_ttl.unwrap_or(72) * 3600),
+            inbox_ttl: Duration::from_secs(retention0.inbox_ttl.unwrap_or(720) * 3600),
+            sync_ttl: Duration::from_secs(retention0.sync_ttl.unwrap_or(168) * 3600),
+            sweep_interval: Duration::from_millis(retention0.sweep_interval.unwrap_or(10000)),
+        }
+    }
+}
+
+impl IOTask {
+    fn from_io_task0(io_task0: IOTask0) -> Self {
+        IOTask {
+            worker_number: io_task0.worker_number.unwrap_or(4).max(1),
+            queue_size: io_task0.queue_size.unwrap_or(16384),
+            batch_size: io_task0.batch_size.unwrap_or(512).max(1),
+            batch_interval: Duration::from_millis(io_task0.batch_interval.unwrap_or(5)),
         }
     }
 }
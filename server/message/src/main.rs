@@ -4,7 +4,7 @@ use tracing::{error, info};
 
 use crate::{
     config::config,
-    service::{load_io_task, load_msglogger},
+    service::{load_io_task, load_mq_producer, load_msglogger},
 };
 use crate::config::load_config;
 
@@ -107,6 +107,7 @@ async fn main() -> Result<()> {
     );
     load_msglogger().await?;
     load_io_task();
+    load_mq_producer().await?;
     tokio::spawn(async move {
         if let Err(e) = cluster::start().await {
             error!("cluster error: {}", e);
@@ -6,14 +6,14 @@ use tonic::{
 
 use super::node_proto::{
     api_client::ApiClient, scheduler_client::SchedulerClient, AllGroupNodeListReq,
-    CurrNodeGroupIdUserListReq, SeqnumAllNodeReq, SeqnumNodeUserSelectReq, SeqnumNodeAddressReq,
+    CurrNodeGroupIdUserListReq, GroupUserListReq, MsgExistListReq, SeqnumAllNodeReq,
+    SeqnumNodeUserSelectReq, SeqnumNodeAddressReq,
 };
 use crate::{config::config, util::my_id};
 
 #[derive(Clone)]
 pub(crate) struct RpcClient {
     scheduler_client: SchedulerClient<Channel>,
-    #[allow(unused)]
     api_client: ApiClient<Channel>,
 }
 
@@ -61,6 +61,30 @@ impl RpcClient {
         Ok(response.into_inner().user_list)
     }
 
+    /// all users of the group and the admins among them.
+    pub(crate) async fn call_group_user_list(
+        &mut self,
+        group_id: u64,
+    ) -> Result<(Vec<u64>, Vec<u64>)> {
+        let request = Request::new(GroupUserListReq { group_id });
+        let response = self.api_client.group_user_list(request).await?.into_inner();
+        Ok((response.user_list, response.admin_list))
+    }
+
+    /// those of the seqnums in the conversation, see `lib::util::conversation_id`.
+    pub(crate) async fn call_msg_exist_list(
+        &mut self,
+        conversation_id: String,
+        seq_num_list: Vec<u64>,
+    ) -> Result<Vec<u64>> {
+        let request = Request::new(MsgExistListReq {
+            conversation_id,
+            seq_num_list,
+        });
+        let response = self.api_client.msg_exist_list(request).await?.into_inner();
+        Ok(response.seq_num_list)
+    }
+
     pub(crate) async fn call_all_group_node_list(&mut self, group_id: u64) -> Result<Vec<u32>> {
         let request = Request::new(AllGroupNodeListReq { group_id });
         let response = self.scheduler_client.all_group_node_list(request).await?;
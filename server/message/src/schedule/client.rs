@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use ahash::AHashMap;
-use common::scheduler::connect2scheduler;
+use common::scheduler::{connect2scheduler, keep_alive};
 use lib::{
     entity::{ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
     net::{client::ClientConfigBuilder, GenericParameterMap, InnerStates, InnerStatesValue},
@@ -19,6 +19,7 @@ use crate::{
         handler::{
             business::{AddFriend, JoinGroup, LeaveGroup, RemoveFriend, SystemMessage},
             control_text::ControlText,
+            pure_text::PureText,
         },
     },
     util::my_id,
@@ -48,6 +49,8 @@ impl Client {
         handler_list.push(Box::new(PreProcess::new(get_seqnum_client_map())));
         handler_list.push(Box::new(MQPusher::new()));
         handler_list.push(Box::new(ControlText {}));
+        // contents sent by the api for users, scheduled messages for example.
+        handler_list.push(Box::new(PureText {}));
         handler_list.push(Box::new(JoinGroup {}));
         handler_list.push(Box::new(LeaveGroup {}));
         handler_list.push(Box::new(AddFriend {}));
@@ -62,6 +65,10 @@ impl Client {
             ReqwestResourceID::MessageNodeUnregister,
             Box::new(internal::NodeUnregister {}),
         );
+        handler_map.insert(
+            ReqwestResourceID::MessageNodeCrash,
+            Box::new(internal::NodeCrash {}),
+        );
         handler_map.insert(
             ReqwestResourceID::MessageForward,
             Box::new(internal::MessageForward { handler_list }),
@@ -93,15 +100,25 @@ impl Client {
             );
             states
         });
-        let _operator = connect2scheduler(
+        let operator = connect2scheduler(
             client_config,
             Duration::from_millis(3000),
             handler_map,
-            server_info,
+            server_info.clone(),
+            config().scheduler.join_token.clone(),
             states_gen,
             ReqwestResourceID::MessageNodeRegister,
         )
         .await?;
+        tokio::spawn(async move {
+            keep_alive(
+                &operator,
+                server_info,
+                config().scheduler.join_token.clone(),
+                ReqwestResourceID::MessageNodeRegister,
+            )
+            .await;
+        });
         Ok(())
     }
 }
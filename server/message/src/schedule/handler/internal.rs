@@ -8,7 +8,7 @@ use lib_net_tokio::net::{Handler, ReqwestHandler};
 use tracing::error;
 
 use lib::{
-    entity::{Msg, ReqwestMsg, ServerInfo, Type},
+    entity::{Msg, ReqwestMsg, ServerInfo, Type, FORWARD_FAILED, FORWARD_REFUSED},
     error::HandlerError,
     net::InnerStates,
     Result,
@@ -47,6 +47,17 @@ impl ReqwestHandler for NodeUnregister {
     }
 }
 
+pub(crate) struct NodeCrash {}
+
+#[async_trait]
+impl ReqwestHandler for NodeCrash {
+    async fn run(&self, msg: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let node_info = ServerInfo::from(msg.payload());
+        crate::cluster::node_crash(node_info.id).await?;
+        Ok(ReqwestMsg::default())
+    }
+}
+
 pub(crate) struct MessageForward {
     pub(crate) handler_list: Vec<Box<dyn Handler>>,
 }
@@ -63,7 +74,7 @@ impl ReqwestHandler for MessageForward {
                         continue;
                     }
                     _ => {
-                        return Ok(ReqwestMsg::default());
+                        return Ok(ReqwestMsg::with_resource_id_payload(req.resource_id(), b""));
                     }
                 },
                 Err(e) => {
@@ -73,48 +84,153 @@ impl ReqwestHandler for MessageForward {
                                 continue;
                             }
                             HandlerError::Auth { .. } => {
-                                let res_msg = ReqwestMsg::with_resource_id_payload(
-                                    req.resource_id(),
-                                    b"auth failed",
-                                );
+                                let res_msg = reply(req, FORWARD_REFUSED, b"auth failed");
                                 return Ok(res_msg);
                             }
                             HandlerError::Parse(cause) => {
-                                let res_msg = ReqwestMsg::with_resource_id_payload(
-                                    req.resource_id(),
-                                    cause.as_bytes(),
-                                );
+                                let res_msg = reply(req, FORWARD_REFUSED, cause.as_bytes());
                                 return Ok(res_msg);
                             }
                             HandlerError::IO(e) => {
                                 error!("io error: {}", e);
-                                let res_msg = ReqwestMsg::with_resource_id_payload(
-                                    req.resource_id(),
-                                    b"io error",
-                                );
+                                let res_msg = reply(req, FORWARD_FAILED, b"io error");
                                 return Ok(res_msg);
                             }
                             HandlerError::Other(e) => {
                                 error!("other error: {}", e);
-                                let res_msg = ReqwestMsg::with_resource_id_payload(
-                                    req.resource_id(),
-                                    b"other error",
-                                );
+                                let res_msg = reply(req, FORWARD_FAILED, b"other error");
                                 return Ok(res_msg);
                             }
                         },
                         Err(e) => {
                             error!("unhandled error: {}", e);
-                            let res_msg = ReqwestMsg::with_resource_id_payload(
-                                req.resource_id(),
-                                b"unhandled error",
-                            );
+                            let res_msg = reply(req, FORWARD_FAILED, b"unhandled error");
                             return Ok(res_msg);
                         }
                     };
                 }
             }
         }
-        Ok(ReqwestMsg::default())
+        Ok(ReqwestMsg::with_resource_id_payload(req.resource_id(), b""))
+    }
+}
+
+/// the cause prefixed by whether the scheduler may forward the message again.
+fn reply(req: &ReqwestMsg, flag: u8, cause: &[u8]) -> ReqwestMsg {
+    let mut payload = Vec::with_capacity(cause.len() + 1);
+    payload.push(flag);
+    payload.extend_from_slice(cause);
+    ReqwestMsg::with_resource_id_payload(req.resource_id(), &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ahash::AHashMap;
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use lib::{
+        entity::{Msg, ReqwestMsg, ReqwestResourceID, Type, FORWARD_REFUSED},
+        error::HandlerError,
+        net::InnerStates,
+        Result,
+    };
+    use lib_net_tokio::net::{Handler, ReqwestHandler};
+    use tokio::sync::RwLock;
+
+    use super::MessageForward;
+    use crate::schedule::handler::logic::PreProcess;
+    use crate::service::handler::logic::PreProcess as ClientPreProcess;
+
+    struct Accept {}
+
+    #[async_trait]
+    impl Handler for Accept {
+        async fn run(&self, msg: &mut Arc<Msg>, _states: &mut InnerStates) -> Result<Msg> {
+            Ok((**msg).clone())
+        }
+    }
+
+    struct Refuse {}
+
+    #[async_trait]
+    impl Handler for Refuse {
+        async fn run(&self, _msg: &mut Arc<Msg>, _states: &mut InnerStates) -> Result<Msg> {
+            Err(anyhow!(HandlerError::Parse("invalid mention.".to_string())))
+        }
+    }
+
+    fn control(typ: Type) -> Msg {
+        let mut msg = Msg::text(1, 2, 0, "edited");
+        msg.set_type(typ);
+        msg.set_seqnum(3);
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_forward_edit() {
+        let forward = MessageForward {
+            handler_list: vec![
+                Box::new(PreProcess::new(Arc::new(RwLock::new(AHashMap::new())))),
+                Box::new(Accept {}),
+            ],
+        };
+        let mut req = ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::MessageForward,
+            control(Type::Edit).as_slice(),
+        );
+        let mut states = InnerStates::new();
+        // an empty payload is how the scheduler tells the api the message was accepted.
+        let resp = forward.run(&mut req, &mut states).await.unwrap();
+        assert!(resp.payload().is_empty());
+        // the same edit from a client connection is rejected.
+        let res = ClientPreProcess::new(Arc::new(RwLock::new(AHashMap::new())))
+            .run(&mut Arc::new(control(Type::Edit)), &mut states)
+            .await;
+        assert!(matches!(
+            res.unwrap_err().downcast::<HandlerError>(),
+            Ok(HandlerError::Parse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forward_expire() {
+        let forward = MessageForward {
+            handler_list: vec![
+                Box::new(PreProcess::new(Arc::new(RwLock::new(AHashMap::new())))),
+                Box::new(Accept {}),
+            ],
+        };
+        // the tombstone the api pushes before purging an expired message.
+        let mut tombstone = Msg::raw2(1, 2, 0, &[], &[]);
+        tombstone.set_type(Type::Expire);
+        tombstone.set_seqnum(3);
+        let mut req = ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::MessageForward,
+            tombstone.as_slice(),
+        );
+        let resp = forward
+            .run(&mut req, &mut InnerStates::new())
+            .await
+            .unwrap();
+        assert!(resp.payload().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forward_refused() {
+        let forward = MessageForward {
+            handler_list: vec![Box::new(Refuse {}), Box::new(Accept {})],
+        };
+        let mut req = ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::MessageForward,
+            Msg::text(1, 2, 0, "hello").as_slice(),
+        );
+        let resp = forward
+            .run(&mut req, &mut InnerStates::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.payload()[0], FORWARD_REFUSED);
+        assert_eq!(&resp.payload()[1..], b"invalid mention.");
     }
 }
@@ -4,25 +4,27 @@ use ahash::AHashMap;
 use anyhow::anyhow;
 use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
+use common::mq::MQProducer;
 use lib::{
-    entity::{Msg, ReqwestMsg, ReqwestResourceID},
+    cache::redis_ops::RedisOps,
+    entity::{Msg, ReqwestMsg, ReqwestResourceID, Type},
     error::HandlerError,
     net::{client::ClientConfigBuilder, InnerStates, InnerStatesValue},
-    util::timestamp,
+    util::{conversation_id, timestamp},
     Result,
 };
 use lib_net_tokio::net::{client::ClientReqwestTcp, Handler, ReqwestOperatorManager};
-use rdkafka::{
-    producer::{FutureProducer, FutureRecord},
-    util::Timeout,
-};
 use tokio::sync::RwLock;
 use tracing::error;
 
 use crate::{
     config::config,
     rpc::{get_rpc_client, node::RpcClient},
-    service::{get_mq_producer, get_seqnum_client_holder, handler::is_group_msg, Msglogger},
+    service::{
+        get_mq_producer, get_seqnum_client_holder,
+        handler::{conversation_ttl, fill_real_sender, fill_ttl, is_group_msg},
+        Msglogger,
+    },
     util::my_id,
 };
 
@@ -45,7 +47,11 @@ impl Handler for PreProcess {
     async fn run(&self, msg: &mut Arc<Msg>, states: &mut InnerStates) -> Result<Msg> {
         let client_timestamp = msg.timestamp();
         let type_value = msg.typ().value();
-        if type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160 {
+        // control messages keep the seqnum of the message they change, or the last one read.
+        let is_control = (Type::Edit.value()..=Type::Expire.value()).contains(&type_value);
+        if (type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160)
+            && !is_control
+        {
             let key: u128 = if is_group_msg(msg.receiver()) {
                 (msg.receiver() as u128) << 64 | msg.receiver() as u128
             } else {
@@ -191,14 +197,30 @@ impl Handler for PreProcess {
                     )));
                 }
             };
+            let redis_ops = states
+                .get_mut("generic_map")
+                .unwrap()
+                .as_mut_generic_parameter_map()
+                .unwrap()
+                .get_parameter_mut::<RedisOps>()
+                .unwrap();
+            let conversation_ttl = match conversation_ttl(redis_ops, msg).await {
+                Ok(ttl) => ttl,
+                Err(e) => {
+                    error!("get conversation ttl failed: {}", e);
+                    return Err(anyhow!(HandlerError::Other(
+                        "get conversation ttl failed".to_string()
+                    )));
+                }
+            };
             match Arc::get_mut(msg) {
                 Some(msg) => {
                     msg.set_seqnum(seqnum);
                     msg.set_timestamp(timestamp());
-                    // in case of client forgot set real sender.
-                    if is_group_msg(msg.receiver()) && msg.extension_length() == 0 {
-                        let bytes = msg.sender().to_string();
-                        msg.0.extend_from_slice(bytes.as_bytes());
+                    if !fill_real_sender(msg) || !fill_ttl(msg, conversation_ttl) {
+                        return Err(anyhow!(HandlerError::Parse(
+                            "extension too long".to_string()
+                        )));
                     }
                 }
                 None => {
@@ -224,7 +246,7 @@ impl Handler for PreProcess {
 }
 
 pub(crate) struct MQPusher {
-    mq_producer: FutureProducer,
+    mq_producer: Arc<dyn MQProducer>,
     topic_name: String,
 }
 
@@ -245,31 +267,19 @@ impl Handler for MQPusher {
         } else {
             return Ok(Msg::noop());
         }
-        let mut ok = false;
-        for _ in 0..5 {
-            match self
-                .mq_producer
-                .send(
-                    FutureRecord::to(&self.topic_name)
-                        .key(msg.seqnum().to_string().as_bytes())
-                        .timestamp(timestamp() as i64)
-                        .payload(msg.as_slice()),
-                    Timeout::After(Duration::from_millis(3000)),
-                )
-                .await
-            {
-                Ok(_) => {
-                    ok = true;
-                    break;
-                }
-                Err(e) => {
-                    error!("send message to kafka failed: {}", e.0.to_string());
-                    continue;
-                }
-            }
-        }
-        if !ok {
-            error!("send message to kafka failed, may be to busy: {:?}", msg.0);
+        // keyed by conversation, so messages of a conversation stay in one partition in order.
+        // failed sends are spooled and republished later, an error here means even the spool failed.
+        if let Err(e) = self
+            .mq_producer
+            .send(
+                &self.topic_name,
+                conversation_id(msg.sender(), msg.receiver()).as_bytes(),
+                msg.as_slice(),
+                timestamp() as i64,
+            )
+            .await
+        {
+            error!("send message to mq failed: {}, message lost: {:?}", e, msg.0);
         }
         Ok(Msg::noop())
     }
@@ -4,19 +4,16 @@ use ahash::AHashMap;
 use anyhow::anyhow;
 use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
+use common::mq::MQProducer;
 use lib::{
     cache::redis_ops::RedisOps,
-    entity::{Msg, ReqwestMsg, ReqwestResourceID, Type},
+    entity::{Msg, MsgExtension, ReqwestMsg, ReqwestResourceID, Type, MAX_MSG_TTL},
     error::HandlerError,
     net::{client::ClientConfigBuilder, InnerStates, InnerStatesValue},
-    util::{jwt::verify_token, timestamp},
+    util::{conversation_id, jwt::verify_token, timestamp},
     Result,
 };
 use lib_net_tokio::net::{client::ClientReqwestTcp, Handler, MsgSender, ReqwestOperatorManager};
-use rdkafka::{
-    producer::{FutureProducer, FutureRecord},
-    util::Timeout,
-};
 use tokio::sync::RwLock;
 use tracing::{debug, error};
 
@@ -28,7 +25,12 @@ use crate::{
 };
 use crate::{service::ClientConnectionMap, util::my_id};
 
-use super::is_group_msg;
+use super::{
+    check_mention, check_reference, conversation_ttl, fill_real_sender, fill_ttl, is_group_msg,
+};
+
+/// an emoji takes a few code points at most, it's kept in 64 bytes once encoded.
+const MAX_REACTION_LENGTH: usize = 32;
 
 pub(crate) struct Auth {}
 
@@ -126,7 +128,27 @@ impl Handler for PreProcess {
         // println!("{} {}", timestamp(), msg.timestamp());
         let client_timestamp = msg.timestamp();
         let type_value = msg.typ().value();
-        if type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160 {
+        // the api checks who may change a message and until when, clients can't skip it.
+        // the api's own are forwarded by the scheduler and never pass through here.
+        if msg.typ() == Type::Edit || msg.typ() == Type::Withdraw || msg.typ() == Type::Expire {
+            return Err(anyhow!(HandlerError::Parse(
+                "edit, withdraw and expire are only accepted from the api.".to_string()
+            )));
+        }
+        // payloads are only encrypted between two users, nobody else could read them.
+        if msg.encrypted()
+            && (is_group_msg(msg.receiver())
+                || !(Type::Text.value()..Type::Edit.value()).contains(&type_value))
+        {
+            return Err(anyhow!(HandlerError::Parse(
+                "only 1:1 content messages can be encrypted.".to_string()
+            )));
+        }
+        // read receipts keep the seqnum of the last one read, reactions the one reacted to.
+        let is_control = msg.typ() == Type::Read || msg.typ() == Type::Reaction;
+        if (type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160)
+            && !is_control
+        {
             let key: u128 = if is_group_msg(msg.receiver()) {
                 (msg.receiver() as u128) << 64 | msg.receiver() as u128
             } else {
@@ -158,6 +180,63 @@ impl Handler for PreProcess {
                     .unwrap()
                     .put_parameter(rpc_client);
             }
+            let rpc_client = states
+                .get_mut("generic_map")
+                .unwrap()
+                .as_mut_generic_parameter_map()
+                .unwrap()
+                .get_parameter_mut::<RpcClient>()
+                .unwrap();
+            if MsgExtension::parse(msg.extension())
+                .ttl
+                .is_some_and(|ttl| ttl == 0 || ttl > MAX_MSG_TTL)
+            {
+                return Err(anyhow!(HandlerError::Parse(
+                    "invalid time to live.".to_string()
+                )));
+            }
+            match check_mention(rpc_client, msg).await {
+                Ok(Ok(())) => {}
+                Ok(Err(cause)) => {
+                    return Err(anyhow!(HandlerError::Parse(cause)));
+                }
+                Err(e) => {
+                    error!("check mention failed: {}", e);
+                    return Err(anyhow!(HandlerError::Other(
+                        "check mention failed".to_string()
+                    )));
+                }
+            }
+            match check_reference(rpc_client, msg).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(anyhow!(HandlerError::Parse(
+                        "invalid quote or thread root.".to_string()
+                    )));
+                }
+                Err(e) => {
+                    error!("check reference failed: {}", e);
+                    return Err(anyhow!(HandlerError::Other(
+                        "check reference failed".to_string()
+                    )));
+                }
+            }
+            let redis_ops = states
+                .get_mut("generic_map")
+                .unwrap()
+                .as_mut_generic_parameter_map()
+                .unwrap()
+                .get_parameter_mut::<RedisOps>()
+                .unwrap();
+            let conversation_ttl = match conversation_ttl(redis_ops, msg).await {
+                Ok(ttl) => ttl,
+                Err(e) => {
+                    error!("get conversation ttl failed: {}", e);
+                    return Err(anyhow!(HandlerError::Other(
+                        "get conversation ttl failed".to_string()
+                    )));
+                }
+            };
             if states
                 .get("seqnum_node_select_map")
                 .unwrap()
@@ -314,10 +393,10 @@ impl Handler for PreProcess {
                 Some(msg) => {
                     msg.set_seqnum(seqnum);
                     msg.set_timestamp(timestamp());
-                    // in case of client forgot set real sender.
-                    if is_group_msg(msg.receiver()) && msg.extension_length() == 0 {
-                        let bytes = msg.sender().to_string();
-                        msg.0.extend_from_slice(bytes.as_bytes());
+                    if !fill_real_sender(msg) || !fill_ttl(msg, conversation_ttl) {
+                        return Err(anyhow!(HandlerError::Parse(
+                            "extension too long".to_string()
+                        )));
                     }
                 }
                 None => {
@@ -332,6 +411,26 @@ impl Handler for PreProcess {
                 .get_parameter_mut::<Msglogger>()
                 .unwrap();
             logger.log(msg.clone()).await?;
+        } else if msg.typ() == Type::Reaction {
+            if msg.payload_length() == 0 || msg.payload_length() > MAX_REACTION_LENGTH {
+                return Err(anyhow!(HandlerError::Parse(
+                    "invalid reaction.".to_string()
+                )));
+            }
+            // the time decides which of two toggles is the latest, the client's clock can't.
+            match Arc::get_mut(msg) {
+                Some(msg) => {
+                    msg.set_timestamp(timestamp());
+                    if !fill_real_sender(msg) {
+                        return Err(anyhow!(HandlerError::Parse(
+                            "extension too long".to_string()
+                        )));
+                    }
+                }
+                None => {
+                    return Err(anyhow!("cannot get mutable reference of msg"));
+                }
+            };
         }
         states.insert(
             "client_timestamp".to_owned(),
@@ -342,7 +441,7 @@ impl Handler for PreProcess {
 }
 
 pub(crate) struct MQPusher {
-    mq_producer: FutureProducer,
+    mq_producer: Arc<dyn MQProducer>,
     topic_name: String,
 }
 
@@ -359,32 +458,23 @@ impl MQPusher {
 impl Handler for MQPusher {
     async fn run(&self, msg: &mut Arc<Msg>, _states: &mut InnerStates) -> Result<Msg> {
         let type_value = msg.typ().value();
-        if type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160 {
-            let mut ok = false;
-            for _ in 0..5 {
-                match self
-                    .mq_producer
-                    .send(
-                        FutureRecord::to(&self.topic_name)
-                            .key(msg.seqnum().to_string().as_bytes())
-                            .timestamp(timestamp() as i64)
-                            .payload(msg.as_slice()),
-                        Timeout::After(Duration::from_millis(3000)),
-                    )
-                    .await
-                {
-                    Ok(_) => {
-                        ok = true;
-                        break;
-                    }
-                    Err(e) => {
-                        error!("send message to kafka failed: {}", e.0.to_string());
-                        continue;
-                    }
-                }
-            }
-            if !ok {
-                error!("send message to kafka failed, may be to busy: {:?}", msg.0);
+        // read receipts are not messages, nothing to persist.
+        if (type_value >= 32 && type_value < 96 || type_value >= 128 && type_value < 160)
+            && msg.typ() != Type::Read
+        {
+            // keyed by conversation, so messages of a conversation stay in one partition in order.
+            // failed sends are spooled and republished later, an error here means even the spool failed.
+            if let Err(e) = self
+                .mq_producer
+                .send(
+                    &self.topic_name,
+                    conversation_id(msg.sender(), msg.receiver()).as_bytes(),
+                    msg.as_slice(),
+                    timestamp() as i64,
+                )
+                .await
+            {
+                error!("send message to mq failed: {}, message lost: {:?}", e, msg.0);
             }
         }
         Ok(Msg::noop())
@@ -1,25 +1,39 @@
-use std::{any::Any, sync::Arc};
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use ahash::AHashMap;
 use anyhow::anyhow;
+use base64::Engine;
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 use lib::{
-    entity::{Msg, Type, GROUP_ID_THRESHOLD},
+    cache::redis_ops::RedisOps,
+    entity::{Msg, MsgExtension, SyncEvent, Type, GROUP_ID_THRESHOLD},
     error::HandlerError,
     net::{GenericParameter, GenericParameterMap, InnerStates, InnerStatesValue},
-    util::{timestamp, who_we_are},
+    util::{conversation_id, timestamp, who_we_are},
     Result,
 };
 use lib_net_tokio::net::{HandlerList, MsgMpscReceiver, MsgSender};
-use tracing::{debug, error};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    cache::{get_redis_ops, LAST_ONLINE_TIME, MSG_CACHE, USER_INBOX},
+    cache::{
+        get_redis_ops, sync_seq_key, CONVERSATION_TTL, LAST_ONLINE_TIME, MSG_CACHE,
+        PENDING_REACTION, SYNC_EXPIRE, UNREAD_COUNT, USER_CONVERSATION, USER_INBOX, USER_ONLINE,
+        USER_SYNC,
+    },
     cluster::get_cluster_connection_map,
     config::config,
     rpc,
-    service::get_io_task_sender,
+    service::{get_io_task_sender, retention},
     util::my_id,
 };
 
@@ -29,19 +43,23 @@ pub(crate) mod business;
 pub(crate) mod control_text;
 pub(crate) mod logic;
 pub(crate) mod pure_text;
+pub(crate) mod read;
 
 pub(self) type GroupTaskSender = tokio::sync::mpsc::Sender<(Arc<Msg>, bool)>;
 pub(self) type GroupTaskReceiver = tokio::sync::mpsc::Receiver<(Arc<Msg>, bool)>;
 
+/// one sender for every io task worker, messages of a conversation always go to the same one.
 #[derive(Clone)]
-pub(crate) struct IOTaskSender(pub(crate) tokio::sync::mpsc::Sender<IOTaskMsg>);
+pub(crate) struct IOTaskSender(pub(crate) Arc<Vec<tokio::sync::mpsc::Sender<IOTaskMsg>>>);
 
 pub(crate) struct IOTaskReceiver(pub(crate) tokio::sync::mpsc::Receiver<IOTaskMsg>);
 
 #[derive(Debug, Clone)]
 pub(crate) enum IOTaskMsg {
     Direct(Arc<Msg>),
-    Broadcast(Arc<Msg>, u64, bool),
+    /// a group message for one member: the member, whether the message is cached already, and
+    /// whether it's unread for the member, which is false only for the real sender.
+    Broadcast(Arc<Msg>, u64, bool, bool),
 }
 
 impl GenericParameter for IOTaskSender {
@@ -64,12 +82,71 @@ impl GenericParameter for IOTaskReceiver {
     }
 }
 
+impl IOTaskMsg {
+    /// the worker of a message, chosen by its conversation to keep messages in order.
+    #[inline]
+    fn shard(&self, worker_number: usize) -> usize {
+        let key = match self {
+            IOTaskMsg::Direct(msg) => msg.sender() ^ msg.receiver(),
+            IOTaskMsg::Broadcast(msg, _, _, _) => msg.receiver(),
+        };
+        (key % worker_number as u64) as usize
+    }
+}
+
 impl IOTaskSender {
+    /// wait if the worker is full, so producers are slowed down instead of messages dropped.
     pub(crate) async fn send(&self, msg: IOTaskMsg) -> Result<()> {
-        if let Err(e) = self.0.send(msg).await {
-            return Err(anyhow!(e.to_string()));
+        let sender = &self.0[msg.shard(self.0.len())];
+        IO_TASK_METRICS.depth.fetch_add(1, Ordering::AcqRel);
+        let res = match sender.try_send(msg) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(msg)) => {
+                IO_TASK_METRICS.blocked.fetch_add(1, Ordering::AcqRel);
+                sender.send(msg).await.map_err(|e| anyhow!(e.to_string()))
+            }
+            Err(e) => Err(anyhow!(e.to_string())),
+        };
+        if res.is_err() {
+            IO_TASK_METRICS.depth.fetch_sub(1, Ordering::AcqRel);
         }
-        Ok(())
+        res
+    }
+}
+
+/// counters of the io task workers.
+#[derive(Debug, Default)]
+struct IOTaskMetrics {
+    /// messages waiting in all workers.
+    depth: AtomicU64,
+    /// sends which waited for a full worker.
+    blocked: AtomicU64,
+    batches: AtomicU64,
+    written: AtomicU64,
+    /// messages of batches still failing after being tried again, the cache, inbox,
+    /// conversation, unread and sync writes left undone are lost.
+    failed: AtomicU64,
+}
+
+/// log the counters of io task workers once a minute, skipped while idle.
+pub(super) async fn report_io_task() {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    let mut last_batches = 0;
+    loop {
+        interval.tick().await;
+        let batches = IO_TASK_METRICS.batches.load(Ordering::Acquire);
+        if batches == last_batches {
+            continue;
+        }
+        last_batches = batches;
+        info!(
+            "io task: {} queued, {} sends waited, {} batches, {} written, {} failed",
+            IO_TASK_METRICS.depth.load(Ordering::Acquire),
+            IO_TASK_METRICS.blocked.load(Ordering::Acquire),
+            batches,
+            IO_TASK_METRICS.written.load(Ordering::Acquire),
+            IO_TASK_METRICS.failed.load(Ordering::Acquire),
+        );
     }
 }
 
@@ -80,11 +157,28 @@ impl IOTaskReceiver {
 }
 
 lazy_static! {
+    static ref IO_TASK_METRICS: IOTaskMetrics = IOTaskMetrics::default();
     static ref GROUP_SENDER_MAP: Arc<DashMap<u64, GroupTaskSender>> = Arc::new(DashMap::new());
     /// only represents the current node's group id and user id list
     static ref GROUP_USER_LIST: Arc<DashMap<u64, Vec<u64>>> = Arc::new(DashMap::new());
+    /// all members and the admins of groups mentioned in lately, for `check_mention`.
+    static ref GROUP_MEMBER_MAP: Arc<DashMap<u64, GroupMember>> = Arc::new(DashMap::new());
+    /// the time it's got and the time to live of conversations sent to lately, for `fill_ttl`.
+    static ref CONVERSATION_TTL_MAP: Arc<DashMap<String, (Instant, Option<u64>)>> =
+        Arc::new(DashMap::new());
 }
 
+/// the time it's got, all members and the admins.
+type GroupMember = (Instant, Vec<u64>, Vec<u64>);
+
+/// a member who left may be mentioned for this long.
+const GROUP_MEMBER_TTL: Duration = Duration::from_secs(60);
+/// a time to live set to a conversation applies to the messages sent this long after.
+const CONVERSATION_TTL_TTL: Duration = Duration::from_secs(10);
+/// times a batch is written again after failing, with the delay doubled each time.
+const IO_TASK_RETRY: u32 = 3;
+const IO_TASK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
 /// ```
 ///  -------------------------
 /// |                         |
@@ -166,6 +260,14 @@ pub(super) async fn handler_func(
                 Ok(res_msg) => {
                     sender.send(Arc::new(res_msg)).await?;
                     user_id = auth_msg.sender();
+                    // read by msgprocessor to tell who needs offline push.
+                    redis_ops
+                        .set_exp(
+                            &format!("{}{}", USER_ONLINE, user_id),
+                            &my_id(),
+                            online_ttl(),
+                        )
+                        .await?;
                 }
                 Err(e) => {
                     error!("auth handler error: {}", e);
@@ -180,10 +282,26 @@ pub(super) async fn handler_func(
             return Err(anyhow!("cannot receive auth message"));
         }
     };
+    let mut last_refresh = Instant::now();
     loop {
         let msg = receiver.recv().await;
         match msg {
             Some(mut msg) => {
+                // pings of an idle client keep it online as well, set again in case it
+                // expired while redis was unreachable.
+                if last_refresh.elapsed() >= online_ttl() / 4 {
+                    last_refresh = Instant::now();
+                    if let Err(e) = redis_ops
+                        .set_exp(
+                            &format!("{}{}", USER_ONLINE, user_id),
+                            &my_id(),
+                            online_ttl(),
+                        )
+                        .await
+                    {
+                        error!("refresh online state of {} error: {}", user_id, e);
+                    }
+                }
                 call_handler_list(&sender, &mut msg, handler_list, states).await?;
             }
             None => {
@@ -194,6 +312,14 @@ pub(super) async fn handler_func(
         }
     }
     client_map.remove(&user_id);
+    // the user may have connected to another node already.
+    redis_ops
+        .lua1::<u32, _, _>(
+            "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end",
+            &format!("{}{}", USER_ONLINE, user_id),
+            my_id().to_string(),
+        )
+        .await?;
     // we choose to use [now - last idle timeout] to be the last online time.
     redis_ops
         .set(
@@ -204,6 +330,13 @@ pub(super) async fn handler_func(
     Ok(())
 }
 
+/// `USER_ONLINE_` outlives a connection closed by idle timeout a little, so it's gone soon
+/// after the node dies without cleaning it up.
+#[inline]
+fn online_ttl() -> Duration {
+    Duration::from_millis(config().transport.connection_idle_timeout * 2)
+}
+
 /// this function is used to deal with logic/business message received from client.
 #[inline(always)]
 pub(crate) async fn call_handler_list(
@@ -287,62 +420,424 @@ pub(crate) fn is_group_msg(user_id: u64) -> bool {
     user_id >= GROUP_ID_THRESHOLD
 }
 
+/// group members receive a message as sent by the group, so the real sender is kept in the
+/// extension, a sender written by the client is replaced and the other pairs are kept. false if
+/// the extension can't hold it.
+pub(crate) fn fill_real_sender(msg: &mut Msg) -> bool {
+    if !is_group_msg(msg.receiver()) {
+        return true;
+    }
+    let old_extension = String::from_utf8_lossy(msg.extension()).to_string();
+    let mut pair_list = vec![format!("s={}", msg.sender())];
+    // a bare number is a sender alone.
+    if old_extension.parse::<u64>().is_err() {
+        pair_list.extend(
+            old_extension
+                .split(';')
+                .filter(|pair| !pair.is_empty() && !pair.starts_with("s="))
+                .map(|pair| pair.to_string()),
+        );
+    }
+    let extension = pair_list.join(";");
+    if extension == old_extension {
+        return true;
+    }
+    msg.replace_extension(extension.as_bytes())
+}
+
+/// the time to live of the conversation of the message, none for control messages and those
+/// with their own.
+pub(crate) async fn conversation_ttl(redis_ops: &mut RedisOps, msg: &Msg) -> Result<Option<u64>> {
+    if !is_content(msg) || MsgExtension::parse(msg.extension()).ttl.is_some() {
+        return Ok(None);
+    }
+    let conversation = conversation_id(msg.sender(), msg.receiver());
+    if let Some(entry) = CONVERSATION_TTL_MAP.get(&conversation) {
+        if entry.0.elapsed() < CONVERSATION_TTL_TTL {
+            return Ok(entry.1);
+        }
+    }
+    let ttl = redis_ops
+        .get::<Option<u64>>(&format!("{}{}", CONVERSATION_TTL, conversation))
+        .await?;
+    CONVERSATION_TTL_MAP.insert(conversation, (Instant::now(), ttl));
+    Ok(ttl)
+}
+
+/// a message without a time to live of its own lives as long as its conversation says, which is
+/// written in the extension, so clients delete it on time as `msgprocessor` does. false if the
+/// extension can't hold it.
+pub(crate) fn fill_ttl(msg: &mut Msg, conversation_ttl: Option<u64>) -> bool {
+    let ttl = match conversation_ttl {
+        Some(ttl) => ttl,
+        None => return true,
+    };
+    let old_extension = String::from_utf8_lossy(msg.extension()).to_string();
+    let extension = if old_extension.is_empty() {
+        format!("e={}", ttl)
+    } else {
+        format!("{};e={}", old_extension, ttl)
+    };
+    msg.replace_extension(extension.as_bytes())
+}
+
+/// the time the message expires at, in milliseconds.
+#[inline]
+fn expire_at(msg: &Msg) -> Option<u64> {
+    MsgExtension::parse(msg.extension())
+        .ttl
+        .map(|ttl| msg.timestamp() + ttl * 1000)
+}
+
+/// the mentions the sender may make, see `MsgExtension::check_mention`, the error is the cause
+/// to show the sender.
+pub(crate) async fn check_mention(
+    rpc_client: &mut rpc::node::RpcClient,
+    msg: &Msg,
+) -> Result<std::result::Result<(), String>> {
+    let extension = MsgExtension::parse(msg.extension());
+    if !is_content(msg) || !extension.has_mention() {
+        return Ok(Ok(()));
+    }
+    let (user_list, admin_list) = if is_group_msg(msg.receiver()) {
+        group_member_list(rpc_client, msg.receiver()).await?
+    } else {
+        (vec![], vec![])
+    };
+    Ok(extension.check_mention(msg.sender(), msg.receiver(), &user_list, &admin_list))
+}
+
+async fn group_member_list(
+    rpc_client: &mut rpc::node::RpcClient,
+    group_id: u64,
+) -> Result<(Vec<u64>, Vec<u64>)> {
+    if let Some(entry) = GROUP_MEMBER_MAP.get(&group_id) {
+        if entry.0.elapsed() < GROUP_MEMBER_TTL {
+            return Ok((entry.1.clone(), entry.2.clone()));
+        }
+    }
+    let (user_list, admin_list) = rpc_client.call_group_user_list(group_id).await?;
+    GROUP_MEMBER_MAP.insert(
+        group_id,
+        (Instant::now(), user_list.clone(), admin_list.clone()),
+    );
+    Ok((user_list, admin_list))
+}
+
+/// the quoted message and the thread root must be in the conversation already, which makes them
+/// earlier than the message.
+pub(crate) async fn check_reference(
+    rpc_client: &mut rpc::node::RpcClient,
+    msg: &Msg,
+) -> Result<bool> {
+    if !is_content(msg) {
+        return Ok(true);
+    }
+    let extension = MsgExtension::parse(msg.extension());
+    let seq_num_list = extension
+        .quote
+        .into_iter()
+        .chain(extension.thread_root)
+        .collect::<Vec<u64>>();
+    if seq_num_list.is_empty() {
+        return Ok(true);
+    }
+    let exist_list = rpc_client
+        .call_msg_exist_list(
+            conversation_id(msg.sender(), msg.receiver()),
+            seq_num_list.clone(),
+        )
+        .await?;
+    Ok(seq_num_list
+        .iter()
+        .all(|seq_num| exist_list.contains(seq_num)))
+}
+
+/// messages counted as unread, control messages only change those already counted.
+#[inline]
+fn is_content(msg: &Msg) -> bool {
+    (Type::Text.value()..Type::Edit.value()).contains(&msg.typ().value())
+}
+
+/// edits and withdraws, the api replaces the cached message they change in place, expiries,
+/// the api removes it, and reactions, kept aside from the messages they belong to.
+#[inline]
+fn is_change(msg: &Msg) -> bool {
+    (Type::Edit.value()..=Type::Expire.value()).contains(&msg.typ().value())
+        && msg.typ() != Type::Read
+}
+
+/// the entry of a reaction toggle in `PENDING_REACTION_`, the same toggle written twice is kept
+/// once.
+fn pending_reaction(msg: &Msg) -> Option<String> {
+    if msg.typ() != Type::Reaction {
+        return None;
+    }
+    let user_id = if is_group_msg(msg.receiver()) && msg.sender() == msg.receiver() {
+        MsgExtension::parse(msg.extension()).sender?
+    } else {
+        msg.sender()
+    };
+    // encoded as `msgprocessor` saves it.
+    let engine = base64::engine::GeneralPurpose::new(
+        &base64::alphabet::URL_SAFE,
+        base64::engine::general_purpose::NO_PAD,
+    );
+    Some(format!(
+        "{}:{}:{}:{}",
+        msg.seqnum(),
+        user_id,
+        msg.timestamp(),
+        engine.encode(String::from_utf8_lossy(msg.payload()).to_string())
+    ))
+}
+
+/// messages every device of the user should converge on, the content and changes to it.
+#[inline]
+fn sync_event(msg: &Msg) -> Option<String> {
+    if !(Type::Text.value()..=Type::Expire.value()).contains(&msg.typ().value())
+        || msg.typ() == Type::Read
+    {
+        return None;
+    }
+    serde_json::to_string(&SyncEvent::Msg { msg: msg.clone() }).ok()
+}
+
 /// only messages that need to be deal by post-service or cached into cache will be sent to this task.
 /// those messages types maybe: all message part / all business part
-pub(super) async fn io_task(mut io_task_receiver: IOTaskReceiver) -> Result<()> {
+///
+/// messages are collected into batches, and every batch is written by pipelines, so a group
+/// message costs a few round trips rather than two for each member.
+pub(super) async fn io_task(worker_id: usize, mut io_task_receiver: IOTaskReceiver) -> Result<()> {
     let mut redis_ops = get_redis_ops().await;
-
+    let batch_size = config().io_task.batch_size;
+    let mut batch = Vec::with_capacity(batch_size);
     loop {
         match io_task_receiver.recv().await {
-            Some(task_msg) => {
-                let users_identify;
-                let msg: Arc<Msg>;
-                let receiver: u64;
-                match task_msg {
-                    IOTaskMsg::Direct(direct_msg) => {
-                        users_identify = who_we_are(direct_msg.sender(), direct_msg.receiver());
-                        receiver = direct_msg.receiver();
-                        msg = direct_msg;
-                        // todo delete old data
-                        redis_ops
-                            .push_sort_queue(
-                                &format!("{}{}", MSG_CACHE, users_identify),
-                                &msg.as_slice(),
-                                msg.seqnum() as f64,
-                            )
-                            .await?;
+            Some(task_msg) => batch.push(task_msg),
+            None => {
+                error!("io task receiver closed");
+                return Err(anyhow!("io task receiver closed"));
+            }
+        }
+        let deadline = tokio::time::Instant::now() + config().io_task.batch_interval;
+        while batch.len() < batch_size {
+            match tokio::time::timeout_at(deadline, io_task_receiver.recv()).await {
+                Ok(Some(task_msg)) => batch.push(task_msg),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let size = batch.len() as u64;
+        IO_TASK_METRICS.depth.fetch_sub(size, Ordering::AcqRel);
+        let mut cache_list = vec![];
+        let mut inbox_list = vec![];
+        let mut unread_list = vec![];
+        let mut conversation_list = vec![];
+        let mut sync_list = vec![];
+        let mut reaction_list = vec![];
+        for task_msg in batch.drain(..) {
+            let (users_identify, receiver, msg) = match task_msg {
+                IOTaskMsg::Direct(msg) => {
+                    let users_identify = who_we_are(msg.sender(), msg.receiver());
+                    if !is_change(&msg) {
+                        cache_list.push((
+                            format!("{}{}", MSG_CACHE, users_identify),
+                            msg.as_slice().to_vec(),
+                            msg.seqnum() as f64,
+                        ));
+                    }
+                    if is_content(&msg) {
+                        conversation_list.push((
+                            format!("{}{}", USER_CONVERSATION, msg.sender()),
+                            msg.receiver(),
+                            msg.timestamp() as f64,
+                        ));
+                        conversation_list.push((
+                            format!("{}{}", USER_CONVERSATION, msg.receiver()),
+                            msg.sender(),
+                            msg.timestamp() as f64,
+                        ));
+                    }
+                    if let Some(reaction) = pending_reaction(&msg) {
+                        reaction_list.push((
+                            format!("{}{}", PENDING_REACTION, users_identify),
+                            reaction,
+                            msg.timestamp() as f64,
+                        ));
+                    }
+                    // a message crossing nodes is written by both, the same event is added only once.
+                    if let Some(event) = sync_event(&msg) {
+                        sync_list.push((msg.sender(), event.clone(), expire_at(&msg)));
+                        sync_list.push((msg.receiver(), event, expire_at(&msg)));
+                        retention::touch_user(msg.sender());
                     }
-                    IOTaskMsg::Broadcast(broadcast_msg, real_receiver, duplication) => {
-                        users_identify =
-                            who_we_are(broadcast_msg.receiver(), broadcast_msg.receiver());
-                        receiver = real_receiver;
-                        msg = broadcast_msg;
-                        if !duplication {
-                            // todo delete old data
-                            redis_ops
-                                .push_sort_queue(
-                                    &format!("{}{}", MSG_CACHE, users_identify),
-                                    &msg.as_slice(),
-                                    msg.seqnum() as f64,
-                                )
-                                .await?;
+                    // a message crossing nodes is cached by both, but counted by the receiver's.
+                    if is_content(&msg) && msg.node_id() == my_id() {
+                        unread_list.push((
+                            format!("{}{}", UNREAD_COUNT, msg.receiver()),
+                            msg.sender(),
+                            1,
+                        ));
+                    }
+                    (users_identify, msg.receiver(), msg)
+                }
+                IOTaskMsg::Broadcast(msg, real_receiver, duplication, unread) => {
+                    let users_identify = who_we_are(msg.receiver(), msg.receiver());
+                    if !duplication && !is_change(&msg) {
+                        cache_list.push((
+                            format!("{}{}", MSG_CACHE, users_identify),
+                            msg.as_slice().to_vec(),
+                            msg.seqnum() as f64,
+                        ));
+                    }
+                    if is_content(&msg) {
+                        conversation_list.push((
+                            format!("{}{}", USER_CONVERSATION, real_receiver),
+                            msg.receiver(),
+                            msg.timestamp() as f64,
+                        ));
+                    }
+                    if !duplication {
+                        if let Some(reaction) = pending_reaction(&msg) {
+                            reaction_list.push((
+                                format!("{}{}", PENDING_REACTION, users_identify),
+                                reaction,
+                                msg.timestamp() as f64,
+                            ));
                         }
                     }
+                    if let Some(event) = sync_event(&msg) {
+                        sync_list.push((real_receiver, event, expire_at(&msg)));
+                    }
+                    if unread && is_content(&msg) {
+                        unread_list.push((
+                            format!("{}{}", UNREAD_COUNT, real_receiver),
+                            msg.receiver(),
+                            1,
+                        ));
+                    }
+                    (users_identify, real_receiver, msg)
                 }
-                redis_ops
-                    .push_sort_queue(
-                        &format!("{}{}", USER_INBOX, receiver),
-                        &msg.sender(),
-                        msg.timestamp() as f64,
-                    )
-                    .await?;
-                // recorder_sender.send(msg).await?;
+            };
+            inbox_list.push((
+                format!("{}{}", USER_INBOX, receiver),
+                msg.sender(),
+                msg.timestamp() as f64,
+            ));
+            // old entries are trimmed by the retention sweeper.
+            retention::touch(&users_identify, receiver);
+        }
+        // events of a user are scored by the user's counter in the order they are written.
+        let mut user_sync_map: AHashMap<u64, SyncEventList> = AHashMap::new();
+        for (user_id, event, expire_at) in sync_list.drain(..) {
+            user_sync_map
+                .entry(user_id)
+                .or_default()
+                .push((event, expire_at));
+        }
+        let mut batch_write = BatchWrite {
+            cache_list,
+            inbox_list,
+            conversation_list,
+            reaction_list,
+            unread_list,
+            sync_list: user_sync_map.into_iter().collect(),
+            expire_list: vec![],
+        };
+        let mut attempt = 0;
+        let res = loop {
+            match batch_write.write(&mut redis_ops).await {
+                Ok(_) => break Ok(()),
+                Err(e) if attempt < IO_TASK_RETRY => {
+                    warn!(
+                        "io task {} write {} messages error: {}, try again",
+                        worker_id, size, e
+                    );
+                    tokio::time::sleep(IO_TASK_RETRY_DELAY * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => break Err(e),
             }
-            None => {
-                error!("io task receiver closed");
-                return Err(anyhow!("io task receiver closed"));
+        };
+        IO_TASK_METRICS.batches.fetch_add(1, Ordering::AcqRel);
+        match res {
+            Ok(_) => {
+                IO_TASK_METRICS.written.fetch_add(size, Ordering::AcqRel);
+            }
+            Err(e) => {
+                IO_TASK_METRICS.failed.fetch_add(size, Ordering::AcqRel);
+                error!("io task {} write {} messages error: {}", worker_id, size, e);
             }
         }
+        let depth = IO_TASK_METRICS.depth.load(Ordering::Acquire);
+        if depth as usize > config().io_task.queue_size / 2 * config().io_task.worker_number {
+            warn!(
+                "io task is falling behind, {} messages queued, senders waited {} times",
+                depth,
+                IO_TASK_METRICS.blocked.load(Ordering::Acquire)
+            );
+        }
+    }
+}
+
+/// sync events of a user, with when the message of each expires.
+type SyncEventList = Vec<(String, Option<u64>)>;
+
+/// the writes of a batch of io tasks, each list is emptied once written, so a batch written again
+/// after an error goes on from the write failed. the unread counts and sync events are not
+/// idempotent, they must not be written twice.
+struct BatchWrite {
+    cache_list: Vec<(String, Vec<u8>, f64)>,
+    inbox_list: Vec<(String, u64, f64)>,
+    conversation_list: Vec<(String, u64, f64)>,
+    reaction_list: Vec<(String, String, f64)>,
+    unread_list: Vec<(String, u64, i64)>,
+    sync_list: Vec<(u64, SyncEventList)>,
+    expire_list: Vec<(String, String, f64)>,
+}
+
+impl BatchWrite {
+    async fn write(&mut self, redis_ops: &mut RedisOps) -> Result<()> {
+        // the cache is written first, so a listed conversation always has messages.
+        redis_ops.push_sort_queue_batch(&self.cache_list).await?;
+        self.cache_list.clear();
+        redis_ops.push_sort_queue_batch(&self.inbox_list).await?;
+        self.inbox_list.clear();
+        redis_ops
+            .push_sort_queue_batch(&self.conversation_list)
+            .await?;
+        self.conversation_list.clear();
+        redis_ops.push_sort_queue_batch(&self.reaction_list).await?;
+        self.reaction_list.clear();
+        redis_ops.increase_hash_batch(&self.unread_list).await?;
+        self.unread_list.clear();
+        while let Some((user_id, event_list)) = self.sync_list.last() {
+            let seq = redis_ops
+                .push_sort_queue_seq(
+                    &format!("{}{}", USER_SYNC, user_id),
+                    &sync_seq_key(*user_id),
+                    &event_list
+                        .iter()
+                        .map(|(event, _)| event)
+                        .collect::<Vec<&String>>(),
+                )
+                .await?;
+            // the api removes events of expired messages by their scores.
+            let first = seq + 1 - event_list.len() as u64;
+            for (i, (_, expire_at)) in event_list.iter().enumerate() {
+                if let Some(expire_at) = expire_at {
+                    self.expire_list.push((
+                        SYNC_EXPIRE.to_string(),
+                        format!("{}:{}", user_id, first + i as u64),
+                        *expire_at as f64,
+                    ));
+                }
+            }
+            self.sync_list.pop();
+        }
+        redis_ops.push_sort_queue_batch(&self.expire_list).await?;
+        self.expire_list.clear();
+        Ok(())
     }
 }
 
@@ -381,6 +876,19 @@ async fn load_group_user_list(group_id: u64) -> Result<()> {
     Ok(())
 }
 
+/// reload user list of all groups handled by this node, used when users are moved here.
+pub(crate) async fn reload_group_user_list() {
+    let group_id_list = GROUP_USER_LIST
+        .iter()
+        .map(|entry| *entry.key())
+        .collect::<Vec<u64>>();
+    for group_id in group_id_list {
+        if let Err(e) = load_group_user_list(group_id).await {
+            error!("reload group {} user list error: {}", group_id, e);
+        }
+    }
+}
+
 pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver) -> Result<()> {
     debug!("group task {} start", group_id);
     if let Err(e) = load_group_user_list(group_id).await {
@@ -413,6 +921,7 @@ pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver)
                 }
                 // when send to clients, the message need sender set to group id first.
                 // the truly sender will be set in extension part by original client.
+                let real_sender = msg.sender();
                 let mut new_msg = (*msg).clone();
                 new_msg.set_sender(msg.receiver());
                 new_msg.set_receiver(msg.receiver());
@@ -422,7 +931,12 @@ pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver)
                     Some(user_list) => {
                         for user_id in user_list.iter() {
                             if let Err(_) = io_task_sender
-                                .send(IOTaskMsg::Broadcast(msg.clone(), *user_id, duplication))
+                                .send(IOTaskMsg::Broadcast(
+                                    msg.clone(),
+                                    *user_id,
+                                    duplication,
+                                    *user_id != real_sender,
+                                ))
                                 .await
                             {
                                 error!("send to io task failed");
@@ -458,6 +972,53 @@ pub(self) async fn group_task(group_id: u64, mut io_receiver: GroupTaskReceiver)
 mod tests {
     use std::sync::Arc;
 
+    use lib::entity::{Msg, MsgExtension, GROUP_ID_THRESHOLD};
+
+    use super::{fill_real_sender, fill_ttl};
+
+    #[test]
+    fn test_fill_real_sender() {
+        let group_id = GROUP_ID_THRESHOLD + 1;
+        let mut msg = Msg::text(1, group_id, 0, "hello");
+        assert!(fill_real_sender(&mut msg));
+        assert_eq!(msg.extension(), b"s=1");
+        // a sender written by the client is replaced, the other pairs are kept.
+        let mut msg = Msg::text(1, group_id, 0, "hello");
+        assert!(msg.replace_extension(b"q=3;s=2;m=4,5"));
+        assert!(fill_real_sender(&mut msg));
+        let extension = MsgExtension::parse(msg.extension());
+        assert_eq!(extension.sender, Some(1));
+        assert_eq!(extension.quote, Some(3));
+        assert_eq!(extension.mention_list, vec![4, 5]);
+        let mut msg = Msg::text(1, group_id, 0, "hello");
+        assert!(msg.replace_extension(b"2"));
+        assert!(fill_real_sender(&mut msg));
+        assert_eq!(msg.extension(), b"s=1");
+        // chats keep theirs as is.
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        assert!(msg.replace_extension(b"s=2"));
+        assert!(fill_real_sender(&mut msg));
+        assert_eq!(msg.extension(), b"s=2");
+    }
+
+    #[test]
+    fn test_fill_ttl() {
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        assert!(fill_ttl(&mut msg, None));
+        assert_eq!(msg.extension(), b"");
+        assert!(fill_ttl(&mut msg, Some(30)));
+        assert_eq!(msg.extension(), b"e=30");
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        assert!(msg.replace_extension(b"q=3"));
+        assert!(fill_ttl(&mut msg, Some(30)));
+        let extension = MsgExtension::parse(msg.extension());
+        assert_eq!((extension.quote, extension.ttl), (Some(3), Some(30)));
+        assert_eq!(msg.payload(), b"hello");
+        let mut msg = Msg::text(1, 2, 0, "hello");
+        assert!(msg.replace_extension(&[b'0'; 60]));
+        assert!(!fill_ttl(&mut msg, Some(30)));
+    }
+
     #[tokio::test]
     async fn test() {
         #[derive(Debug)]
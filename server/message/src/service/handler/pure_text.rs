@@ -4,7 +4,7 @@ use ahash::AHashMap;
 use anyhow::anyhow;
 use async_trait::async_trait;
 use lib::{
-    entity::Msg,
+    entity::{Msg, Type},
     error::HandlerError,
     net::{InnerStates, InnerStatesValue},
     Result,
@@ -28,7 +28,8 @@ pub(crate) struct PureText;
 impl Handler for PureText {
     async fn run(&self, msg: &mut Arc<Msg>, states: &mut InnerStates) -> Result<Msg> {
         let type_value = msg.typ().value();
-        if type_value < 32 || type_value >= 64 {
+        // reactions are delivered the same way, only they take no place in the timeline.
+        if (type_value < 32 || type_value >= 64) && msg.typ() != Type::Reaction {
             return Err(anyhow!(HandlerError::NotMine));
         }
         let receiver = msg.receiver();
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lib::{
+    cache::redis_ops::RedisOps,
+    entity::{Msg, SyncEvent, Type},
+    error::HandlerError,
+    net::InnerStates,
+    Result,
+};
+use lib_net_tokio::net::Handler;
+
+use crate::{
+    cache::{sync_seq_key, LAST_READ, UNREAD_COUNT, USER_SYNC},
+    service::retention,
+    util::my_id,
+};
+
+/// read receipts are kept as the state of the reader, shared by all of the reader's devices, and
+/// never stored as messages.
+pub(crate) struct Read;
+
+#[async_trait]
+impl Handler for Read {
+    async fn run(&self, msg: &mut Arc<Msg>, inner_states: &mut InnerStates) -> Result<Msg> {
+        if Type::Read != msg.typ() {
+            return Err(anyhow!(HandlerError::NotMine));
+        }
+        let mut redis_ops = inner_states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<RedisOps>()
+            .unwrap()
+            .clone();
+        let user_id = msg.sender();
+        let peer_id = msg.receiver();
+        redis_ops
+            .set(
+                &format!("{}{}-{}", LAST_READ, user_id, peer_id),
+                &msg.seqnum(),
+            )
+            .await?;
+        redis_ops
+            .remove_hash(&format!("{}{}", UNREAD_COUNT, user_id), &peer_id)
+            .await?;
+        let event = serde_json::to_string(&SyncEvent::Read {
+            peer_id,
+            seqnum: msg.seqnum(),
+        })?;
+        redis_ops
+            .push_sort_queue_seq(
+                &format!("{}{}", USER_SYNC, user_id),
+                &sync_seq_key(user_id),
+                &[event],
+            )
+            .await?;
+        retention::touch_user(user_id);
+        let client_timestamp = inner_states
+            .get("client_timestamp")
+            .unwrap()
+            .as_num()
+            .unwrap();
+        Ok(msg.generate_ack(my_id(), client_timestamp))
+    }
+}
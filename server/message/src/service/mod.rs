@@ -9,7 +9,7 @@ use std::{
 use std::net::ToSocketAddrs;
 
 use ahash::AHashMap;
-use anyhow::anyhow;
+use common::mq::{spool::SpooledProducer, MQProducer};
 use dashmap::{mapref::one::Ref, DashMap};
 use lazy_static::lazy_static;
 use lib::{
@@ -18,14 +18,8 @@ use lib::{
     Result,
 };
 use lib_net_tokio::net::{client::ClientReqwestTcp, MsgSender, ReqwestOperatorManager};
-use rdkafka::{
-    admin::{AdminClient, AdminOptions, NewTopic, ResourceSpecifier, TopicReplication},
-    client::DefaultClientContext,
-    producer::FutureProducer,
-    ClientConfig,
-};
 use sysinfo::SystemExt;
-use tokio::sync::RwLock;
+use tokio::sync::{OnceCell, RwLock};
 use tracing::{error, info};
 
 use self::{handler::io_task, msglogger::MsgloggerClient};
@@ -38,6 +32,7 @@ use crate::{
 
 pub(crate) mod handler;
 pub(self) mod msglogger;
+pub(crate) mod retention;
 pub(crate) mod server;
 
 pub(crate) struct ClientConnectionMap(pub(crate) Arc<DashMap<u64, MsgSender>>);
@@ -45,7 +40,7 @@ pub(crate) struct ClientConnectionMap(pub(crate) Arc<DashMap<u64, MsgSender>>);
 pub(crate) struct Msglogger(pub(self) Arc<MsgloggerClient>);
 
 pub(crate) static mut IO_TASK_SENDER: Option<IOTaskSender> = None;
-pub(crate) static mut IO_TASK_RECEIVER: Option<IOTaskReceiver> = None;
+pub(crate) static mut IO_TASK_RECEIVER: Option<Vec<IOTaskReceiver>> = None;
 
 lazy_static! {
     // this map has a lot of write and read operations, so use dashmap is better
@@ -58,9 +53,10 @@ lazy_static! {
     pub(self) static ref SEQNUM_CLIENT_HOLDER: Arc<RwLock<AHashMap<u32, ClientReqwestTcp>>> =
         Arc::new(RwLock::new(AHashMap::new()));
     pub(self) static ref CLIENT_INDEX: AtomicUsize = AtomicUsize::new(0);
-    pub(self) static ref MQ_PRODUCER: FutureProducer = load_producer();
 }
 
+static MQ_PRODUCER: OnceCell<Arc<dyn MQProducer>> = OnceCell::const_new();
+
 /// this map's write operation only happens on application startup
 /// so it's safe to use unsafe
 static mut MSGLOGGER_CLIENT_MAP: Option<AHashMap<usize, Msglogger>> = None;
@@ -86,8 +82,11 @@ pub(crate) fn get_msglogger_client() -> Msglogger {
     }
 }
 
-pub(crate) fn get_mq_producer() -> FutureProducer {
-    MQ_PRODUCER.clone()
+pub(crate) fn get_mq_producer() -> Arc<dyn MQProducer> {
+    MQ_PRODUCER
+        .get()
+        .expect("mq producer not initialized")
+        .clone()
 }
 
 pub(crate) fn get_io_task_sender() -> &'static IOTaskSender {
@@ -135,11 +134,17 @@ impl Msglogger {
 }
 
 pub(crate) fn load_io_task() {
-    // todo size optimization
-    let (io_task_sender, io_task_receiver) = tokio::sync::mpsc::channel::<IOTaskMsg>(65536);
+    let mut sender_list = vec![];
+    let mut receiver_list = vec![];
+    for _ in 0..config().io_task.worker_number {
+        let (io_task_sender, io_task_receiver) =
+            tokio::sync::mpsc::channel::<IOTaskMsg>(config().io_task.queue_size);
+        sender_list.push(io_task_sender);
+        receiver_list.push(IOTaskReceiver(io_task_receiver));
+    }
     unsafe {
-        IO_TASK_SENDER = Some(IOTaskSender(io_task_sender));
-        IO_TASK_RECEIVER = Some(IOTaskReceiver(io_task_receiver))
+        IO_TASK_SENDER = Some(IOTaskSender(Arc::new(sender_list)));
+        IO_TASK_RECEIVER = Some(receiver_list)
     };
 }
 
@@ -190,67 +195,42 @@ pub(crate) async fn load_msglogger() -> Result<()> {
     Ok(())
 }
 
-pub(self) fn load_producer() -> FutureProducer {
-    let producer: FutureProducer = ClientConfig::new()
-        .set("bootstrap.servers", &config().message_queue.address)
-        .set("message.timeout.ms", "3000")
-        .create()
-        .unwrap();
+/// the topic of this node is created here, before any message is pushed.
+pub(crate) async fn load_mq_producer() -> Result<()> {
+    let producer = SpooledProducer::new(
+        config().message_queue.backend.producer().await?,
+        config().message_queue.spool_dir.clone(),
+        config().message_queue.dead_letter_attempts,
+    )?;
     producer
+        .ensure_topic(
+            &format!("msg-{:06}", my_id()),
+            config().message_queue.partitions,
+        )
+        .await?;
+    _ = MQ_PRODUCER.set(producer);
+    Ok(())
 }
 
 pub(crate) async fn start() -> Result<()> {
-    // create topic
-    let topic_name = format!("msg-{:06}", my_id());
-    let mut client_config = ClientConfig::new();
-    client_config.set("bootstrap.servers", &config().message_queue.address);
-    let admin_client: AdminClient<DefaultClientContext> = client_config.create().unwrap();
-    let admin_options =
-        AdminOptions::new().operation_timeout(Some(std::time::Duration::from_secs(5)));
-    let topic_metadata = match admin_client
-        .describe_configs(vec![&ResourceSpecifier::Topic(&topic_name)], &admin_options)
-        .await
-    {
-        Ok(topic_metadata) => topic_metadata,
-        Err(e) => {
-            error!("describe topic error: {}", e);
-            return Err(anyhow!("describe topic error: {}", e));
-        }
-    };
-    match topic_metadata[0] {
-        Ok(ref item) => {
-            if item.entries.len() == 0 {
-                let partition = config()
-                    .message_queue
-                    .address
-                    .split(',')
-                    .collect::<Vec<_>>()
-                    .len() as i32;
-                admin_client
-                    .create_topics(
-                        [&NewTopic::new(
-                            &topic_name,
-                            partition,
-                            TopicReplication::Fixed(1),
-                        )],
-                        &admin_options,
-                    )
-                    .await?;
+    // start io task
+    let io_task_receiver_list = unsafe { IO_TASK_RECEIVER.take().unwrap() };
+    for (worker_id, io_task_receiver) in io_task_receiver_list.into_iter().enumerate() {
+        tokio::spawn(async move {
+            if let Err(e) = io_task(worker_id, io_task_receiver).await {
+                error!("io task {} error: {}", worker_id, e);
             }
-        }
-        Err(e) => {
-            error!("describe topic error: {}", e);
-            return Err(anyhow!("describe topic error: {}", e));
-        }
+        });
     }
 
-    // start io task
     tokio::spawn(async move {
-        if let Err(e) = io_task(unsafe { IO_TASK_RECEIVER.take().unwrap() }).await {
-            error!("io task error: {}", e);
+        handler::report_io_task().await;
+    });
+    tokio::spawn(async move {
+        if let Err(e) = retention::sweep().await {
+            error!("retention sweeper error: {}", e);
         }
     });
-
     load_seqnum_map().await?;
     server::Server::run().await?;
     Ok(())
@@ -0,0 +1,151 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashSet;
+use lazy_static::lazy_static;
+use lib::{cache::redis_ops::RedisOps, entity::SyncEvent, util::timestamp, Result};
+use tracing::{debug, error};
+
+use crate::{
+    cache::{
+        get_redis_ops, sync_seq_key, MSG_CACHE, PENDING_REACTION, USER_INBOX, USER_SYNC,
+        USER_SYNC_MARK,
+    },
+    config::config,
+};
+
+/// far longer than `msgprocessor` takes to save a toggle, one applied already is skipped anyway.
+const PENDING_REACTION_TTL: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    static ref SYNC_FLOOR: String = serde_json::to_string(&SyncEvent::Floor).unwrap();
+    /// caches written since the last sweep, only those need to be trimmed.
+    static ref TOUCHED_CONVERSATION_SET: Arc<DashSet<String>> = Arc::new(DashSet::new());
+    static ref TOUCHED_USER_SET: Arc<DashSet<u64>> = Arc::new(DashSet::new());
+}
+
+/// called by `io_task` for every cached message.
+#[inline]
+pub(crate) fn touch(users_identify: &str, receiver: u64) {
+    if !TOUCHED_CONVERSATION_SET.contains(users_identify) {
+        TOUCHED_CONVERSATION_SET.insert(users_identify.to_owned());
+    }
+    touch_user(receiver);
+}
+
+/// called for every user whose inbox or sync events are written.
+#[inline]
+pub(crate) fn touch_user(user_id: u64) {
+    TOUCHED_USER_SET.insert(user_id);
+}
+
+/// trim `MSG_CACHE` to the latest messages, `PENDING_REACTION` to the recent toggles,
+/// `USER_INBOX` to the recent senders and `USER_SYNC` to the recent events, once per interval
+/// for every touched key, rather than once per message.
+///
+/// `USER_SYNC` never expires, a floor event marks where it's trimmed, so a device with an older
+/// sync token knows it missed something. its scores are counters rather than times, so every
+/// sweep marks the counter of the user at the time, and events are trimmed up to the counter
+/// marked before the cutoff.
+pub(super) async fn sweep() -> Result<()> {
+    let mut interval = tokio::time::interval(config().retention.sweep_interval);
+    let mut redis_ops = get_redis_ops().await;
+    loop {
+        interval.tick().await;
+        let mut conversation_list = vec![];
+        TOUCHED_CONVERSATION_SET.retain(|users_identify| {
+            conversation_list.push(users_identify.clone());
+            false
+        });
+        let mut user_list = vec![];
+        TOUCHED_USER_SET.retain(|user_id| {
+            user_list.push(*user_id);
+            false
+        });
+        for users_identify in conversation_list.iter() {
+            let key = format!("{}{}", MSG_CACHE, users_identify);
+            if let Err(e) = redis_ops
+                .trim_sort_queue(&key, config().retention.msg_cache_size)
+                .await
+            {
+                error!("trim {} error: {}", key, e);
+                continue;
+            }
+            _ = redis_ops
+                .expire(&key, config().retention.msg_cache_ttl)
+                .await;
+            let key = format!("{}{}", PENDING_REACTION, users_identify);
+            let cutoff = timestamp().saturating_sub(PENDING_REACTION_TTL.as_millis() as u64);
+            if let Err(e) = redis_ops
+                .remove_sort_queue_old_data(&key, cutoff as f64)
+                .await
+            {
+                error!("trim {} error: {}", key, e);
+                continue;
+            }
+            _ = redis_ops.expire(&key, PENDING_REACTION_TTL).await;
+        }
+        // inbox scores and sync marks are timestamps in milliseconds.
+        let inbox_cutoff =
+            timestamp().saturating_sub(config().retention.inbox_ttl.as_millis() as u64);
+        let sync_cutoff =
+            timestamp().saturating_sub(config().retention.sync_ttl.as_millis() as u64);
+        for user_id in user_list.iter() {
+            let key = format!("{}{}", USER_INBOX, user_id);
+            if let Err(e) = redis_ops
+                .remove_sort_queue_old_data(&key, inbox_cutoff as f64)
+                .await
+            {
+                error!("trim {} error: {}", key, e);
+                continue;
+            }
+            _ = redis_ops.expire(&key, config().retention.inbox_ttl).await;
+            if let Err(e) = trim_sync(&mut redis_ops, *user_id, sync_cutoff).await {
+                error!("trim {}{} error: {}", USER_SYNC, user_id, e);
+            }
+        }
+        debug!(
+            "{} conversation caches and {} users trimmed",
+            conversation_list.len(),
+            user_list.len()
+        );
+    }
+}
+
+async fn trim_sync(redis_ops: &mut RedisOps, user_id: u64, cutoff: u64) -> Result<()> {
+    let key = format!("{}{}", USER_SYNC, user_id);
+    let mark_key = format!("{}{}", USER_SYNC_MARK, user_id);
+    let seq = redis_ops
+        .get::<Option<u64>>(&sync_seq_key(user_id))
+        .await?
+        .unwrap_or(0);
+    // a mark already there keeps its time, it's the first time the counter was seen.
+    if redis_ops
+        .get_sort_queue_score(&mark_key, &seq)
+        .await?
+        .is_none()
+    {
+        redis_ops
+            .push_sort_queue(&mark_key, &seq, timestamp() as f64)
+            .await?;
+    }
+    _ = redis_ops
+        .expire(&mark_key, config().retention.sync_ttl * 2)
+        .await;
+    let mark_list = redis_ops
+        .peek_sort_queue_more_with_score::<u64>(&mark_key, 0, 1, f64::MIN, cutoff as f64, false)
+        .await?;
+    let (floor, time) = match mark_list.first() {
+        Some(mark) => *mark,
+        None => return Ok(()),
+    };
+    redis_ops
+        .remove_sort_queue_old_data(&key, floor as f64)
+        .await?;
+    redis_ops
+        .push_sort_queue(&key, &*SYNC_FLOOR, floor as f64)
+        .await?;
+    // the latest mark before the cutoff is kept for the next sweep.
+    redis_ops
+        .remove_sort_queue_old_data(&mark_key, time - 1.0)
+        .await
+}
@@ -21,6 +21,7 @@ use super::{
         business::{AddFriend, JoinGroup, LeaveGroup, RemoveFriend, SystemMessage},
         logic::{Auth, Echo, MQPusher, PreProcess},
         pure_text::PureText,
+        read::Read,
     },
 };
 use crate::service::{get_io_task_sender, handler::IOTaskSender};
@@ -136,6 +137,7 @@ impl Server {
         handler_list.push(Box::new(MQPusher::new()));
         handler_list.push(Box::new(Echo {}));
         handler_list.push(Box::new(PureText {}));
+        handler_list.push(Box::new(Read {}));
         handler_list.push(Box::new(JoinGroup {}));
         handler_list.push(Box::new(LeaveGroup {}));
         handler_list.push(Box::new(AddFriend {}));
@@ -9,7 +9,10 @@ pub(crate) static REDIS_OPS: OnceCell<RedisOps> = OnceCell::const_new();
 pub(super) async fn get_redis_ops() -> RedisOps {
     (REDIS_OPS
         .get_or_init(|| async {
-            RedisOps::connect(CONFIG.redis.addresses.clone())
+            RedisOps::connect(
+                CONFIG.redis.addresses.clone(),
+                Some(CONFIG.redis.passwords.clone()),
+            )
                 .await
                 .unwrap()
         })
@@ -18,3 +21,13 @@ pub(super) async fn get_redis_ops() -> RedisOps {
 }
 
 pub(crate) static NODE_ID: &str = "NODE_ID_MSGPROCESSOR_";
+/// how far this msgprocessor lags behind a topic, in milliseconds, read by the scheduler.
+pub(crate) static MQ_TOPIC_LAG: &str = "MQ_TOPIC_LAG_";
+/// 1 once every record of a topic is committed, read by the scheduler.
+pub(crate) static MQ_TOPIC_DRAINED: &str = "MQ_TOPIC_DRAINED_";
+/// the message node a user is connected to, set by the message node.
+pub(crate) static USER_ONLINE: &str = "USER_ONLINE_";
+/// `<platform>:<token>` of every device a user wants pushes on, registered through api.
+pub(crate) static PUSH_DEVICE_SET: &str = "PUSH_DEVICE_SET_";
+/// users and groups a user doesn't want pushes from.
+pub(crate) static PUSH_MUTE_SET: &str = "PUSH_MUTE_SET_";
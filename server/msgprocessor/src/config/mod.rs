@@ -1,6 +1,7 @@
 use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Context;
+use common::mq::Backend;
 use lazy_static::lazy_static;
 use tracing::Level;
 
@@ -11,6 +12,9 @@ struct Config0 {
     redis: Option<Redis0>,
     scheduler: Option<Scheduler0>,
     message_queue: Option<MessageQueue0>,
+    sql: Option<Sql0>,
+    persistence: Option<Persistence0>,
+    push: Option<Push0>,
 }
 
 #[derive(Debug)]
@@ -20,6 +24,9 @@ pub(crate) struct Config {
     pub(crate) redis: Redis,
     pub(crate) scheduler: Scheduler,
     pub(crate) message_queue: MessageQueue,
+    pub(crate) sql: Sql,
+    pub(crate) persistence: Persistence,
+    pub(crate) push: Push,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -37,11 +44,13 @@ pub(crate) struct Transport {
 #[derive(serde::Deserialize, Debug)]
 struct Redis0 {
     addresses: Option<Vec<String>>,
+    passwords: Option<Vec<String>>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Redis {
     pub(crate) addresses: Vec<SocketAddr>,
+    pub(crate) passwords: Vec<String>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -49,6 +58,7 @@ struct Scheduler0 {
     address: Option<String>,
     domain: Option<String>,
     cert_path: Option<String>,
+    join_token: Option<String>,
 }
 
 #[derive(Debug)]
@@ -56,16 +66,120 @@ pub(crate) struct Scheduler {
     pub(crate) address: SocketAddr,
     pub(crate) domain: String,
     pub(crate) cert: rustls::Certificate,
+    /// issued by `scheduler --issue_join_token`, presented when connecting to it.
+    pub(crate) join_token: String,
 }
 
 #[derive(serde::Deserialize, Debug)]
 struct MessageQueue0 {
+    backend: Option<String>,
     address: Option<String>,
+    dir: Option<String>,
 }
 
 #[derive(Debug)]
 pub(crate) struct MessageQueue {
+    /// must be the same as the one of message nodes.
+    pub(crate) backend: Backend,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Sql0 {
+    address: Option<String>,
+    database: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    max_connections: Option<u32>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Sql {
     pub(crate) address: String,
+    pub(crate) database: String,
+    pub(crate) username: String,
+    pub(crate) password: String,
+    pub(crate) max_connections: u32,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Persistence0 {
+    batch_size: Option<usize>,
+    batch_interval: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Persistence {
+    /// messages written to database in one transaction at most.
+    pub(crate) batch_size: usize,
+    /// a batch not full is written after such time.
+    pub(crate) batch_interval: Duration,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Push0 {
+    queue_size: Option<usize>,
+    batch_size: Option<usize>,
+    batch_interval: Option<u64>,
+    rate: Option<u32>,
+    apns: Option<Apns0>,
+    fcm: Option<Fcm0>,
+    http: Option<Http0>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Apns0 {
+    key_path: Option<String>,
+    key_id: Option<String>,
+    team_id: Option<String>,
+    topic: Option<String>,
+    sandbox: Option<bool>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Fcm0 {
+    server_key: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Http0 {
+    url: Option<String>,
+}
+
+/// offline push is off unless any provider is configured.
+#[derive(Debug)]
+pub(crate) struct Push {
+    /// messages waiting to be checked for offline recipients, newer ones are dropped when full.
+    pub(crate) queue_size: usize,
+    /// notifications handed to a provider at once.
+    pub(crate) batch_size: usize,
+    pub(crate) batch_interval: Duration,
+    /// notifications per second of every provider.
+    pub(crate) rate: u32,
+    pub(crate) apns: Option<Apns>,
+    pub(crate) fcm: Option<Fcm>,
+    pub(crate) http: Option<Http>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Apns {
+    /// the `.p8` signing key downloaded from apple developer account.
+    pub(crate) key: Vec<u8>,
+    pub(crate) key_id: String,
+    pub(crate) team_id: String,
+    /// bundle id of the app.
+    pub(crate) topic: String,
+    pub(crate) sandbox: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct Fcm {
+    pub(crate) server_key: String,
+}
+
+/// a plain http endpoint receiving notifications as json, for tests and self-hosted gateways.
+#[derive(Debug)]
+pub(crate) struct Http {
+    pub(crate) url: String,
 }
 
 impl Config {
@@ -78,12 +192,29 @@ impl Config {
             "error" => Level::ERROR,
             _ => Level::INFO,
         };
+        let redis = Redis::from_redis0(config0.redis.unwrap());
+        let message_queue =
+            MessageQueue::from_message_queue0(config0.message_queue.unwrap(), &redis);
         Config {
             log_level,
             transport: Transport::from_transport0(config0.transport.unwrap()),
-            redis: Redis::from_redis0(config0.redis.unwrap()),
+            redis,
             scheduler: Scheduler::from_scheduler0(config0.scheduler.unwrap()),
-            message_queue: MessageQueue::from_message_queue0(config0.message_queue.unwrap()),
+            message_queue,
+            sql: Sql::from_sql0(config0.sql.unwrap()),
+            persistence: Persistence::from_persistence0(config0.persistence.unwrap_or(Persistence0 {
+                batch_size: None,
+                batch_interval: None,
+            })),
+            push: Push::from_push0(config0.push.unwrap_or(Push0 {
+                queue_size: None,
+                batch_size: None,
+                batch_interval: None,
+                rate: None,
+                apns: None,
+                fcm: None,
+                http: None,
+            })),
         }
     }
 }
@@ -107,7 +238,10 @@ impl Redis {
                     .expect("parse redis address failed"),
             );
         }
-        Redis { addresses: addr }
+        Redis {
+            addresses: addr,
+            passwords: redis0.passwords.unwrap_or_default(),
+        }
     }
 }
 
@@ -124,14 +258,85 @@ impl Scheduler {
                 .expect("parse scheduler address failed"),
             domain: scheduler0.domain.take().unwrap(),
             cert: rustls::Certificate(cert),
+            join_token: scheduler0.join_token.take().unwrap_or_default(),
         }
     }
 }
 
 impl MessageQueue {
-    fn from_message_queue0(message_queue0: MessageQueue0) -> Self {
-        MessageQueue {
-            address: message_queue0.address.unwrap(),
+    /// the redis backend shares the redis cluster of this node.
+    fn from_message_queue0(mut message_queue0: MessageQueue0, redis: &Redis) -> Self {
+        let backend = match message_queue0.backend.as_deref().unwrap_or("kafka") {
+            "kafka" => Backend::Kafka {
+                address: message_queue0.address.take().unwrap(),
+            },
+            "redis" => Backend::Redis {
+                addresses: redis.addresses.clone(),
+                passwords: redis.passwords.clone(),
+            },
+            "file" => Backend::File {
+                dir: PathBuf::from(message_queue0.dir.take().unwrap_or("./message/mq".to_string())),
+            },
+            backend => panic!("unknown message queue backend: {}", backend),
+        };
+        MessageQueue { backend }
+    }
+}
+
+impl Sql {
+    fn from_sql0(sql0: Sql0) -> Sql {
+        Sql {
+            address: sql0.address.unwrap(),
+            database: sql0.database.unwrap(),
+            username: sql0.username.unwrap(),
+            password: sql0.password.unwrap(),
+            max_connections: sql0.max_connections.unwrap(),
+        }
+    }
+}
+
+impl Persistence {
+    fn from_persistence0(persistence0: Persistence0) -> Self {
+        Persistence {
+            batch_size: persistence0.batch_size.unwrap_or(256),
+            batch_interval: Duration::from_millis(persistence0.batch_interval.unwrap_or(100)),
+        }
+    }
+}
+
+impl Push {
+    fn from_push0(push0: Push0) -> Self {
+        Push {
+            queue_size: push0.queue_size.unwrap_or(10000),
+            batch_size: push0.batch_size.unwrap_or(100),
+            batch_interval: Duration::from_millis(push0.batch_interval.unwrap_or(200)),
+            rate: push0.rate.unwrap_or(500),
+            apns: push0.apns.map(Apns::from_apns0),
+            fcm: push0.fcm.map(|fcm0| Fcm {
+                server_key: fcm0.server_key.unwrap(),
+            }),
+            http: push0.http.map(|http0| Http {
+                url: http0.url.unwrap(),
+            }),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.apns.is_some() || self.fcm.is_some() || self.http.is_some()
+    }
+}
+
+impl Apns {
+    fn from_apns0(mut apns0: Apns0) -> Self {
+        let key = fs::read(PathBuf::from(apns0.key_path.as_ref().unwrap()))
+            .context("read apns key file failed.")
+            .unwrap();
+        Apns {
+            key,
+            key_id: apns0.key_id.take().unwrap(),
+            team_id: apns0.team_id.take().unwrap(),
+            topic: apns0.topic.take().unwrap(),
+            sandbox: apns0.sandbox.unwrap_or(false),
         }
     }
 }
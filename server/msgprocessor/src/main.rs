@@ -7,7 +7,9 @@ pub(crate) mod cache;
 pub(crate) mod config;
 pub(crate) mod model;
 pub(crate) mod mq;
+pub(crate) mod push;
 pub(crate) mod scheduler;
+pub(crate) mod sql;
 pub(crate) mod util;
 
 #[tokio::main]
@@ -28,6 +30,10 @@ async fn main() -> Result<()> {
         "prim msgprocessor[{}] running on",
         my_id()
     );
+    push::start().await?;
+    tokio::spawn(async move {
+        mq::report_lag().await;
+    });
     scheduler::start().await?;
     let (_tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
     rx.recv().await;
@@ -0,0 +1,228 @@
+use std::{
+    ops::Add,
+    time::{Duration, SystemTime},
+};
+
+use base64::Engine;
+use chrono::{DateTime, Local};
+use lib::{
+    entity::{Msg, MsgExtension, Type, MAX_MSG_TTL},
+    util::conversation_id,
+    Result,
+};
+use sqlx::{Postgres, Transaction};
+
+/// a row of `msg.message`, encoded the same way as `api` reads it.
+#[derive(Debug, Clone)]
+pub(crate) struct Message {
+    pub(crate) sender: i64,
+    pub(crate) receiver: i64,
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) seq_num: i64,
+    pub(crate) typ: Type,
+    pub(crate) version: i16,
+    pub(crate) extension: String,
+    pub(crate) payload: String,
+    pub(crate) conversation_id: String,
+    /// the message quoted, see `MsgExtension`.
+    pub(crate) quote_seq: Option<i64>,
+    /// root of the thread the message replies in.
+    pub(crate) thread_root: Option<i64>,
+    /// when the message is purged, by its own time to live or that of the conversation.
+    pub(crate) expire_at: Option<DateTime<Local>>,
+}
+
+impl From<&Msg> for Message {
+    fn from(msg: &Msg) -> Self {
+        let t: DateTime<Local> =
+            DateTime::from(SystemTime::UNIX_EPOCH.add(Duration::from_millis(msg.timestamp())));
+        let engine = base64::engine::GeneralPurpose::new(
+            &base64::alphabet::URL_SAFE,
+            base64::engine::general_purpose::NO_PAD,
+        );
+        let extension = MsgExtension::parse(msg.extension());
+        Self {
+            sender: msg.sender() as i64,
+            receiver: msg.receiver() as i64,
+            timestamp: t,
+            seq_num: msg.seqnum() as i64,
+            typ: msg.typ(),
+            version: msg.version() as i16,
+            extension: engine.encode(String::from_utf8_lossy(msg.extension()).to_string()),
+            payload: engine.encode(String::from_utf8_lossy(msg.payload()).to_string()),
+            conversation_id: conversation_id(msg.sender(), msg.receiver()),
+            quote_seq: extension.quote.map(|seq_num| seq_num as i64),
+            thread_root: extension.thread_root.map(|seq_num| seq_num as i64),
+            expire_at: extension
+                .ttl
+                .map(|ttl| t + chrono::Duration::seconds(ttl.min(MAX_MSG_TTL) as i64)),
+        }
+    }
+}
+
+impl Message {
+    /// a message already stored is left untouched, so replaying a batch is harmless, and one
+    /// withdrawn before it is saved stays withdrawn.
+    pub(crate) async fn insert_batch(
+        tx: &mut Transaction<'_, Postgres>,
+        msg_list: &[Message],
+    ) -> Result<()> {
+        if msg_list.is_empty() {
+            return Ok(());
+        }
+        let mut batch_inserter: sqlx::QueryBuilder<Postgres> = sqlx::QueryBuilder::new("INSERT INTO msg.message (sender, receiver, timestamp, seq_num, type, version, extension, payload, conversation_id, quote_seq, thread_root, expire_at) ");
+        batch_inserter.push_values(msg_list, |mut binder, msg| {
+            binder.push_bind(msg.sender);
+            binder.push_bind(msg.receiver);
+            binder.push_bind(msg.timestamp);
+            binder.push_bind(msg.seq_num);
+            binder.push_bind(msg.typ.value() as i16);
+            binder.push_bind(msg.version);
+            binder.push_bind(&msg.extension);
+            binder.push_bind(&msg.payload);
+            binder.push_bind(&msg.conversation_id);
+            binder.push_bind(msg.quote_seq);
+            binder.push_bind(msg.thread_root);
+            binder.push_bind(msg.expire_at);
+        });
+        batch_inserter.push(" ON CONFLICT (conversation_id, seq_num) DO NOTHING");
+        batch_inserter.build().execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    /// messages without a time to live of their own take that of their conversation.
+    pub(crate) async fn fill_expire_at(
+        tx: &mut Transaction<'_, Postgres>,
+        msg_list: &mut [Message],
+    ) -> Result<()> {
+        let conversation_list = msg_list
+            .iter()
+            .filter(|msg| msg.expire_at.is_none())
+            .map(|msg| msg.conversation_id.clone())
+            .collect::<Vec<String>>();
+        if conversation_list.is_empty() {
+            return Ok(());
+        }
+        let ttl_list: Vec<(String, i32)> = sqlx::query_as(
+            "SELECT conversation_id, ttl FROM msg.conversation_ttl WHERE conversation_id = ANY($1)",
+        )
+        .bind(&conversation_list)
+        .fetch_all(&mut **tx)
+        .await?;
+        if ttl_list.is_empty() {
+            return Ok(());
+        }
+        for msg in msg_list.iter_mut().filter(|msg| msg.expire_at.is_none()) {
+            if let Some((_, ttl)) = ttl_list
+                .iter()
+                .find(|(conversation_id, _)| *conversation_id == msg.conversation_id)
+            {
+                msg.expire_at = Some(msg.timestamp + chrono::Duration::seconds(*ttl as i64));
+            }
+        }
+        Ok(())
+    }
+
+    /// replace the content of the message with the same seqnum, a withdrawn one stays withdrawn.
+    ///
+    /// the replaced content is kept as a revision first, an edit not newer than the last one
+    /// applied is a replay and changes nothing. an edit of a message not saved yet is parked,
+    /// see `apply_pending_edit`.
+    pub(crate) async fn apply_edit(&self, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        sqlx::query("INSERT INTO msg.message_revision (conversation_id, seq_num, revision, payload, editor, edited_at) SELECT conversation_id, seq_num, revision + 1, payload, $3, $4 FROM msg.message WHERE conversation_id = $1 AND seq_num = $2 AND type <> $5 AND (edited_at IS NULL OR edited_at < $4) ON CONFLICT (conversation_id, seq_num, revision) DO NOTHING")
+            .bind(&self.conversation_id)
+            .bind(self.seq_num)
+            .bind(self.sender)
+            .bind(self.timestamp)
+            .bind(Type::Withdraw.value() as i16)
+            .execute(&mut **tx)
+            .await?;
+        let res = sqlx::query("UPDATE msg.message SET payload = $3, revision = revision + 1, edited_at = $4 WHERE conversation_id = $1 AND seq_num = $2 AND type <> $5 AND (edited_at IS NULL OR edited_at < $4)")
+            .bind(&self.conversation_id)
+            .bind(self.seq_num)
+            .bind(&self.payload)
+            .bind(self.timestamp)
+            .bind(Type::Withdraw.value() as i16)
+            .execute(&mut **tx)
+            .await?;
+        if res.rows_affected() > 0 {
+            return Ok(());
+        }
+        sqlx::query("INSERT INTO msg.message_pending_edit (conversation_id, seq_num, payload, editor, edited_at) SELECT $1, $2, $3, $4, $5 WHERE NOT EXISTS (SELECT 1 FROM msg.message WHERE conversation_id = $1 AND seq_num = $2) ON CONFLICT (conversation_id, seq_num, edited_at) DO NOTHING")
+            .bind(&self.conversation_id)
+            .bind(self.seq_num)
+            .bind(&self.payload)
+            .bind(self.sender)
+            .bind(self.timestamp)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    /// apply the edits parked for the messages just saved, in the order they were made.
+    pub(crate) async fn apply_pending_edit(
+        tx: &mut Transaction<'_, Postgres>,
+        msg_list: &[Message],
+    ) -> Result<()> {
+        if msg_list.is_empty() {
+            return Ok(());
+        }
+        let mut edit_list: Vec<(String, i64, String, i64, DateTime<Local>)> = sqlx::query_as("DELETE FROM msg.message_pending_edit WHERE (conversation_id, seq_num) IN (SELECT * FROM UNNEST($1::varchar[], $2::bigint[])) RETURNING conversation_id, seq_num, payload, editor, edited_at")
+            .bind(
+                msg_list
+                    .iter()
+                    .map(|msg| msg.conversation_id.clone())
+                    .collect::<Vec<String>>(),
+            )
+            .bind(msg_list.iter().map(|msg| msg.seq_num).collect::<Vec<i64>>())
+            .fetch_all(&mut **tx)
+            .await?;
+        edit_list.sort_by_key(|(_, _, _, _, edited_at)| *edited_at);
+        for (conversation_id, seq_num, payload, editor, edited_at) in edit_list {
+            let original = match msg_list
+                .iter()
+                .find(|msg| msg.conversation_id == conversation_id && msg.seq_num == seq_num)
+            {
+                Some(original) => original,
+                None => continue,
+            };
+            let edit = Message {
+                sender: editor,
+                timestamp: edited_at,
+                payload,
+                ..original.clone()
+            };
+            edit.apply_edit(tx).await?;
+        }
+        Ok(())
+    }
+
+    /// clear the content of the message with the same seqnum.
+    pub(crate) async fn apply_withdraw(&self, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        sqlx::query("INSERT INTO msg.message (sender, receiver, timestamp, seq_num, type, version, extension, payload, conversation_id) VALUES ($1, $2, $3, $4, $5, $6, '', '', $7) ON CONFLICT (conversation_id, seq_num) DO UPDATE SET type = EXCLUDED.type, payload = '', extension = ''")
+            .bind(self.sender)
+            .bind(self.receiver)
+            .bind(self.timestamp)
+            .bind(self.seq_num)
+            .bind(Type::Withdraw.value() as i16)
+            .bind(self.version)
+            .bind(&self.conversation_id)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    /// toggle the reaction of the sender with the payload on the message with the same seqnum,
+    /// a toggle not newer than the last one applied is a replay and changes nothing.
+    pub(crate) async fn apply_reaction(&self, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        sqlx::query("INSERT INTO msg.message_reaction (conversation_id, seq_num, user_id, emoji, active, updated_at) VALUES ($1, $2, $3, $4, true, $5) ON CONFLICT (conversation_id, seq_num, user_id, emoji) DO UPDATE SET active = NOT msg.message_reaction.active, updated_at = EXCLUDED.updated_at WHERE msg.message_reaction.updated_at < EXCLUDED.updated_at")
+            .bind(&self.conversation_id)
+            .bind(self.seq_num)
+            .bind(self.sender)
+            .bind(&self.payload)
+            .bind(self.timestamp)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ahash::{AHashMap, AHashSet};
+use common::mq::{MQConsumer, MQProducer, Record};
+use lazy_static::lazy_static;
+use lib::{
+    entity::{Msg, Type, HEAD_LEN},
+    Result,
+};
+use tokio::{
+    sync::{watch, Mutex, OnceCell},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    cache::{get_redis_ops, MQ_TOPIC_DRAINED, MQ_TOPIC_LAG},
+    config::CONFIG,
+    model::msg::Message,
+    push,
+    sql::get_sql_pool,
+    util::my_id,
+};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+const LAG_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+struct ConsumerTask {
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+    /// in milliseconds, how far the last batch is behind.
+    lag: Arc<AtomicU64>,
+    /// every record of the topic is committed, false until known.
+    drained: Arc<AtomicBool>,
+}
+
+impl ConsumerTask {
+    /// wait for the in-flight batch to be saved and committed.
+    async fn stop(self) {
+        _ = self.stop.send(true);
+        if let Err(e) = self.handle.await {
+            error!("consumer task exited abnormally: {}", e);
+        }
+    }
+}
+
+lazy_static! {
+    /// consumer tasks of every assigned topic, the index of a task is also its consumer name.
+    static ref ASSIGNMENT: Mutex<AHashMap<String, Vec<ConsumerTask>>> = Mutex::new(AHashMap::new());
+}
+
+/// sends messages never savable to `<topic>-dlq`, the same dead letter topic message nodes use.
+static DEAD_LETTER_PRODUCER: OnceCell<Arc<dyn MQProducer>> = OnceCell::const_new();
+
+/// run `consumer_number` consumers on `topic`.
+///
+/// assigning a topic again only starts the missing ones, or stops the extra ones, so it's
+/// safe for the scheduler to repeat an assignment.
+pub(crate) async fn assign(topic: &str, consumer_number: usize) -> Result<()> {
+    let consumer_number = consumer_number.max(1);
+    let mut assignment = ASSIGNMENT.lock().await;
+    let task_list = assignment.entry(topic.to_owned()).or_default();
+    let mut extra_list = vec![];
+    while task_list.len() > consumer_number {
+        extra_list.push(task_list.pop().unwrap());
+    }
+    for i in 0..consumer_number {
+        // a finished task has panicked, start it again.
+        if i < task_list.len() && !task_list[i].handle.is_finished() {
+            continue;
+        }
+        let consumer = CONFIG
+            .message_queue
+            .backend
+            .consumer(
+                topic,
+                &format!("{}-default", topic),
+                &format!("{}-{}", my_id(), i),
+            )
+            .await?;
+        let (stop, stop_rx) = watch::channel(false);
+        let lag = Arc::new(AtomicU64::new(0));
+        let drained = Arc::new(AtomicBool::new(false));
+        let task_lag = lag.clone();
+        let task_drained = drained.clone();
+        let handle = tokio::spawn(async move {
+            persist(consumer, stop_rx, task_lag, task_drained).await;
+        });
+        let task = ConsumerTask {
+            stop,
+            handle,
+            lag,
+            drained,
+        };
+        if i < task_list.len() {
+            task_list[i] = task;
+        } else {
+            task_list.push(task);
+        }
+    }
+    // others may read and change the assignment while the extra ones finish their batches.
+    drop(assignment);
+    futures::future::join_all(extra_list.into_iter().map(|task| task.stop())).await;
+    info!(
+        "topic {} assigned with {} consumers",
+        topic, consumer_number
+    );
+    Ok(())
+}
+
+/// stop all consumers of `topic`, returns after their last batches are committed.
+pub(crate) async fn unassign(topic: &str) {
+    let task_list = match ASSIGNMENT.lock().await.remove(topic) {
+        Some(task_list) => task_list,
+        None => return,
+    };
+    futures::future::join_all(task_list.into_iter().map(|task| task.stop())).await;
+    info!("topic {} unassigned", topic);
+}
+
+/// topics and the number of their running consumers.
+pub(crate) async fn assignment() -> HashMap<String, usize> {
+    ASSIGNMENT
+        .lock()
+        .await
+        .iter()
+        .map(|(topic, task_list)| {
+            let running = task_list
+                .iter()
+                .filter(|task| !task.handle.is_finished())
+                .count();
+            (topic.clone(), running)
+        })
+        .collect()
+}
+
+/// publish the lag of every assigned topic and whether it's drained, the scheduler takes the
+/// lag into account when balancing topics, and drops topics of departed message nodes once
+/// they are drained.
+pub(crate) async fn report_lag() {
+    let mut interval = tokio::time::interval(LAG_REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let lag_list = ASSIGNMENT
+            .lock()
+            .await
+            .iter()
+            .map(|(topic, task_list)| {
+                let lag = task_list
+                    .iter()
+                    .map(|task| task.lag.load(Ordering::Acquire))
+                    .max()
+                    .unwrap_or(0);
+                // consumers check the whole topic, any of them knowing it's drained is enough.
+                let drained = task_list
+                    .iter()
+                    .any(|task| task.drained.load(Ordering::Acquire));
+                (topic.clone(), lag, drained)
+            })
+            .collect::<Vec<(String, u64, bool)>>();
+        let mut redis_ops = get_redis_ops().await;
+        for (topic, lag, drained) in lag_list {
+            if let Err(e) = redis_ops
+                .set_exp(
+                    &format!("{}{}", MQ_TOPIC_LAG, topic),
+                    &lag,
+                    LAG_REPORT_INTERVAL * 6,
+                )
+                .await
+            {
+                error!("report lag of {} error: {}", topic, e);
+            }
+            if let Err(e) = redis_ops
+                .set_exp(
+                    &format!("{}{}", MQ_TOPIC_DRAINED, topic),
+                    &(drained as u8),
+                    LAG_REPORT_INTERVAL * 6,
+                )
+                .await
+            {
+                error!("report drained of {} error: {}", topic, e);
+            }
+        }
+    }
+}
+
+/// write consumed messages into `msg.message` batch by batch, offsets are committed only after
+/// the batch is committed to database, so a crash in between only replays some messages, which
+/// is harmless for the writes are idempotent.
+///
+/// a read is never cancelled, records it has taken may be lost otherwise, a stop request is
+/// checked between batches instead, which come at least every `batch_interval`.
+async fn persist(
+    mut consumer: Box<dyn MQConsumer>,
+    stop: watch::Receiver<bool>,
+    lag: Arc<AtomicU64>,
+    drained: Arc<AtomicBool>,
+) {
+    let mut last_check: Option<Instant> = None;
+    while !*stop.borrow() {
+        let record_list = match consumer
+            .recv_batch(
+                CONFIG.persistence.batch_size,
+                CONFIG.persistence.batch_interval,
+            )
+            .await
+        {
+            Ok(record_list) => record_list,
+            Err(e) => {
+                error!("mq consumer recv error: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if record_list.is_empty() {
+            lag.store(0, Ordering::Release);
+            // the check asks the queue about the whole topic, so not on every idle batch.
+            if last_check.is_none_or(|last_check| last_check.elapsed() >= LAG_REPORT_INTERVAL) {
+                last_check = Some(Instant::now());
+                match consumer.drained().await {
+                    Ok(is_drained) => drained.store(is_drained, Ordering::Release),
+                    Err(e) => {
+                        drained.store(false, Ordering::Release);
+                        error!("mq consumer drained check error: {}", e);
+                    }
+                }
+            }
+            continue;
+        }
+        drained.store(false, Ordering::Release);
+        let mut backoff = Duration::from_millis(100);
+        loop {
+            match save(&record_list).await {
+                Ok(_) => break,
+                Err(e) if is_retryable(&e) => {
+                    error!("save {} messages error: {}", record_list.len(), e);
+                }
+                Err(e) => {
+                    warn!(
+                        "save {} messages error: {}, saved one by one",
+                        record_list.len(),
+                        e
+                    );
+                    if save_each(&record_list, &stop).await {
+                        break;
+                    }
+                }
+            }
+            // left uncommitted, the next owner of the topic saves them again.
+            if *stop.borrow() {
+                return;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        let mut last_map: AHashMap<i32, &Record> = AHashMap::new();
+        for record in record_list.iter() {
+            last_map.insert(record.partition, record);
+        }
+        for record in last_map.into_values() {
+            if let Err(e) = consumer.commit(record).await {
+                error!("mq consumer commit error: {}", e);
+            }
+        }
+        push::enqueue(&record_list);
+        // a batch not filled up means there is nothing more to read.
+        let behind = if record_list.len() < CONFIG.persistence.batch_size {
+            0
+        } else {
+            let last = record_list.last().unwrap().timestamp;
+            (chrono::Utc::now().timestamp_millis() - last).max(0) as u64
+        };
+        lag.store(behind, Ordering::Release);
+    }
+}
+
+/// save the records of a batch failed as a whole one at a time, so a record never savable
+/// only holds up itself and goes to the dead letter topic.
+///
+/// return false if any record is still not saved, the batch is tried again as a whole.
+async fn save_each(record_list: &[Record], stop: &watch::Receiver<bool>) -> bool {
+    for record in record_list {
+        let res = match save(std::slice::from_ref(record)).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_retryable(&e) => Err(e),
+            Err(e) => {
+                warn!(
+                    "message at {}:{} can't be saved: {}, moved to dead letter",
+                    record.partition, record.offset, e
+                );
+                bury(record).await
+            }
+        };
+        if let Err(e) = res {
+            error!(
+                "save message at {}:{} error: {}",
+                record.partition, record.offset, e
+            );
+            return false;
+        }
+        if *stop.borrow() {
+            return false;
+        }
+    }
+    true
+}
+
+async fn bury(record: &Record) -> Result<()> {
+    let producer = DEAD_LETTER_PRODUCER
+        .get_or_try_init(|| CONFIG.message_queue.backend.producer())
+        .await?;
+    let dead_letter_topic = format!("{}-dlq", record.topic);
+    producer.ensure_topic(&dead_letter_topic, 1).await?;
+    producer
+        .send(
+            &dead_letter_topic,
+            &record.key,
+            &record.payload,
+            record.timestamp,
+        )
+        .await
+}
+
+/// errors of the connection, the server resources or concurrent transactions go away by
+/// themselves, others, like violated constraints or broken data, fail the same way every time.
+fn is_retryable(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Database(e)) => match e.code() {
+            // sqlstate classes: connection exception, transaction rollback, insufficient
+            // resources, operator intervention and system error.
+            Some(code) => ["08", "40", "53", "57", "58"]
+                .iter()
+                .any(|class| code.starts_with(class)),
+            None => true,
+        },
+        Some(
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed,
+        ) => true,
+        Some(_) => false,
+        None => true,
+    }
+}
+
+/// split records into new messages and the changes to apply after them, in the order they
+/// were sent, a message delivered twice is inserted once.
+fn split(record_list: &[Record]) -> (Vec<Message>, Vec<Msg>) {
+    let mut new_list = vec![];
+    let mut control_list = vec![];
+    let mut seen_set = AHashSet::new();
+    for record in record_list {
+        if record.payload.len() < HEAD_LEN {
+            warn!(
+                "broken message at {}:{}, skipped",
+                record.partition, record.offset
+            );
+            continue;
+        }
+        let msg = Msg(record.payload.clone());
+        match msg.typ() {
+            Type::Edit | Type::Withdraw | Type::Reaction => control_list.push(msg),
+            // the api purged the message before telling the others.
+            Type::Expire => {}
+            _ => {
+                let message = Message::from(&msg);
+                if seen_set.insert((message.conversation_id.clone(), message.seq_num)) {
+                    new_list.push(message);
+                }
+            }
+        }
+    }
+    (new_list, control_list)
+}
+
+/// new messages are inserted in one statement along with the edits waiting for them, edits,
+/// withdraws and reactions are applied after them in the order they were sent, all in one
+/// transaction.
+async fn save(record_list: &[Record]) -> Result<()> {
+    let (mut new_list, control_list) = split(record_list);
+    let mut tx = get_sql_pool().await.begin().await?;
+    Message::fill_expire_at(&mut tx, &mut new_list).await?;
+    Message::insert_batch(&mut tx, &new_list).await?;
+    Message::apply_pending_edit(&mut tx, &new_list).await?;
+    for msg in control_list.iter() {
+        let message = Message::from(msg);
+        match msg.typ() {
+            Type::Edit => message.apply_edit(&mut tx).await?,
+            Type::Withdraw => message.apply_withdraw(&mut tx).await?,
+            _ => message.apply_reaction(&mut tx).await?,
+        }
+    }
+    tx.commit().await?;
+    debug!(
+        "{} messages and {} changes saved",
+        new_list.len(),
+        control_list.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use common::mq::Record;
+    use lib::entity::{Msg, Type};
+
+    use super::{is_retryable, split};
+
+    fn record(offset: i64, msg: &Msg) -> Record {
+        Record {
+            topic: "msg-test".to_owned(),
+            partition: 0,
+            offset,
+            key: vec![],
+            payload: msg.as_slice().to_vec(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let text = Msg::text(1, 2, 0, "hello");
+        let mut edit = Msg::text(1, 2, 0, "hello!");
+        edit.set_type(Type::Edit);
+        let mut withdraw = Msg::text(1, 2, 0, "");
+        withdraw.set_type(Type::Withdraw);
+        let mut expire = Msg::text(1, 2, 0, "");
+        expire.set_type(Type::Expire);
+        let mut broken = record(4, &text);
+        broken.payload.truncate(3);
+        let record_list = vec![
+            record(0, &withdraw),
+            record(1, &text),
+            record(2, &edit),
+            record(3, &expire),
+            broken,
+            record(5, &text),
+        ];
+        let (new_list, control_list) = split(&record_list);
+        assert_eq!(new_list.len(), 1);
+        assert_eq!(new_list[0].typ, Type::Text);
+        // changes keep the order they were sent in.
+        assert_eq!(
+            control_list
+                .iter()
+                .map(|msg| msg.typ())
+                .collect::<Vec<Type>>(),
+            vec![Type::Withdraw, Type::Edit]
+        );
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&sqlx::Error::PoolTimedOut.into()));
+        assert!(is_retryable(
+            &sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into()).into()
+        ));
+        assert!(!is_retryable(&sqlx::Error::RowNotFound.into()));
+        assert!(!is_retryable(
+            &sqlx::Error::ColumnNotFound("seq_num".to_owned()).into()
+        ));
+        // unknown errors are tried again rather than dropped.
+        assert!(is_retryable(&anyhow::anyhow!("unknown")));
+    }
+}
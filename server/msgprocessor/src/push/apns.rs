@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::future;
+use hyper::{header, Body, Method, Request, StatusCode, Uri};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use lib::Result;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::config::Apns;
+
+use super::{client::HttpClient, Delivery, Notification, PushProvider};
+
+const APNS_HOST: &str = "api.push.apple.com";
+const APNS_SANDBOX_HOST: &str = "api.sandbox.push.apple.com";
+/// apple rejects provider tokens older than an hour, and refreshing more than once per
+/// 20 minutes.
+const TOKEN_TTL: Duration = Duration::from_secs(50 * 60);
+
+#[derive(serde::Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    iat: u64,
+}
+
+/// apple push notification service over http/2, authorized by a token signed with the `.p8` key.
+pub(crate) struct ApnsProvider {
+    host: &'static str,
+    key: EncodingKey,
+    key_id: String,
+    team_id: String,
+    topic: String,
+    client: HttpClient,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl ApnsProvider {
+    pub(crate) fn new(config: &Apns) -> Result<Self> {
+        let host = if config.sandbox {
+            APNS_SANDBOX_HOST
+        } else {
+            APNS_HOST
+        };
+        let uri = format!("https://{}", host).parse::<Uri>()?;
+        Ok(Self {
+            host,
+            key: EncodingKey::from_ec_pem(&config.key)?,
+            key_id: config.key_id.clone(),
+            team_id: config.team_id.clone(),
+            topic: config.topic.clone(),
+            client: HttpClient::new(&uri, true)?,
+            token: Mutex::new(None),
+        })
+    }
+
+    async fn provider_token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some((token, issued_at)) = token.as_ref() {
+            if issued_at.elapsed() < TOKEN_TTL {
+                return Ok(token.clone());
+            }
+        }
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.key_id.clone());
+        let claims = Claims {
+            iss: &self.team_id,
+            iat: lib::util::timestamp() / 1000,
+        };
+        let new_token = jsonwebtoken::encode(&header, &claims, &self.key)?;
+        *token = Some((new_token.clone(), Instant::now()));
+        Ok(new_token)
+    }
+
+    async fn send(&self, notification: &Notification, provider_token: &str) -> Result<Delivery> {
+        let body = json!({
+            "aps": {
+                "alert": {
+                    "title": notification.title,
+                    "body": notification.body,
+                },
+                "sound": "default",
+                "thread-id": notification.conversation_id,
+            },
+            "sender": notification.sender,
+            "conversation_id": notification.conversation_id,
+            "seqnum": notification.seqnum,
+            "mentioned": notification.mentioned,
+        });
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "https://{}/3/device/{}",
+                self.host, notification.token
+            ))
+            .header(header::AUTHORIZATION, format!("bearer {}", provider_token))
+            .header("apns-topic", &self.topic)
+            .header("apns-push-type", "alert")
+            .header("apns-priority", "10")
+            .body(Body::from(body.to_string()))?;
+        let (status, body) = self.client.request(req).await?;
+        if status.is_success() {
+            return Ok(Delivery::Sent);
+        }
+        let reason = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|resp| resp["reason"].as_str().map(|reason| reason.to_owned()))
+            .unwrap_or_default();
+        if status == StatusCode::GONE || reason == "BadDeviceToken" {
+            return Ok(Delivery::InvalidToken);
+        }
+        Err(anyhow!("apns answered {}: {}", status, reason))
+    }
+}
+
+#[async_trait]
+impl PushProvider for ApnsProvider {
+    fn platform(&self) -> &'static str {
+        "apns"
+    }
+
+    /// requests of a batch share one http/2 connection.
+    async fn send_batch(&self, notification_list: &[Notification]) -> Result<Vec<Delivery>> {
+        let provider_token = self.provider_token().await?;
+        Ok(future::join_all(
+            notification_list
+                .iter()
+                .map(|notification| self.send(notification, &provider_token)),
+        )
+        .await
+        .into_iter()
+        .zip(notification_list)
+        .map(|(res, notification)| {
+            res.unwrap_or_else(|e| {
+                warn!("apns push to {} error: {}", notification.user_id, e);
+                Delivery::Failed
+            })
+        })
+        .collect())
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use futures::future;
+use hyper::{
+    client::conn::{Builder, SendRequest},
+    Body, Request, StatusCode, Uri,
+};
+use lib::Result;
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_rustls::{
+    rustls::{self, ClientConfig, RootCertStore, ServerName},
+    TlsConnector,
+};
+use tracing::{debug, error};
+
+/// a single connection to a push gateway, reconnected on failure.
+///
+/// http/2 requests share the connection, http/1 requests wait for each other.
+pub(super) struct HttpClient {
+    host: String,
+    port: u16,
+    http2: bool,
+    tls: Option<TlsConnector>,
+    sender: Mutex<Option<SendRequest<Body>>>,
+}
+
+impl HttpClient {
+    pub(super) fn new(uri: &Uri, http2: bool) -> Result<Self> {
+        let host = uri
+            .host()
+            .ok_or_else(|| anyhow!("no host in {}", uri))?
+            .to_owned();
+        let is_https = uri.scheme_str() == Some("https");
+        let port = uri.port_u16().unwrap_or(if is_https { 443 } else { 80 });
+        let tls = if is_https {
+            let mut root_store = RootCertStore::empty();
+            for cert in rustls_native_certs::load_native_certs()? {
+                root_store.add(&rustls::Certificate(cert.0))?;
+            }
+            let mut config = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+            config.alpn_protocols = if http2 {
+                vec![b"h2".to_vec()]
+            } else {
+                vec![b"http/1.1".to_vec()]
+            };
+            Some(TlsConnector::from(Arc::new(config)))
+        } else {
+            None
+        };
+        Ok(Self {
+            host,
+            port,
+            http2,
+            tls,
+            sender: Mutex::new(None),
+        })
+    }
+
+    pub(super) async fn request(&self, req: Request<Body>) -> Result<(StatusCode, Vec<u8>)> {
+        let mut guard = self.sender.lock().await;
+        let resp = self.ready(&mut guard).await?.send_request(req);
+        // http/1 has one request in flight at most, so the lock is held until it's done.
+        if self.http2 {
+            drop(guard);
+        }
+        let resp = resp.await?;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        Ok((status, body.to_vec()))
+    }
+
+    async fn ready<'a>(
+        &self,
+        sender: &'a mut Option<SendRequest<Body>>,
+    ) -> Result<&'a mut SendRequest<Body>> {
+        if let Some(inner) = sender.as_mut() {
+            if future::poll_fn(|cx| inner.poll_ready(cx)).await.is_err() {
+                *sender = None;
+            }
+        }
+        if sender.is_none() {
+            *sender = Some(self.connect().await?);
+        }
+        Ok(sender.as_mut().unwrap())
+    }
+
+    async fn connect(&self) -> Result<SendRequest<Body>> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let mut builder = Builder::new();
+        builder.http2_only(self.http2);
+        let host = self.host.clone();
+        let sender = match self.tls.as_ref() {
+            Some(connector) => {
+                let server_name = ServerName::try_from(self.host.as_str())?;
+                let stream = connector.connect(server_name, stream).await?;
+                let (sender, connection) = builder.handshake(stream).await?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        error!("connection to {} error: {}", host, e);
+                    }
+                });
+                sender
+            }
+            None => {
+                let (sender, connection) = builder.handshake(stream).await?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        error!("connection to {} error: {}", host, e);
+                    }
+                });
+                sender
+            }
+        };
+        debug!("connected to {}:{}", self.host, self.port);
+        Ok(sender)
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::future;
+use hyper::{header, Body, Method, Request, Uri};
+use lib::Result;
+use serde_json::json;
+use tracing::warn;
+
+use crate::config::Fcm;
+
+use super::{client::HttpClient, Delivery, Notification, PushProvider};
+
+const FCM_URL: &str = "https://fcm.googleapis.com/fcm/send";
+
+/// the legacy http api of firebase cloud messaging, authorized by the server key.
+pub(crate) struct FcmProvider {
+    uri: Uri,
+    authorization: String,
+    client: HttpClient,
+}
+
+impl FcmProvider {
+    pub(crate) fn new(config: &Fcm) -> Result<Self> {
+        let uri = FCM_URL.parse::<Uri>()?;
+        let client = HttpClient::new(&uri, true)?;
+        Ok(Self {
+            uri,
+            authorization: format!("key={}", config.server_key),
+            client,
+        })
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<Delivery> {
+        let body = json!({
+            "to": notification.token,
+            "notification": {
+                "title": notification.title,
+                "body": notification.body,
+            },
+            "data": {
+                "sender": notification.sender,
+                "conversation_id": notification.conversation_id,
+                "seqnum": notification.seqnum,
+                "mentioned": notification.mentioned,
+            },
+        });
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(header::AUTHORIZATION, &self.authorization)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?;
+        let (status, body) = self.client.request(req).await?;
+        if !status.is_success() {
+            return Err(anyhow!("fcm answered {}", status));
+        }
+        let resp = serde_json::from_slice::<serde_json::Value>(&body)?;
+        match resp["results"][0]["error"].as_str() {
+            None => Ok(Delivery::Sent),
+            Some("NotRegistered") | Some("InvalidRegistration") => Ok(Delivery::InvalidToken),
+            Some(e) => Err(anyhow!("fcm push error: {}", e)),
+        }
+    }
+}
+
+#[async_trait]
+impl PushProvider for FcmProvider {
+    fn platform(&self) -> &'static str {
+        "fcm"
+    }
+
+    /// requests of a batch share one http/2 connection.
+    async fn send_batch(&self, notification_list: &[Notification]) -> Result<Vec<Delivery>> {
+        Ok(future::join_all(
+            notification_list
+                .iter()
+                .map(|notification| self.send(notification)),
+        )
+        .await
+        .into_iter()
+        .zip(notification_list)
+        .map(|(res, notification)| {
+            res.unwrap_or_else(|e| {
+                warn!("fcm push to {} error: {}", notification.user_id, e);
+                Delivery::Failed
+            })
+        })
+        .collect())
+    }
+}
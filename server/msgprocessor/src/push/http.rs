@@ -0,0 +1,186 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use hyper::{header, Body, Method, Request, Uri};
+use lib::Result;
+
+use crate::config::Http;
+
+use super::{client::HttpClient, Delivery, Notification, PushProvider};
+
+/// posts every batch as a json array to a plain http endpoint.
+///
+/// the endpoint may answer `{"invalid": [<token>, ...]}` to have tokens removed.
+pub(crate) struct HttpProvider {
+    uri: Uri,
+    client: HttpClient,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct HttpResp {
+    #[serde(default)]
+    invalid: Vec<String>,
+}
+
+impl HttpProvider {
+    pub(crate) fn new(config: &Http) -> Result<Self> {
+        let uri = config.url.parse::<Uri>()?;
+        let client = HttpClient::new(&uri, false)?;
+        Ok(Self { uri, client })
+    }
+}
+
+#[async_trait]
+impl PushProvider for HttpProvider {
+    fn platform(&self) -> &'static str {
+        "http"
+    }
+
+    async fn send_batch(&self, notification_list: &[Notification]) -> Result<Vec<Delivery>> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(header::HOST, self.uri.authority().unwrap().as_str())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(notification_list)?))?;
+        let (status, body) = self.client.request(req).await?;
+        if !status.is_success() {
+            return Err(anyhow!("push endpoint answered {}", status));
+        }
+        let resp = serde_json::from_slice::<HttpResp>(&body).unwrap_or_default();
+        Ok(notification_list
+            .iter()
+            .map(|notification| {
+                if resp.invalid.contains(&notification.token) {
+                    Delivery::InvalidToken
+                } else {
+                    Delivery::Sent
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server, StatusCode,
+    };
+    use tokio::sync::Mutex;
+
+    use super::HttpProvider;
+    use crate::{
+        config::Http,
+        push::{Delivery, Notification, PushProvider},
+    };
+
+    type Received = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    /// answer every request with `status` and `body` on a local port, keep the bodies received.
+    async fn serve(status: StatusCode, body: &'static str) -> (SocketAddr, Received) {
+        let received: Received = Arc::new(Mutex::new(vec![]));
+        let received0 = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let received = received0.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let received = received.clone();
+                    async move {
+                        let bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        received
+                            .lock()
+                            .await
+                            .push(serde_json::from_slice(&bytes).unwrap());
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::from(body))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, received)
+    }
+
+    fn provider(addr: SocketAddr) -> HttpProvider {
+        HttpProvider::new(&Http {
+            url: format!("http://{}/push", addr),
+        })
+        .unwrap()
+    }
+
+    fn notification(token: &str) -> Notification {
+        Notification {
+            user_id: 1,
+            token: token.to_owned(),
+            title: "title".to_owned(),
+            body: "body".to_owned(),
+            sender: 2,
+            conversation_id: "1-2".to_owned(),
+            seqnum: 3,
+            mentioned: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_batch() {
+        let (addr, received) = serve(StatusCode::OK, r#"{"invalid": ["b"]}"#).await;
+        let provider = provider(addr);
+        let delivery_list = provider
+            .send_batch(&[notification("a"), notification("b")])
+            .await
+            .unwrap();
+        assert_eq!(delivery_list, vec![Delivery::Sent, Delivery::InvalidToken]);
+        // the connection is reused.
+        let delivery_list = provider.send_batch(&[notification("c")]).await.unwrap();
+        assert_eq!(delivery_list, vec![Delivery::Sent]);
+
+        let received = received.lock().await;
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0][0]["token"], "a");
+        assert_eq!(received[0][1]["token"], "b");
+        assert_eq!(received[0][1]["seqnum"], 3);
+        assert_eq!(received[1][0]["token"], "c");
+    }
+
+    #[tokio::test]
+    async fn test_body_ignored() {
+        // an endpoint answering nothing useful still has the batch sent.
+        let (addr, _) = serve(StatusCode::OK, "ok").await;
+        let delivery_list = provider(addr)
+            .send_batch(&[notification("a")])
+            .await
+            .unwrap();
+        assert_eq!(delivery_list, vec![Delivery::Sent]);
+    }
+
+    #[tokio::test]
+    async fn test_error_status() {
+        let (addr, received) = serve(StatusCode::INTERNAL_SERVER_ERROR, "").await;
+        assert!(provider(addr)
+            .send_batch(&[notification("a")])
+            .await
+            .is_err());
+        assert_eq!(received.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // nothing listens on the port once the listener is dropped.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(provider(addr)
+            .send_batch(&[notification("a")])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! offline push: messages whose recipients are not connected to any message node are turned into
+//! notifications, and delivered through the provider of every device the recipient registered.
+
+pub(crate) mod apns;
+pub(crate) mod client;
+pub(crate) mod fcm;
+pub(crate) mod http;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use async_trait::async_trait;
+use common::mq::Record;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use lib::{
+    entity::{Msg, MsgExtension, Type, GROUP_ID_THRESHOLD, HEAD_LEN},
+    util::conversation_id,
+    Result,
+};
+use tokio::sync::{mpsc, OnceCell};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    cache::{get_redis_ops, PUSH_DEVICE_SET, PUSH_MUTE_SET, USER_ONLINE},
+    config::CONFIG,
+    sql::get_sql_pool,
+};
+
+/// group members are cached for such time.
+const GROUP_MEMBER_TTL: Duration = Duration::from_secs(60);
+const PREVIEW_LEN: usize = 64;
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub(crate) struct Notification {
+    pub(crate) user_id: u64,
+    /// device token issued by the provider.
+    pub(crate) token: String,
+    pub(crate) title: String,
+    pub(crate) body: String,
+    pub(crate) sender: u64,
+    pub(crate) conversation_id: String,
+    pub(crate) seqnum: u64,
+    /// the user is mentioned in the group, `@all` included.
+    pub(crate) mentioned: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Delivery {
+    Sent,
+    /// the device token is expired or unregistered, it's removed.
+    InvalidToken,
+    /// rejected for other reasons, not retried.
+    Failed,
+}
+
+/// a push gateway, such as APNs or FCM.
+#[async_trait]
+pub(crate) trait PushProvider: Send + Sync + 'static {
+    /// the platform devices register with, e.g. `apns`.
+    fn platform(&self) -> &'static str;
+
+    /// results are in the same order as `notification_list`, an error means none is sent.
+    async fn send_batch(&self, notification_list: &[Notification]) -> Result<Vec<Delivery>>;
+}
+
+static MSG_SENDER: OnceCell<mpsc::Sender<Msg>> = OnceCell::const_new();
+
+lazy_static! {
+    static ref GROUP_MEMBER_MAP: Arc<DashMap<u64, (Instant, Vec<u64>)>> = Arc::new(DashMap::new());
+}
+
+/// start the push pipeline if any provider is configured.
+pub(crate) async fn start() -> Result<()> {
+    if !CONFIG.push.is_enabled() {
+        info!("no push provider configured, offline push is off");
+        return Ok(());
+    }
+    let mut provider_list: Vec<Arc<dyn PushProvider>> = vec![];
+    if let Some(apns) = CONFIG.push.apns.as_ref() {
+        provider_list.push(Arc::new(apns::ApnsProvider::new(apns)?));
+    }
+    if let Some(fcm) = CONFIG.push.fcm.as_ref() {
+        provider_list.push(Arc::new(fcm::FcmProvider::new(fcm)?));
+    }
+    if let Some(http) = CONFIG.push.http.as_ref() {
+        provider_list.push(Arc::new(http::HttpProvider::new(http)?));
+    }
+    let mut sender_map = AHashMap::new();
+    for provider in provider_list {
+        let (tx, rx) = mpsc::channel(CONFIG.push.queue_size);
+        sender_map.insert(provider.platform(), tx);
+        tokio::spawn(async move {
+            deliver(provider, rx).await;
+        });
+    }
+    let (tx, rx) = mpsc::channel(CONFIG.push.queue_size);
+    _ = MSG_SENDER.set(tx);
+    tokio::spawn(async move {
+        detect(rx, sender_map).await;
+    });
+    Ok(())
+}
+
+/// hand persisted messages to the push pipeline, dropped when it's full, which means the
+/// providers can't keep up anyway.
+pub(crate) fn enqueue(record_list: &[Record]) {
+    let sender = match MSG_SENDER.get() {
+        Some(sender) => sender,
+        None => return,
+    };
+    for record in record_list {
+        if record.payload.len() < HEAD_LEN {
+            continue;
+        }
+        let msg = Msg(record.payload.clone());
+        let type_value = msg.typ().value();
+        if !(Type::Text.value()..Type::Edit.value()).contains(&type_value) {
+            continue;
+        }
+        if sender.try_send(msg).is_err() {
+            warn!("push queue is full, message dropped");
+        }
+    }
+}
+
+async fn detect(
+    mut rx: mpsc::Receiver<Msg>,
+    sender_map: AHashMap<&'static str, mpsc::Sender<Notification>>,
+) {
+    while let Some(msg) = rx.recv().await {
+        let notification_list = match notifications_of(&msg).await {
+            Ok(notification_list) => notification_list,
+            Err(e) => {
+                error!("resolve push of {} error: {}", msg.seqnum(), e);
+                continue;
+            }
+        };
+        for (platform, notification) in notification_list {
+            match sender_map.get(platform.as_str()) {
+                Some(sender) => {
+                    if sender.try_send(notification).is_err() {
+                        warn!("{} push queue is full, notification dropped", platform);
+                    }
+                }
+                None => debug!("no provider for platform {}", platform),
+            }
+        }
+    }
+}
+
+/// one notification for every device of every offline recipient who doesn't mute the chat,
+/// or is mentioned in the muted group.
+async fn notifications_of(msg: &Msg) -> Result<Vec<(String, Notification)>> {
+    let sender = msg.sender();
+    let receiver = msg.receiver();
+    let recipient_list = if receiver >= GROUP_ID_THRESHOLD {
+        group_member_list(receiver)
+            .await?
+            .into_iter()
+            .filter(|user_id| *user_id != sender)
+            .collect::<Vec<u64>>()
+    } else {
+        vec![receiver]
+    };
+    // a group is muted as a whole, a chat is muted by its peer.
+    let peer_id = if receiver >= GROUP_ID_THRESHOLD {
+        receiver
+    } else {
+        sender
+    };
+    let extension = MsgExtension::parse(msg.extension());
+    let mut redis_ops = get_redis_ops().await;
+    let mut title = None;
+    let mut list = vec![];
+    for user_id in recipient_list {
+        let mentioned = receiver >= GROUP_ID_THRESHOLD && extension.mentions(user_id);
+        if redis_ops
+            .get::<u32>(&format!("{}{}", USER_ONLINE, user_id))
+            .await
+            .is_ok()
+        {
+            continue;
+        }
+        let mute_list = redis_ops
+            .peek_set::<Vec<u64>>(&format!("{}{}", PUSH_MUTE_SET, user_id))
+            .await
+            .unwrap_or_default();
+        if mute_list.contains(&peer_id) && !mentioned {
+            continue;
+        }
+        let device_list = redis_ops
+            .peek_set::<Vec<String>>(&format!("{}{}", PUSH_DEVICE_SET, user_id))
+            .await
+            .unwrap_or_default();
+        for device in device_list {
+            let (platform, token) = match device.split_once(':') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            if title.is_none() {
+                title = Some(nickname_of(sender).await);
+            }
+            list.push((
+                platform.to_owned(),
+                Notification {
+                    user_id,
+                    token: token.to_owned(),
+                    title: title.clone().unwrap(),
+                    body: preview(msg),
+                    sender,
+                    conversation_id: conversation_id(sender, receiver),
+                    seqnum: msg.seqnum(),
+                    mentioned,
+                },
+            ));
+        }
+    }
+    Ok(list)
+}
+
+async fn group_member_list(group_id: u64) -> Result<Vec<u64>> {
+    if let Some(entry) = GROUP_MEMBER_MAP.get(&group_id) {
+        if entry.0.elapsed() < GROUP_MEMBER_TTL {
+            return Ok(entry.1.clone());
+        }
+    }
+    // admins are kept apart from the other members.
+    let (member_list, admin_list): (Vec<serde_json::Value>, Vec<serde_json::Value>) = sqlx::query_as(
+        "SELECT member_list, admin_list FROM api.group WHERE group_id = $1 AND delete_at = to_timestamp(0)",
+    )
+    .bind(group_id as i64)
+    .fetch_one(get_sql_pool().await)
+    .await?;
+    let list = member_list
+        .iter()
+        .chain(admin_list.iter())
+        .filter_map(|member| member["user_id"].as_u64())
+        .collect::<Vec<u64>>();
+    GROUP_MEMBER_MAP.insert(group_id, (Instant::now(), list.clone()));
+    Ok(list)
+}
+
+async fn nickname_of(user_id: u64) -> String {
+    let res: std::result::Result<(String,), sqlx::Error> =
+        sqlx::query_as("SELECT nickname FROM api.user WHERE account_id = $1")
+            .bind(user_id as i64)
+            .fetch_one(get_sql_pool().await)
+            .await;
+    match res {
+        Ok((nickname,)) if !nickname.is_empty() => nickname,
+        _ => user_id.to_string(),
+    }
+}
+
+#[inline]
+fn preview(msg: &Msg) -> String {
+    if msg.encrypted() {
+        return "[Encrypted Message]".to_owned();
+    }
+    match msg.typ() {
+        Type::Text => {
+            let text = String::from_utf8_lossy(msg.payload());
+            match text.char_indices().nth(PREVIEW_LEN) {
+                Some((end, _)) => format!("{}...", &text[..end]),
+                None => text.into_owned(),
+            }
+        }
+        Type::Meme => "[Meme]".to_owned(),
+        Type::File => "[File]".to_owned(),
+        Type::Image => "[Image]".to_owned(),
+        Type::Video => "[Video]".to_owned(),
+        Type::Audio => "[Audio]".to_owned(),
+        _ => "[Message]".to_owned(),
+    }
+}
+
+/// send in batches, no more than `rate` notifications per second.
+async fn deliver(provider: Arc<dyn PushProvider>, mut rx: mpsc::Receiver<Notification>) {
+    let platform = provider.platform();
+    let per_notification = Duration::from_secs(1) / CONFIG.push.rate.max(1);
+    let mut next_allowed = Instant::now();
+    loop {
+        let first = match rx.recv().await {
+            Some(notification) => notification,
+            None => return,
+        };
+        let mut batch = vec![first];
+        let deadline = tokio::time::Instant::now() + CONFIG.push.batch_interval;
+        while batch.len() < CONFIG.push.batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(notification)) => batch.push(notification),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let now = Instant::now();
+        if next_allowed > now {
+            tokio::time::sleep(next_allowed - now).await;
+        }
+        next_allowed = Instant::now().max(next_allowed) + per_notification * batch.len() as u32;
+        let delivery_list = match provider.send_batch(&batch).await {
+            Ok(delivery_list) => delivery_list,
+            Err(e) => {
+                error!(
+                    "{} push of {} notifications error: {}",
+                    platform,
+                    batch.len(),
+                    e
+                );
+                continue;
+            }
+        };
+        let mut redis_ops = get_redis_ops().await;
+        for (notification, delivery) in batch.iter().zip(delivery_list) {
+            if delivery == Delivery::InvalidToken {
+                debug!(
+                    "{} token of {} is invalid, removed",
+                    platform, notification.user_id
+                );
+                _ = redis_ops
+                    .remove_set(
+                        &format!("{}{}", PUSH_DEVICE_SET, notification.user_id),
+                        &format!("{}:{}", platform, notification.token),
+                    )
+                    .await;
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use ahash::AHashMap;
-use common::scheduler::connect2scheduler;
+use common::scheduler::{connect2scheduler, keep_alive};
 use lib::{
     entity::{ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
     net::{client::ClientConfigBuilder, GenericParameterMap, InnerStates, InnerStatesValue},
@@ -32,12 +32,16 @@ impl Client {
             ReqwestResourceID::AssignMQProcessor,
             Box::new(internal::AssignProcessor {}),
         );
+        handler_map.insert(
+            ReqwestResourceID::UnassignMQProcessor,
+            Box::new(internal::UnassignProcessor {}),
+        );
         let handler_map = ReqwestHandlerMap::new(handler_map);
 
-        let service_address = CONFIG.scheduler.address;
+        let service_address = CONFIG.scheduler.address.to_string();
         let server_info = ServerInfo {
             id: my_id(),
-            service_address,
+            service_address: service_address.clone(),
             cluster_address: Some(service_address),
             connection_id: 0,
             status: ServerStatus::Online,
@@ -55,15 +59,25 @@ impl Client {
             );
             states
         });
-        let _operator = connect2scheduler(
+        let operator = connect2scheduler(
             client_config,
             Duration::from_millis(3000),
             handler_map,
-            server_info,
+            server_info.clone(),
+            CONFIG.scheduler.join_token.clone(),
             states_gen,
             ReqwestResourceID::MsgprocessorNodeRegister,
         )
         .await?;
+        tokio::spawn(async move {
+            keep_alive(
+                &operator,
+                server_info,
+                CONFIG.scheduler.join_token.clone(),
+                ReqwestResourceID::MsgprocessorNodeRegister,
+            )
+            .await;
+        });
         Ok(())
     }
 }
@@ -1,19 +1,15 @@
 use async_trait::async_trait;
 use lib_net_tokio::net::ReqwestHandler;
-use rdkafka::{
-    consumer::{CommitMode, Consumer, StreamConsumer},
-    ClientConfig, Message,
-};
 use tracing::{error, info};
 
 use lib::{
-    entity::{Msg, ReqwestMsg},
+    entity::{ReqwestMsg, ReqwestResourceID},
     error::HandlerError,
     net::InnerStates,
     Result,
 };
 
-use crate::config::CONFIG;
+use crate::{mq, util::my_id};
 
 pub(crate) struct AssignProcessor {}
 
@@ -23,64 +19,26 @@ impl ReqwestHandler for AssignProcessor {
     /// - `id`: the id of the message node
     /// - `topic`: the topic of the message node
     /// - `consumer_number`: the number of consumer of the message node
+    ///
+    /// the response carries the assignment state of this msgprocessor.
     async fn run(&self, msg: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
         let params = serde_json::from_slice::<serde_json::Value>(msg.payload())?;
         params
             .as_object()
             .ok_or(HandlerError::Parse("parse params error".to_string()))?;
-        let id = params["id"].as_u64().unwrap();
-        let topic = params["topic"].as_str().unwrap();
-        let consumer_number = params["consumer_number"].as_u64().unwrap();
+        let id = params["id"].as_u64().unwrap_or_default();
+        let topic = params["topic"]
+            .as_str()
+            .ok_or(HandlerError::Parse("topic is required".to_string()))?;
+        let consumer_number = params["consumer_number"].as_u64().unwrap_or(1);
         info!(
             "start consumer group for message id: {}, topic: {}, consumer_number: {}",
             id, topic, consumer_number
         );
-        for _ in 0..consumer_number + 1 {
-            let consumer: StreamConsumer = match ClientConfig::new()
-                .set("group.id", &format!("{}-default", topic))
-                .set("bootstrap.servers", &CONFIG.message_queue.address)
-                .set("enable.partition.eof", "false")
-                .set("session.timeout.ms", "6000")
-                .set("enable.auto.commit", "false")
-                .create()
-            {
-                Ok(consumer) => consumer,
-                Err(e) => {
-                    error!("consumer creation failed: {}", e);
-                    continue;
-                }
-            };
-            if let Err(e) = consumer.subscribe(&["msg-test"]) {
-                error!("subscribe error: {}", e);
-                continue;
-            }
-            tokio::spawn(async move {
-                loop {
-                    match consumer.recv().await {
-                        Err(e) => {
-                            error!("kafka consumer recv error: {}", e.to_string());
-                            continue;
-                        }
-                        Ok(msg) => {
-                            match msg.payload() {
-                                Some(bytes) => {
-                                    let msg = Msg(bytes.to_owned());
-                                }
-                                None => {
-                                    error!("kafka consumer recv error: payload is none");
-                                    continue;
-                                }
-                            }
-                            if let Err(e) = consumer.commit_message(&msg, CommitMode::Sync) {
-                                error!("kafka consumer commit error: {}", e.to_string());
-                                continue;
-                            }
-                        }
-                    }
-                }
-            });
+        if let Err(e) = mq::assign(topic, consumer_number as usize).await {
+            error!("assign topic {} failed: {}", topic, e);
         }
-        Ok(ReqwestMsg::default())
+        assignment_state(msg.resource_id()).await
     }
 }
 
@@ -88,7 +46,30 @@ pub(crate) struct UnassignProcessor {}
 
 #[async_trait]
 impl ReqwestHandler for UnassignProcessor {
-    async fn run(&self, _msg: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
-        Ok(ReqwestMsg::default())
+    /// the params is a json object, it contains the following fields:
+    /// - `topic`: the topic to give up
+    ///
+    /// replied after the in-flight batches are committed, so the next owner of the topic
+    /// starts right after them.
+    async fn run(&self, msg: &mut ReqwestMsg, _states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let params = serde_json::from_slice::<serde_json::Value>(msg.payload())?;
+        let topic = params["topic"]
+            .as_str()
+            .ok_or(HandlerError::Parse("topic is required".to_string()))?;
+        info!("stop consumer group of topic: {}", topic);
+        mq::unassign(topic).await;
+        assignment_state(msg.resource_id()).await
     }
 }
+
+/// `{"id": <my id>, "topics": {<topic>: <running consumers>}}`.
+async fn assignment_state(resource_id: ReqwestResourceID) -> Result<ReqwestMsg> {
+    let state = serde_json::json!({
+        "id": my_id(),
+        "topics": mq::assignment().await,
+    });
+    Ok(ReqwestMsg::with_resource_id_payload(
+        resource_id,
+        &serde_json::to_vec(&state)?,
+    ))
+}
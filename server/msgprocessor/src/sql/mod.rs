@@ -0,0 +1,29 @@
+use std::str::FromStr;
+
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    ConnectOptions, Pool, Postgres,
+};
+use tokio::sync::OnceCell;
+
+use crate::config::CONFIG;
+
+static SQL_POOL: OnceCell<Pool<Postgres>> = OnceCell::const_new();
+
+pub(crate) async fn get_sql_pool() -> &'static Pool<Postgres> {
+    SQL_POOL
+        .get_or_init(|| async {
+            let mut options = PgConnectOptions::from_str(&format!(
+                "postgres://{}:{}@{}/{}",
+                CONFIG.sql.username, CONFIG.sql.password, CONFIG.sql.address, CONFIG.sql.database
+            ))
+            .unwrap();
+            options.disable_statement_logging();
+            PgPoolOptions::new()
+                .max_connections(CONFIG.sql.max_connections)
+                .connect_with(options)
+                .await
+                .unwrap()
+        })
+        .await
+}
@@ -24,4 +24,14 @@ pub(super) async fn get_redis_ops() -> RedisOps {
 }
 
 pub(crate) static USER_NODE_MAP: &str = "USER_NODE_MAP_";
+/// reverse index of `USER_NODE_MAP_`, users placed on the node.
+pub(crate) static NODE_USER_SET: &str = "NODE_USER_SET_";
+/// the message node a user is connected to, set by the message node.
+pub(crate) static USER_ONLINE: &str = "USER_ONLINE_";
 pub(crate) static NODE_ID: &str = "NODE_ID_SCHEDULER_";
+/// latest rejected node registrations.
+pub(crate) static NODE_AUTH_AUDIT: &str = "NODE_AUTH_AUDIT";
+/// how far a msgprocessor lags behind a topic, in milliseconds, reported by the msgprocessor.
+pub(crate) static MQ_TOPIC_LAG: &str = "MQ_TOPIC_LAG_";
+/// 1 once every record of a topic is committed, reported by the msgprocessor.
+pub(crate) static MQ_TOPIC_DRAINED: &str = "MQ_TOPIC_DRAINED_";
@@ -3,7 +3,7 @@ use std::{sync::Arc, time::Duration};
 use ahash::AHashMap;
 use anyhow::anyhow;
 use lib::{
-    entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
+    entity::{ReqwestMsg, ReqwestResourceID, ServerStatus},
     net::client::ClientConfigBuilder,
     Result,
 };
@@ -13,9 +13,8 @@ use lib_net_tokio::net::{
 };
 
 use crate::{
-    cluster::handler::{logic, message},
+    cluster::handler::{logic, raft},
     config::config,
-    util::my_id,
 };
 
 use super::{get_cluster_connection_set, server::ClientConnectionHandler};
@@ -53,26 +52,24 @@ impl Client {
                 .with_max_bi_streams(config().transport.max_bi_streams);
             let client_config = client_config.build().unwrap();
 
-            let server_info = ServerInfo {
-                id: my_id(),
-                service_address: config().server.service_address.clone(),
-                cluster_address: Some(config().server.cluster_address.clone()),
-                connection_id: 0,
-                status: ServerStatus::Online,
-                typ: ServerType::SchedulerCluster,
-                load: None,
-            };
+            let auth_info = logic::self_auth_info(ServerStatus::Online);
 
-            let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
+            let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> =
+                AHashMap::new();
             handler_map.insert(ReqwestResourceID::NodeAuth, Box::new(logic::ClientAuth {}));
             handler_map.insert(
-                ReqwestResourceID::MessageNodeRegister,
-                Box::new(message::NodeRegister {}),
+                ReqwestResourceID::RaftRequestVote,
+                Box::new(raft::RequestVote {}),
             );
             handler_map.insert(
-                ReqwestResourceID::MessageNodeUnregister,
-                Box::new(message::NodeUnregister {}),
+                ReqwestResourceID::RaftAppendEntries,
+                Box::new(raft::AppendEntries {}),
             );
+            handler_map.insert(
+                ReqwestResourceID::RaftInstallSnapshot,
+                Box::new(raft::InstallSnapshot {}),
+            );
+            handler_map.insert(ReqwestResourceID::RaftPropose, Box::new(raft::Propose {}));
             let handler_map = ReqwestHandlerMap::new(handler_map);
             let generator: ReqwestHandlerGenerator =
                 Box::new(move || -> Box<dyn NewReqwestConnectionHandler> {
@@ -85,11 +82,12 @@ impl Client {
 
             let auth_msg = ReqwestMsg::with_resource_id_payload(
                 ReqwestResourceID::NodeAuth,
-                &server_info.to_bytes(),
+                &auth_info.to_bytes(),
             );
             let resp = operator.call(auth_msg).await?;
-            if resp.payload() != b"true" {
-                return Err(anyhow!("auth failed"));
+            // the server authenticates back with `NodeAuth` on the same connection.
+            if resp.payload().is_empty() {
+                return Err(anyhow!("rejected by cluster server {}", addr));
             }
         }
         Ok(())
@@ -1,16 +1,25 @@
 use std::net::ToSocketAddrs;
 
+use anyhow::anyhow;
 use async_trait::async_trait;
-use lib::entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType};
+use lib::entity::{
+    NodeAuthInfo, ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType,
+};
 use lib::net::{InnerStates, InnerStatesValue};
+use lib::util::jwt::{join_token, verify_join_token};
 use lib::Result;
 use lib_net_tokio::net::server::ReqwestCaller;
 use lib_net_tokio::net::ReqwestHandler;
-use tracing::info;
+use tracing::{error, info, warn};
 
 use crate::{cluster::ClusterCallerMap, config::config};
 use crate::{cluster::ClusterConnectionSet, util::my_id};
 
+/// kind of the join tokens schedulers sign for each other, never issued to nodes.
+const SCHEDULER_KIND: &str = "scheduler";
+/// the token only needs to live through the handshake.
+const SCHEDULER_TOKEN_TTL: u64 = 60;
+
 pub(crate) struct ServerAuth {}
 
 #[async_trait]
@@ -38,7 +47,13 @@ impl ReqwestHandler for ServerAuth {
             .get_parameter::<ReqwestCaller>()
             .unwrap();
 
-        let server_info = ServerInfo::from(req.payload());
+        let auth_info: NodeAuthInfo = NodeAuthInfo::from(req.payload());
+        if let Err(reason) = authenticate(&auth_info, states) {
+            warn!("cluster server {} rejected: {}", auth_info.info.id, reason);
+            // an empty reply tells the peer it's rejected.
+            return Ok(ReqwestMsg::with_resource_id_payload(req.resource_id(), b""));
+        }
+        let server_info = auth_info.info;
         info!("cluster server {} connected", server_info.id);
         cluster_set.insert(
             server_info
@@ -50,23 +65,28 @@ impl ReqwestHandler for ServerAuth {
                 .unwrap(),
         );
         cluster_map.insert(server_info.id, client_caller.clone());
+        let client_caller = client_caller.clone();
         states.insert(
             "node_id".to_owned(),
             InnerStatesValue::Num(server_info.id as u64),
         );
 
-        let res_server_info = ServerInfo {
-            id: my_id(),
-            service_address: config().server.service_address.clone(),
-            cluster_address: Some(config().server.cluster_address.clone()),
-            connection_id: 0,
-            status: ServerStatus::Normal,
-            typ: ServerType::SchedulerCluster,
-            load: None,
-        };
+        // the peer authenticates this scheduler in turn, before it accepts raft requests from it.
+        let peer_id = server_info.id;
+        tokio::spawn(async move {
+            let auth_msg = ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::NodeAuth,
+                &self_auth_info(ServerStatus::Normal).to_bytes(),
+            );
+            match client_caller.call(auth_msg).await {
+                Ok(resp) if !resp.payload().is_empty() => {}
+                Ok(_) => error!("rejected by cluster server {}", peer_id),
+                Err(e) => error!("auth to cluster server {} error: {}", peer_id, e),
+            }
+        });
         Ok(ReqwestMsg::with_resource_id_payload(
             ReqwestResourceID::NodeAuth,
-            &res_server_info.to_bytes(),
+            &self_auth_info(ServerStatus::Normal).info.to_bytes(),
         ))
     }
 }
@@ -98,7 +118,12 @@ impl ReqwestHandler for ClientAuth {
             .get_parameter::<ReqwestCaller>()
             .unwrap();
 
-        let res_server_info = ServerInfo::from(req.payload());
+        let auth_info: NodeAuthInfo = NodeAuthInfo::from(req.payload());
+        if let Err(reason) = authenticate(&auth_info, states) {
+            warn!("cluster server {} rejected: {}", auth_info.info.id, reason);
+            return Ok(ReqwestMsg::with_resource_id_payload(req.resource_id(), b""));
+        }
+        let res_server_info = auth_info.info;
         cluster_set.insert(
             res_server_info
                 .cluster_address
@@ -114,6 +139,65 @@ impl ReqwestHandler for ClientAuth {
             InnerStatesValue::Num(res_server_info.id as u64),
         );
 
-        Ok(ReqwestMsg::default())
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &self_auth_info(ServerStatus::Online).info.to_bytes(),
+        ))
+    }
+}
+
+/// the auth payload of this scheduler, with a token signed by the join secret of the cluster.
+pub(crate) fn self_auth_info(status: ServerStatus) -> NodeAuthInfo {
+    NodeAuthInfo {
+        info: ServerInfo {
+            id: my_id(),
+            service_address: config().server.service_address.clone(),
+            cluster_address: Some(config().server.cluster_address.clone()),
+            connection_id: 0,
+            status,
+            typ: ServerType::SchedulerCluster,
+            load: None,
+        },
+        token: join_token(
+            config().auth.join_secret.as_bytes(),
+            SCHEDULER_KIND,
+            my_id(),
+            SCHEDULER_TOKEN_TTL,
+        ),
+    }
+}
+
+/// the peer must be another member of the raft group, holding a scheduler token bound to its id.
+/// a connection can not change its id once authenticated.
+fn authenticate(auth_info: &NodeAuthInfo, states: &InnerStates) -> std::result::Result<(), String> {
+    let peer_id = auth_info.info.id;
+    if let Some(node_id) = states.get("node_id") {
+        if node_id.as_num() != Some(peer_id as u64) {
+            return Err(format!(
+                "connection is already authenticated as {:?}",
+                node_id.as_num()
+            ));
+        }
+    }
+    if peer_id == my_id() || !config().raft.members.contains(&peer_id) {
+        return Err(format!("{} is not a member of the cluster", peer_id));
+    }
+    let (token_kind, token_node_id) =
+        verify_join_token(&auth_info.token, config().auth.join_secret.as_bytes())
+            .map_err(|e| format!("invalid token: {}", e))?;
+    if token_kind != SCHEDULER_KIND || token_node_id != peer_id {
+        return Err(format!(
+            "token for {} node {} is presented by scheduler {}",
+            token_kind, token_node_id, peer_id
+        ));
+    }
+    Ok(())
+}
+
+/// the scheduler the connection has authenticated as, raft requests are only accepted from it.
+pub(crate) fn authenticated_peer(states: &InnerStates) -> Result<u32> {
+    match states.get("node_id").and_then(|node_id| node_id.as_num()) {
+        Some(node_id) if config().raft.members.contains(&(node_id as u32)) => Ok(node_id as u32),
+        _ => Err(anyhow!("raft request from an unauthenticated connection")),
     }
 }
@@ -1,2 +1,2 @@
 pub(super) mod logic;
-pub(crate) mod message;
+pub(crate) mod raft;
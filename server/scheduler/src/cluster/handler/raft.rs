@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+use lib::{entity::ReqwestMsg, net::InnerStates, Result};
+use lib_net_tokio::net::ReqwestHandler;
+
+use crate::raft::{get_raft, AppendReq, Command, SnapshotReq, VoteReq};
+
+use super::logic::authenticated_peer;
+
+pub(crate) struct RequestVote {}
+
+#[async_trait]
+impl ReqwestHandler for RequestVote {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        authenticated_peer(states)?;
+        let vote_req: VoteReq = serde_json::from_slice(req.payload())?;
+        let resp = get_raft().handle_vote(vote_req).await?;
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &serde_json::to_vec(&resp)?,
+        ))
+    }
+}
+
+pub(crate) struct AppendEntries {}
+
+#[async_trait]
+impl ReqwestHandler for AppendEntries {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        authenticated_peer(states)?;
+        let append_req: AppendReq = serde_json::from_slice(req.payload())?;
+        let resp = get_raft().handle_append(append_req).await?;
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &serde_json::to_vec(&resp)?,
+        ))
+    }
+}
+
+pub(crate) struct InstallSnapshot {}
+
+#[async_trait]
+impl ReqwestHandler for InstallSnapshot {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        authenticated_peer(states)?;
+        let snapshot_req: SnapshotReq = serde_json::from_slice(req.payload())?;
+        let resp = get_raft().handle_snapshot(snapshot_req).await?;
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &serde_json::to_vec(&resp)?,
+        ))
+    }
+}
+
+pub(crate) struct Propose {}
+
+#[async_trait]
+impl ReqwestHandler for Propose {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        authenticated_peer(states)?;
+        let command: Command = serde_json::from_slice(req.payload())?;
+        // errors are sent back instead of dropping the reply, so the follower won't wait for timeout.
+        let resp = get_raft()
+            .propose_forwarded(command)
+            .await
+            .map_err(|e| e.to_string());
+        Ok(ReqwestMsg::with_resource_id_payload(
+            req.resource_id(),
+            &serde_json::to_vec(&resp)?,
+        ))
+    }
+}
@@ -17,8 +17,8 @@ use tokio::sync::mpsc;
 use tracing::error;
 
 use super::{
-    get_cluster_caller_map,
-    handler::{logic, message},
+    get_cluster_caller_map, get_cluster_connection_set,
+    handler::{logic, raft},
 };
 
 pub(super) struct ClientConnectionHandler {
@@ -46,11 +46,13 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
         let (send, mut recv) = msg_operators;
         let client_map = get_client_caller_map();
         let cluster_map = get_cluster_caller_map();
+        let cluster_set = get_cluster_connection_set();
         let client_caller = self.client_caller.take().unwrap();
 
         let mut generic_map = GenericParameterMap(AHashMap::new());
         generic_map.put_parameter(client_map);
         generic_map.put_parameter(cluster_map);
+        generic_map.put_parameter(cluster_set);
         generic_map.put_parameter(client_caller);
 
         self.states.insert(
@@ -79,8 +81,10 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
                     };
                 }
                 None => {
-                    let node_id = self.states.get("node_id").unwrap().as_num().unwrap() as u32;
-                    get_cluster_caller_map().remove(node_id);
+                    // a connection never authenticated is not in the map.
+                    if let Some(node_id) = self.states.get("node_id").and_then(|v| v.as_num()) {
+                        get_cluster_caller_map().remove(node_id as u32);
+                    }
                     break;
                 }
             }
@@ -131,13 +135,18 @@ impl Server {
         let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
         handler_map.insert(ReqwestResourceID::NodeAuth, Box::new(logic::ServerAuth {}));
         handler_map.insert(
-            ReqwestResourceID::MessageNodeRegister,
-            Box::new(message::NodeRegister {}),
+            ReqwestResourceID::RaftRequestVote,
+            Box::new(raft::RequestVote {}),
         );
         handler_map.insert(
-            ReqwestResourceID::MessageNodeUnregister,
-            Box::new(message::NodeUnregister {}),
+            ReqwestResourceID::RaftAppendEntries,
+            Box::new(raft::AppendEntries {}),
         );
+        handler_map.insert(
+            ReqwestResourceID::RaftInstallSnapshot,
+            Box::new(raft::InstallSnapshot {}),
+        );
+        handler_map.insert(ReqwestResourceID::RaftPropose, Box::new(raft::Propose {}));
         let handler_map = ReqwestHandlerMap::new(handler_map);
         let generator: ReqwestHandlerGenerator =
             Box::new(move || -> Box<dyn NewReqwestConnectionHandler> {
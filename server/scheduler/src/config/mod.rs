@@ -1,5 +1,5 @@
 use std::net::ToSocketAddrs;
-use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+use std::{collections::HashMap, fs, net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::Context;
 use tracing::Level;
@@ -12,6 +12,12 @@ struct Config0 {
     redis: Option<Redis0>,
     cluster: Option<Cluster0>,
     rpc: Option<Rpc0>,
+    ring: Option<Ring0>,
+    load: Option<Load0>,
+    liveness: Option<Liveness0>,
+    raft: Option<Raft0>,
+    auth: Option<Auth0>,
+    assignment: Option<Assignment0>,
 }
 
 #[derive(Debug)]
@@ -22,6 +28,12 @@ pub(crate) struct Config {
     pub(crate) redis: Redis,
     pub(crate) cluster: Cluster,
     pub(crate) rpc: Rpc,
+    pub(crate) ring: Ring,
+    pub(crate) load: Load,
+    pub(crate) liveness: Liveness,
+    pub(crate) raft: Raft,
+    pub(crate) auth: Auth,
+    pub(crate) assignment: Assignment,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -116,6 +128,99 @@ pub(crate) struct Rpc {
     pub(crate) api: RpcAPI,
 }
 
+#[derive(serde::Deserialize, Debug)]
+struct Ring0 {
+    virtual_nodes: Option<u32>,
+    weights: Option<HashMap<String, u32>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Ring {
+    pub(crate) virtual_nodes: u32,
+    /// weight of message node by node id, node not listed has weight 1.
+    pub(crate) weights: HashMap<u32, u32>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Load0 {
+    cpu_threshold: Option<f32>,
+    mem_threshold: Option<f32>,
+    disk_threshold: Option<f32>,
+}
+
+/// usage thresholds in percent, node reports load above any of them is marked as overload.
+#[derive(Debug)]
+pub(crate) struct Load {
+    pub(crate) cpu_threshold: f32,
+    pub(crate) mem_threshold: f32,
+    pub(crate) disk_threshold: f32,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Liveness0 {
+    heartbeat_timeout: Option<u64>,
+    check_interval: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Liveness {
+    /// node without any heartbeat for such time is considered as crashed.
+    pub(crate) heartbeat_timeout: Duration,
+    pub(crate) check_interval: Duration,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Raft0 {
+    members: Option<Vec<u32>>,
+    dir: Option<String>,
+    election_timeout: Option<u64>,
+    heartbeat_interval: Option<u64>,
+    snapshot_threshold: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Raft {
+    /// ids of all schedulers replicating the registry, a single scheduler by default.
+    pub(crate) members: Vec<u32>,
+    /// where raft log and vote are kept across restarts.
+    pub(crate) dir: PathBuf,
+    pub(crate) election_timeout: Duration,
+    pub(crate) heartbeat_interval: Duration,
+    /// applied entries kept in the raft log before they are compacted into a snapshot.
+    pub(crate) snapshot_threshold: u64,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Auth0 {
+    join_secret: Option<String>,
+    audit_size: Option<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Auth {
+    /// signs and verifies join tokens presented by nodes.
+    pub(crate) join_secret: String,
+    /// how many rejected registrations are kept for audit.
+    pub(crate) audit_size: usize,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct Assignment0 {
+    check_interval: Option<u64>,
+    consumer_number: Option<u64>,
+    lag_unit: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Assignment {
+    /// how often the leader balances message node topics across msgprocessors.
+    pub(crate) check_interval: Duration,
+    /// consumers a msgprocessor runs for each topic it's assigned.
+    pub(crate) consumer_number: u64,
+    /// a topic lagging behind by such time weighs as much as one more topic.
+    pub(crate) lag_unit: Duration,
+}
+
 impl Config {
     fn from_config0(config0: Config0) -> Config {
         let log_level = match config0.log_level.unwrap_or("info".to_string()).as_ref() {
@@ -133,6 +238,32 @@ impl Config {
             redis: Redis::from_redis0(config0.redis.unwrap()),
             cluster: Cluster::from_scheduler0(config0.cluster.unwrap()),
             rpc: Rpc::from_rpc0(config0.rpc.unwrap()),
+            ring: Ring::from_ring0(config0.ring.unwrap_or(Ring0 {
+                virtual_nodes: None,
+                weights: None,
+            })),
+            load: Load::from_load0(config0.load.unwrap_or(Load0 {
+                cpu_threshold: None,
+                mem_threshold: None,
+                disk_threshold: None,
+            })),
+            liveness: Liveness::from_liveness0(config0.liveness.unwrap_or(Liveness0 {
+                heartbeat_timeout: None,
+                check_interval: None,
+            })),
+            raft: Raft::from_raft0(config0.raft.unwrap_or(Raft0 {
+                members: None,
+                dir: None,
+                election_timeout: None,
+                heartbeat_interval: None,
+                snapshot_threshold: None,
+            })),
+            auth: Auth::from_auth0(config0.auth.unwrap()),
+            assignment: Assignment::from_assignment0(config0.assignment.unwrap_or(Assignment0 {
+                check_interval: None,
+                consumer_number: None,
+                lag_unit: None,
+            })),
         }
     }
 }
@@ -179,7 +310,10 @@ impl Redis {
                     .collect::<Vec<SocketAddr>>()[0],
             );
         }
-        Redis { addresses: addr, passwords: redis0.passwords.unwrap_or(vec![]) }
+        Redis {
+            addresses: addr,
+            passwords: redis0.passwords.unwrap_or(vec![]),
+        }
     }
 }
 
@@ -241,6 +375,76 @@ impl RpcAPI {
     }
 }
 
+impl Ring {
+    fn from_ring0(ring0: Ring0) -> Self {
+        let mut weights = HashMap::new();
+        for (node_id, weight) in ring0.weights.unwrap_or_default() {
+            weights.insert(
+                node_id
+                    .parse::<u32>()
+                    .expect("parse ring weight node id failed"),
+                weight,
+            );
+        }
+        Ring {
+            virtual_nodes: ring0.virtual_nodes.unwrap_or(160),
+            weights,
+        }
+    }
+}
+
+impl Load {
+    fn from_load0(load0: Load0) -> Self {
+        Load {
+            cpu_threshold: load0.cpu_threshold.unwrap_or(85.0),
+            mem_threshold: load0.mem_threshold.unwrap_or(90.0),
+            disk_threshold: load0.disk_threshold.unwrap_or(95.0),
+        }
+    }
+}
+
+impl Liveness {
+    fn from_liveness0(liveness0: Liveness0) -> Self {
+        Liveness {
+            heartbeat_timeout: Duration::from_millis(liveness0.heartbeat_timeout.unwrap_or(5000)),
+            check_interval: Duration::from_millis(liveness0.check_interval.unwrap_or(1000)),
+        }
+    }
+}
+
+impl Raft {
+    fn from_raft0(raft0: Raft0) -> Self {
+        Raft {
+            members: raft0.members.unwrap_or_default(),
+            dir: PathBuf::from(raft0.dir.unwrap_or("./scheduler/raft".to_string())),
+            election_timeout: Duration::from_millis(raft0.election_timeout.unwrap_or(1000)),
+            heartbeat_interval: Duration::from_millis(raft0.heartbeat_interval.unwrap_or(200)),
+            snapshot_threshold: raft0.snapshot_threshold.unwrap_or(1000).max(1),
+        }
+    }
+}
+
+impl Auth {
+    fn from_auth0(auth0: Auth0) -> Self {
+        let join_secret = auth0.join_secret.unwrap();
+        assert!(!join_secret.is_empty(), "join secret should not be empty");
+        Auth {
+            join_secret,
+            audit_size: auth0.audit_size.unwrap_or(1000),
+        }
+    }
+}
+
+impl Assignment {
+    fn from_assignment0(assignment0: Assignment0) -> Self {
+        Assignment {
+            check_interval: Duration::from_millis(assignment0.check_interval.unwrap_or(3000)),
+            consumer_number: assignment0.consumer_number.unwrap_or(1),
+            lag_unit: Duration::from_millis(assignment0.lag_unit.unwrap_or(10000)),
+        }
+    }
+}
+
 pub(crate) fn load_config(config_path: &str) {
     let toml_str = fs::read_to_string(config_path).unwrap();
     let config0: Config0 = toml::from_str(&toml_str).unwrap();
@@ -1,11 +1,13 @@
 mod cache;
 mod cluster;
 mod config;
+mod raft;
 mod rpc;
 mod service;
 mod util;
 
-use lib::{joy, Result};
+use anyhow::anyhow;
+use lib::{joy, util::jwt, Result};
 use structopt::StructOpt;
 use tracing::{error, info};
 
@@ -26,6 +28,17 @@ pub(crate) struct Opt {
         default_value = "524289"
     )]
     pub(crate) my_id: u32,
+    #[structopt(
+        long = "issue_join_token",
+        long_help = r"print a join token for a node kind(message, seqnum or msgprocessor) and exit, use 'kind:id' to bind the token to a node id"
+    )]
+    pub(crate) issue_join_token: Option<String>,
+    #[structopt(
+        long = "join_token_ttl",
+        long_help = r"lifetime of the issued join token in days",
+        default_value = "365"
+    )]
+    pub(crate) join_token_ttl: u64,
 }
 
 #[tokio::main]
@@ -40,6 +53,28 @@ async fn main() -> Result<()> {
         Err(_) => opt.config,
     };
     load_config(&config_path);
+    if let Some(join_token) = opt.issue_join_token {
+        let (kind, node_id) = match join_token.split_once(':') {
+            Some((kind, node_id)) => (kind, node_id.parse::<u32>()?),
+            None => (join_token.as_str(), 0),
+        };
+        if !util::NODE_KINDS.contains(&kind) {
+            return Err(anyhow!("unknown node kind: {}", kind));
+        }
+        if node_id != 0 && util::node_kind(node_id) != Some(kind) {
+            return Err(anyhow!("node {} is not a {} node", node_id, kind));
+        }
+        println!(
+            "{}",
+            jwt::join_token(
+                config().auth.join_secret.as_bytes(),
+                kind,
+                node_id,
+                opt.join_token_ttl * 24 * 60 * 60,
+            )
+        );
+        return Ok(());
+    }
     util::load_my_id(my_id).await?;
     tracing_subscriber::fmt()
         .event_format(
@@ -57,6 +92,7 @@ async fn main() -> Result<()> {
         util::my_id(),
         config().server.service_address
     );
+    raft::start().await?;
     tokio::spawn(async move {
         if let Err(e) = cluster::start().await {
             error!("cluster error: {}", e);
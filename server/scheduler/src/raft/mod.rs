@@ -0,0 +1,1246 @@
+//! a small raft implementation replicating the node registry between schedulers,
+//! so every scheduler sees the same members, node ids and shard owners, in the same order.
+//!
+//! only the parts needed by a static group of a few schedulers are implemented:
+//! leader election, log replication, durable term/vote/log and snapshots compacting the log.
+//! membership of the group itself comes from the config.
+
+pub(crate) mod registry;
+mod storage;
+mod transport;
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lib::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch, Mutex, OnceCell};
+use tracing::{debug, error, info};
+
+pub(crate) use registry::{ApplyResult, Command, Registry};
+pub(crate) use storage::Snapshot;
+use storage::{HardState, Storage};
+
+use crate::{config::config, util::my_id};
+
+/// max entries carried by one append request, keeps a request far below the frame limit.
+const MAX_ENTRIES_PER_APPEND: usize = 32;
+/// bytes of a serialized snapshot carried by one request, json takes up to 4 bytes for each,
+/// so a request stays below the frame limit.
+const SNAPSHOT_CHUNK_SIZE: usize = 8 * 1024;
+const PROPOSE_TIMEOUT: Duration = Duration::from_millis(5000);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) term: u64,
+    pub(crate) index: u64,
+    pub(crate) command: Command,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct VoteReq {
+    pub(crate) term: u64,
+    pub(crate) candidate_id: u32,
+    pub(crate) last_log_index: u64,
+    pub(crate) last_log_term: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct VoteResp {
+    pub(crate) term: u64,
+    pub(crate) vote_granted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct AppendReq {
+    pub(crate) term: u64,
+    pub(crate) leader_id: u32,
+    pub(crate) prev_log_index: u64,
+    pub(crate) prev_log_term: u64,
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) leader_commit: u64,
+}
+
+/// sent instead of entries a follower lags behind, which are compacted into the snapshot already.
+/// the serialized snapshot is sent in chunks, the follower installs it once `done`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct SnapshotReq {
+    pub(crate) term: u64,
+    pub(crate) leader_id: u32,
+    /// chunks of different snapshots are never put together.
+    pub(crate) last_index: u64,
+    /// where `data` starts in the serialized snapshot.
+    pub(crate) offset: u64,
+    pub(crate) data: Vec<u8>,
+    pub(crate) done: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct AppendResp {
+    pub(crate) term: u64,
+    pub(crate) success: bool,
+    /// on success the last index matched with the leader,
+    /// otherwise a hint that the leader should retry from `match_index + 1`.
+    pub(crate) match_index: u64,
+}
+
+/// how schedulers reach each other, abstracted so tests can run a group in one process.
+#[async_trait]
+pub(crate) trait Transport: Send + Sync + 'static {
+    async fn request_vote(&self, target: u32, req: VoteReq) -> Result<VoteResp>;
+
+    async fn append_entries(&self, target: u32, req: AppendReq) -> Result<AppendResp>;
+
+    async fn install_snapshot(&self, target: u32, req: SnapshotReq) -> Result<AppendResp>;
+
+    /// hand a command to the leader, return after it is applied there.
+    async fn propose(&self, target: u32, command: Command) -> Result<ApplyResult>;
+}
+
+/// called for every committed command after it is applied to the registry, in log order.
+/// a snapshot replacing the registry is handed over as the commands making the difference.
+pub(crate) type ApplyHook = Box<dyn Fn(&Command, &ApplyResult) + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub(crate) struct RaftConfig {
+    pub(crate) election_timeout: Duration,
+    pub(crate) heartbeat_interval: Duration,
+    /// applied entries kept in the log before they are compacted into a snapshot.
+    pub(crate) snapshot_threshold: u64,
+    pub(crate) snapshot_chunk_size: usize,
+}
+
+/// a snapshot being sent to a peer, serialized once and sent from `offset` on.
+struct Transfer {
+    last_index: u64,
+    data: Vec<u8>,
+    offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+struct State {
+    role: Role,
+    term: u64,
+    voted_for: Option<u32>,
+    leader_id: Option<u32>,
+    /// entries up to this index are compacted into the snapshot.
+    snapshot_index: u64,
+    snapshot_term: u64,
+    /// entry with index `i` is at `log[i - snapshot_index - 1]`.
+    log: Vec<Entry>,
+    commit_index: u64,
+    last_applied: u64,
+    next_index: HashMap<u32, u64>,
+    match_index: HashMap<u32, u64>,
+    /// peers with an append request on the way, at most one per peer.
+    inflight: HashSet<u32>,
+    /// snapshots the leader is sending.
+    transfers: HashMap<u32, Transfer>,
+    /// chunks of the snapshot a follower is receiving, with its last index.
+    receiving: Option<(u64, Vec<u8>)>,
+    /// when the leader last heard from each peer.
+    last_contact: HashMap<u32, Instant>,
+    election_deadline: Instant,
+    last_broadcast: Instant,
+    /// results of local proposals and the term of the entry applied, filled when applied.
+    waiting: HashMap<u64, Option<(u64, ApplyResult)>>,
+    registry: Registry,
+    storage: Storage,
+}
+
+impl State {
+    #[inline]
+    fn last_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    #[inline]
+    fn last_term(&self) -> u64 {
+        self.log
+            .last()
+            .map(|entry| entry.term)
+            .unwrap_or(self.snapshot_term)
+    }
+
+    /// none for entries not in the log, including the compacted ones.
+    #[inline]
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            Some(self.snapshot_term)
+        } else if index < self.snapshot_index {
+            None
+        } else {
+            self.log
+                .get((index - self.snapshot_index) as usize - 1)
+                .map(|entry| entry.term)
+        }
+    }
+
+    /// position of the entry with `index` in the log, which must not be compacted.
+    #[inline]
+    fn offset(&self, index: u64) -> usize {
+        (index - self.snapshot_index) as usize - 1
+    }
+
+    fn save_hard_state(&self) -> Result<()> {
+        self.storage.save_hard_state(&HardState {
+            term: self.term,
+            voted_for: self.voted_for,
+        })
+    }
+}
+
+struct Inner {
+    id: u32,
+    peers: Vec<u32>,
+    config: RaftConfig,
+    transport: Box<dyn Transport>,
+    on_apply: ApplyHook,
+    applied: watch::Sender<u64>,
+    state: Mutex<State>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Raft(Arc<Inner>);
+
+impl Raft {
+    /// `members` are ids of all schedulers in the group, including `id`.
+    pub(crate) fn new(
+        id: u32,
+        members: &[u32],
+        config: RaftConfig,
+        dir: &Path,
+        transport: Box<dyn Transport>,
+        on_apply: ApplyHook,
+    ) -> Result<Self> {
+        let storage = Storage::open(dir, id)?;
+        let (hard_state, snapshot, log) = storage.load()?;
+        info!(
+            "raft[{}] recovered at term {} with snapshot at {} and {} entries",
+            id,
+            hard_state.term,
+            snapshot.last_index,
+            log.len()
+        );
+        for command in Registry::default().diff(&snapshot.registry) {
+            on_apply(&command, &ApplyResult::Applied);
+        }
+        let (applied, _) = watch::channel(snapshot.last_index);
+        let state = State {
+            role: Role::Follower,
+            term: hard_state.term,
+            voted_for: hard_state.voted_for,
+            leader_id: None,
+            snapshot_index: snapshot.last_index,
+            snapshot_term: snapshot.last_term,
+            log,
+            commit_index: snapshot.last_index,
+            last_applied: snapshot.last_index,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            inflight: HashSet::new(),
+            transfers: HashMap::new(),
+            receiving: None,
+            last_contact: HashMap::new(),
+            election_deadline: Instant::now() + random_timeout(&config),
+            last_broadcast: Instant::now(),
+            waiting: HashMap::new(),
+            registry: snapshot.registry,
+            storage,
+        };
+        Ok(Self(Arc::new(Inner {
+            id,
+            peers: members
+                .iter()
+                .copied()
+                .filter(|member| *member != id)
+                .collect(),
+            config,
+            transport,
+            on_apply,
+            applied,
+            state: Mutex::new(state),
+        })))
+    }
+
+    #[allow(unused)]
+    pub(crate) fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    #[allow(unused)]
+    pub(crate) async fn leader(&self) -> Option<u32> {
+        self.0.state.lock().await.leader_id
+    }
+
+    #[allow(unused)]
+    pub(crate) async fn is_leader(&self) -> bool {
+        self.0.state.lock().await.role == Role::Leader
+    }
+
+    /// a copy of the registry applied on this scheduler, may lag behind the leader.
+    pub(crate) async fn registry(&self) -> Registry {
+        self.0.state.lock().await.registry.clone()
+    }
+
+    /// whether `peer` answered this scheduler as leader within `within`, always false on
+    /// followers. every peer gets a fresh `within` when a leader is elected.
+    pub(crate) async fn is_reachable(&self, peer: u32, within: Duration) -> bool {
+        if peer == self.0.id {
+            return true;
+        }
+        let state = self.0.state.lock().await;
+        state.role == Role::Leader
+            && state
+                .last_contact
+                .get(&peer)
+                .is_some_and(|last| last.elapsed() <= within)
+    }
+
+    /// drive elections and heartbeats, never returns.
+    pub(crate) async fn run(&self) {
+        let tick = (self.0.config.heartbeat_interval / 4).max(Duration::from_millis(5));
+        let mut interval = tokio::time::interval(tick);
+        loop {
+            interval.tick().await;
+            let mut state = self.0.state.lock().await;
+            match state.role {
+                Role::Leader => {
+                    if state.last_broadcast.elapsed() >= self.0.config.heartbeat_interval {
+                        state.last_broadcast = Instant::now();
+                        drop(state);
+                        self.broadcast();
+                    }
+                }
+                _ => {
+                    if Instant::now() >= state.election_deadline {
+                        drop(state);
+                        tokio::spawn(self.clone().elect());
+                    }
+                }
+            }
+        }
+    }
+
+    /// replicate `command` and wait until it is applied, forwarded to the leader if this scheduler
+    /// is not.
+    pub(crate) async fn propose(&self, command: Command) -> Result<ApplyResult> {
+        self.propose0(command, true).await
+    }
+
+    /// proposal forwarded by a follower, not forwarded again to avoid loops during elections.
+    pub(crate) async fn propose_forwarded(&self, command: Command) -> Result<ApplyResult> {
+        self.propose0(command, false).await
+    }
+
+    async fn propose0(&self, command: Command, forward: bool) -> Result<ApplyResult> {
+        let (index, term) = {
+            let mut state = self.0.state.lock().await;
+            if state.role != Role::Leader {
+                let leader_id = state.leader_id;
+                drop(state);
+                return match leader_id {
+                    Some(leader_id) if forward && leader_id != self.0.id => {
+                        self.0.transport.propose(leader_id, command).await
+                    }
+                    _ => Err(anyhow!("raft[{}] no leader for now", self.0.id)),
+                };
+            }
+            let index = self.append_local(&mut state, command)?;
+            state.waiting.insert(index, None);
+            if self.0.peers.is_empty() {
+                self.advance_commit(&mut state);
+            }
+            (index, state.term)
+        };
+        self.broadcast();
+        let mut applied = self.0.applied.subscribe();
+        let in_time = matches!(
+            tokio::time::timeout(
+                PROPOSE_TIMEOUT,
+                applied.wait_for(|applied| *applied >= index),
+            )
+            .await,
+            Ok(Ok(_))
+        );
+        let mut state = self.0.state.lock().await;
+        let result = state.waiting.remove(&index).flatten();
+        if !in_time {
+            return Err(anyhow!(
+                "raft[{}] entry {} not applied in time",
+                self.0.id,
+                index
+            ));
+        }
+        match result {
+            Some((applied_term, result)) if applied_term == term => Ok(result),
+            // the slot may be filled by another leader's entry at the same index.
+            Some(_) => Err(anyhow!(
+                "raft[{}] entry {} is overwritten",
+                self.0.id,
+                index
+            )),
+            // covered by a snapshot from the leader.
+            None => Err(anyhow!("raft[{}] entry {} has no result", self.0.id, index)),
+        }
+    }
+
+    pub(crate) async fn handle_vote(&self, req: VoteReq) -> Result<VoteResp> {
+        let mut state = self.0.state.lock().await;
+        if req.term > state.term {
+            self.become_follower(&mut state, req.term, None)?;
+        }
+        let up_to_date = req.last_log_term > state.last_term()
+            || (req.last_log_term == state.last_term() && req.last_log_index >= state.last_index());
+        let vote_granted = req.term == state.term
+            && up_to_date
+            && (state.voted_for.is_none() || state.voted_for == Some(req.candidate_id));
+        if vote_granted {
+            state.voted_for = Some(req.candidate_id);
+            state.save_hard_state()?;
+            state.election_deadline = Instant::now() + random_timeout(&self.0.config);
+        }
+        Ok(VoteResp {
+            term: state.term,
+            vote_granted,
+        })
+    }
+
+    pub(crate) async fn handle_append(&self, req: AppendReq) -> Result<AppendResp> {
+        let mut state = self.0.state.lock().await;
+        if req.term < state.term {
+            return Ok(AppendResp {
+                term: state.term,
+                success: false,
+                match_index: 0,
+            });
+        }
+        if req.term > state.term || state.role != Role::Follower {
+            self.become_follower(&mut state, req.term, Some(req.leader_id))?;
+        }
+        state.leader_id = Some(req.leader_id);
+        state.election_deadline = Instant::now() + random_timeout(&self.0.config);
+
+        if req.prev_log_index > state.last_index() {
+            return Ok(AppendResp {
+                term: state.term,
+                success: false,
+                match_index: state.last_index(),
+            });
+        }
+        // compacted entries are committed, so they match the leader's.
+        if req.prev_log_index > state.snapshot_index
+            && state.term_at(req.prev_log_index) != Some(req.prev_log_term)
+        {
+            return Ok(AppendResp {
+                term: state.term,
+                success: false,
+                match_index: req.prev_log_index - 1,
+            });
+        }
+        let last_new_index = req.prev_log_index + req.entries.len() as u64;
+        let mut truncated = false;
+        let mut appended = vec![];
+        for entry in req.entries {
+            if entry.index <= state.snapshot_index {
+                continue;
+            }
+            match state.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    let offset = state.offset(entry.index);
+                    state.log.truncate(offset);
+                    truncated = true;
+                }
+                None => {}
+            }
+            state.log.push(entry.clone());
+            appended.push(entry);
+        }
+        if truncated {
+            state.storage.rewrite(&state.log)?;
+        } else if !appended.is_empty() {
+            state.storage.append(&appended)?;
+        }
+        if req.leader_commit > state.commit_index {
+            // a stale request may match fewer entries than committed already.
+            state.commit_index = state
+                .commit_index
+                .max(req.leader_commit.min(last_new_index));
+            self.apply_committed(&mut state);
+        }
+        Ok(AppendResp {
+            term: state.term,
+            success: true,
+            match_index: last_new_index,
+        })
+    }
+
+    pub(crate) async fn handle_snapshot(&self, req: SnapshotReq) -> Result<AppendResp> {
+        let mut state = self.0.state.lock().await;
+        if req.term < state.term {
+            return Ok(AppendResp {
+                term: state.term,
+                success: false,
+                match_index: 0,
+            });
+        }
+        if req.term > state.term || state.role != Role::Follower {
+            self.become_follower(&mut state, req.term, Some(req.leader_id))?;
+        }
+        state.leader_id = Some(req.leader_id);
+        state.election_deadline = Instant::now() + random_timeout(&self.0.config);
+
+        if req.offset == 0 {
+            state.receiving = Some((req.last_index, vec![]));
+        }
+        let term = state.term;
+        let refused = AppendResp {
+            term,
+            success: false,
+            match_index: state.commit_index,
+        };
+        // a chunk sent again after its reply is lost overwrites the one received.
+        match state.receiving.as_mut() {
+            Some((last_index, data))
+                if *last_index == req.last_index && req.offset as usize <= data.len() =>
+            {
+                data.truncate(req.offset as usize);
+                data.extend_from_slice(&req.data);
+            }
+            _ => return Ok(refused),
+        }
+        if !req.done {
+            return Ok(AppendResp {
+                term,
+                success: true,
+                match_index: 0,
+            });
+        }
+        let (_, data) = state.receiving.take().unwrap();
+        let snapshot: Snapshot = match serde_json::from_slice(&data) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!("raft[{}] parse snapshot error: {}", self.0.id, e);
+                return Ok(refused);
+            }
+        };
+        if snapshot.last_index <= state.commit_index {
+            return Ok(AppendResp {
+                term: state.term,
+                success: true,
+                match_index: snapshot.last_index,
+            });
+        }
+        // entries after the snapshot are kept if the log agrees with it.
+        if state.term_at(snapshot.last_index) == Some(snapshot.last_term) {
+            let offset = state.offset(snapshot.last_index);
+            state.log.drain(..=offset);
+        } else {
+            state.log.clear();
+        }
+        state.storage.compact(&snapshot, &state.log)?;
+        info!(
+            "raft[{}] installs snapshot at {} from {}",
+            self.0.id, snapshot.last_index, req.leader_id
+        );
+        for command in state.registry.diff(&snapshot.registry) {
+            (self.0.on_apply)(&command, &ApplyResult::Applied);
+        }
+        state.snapshot_index = snapshot.last_index;
+        state.snapshot_term = snapshot.last_term;
+        state.commit_index = snapshot.last_index;
+        state.last_applied = snapshot.last_index;
+        state.registry = snapshot.registry;
+        self.0.applied.send_replace(state.last_applied);
+        Ok(AppendResp {
+            term: state.term,
+            success: true,
+            match_index: state.snapshot_index,
+        })
+    }
+
+    async fn elect(self) {
+        let req = {
+            let mut state = self.0.state.lock().await;
+            if state.role == Role::Leader || Instant::now() < state.election_deadline {
+                return;
+            }
+            state.term += 1;
+            state.role = Role::Candidate;
+            state.voted_for = Some(self.0.id);
+            state.leader_id = None;
+            state.election_deadline = Instant::now() + random_timeout(&self.0.config);
+            if let Err(e) = state.save_hard_state() {
+                error!("raft[{}] save hard state error: {}", self.0.id, e);
+                return;
+            }
+            if self.0.peers.is_empty() {
+                self.become_leader(&mut state);
+                return;
+            }
+            VoteReq {
+                term: state.term,
+                candidate_id: self.0.id,
+                last_log_index: state.last_index(),
+                last_log_term: state.last_term(),
+            }
+        };
+        debug!("raft[{}] start election of term {}", self.0.id, req.term);
+        let (tx, mut rx) = mpsc::channel(self.0.peers.len());
+        for peer in self.0.peers.iter().copied() {
+            let raft = self.clone();
+            let req = req.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let res = tokio::time::timeout(
+                    raft.0.config.election_timeout / 2,
+                    raft.0.transport.request_vote(peer, req),
+                )
+                .await;
+                let _ = tx.send(res).await;
+            });
+        }
+        drop(tx);
+        let mut votes = 1;
+        while let Some(res) = rx.recv().await {
+            let resp = match res {
+                Ok(Ok(resp)) => resp,
+                _ => continue,
+            };
+            let mut state = self.0.state.lock().await;
+            if resp.term > state.term {
+                if let Err(e) = self.become_follower(&mut state, resp.term, None) {
+                    error!("raft[{}] save hard state error: {}", self.0.id, e);
+                }
+                return;
+            }
+            if state.role != Role::Candidate || state.term != req.term {
+                return;
+            }
+            if resp.vote_granted {
+                votes += 1;
+                if votes >= self.quorum() {
+                    self.become_leader(&mut state);
+                    drop(state);
+                    self.broadcast();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn become_leader(&self, state: &mut State) {
+        info!("raft[{}] becomes leader of term {}", self.0.id, state.term);
+        state.role = Role::Leader;
+        state.leader_id = Some(self.0.id);
+        let next_index = state.last_index() + 1;
+        for peer in self.0.peers.iter() {
+            state.next_index.insert(*peer, next_index);
+            state.match_index.insert(*peer, 0);
+            state.last_contact.insert(*peer, Instant::now());
+        }
+        state.inflight.clear();
+        state.transfers.clear();
+        state.last_broadcast = Instant::now();
+        // entries of previous terms can only be committed along with one of the current term.
+        if let Err(e) = self.append_local(state, Command::Noop) {
+            error!("raft[{}] append noop error: {}", self.0.id, e);
+        }
+        if self.0.peers.is_empty() {
+            self.advance_commit(state);
+        }
+    }
+
+    fn become_follower(&self, state: &mut State, term: u64, leader_id: Option<u32>) -> Result<()> {
+        if term > state.term {
+            state.term = term;
+            state.voted_for = None;
+            state.save_hard_state()?;
+        }
+        if state.role != Role::Follower {
+            debug!("raft[{}] steps down at term {}", self.0.id, term);
+        }
+        state.role = Role::Follower;
+        state.leader_id = leader_id;
+        state.election_deadline = Instant::now() + random_timeout(&self.0.config);
+        Ok(())
+    }
+
+    fn append_local(&self, state: &mut State, command: Command) -> Result<u64> {
+        let entry = Entry {
+            term: state.term,
+            index: state.last_index() + 1,
+            command,
+        };
+        state.storage.append(std::slice::from_ref(&entry))?;
+        state.log.push(entry);
+        Ok(state.last_index())
+    }
+
+    fn broadcast(&self) {
+        for peer in self.0.peers.iter().copied() {
+            let raft = self.clone();
+            tokio::spawn(async move { while raft.replicate(peer).await {} });
+        }
+    }
+
+    /// send one append request to `peer`, or a chunk of the snapshot if the entries it needs are
+    /// compacted, return true if more should be sent right away.
+    async fn replicate(&self, peer: u32) -> bool {
+        let (term, prev_log_index, chunk, req) = {
+            let mut state = self.0.state.lock().await;
+            if state.role != Role::Leader || state.inflight.contains(&peer) {
+                return false;
+            }
+            state.inflight.insert(peer);
+            let last_index = state.last_index();
+            let next_index = *state.next_index.get(&peer).unwrap_or(&(last_index + 1));
+            let prev_log_index = next_index - 1;
+            // the length of the chunk sent and whether it's the last one.
+            let mut chunk = None;
+            let req = if prev_log_index < state.snapshot_index {
+                let transfer = match state.transfers.remove(&peer) {
+                    Some(transfer) => transfer,
+                    None => {
+                        // the applied registry is as good a snapshot as the one kept on disk,
+                        // and newer.
+                        let snapshot = Snapshot {
+                            last_index: state.last_applied,
+                            last_term: state.term_at(state.last_applied).unwrap_or(0),
+                            registry: state.registry.clone(),
+                        };
+                        match serde_json::to_vec(&snapshot) {
+                            Ok(data) => Transfer {
+                                last_index: snapshot.last_index,
+                                data,
+                                offset: 0,
+                            },
+                            Err(e) => {
+                                error!("raft[{}] serialize snapshot error: {}", self.0.id, e);
+                                state.inflight.remove(&peer);
+                                return false;
+                            }
+                        }
+                    }
+                };
+                let end = transfer
+                    .data
+                    .len()
+                    .min(transfer.offset + self.0.config.snapshot_chunk_size);
+                let req = SnapshotReq {
+                    term: state.term,
+                    leader_id: self.0.id,
+                    last_index: transfer.last_index,
+                    offset: transfer.offset as u64,
+                    data: transfer.data[transfer.offset..end].to_vec(),
+                    done: end == transfer.data.len(),
+                };
+                chunk = Some((req.data.len(), req.done));
+                state.transfers.insert(peer, transfer);
+                Err(req)
+            } else {
+                let offset = (prev_log_index - state.snapshot_index) as usize;
+                Ok(AppendReq {
+                    term: state.term,
+                    leader_id: self.0.id,
+                    prev_log_index,
+                    prev_log_term: state.term_at(prev_log_index).unwrap_or(0),
+                    entries: state.log[offset..]
+                        .iter()
+                        .take(MAX_ENTRIES_PER_APPEND)
+                        .cloned()
+                        .collect(),
+                    leader_commit: state.commit_index,
+                })
+            };
+            (state.term, prev_log_index, chunk, req)
+        };
+        let res = match req {
+            Ok(req) => {
+                tokio::time::timeout(
+                    self.0.config.election_timeout / 2,
+                    self.0.transport.append_entries(peer, req),
+                )
+                .await
+            }
+            Err(req) => {
+                tokio::time::timeout(
+                    self.0.config.election_timeout / 2,
+                    self.0.transport.install_snapshot(peer, req),
+                )
+                .await
+            }
+        };
+        let mut state = self.0.state.lock().await;
+        state.inflight.remove(&peer);
+        let resp = match res {
+            Ok(Ok(resp)) => resp,
+            Ok(Err(e)) => {
+                debug!("raft[{}] append to {} error: {}", self.0.id, peer, e);
+                return false;
+            }
+            Err(_) => return false,
+        };
+        state.last_contact.insert(peer, Instant::now());
+        if resp.term > state.term {
+            if let Err(e) = self.become_follower(&mut state, resp.term, None) {
+                error!("raft[{}] save hard state error: {}", self.0.id, e);
+            }
+            return false;
+        }
+        if state.role != Role::Leader || state.term != term {
+            return false;
+        }
+        match chunk {
+            Some((len, false)) if resp.success => {
+                if let Some(transfer) = state.transfers.get_mut(&peer) {
+                    transfer.offset += len;
+                }
+                return true;
+            }
+            // done, or the follower lost the chunks before, which starts the snapshot over.
+            Some(_) => {
+                state.transfers.remove(&peer);
+            }
+            None => {}
+        }
+        if resp.success {
+            let match_index = state.match_index.entry(peer).or_insert(0);
+            *match_index = (*match_index).max(resp.match_index);
+            let next_index = *match_index + 1;
+            state.next_index.insert(peer, next_index);
+            self.advance_commit(&mut state);
+            next_index <= state.last_index()
+        } else {
+            let next_index = (resp.match_index + 1).clamp(1, prev_log_index.max(1));
+            state.next_index.insert(peer, next_index);
+            true
+        }
+    }
+
+    fn advance_commit(&self, state: &mut State) {
+        let mut index = state.last_index();
+        // terms in the log never decrease, stop at the first entry of an earlier term.
+        while index > state.commit_index && state.term_at(index) == Some(state.term) {
+            let replicas = 1 + self
+                .0
+                .peers
+                .iter()
+                .filter(|peer| state.match_index.get(peer).copied().unwrap_or(0) >= index)
+                .count();
+            if replicas >= self.quorum() {
+                state.commit_index = index;
+                break;
+            }
+            index -= 1;
+        }
+        self.apply_committed(state);
+    }
+
+    fn apply_committed(&self, state: &mut State) {
+        while state.last_applied < state.commit_index {
+            let entry = state.log[state.offset(state.last_applied + 1)].clone();
+            state.last_applied = entry.index;
+            let result = state.registry.apply(&entry.command);
+            (self.0.on_apply)(&entry.command, &result);
+            if let Some(slot) = state.waiting.get_mut(&entry.index) {
+                *slot = Some((entry.term, result));
+            }
+        }
+        if state.last_applied - state.snapshot_index >= self.0.config.snapshot_threshold {
+            if let Err(e) = self.compact(state) {
+                error!("raft[{}] compact log error: {}", self.0.id, e);
+            }
+        }
+        self.0.applied.send_replace(state.last_applied);
+    }
+
+    /// drop the applied entries from the log, keeping the registry they make as the snapshot.
+    fn compact(&self, state: &mut State) -> Result<()> {
+        let snapshot = Snapshot {
+            last_index: state.last_applied,
+            last_term: state.term_at(state.last_applied).unwrap_or(0),
+            registry: state.registry.clone(),
+        };
+        let offset = state.offset(snapshot.last_index);
+        state.storage.compact(&snapshot, &state.log[offset + 1..])?;
+        state.log.drain(..=offset);
+        state.snapshot_index = snapshot.last_index;
+        state.snapshot_term = snapshot.last_term;
+        debug!(
+            "raft[{}] log compacted up to {}",
+            self.0.id, snapshot.last_index
+        );
+        Ok(())
+    }
+
+    #[inline]
+    fn quorum(&self) -> usize {
+        let members = self.0.peers.len() + 1;
+        members / 2 + 1
+    }
+}
+
+/// somewhere in [election_timeout, 2 * election_timeout), so candidates rarely split votes.
+#[inline]
+fn random_timeout(config: &RaftConfig) -> Duration {
+    let base = config.election_timeout.as_millis() as u64;
+    Duration::from_millis(base + fastrand::u64(0..base.max(1)))
+}
+
+static RAFT: OnceCell<Raft> = OnceCell::const_new();
+
+pub(crate) fn get_raft() -> &'static Raft {
+    RAFT.get().expect("raft is not started")
+}
+
+pub(crate) async fn start() -> Result<()> {
+    let mut members = config().raft.members.clone();
+    if members.is_empty() {
+        members.push(my_id());
+    }
+    if !members.contains(&my_id()) {
+        return Err(anyhow!(
+            "scheduler {} is not a member of raft group {:?}",
+            my_id(),
+            members
+        ));
+    }
+    members.sort();
+    let raft = Raft::new(
+        my_id(),
+        &members,
+        RaftConfig {
+            election_timeout: config().raft.election_timeout,
+            heartbeat_interval: config().raft.heartbeat_interval,
+            snapshot_threshold: config().raft.snapshot_threshold,
+            snapshot_chunk_size: SNAPSHOT_CHUNK_SIZE,
+        },
+        &config().raft.dir,
+        Box::new(transport::ClusterTransport {}),
+        Box::new(crate::service::membership::on_apply),
+    )?;
+    if RAFT.set(raft.clone()).is_err() {
+        return Err(anyhow!("raft is started twice"));
+    }
+    tokio::spawn(async move { raft.run().await });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        path::PathBuf,
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use lib::{entity::ServerInfo, Result};
+    use tokio::task::JoinHandle;
+
+    use super::{
+        AppendReq, AppendResp, ApplyResult, Command, Raft, RaftConfig, SnapshotReq, Transport,
+        VoteReq, VoteResp,
+    };
+
+    /// schedulers of one process, `down` ones can neither send nor receive.
+    #[derive(Default)]
+    struct Network {
+        nodes: RwLock<HashMap<u32, Raft>>,
+        down: RwLock<HashSet<u32>>,
+    }
+
+    struct LocalTransport {
+        id: u32,
+        network: Arc<Network>,
+    }
+
+    impl LocalTransport {
+        fn target(&self, target: u32) -> Result<Raft> {
+            let down = self.network.down.read().unwrap();
+            if down.contains(&self.id) || down.contains(&target) {
+                return Err(anyhow!("{} -> {} is unreachable", self.id, target));
+            }
+            self.network
+                .nodes
+                .read()
+                .unwrap()
+                .get(&target)
+                .cloned()
+                .ok_or_else(|| anyhow!("{} is not started", target))
+        }
+    }
+
+    #[async_trait]
+    impl Transport for LocalTransport {
+        async fn request_vote(&self, target: u32, req: VoteReq) -> Result<VoteResp> {
+            self.target(target)?.handle_vote(req).await
+        }
+
+        async fn append_entries(&self, target: u32, req: AppendReq) -> Result<AppendResp> {
+            self.target(target)?.handle_append(req).await
+        }
+
+        async fn install_snapshot(&self, target: u32, req: SnapshotReq) -> Result<AppendResp> {
+            self.target(target)?.handle_snapshot(req).await
+        }
+
+        async fn propose(&self, target: u32, command: Command) -> Result<ApplyResult> {
+            self.target(target)?.propose_forwarded(command).await
+        }
+    }
+
+    struct Cluster {
+        dir: PathBuf,
+        members: Vec<u32>,
+        network: Arc<Network>,
+        tasks: HashMap<u32, JoinHandle<()>>,
+    }
+
+    impl Cluster {
+        fn new(members: Vec<u32>) -> Self {
+            let dir = std::env::temp_dir().join(format!("prim-raft-{}", fastrand::u64(..)));
+            let mut cluster = Self {
+                dir,
+                members: members.clone(),
+                network: Arc::new(Network::default()),
+                tasks: HashMap::new(),
+            };
+            for id in members {
+                cluster.start(id);
+            }
+            cluster
+        }
+
+        fn start(&mut self, id: u32) {
+            let raft = Raft::new(
+                id,
+                &self.members,
+                RaftConfig {
+                    election_timeout: Duration::from_millis(150),
+                    heartbeat_interval: Duration::from_millis(30),
+                    snapshot_threshold: 8,
+                    // a snapshot takes a few chunks.
+                    snapshot_chunk_size: 512,
+                },
+                &self.dir,
+                Box::new(LocalTransport {
+                    id,
+                    network: self.network.clone(),
+                }),
+                Box::new(|_, _| {}),
+            )
+            .unwrap();
+            self.network.nodes.write().unwrap().insert(id, raft.clone());
+            self.network.down.write().unwrap().remove(&id);
+            self.tasks
+                .insert(id, tokio::spawn(async move { raft.run().await }));
+        }
+
+        fn stop(&mut self, id: u32) {
+            self.network.down.write().unwrap().insert(id);
+            if let Some(task) = self.tasks.remove(&id) {
+                task.abort();
+            }
+            self.network.nodes.write().unwrap().remove(&id);
+        }
+
+        fn node(&self, id: u32) -> Raft {
+            self.network.nodes.read().unwrap().get(&id).unwrap().clone()
+        }
+
+        async fn leader(&self) -> u32 {
+            for _ in 0..200 {
+                for id in self.tasks.keys() {
+                    if self.node(*id).is_leader().await {
+                        return *id;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            panic!("no leader elected");
+        }
+
+        /// wait until every running scheduler has applied the same registry.
+        async fn converge(&self) {
+            for _ in 0..200 {
+                let mut registry_list = vec![];
+                for id in self.tasks.keys() {
+                    registry_list.push(self.node(*id).registry().await);
+                }
+                if registry_list.windows(2).all(|pair| pair[0] == pair[1]) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            panic!("registries do not converge");
+        }
+    }
+
+    impl Drop for Cluster {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn register(id: u32, address: &str) -> Command {
+        Command::RegisterNode {
+            info: ServerInfo {
+                id,
+                service_address: address.to_string(),
+                ..Default::default()
+            },
+            scheduler_id: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replicate() {
+        let cluster = Cluster::new(vec![1, 2, 3]);
+        let leader = cluster.leader().await;
+        let follower = cluster
+            .members
+            .iter()
+            .copied()
+            .find(|id| *id != leader)
+            .unwrap();
+        // proposals on a follower are forwarded to the leader.
+        let result = cluster
+            .node(follower)
+            .propose(register(10, "127.0.0.1:11120"))
+            .await
+            .unwrap();
+        assert_eq!(result, ApplyResult::Applied);
+        let result = cluster
+            .node(leader)
+            .propose(register(10, "127.0.0.1:11121"))
+            .await
+            .unwrap();
+        assert!(matches!(result, ApplyResult::Rejected(_)));
+        cluster
+            .node(leader)
+            .propose(Command::AssignShard {
+                shard: "msg-0".to_string(),
+                node_id: 10,
+            })
+            .await
+            .unwrap();
+        cluster.converge().await;
+        let registry = cluster.node(follower).registry().await;
+        assert_eq!(registry.nodes.len(), 1);
+        assert_eq!(registry.shards.get("msg-0"), Some(&10));
+    }
+
+    #[tokio::test]
+    async fn test_failover_and_restart() {
+        let mut cluster = Cluster::new(vec![1, 2, 3]);
+        let leader = cluster.leader().await;
+        cluster
+            .node(leader)
+            .propose(register(10, "127.0.0.1:11120"))
+            .await
+            .unwrap();
+        cluster.converge().await;
+
+        cluster.stop(leader);
+        let new_leader = cluster.leader().await;
+        assert_ne!(leader, new_leader);
+        cluster
+            .node(new_leader)
+            .propose(register(11, "127.0.0.1:11121"))
+            .await
+            .unwrap();
+
+        // the old leader recovers its log from disk and catches up.
+        cluster.start(leader);
+        cluster.converge().await;
+        let registry = cluster.node(leader).registry().await;
+        assert_eq!(
+            registry.nodes.keys().copied().collect::<Vec<u32>>(),
+            vec![10, 11]
+        );
+
+        // a whole group restart keeps the registry.
+        for id in cluster.members.clone() {
+            cluster.stop(id);
+        }
+        for id in cluster.members.clone() {
+            cluster.start(id);
+        }
+        let leader = cluster.leader().await;
+        cluster
+            .node(leader)
+            .propose(Command::UnregisterNode { node_id: 10 })
+            .await
+            .unwrap();
+        cluster.converge().await;
+        let registry = cluster.node(leader).registry().await;
+        assert_eq!(
+            registry.nodes.keys().copied().collect::<Vec<u32>>(),
+            vec![11]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let mut cluster = Cluster::new(vec![1, 2, 3]);
+        let leader = cluster.leader().await;
+        let lagging = cluster
+            .members
+            .iter()
+            .copied()
+            .find(|id| *id != leader)
+            .unwrap();
+        cluster.stop(lagging);
+        for id in 10..40 {
+            cluster
+                .node(leader)
+                .propose(register(id, &format!("127.0.0.1:{}", 11120 + id)))
+                .await
+                .unwrap();
+        }
+        assert!(cluster.node(leader).0.state.lock().await.log.len() <= 8);
+
+        // the entries it missed are compacted, so it catches up by the snapshot.
+        cluster.start(lagging);
+        cluster.converge().await;
+        assert_eq!(cluster.node(lagging).registry().await.nodes.len(), 30);
+
+        // the registry is recovered from the snapshot and the entries after it.
+        for id in cluster.members.clone() {
+            cluster.stop(id);
+        }
+        for id in cluster.members.clone() {
+            cluster.start(id);
+        }
+        for id in cluster.members.clone() {
+            let node = cluster.node(id);
+            assert!(node.0.state.lock().await.snapshot_index > 0);
+            assert!(!node.registry().await.nodes.is_empty());
+        }
+        let leader = cluster.leader().await;
+        cluster
+            .node(leader)
+            .propose(Command::UnregisterNode { node_id: 10 })
+            .await
+            .unwrap();
+        cluster.converge().await;
+        assert_eq!(cluster.node(leader).registry().await.nodes.len(), 29);
+    }
+}
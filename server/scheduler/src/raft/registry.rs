@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use lib::entity::{ServerInfo, ServerStatus};
+use serde::{Deserialize, Serialize};
+
+/// changes of the registry, every scheduler applies them in the same order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    /// appended by a new leader to commit entries of previous terms.
+    Noop,
+    /// `scheduler_id` is the scheduler the node connects to.
+    RegisterNode {
+        info: ServerInfo,
+        scheduler_id: u32,
+    },
+    UnregisterNode {
+        node_id: u32,
+    },
+    CrashNode {
+        node_id: u32,
+    },
+    /// hand a shard(e.g. a topic) to a node, replacing the previous owner.
+    AssignShard {
+        shard: String,
+        node_id: u32,
+    },
+    UnassignShard {
+        shard: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) enum ApplyResult {
+    Applied,
+    Rejected(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct NodeRecord {
+    pub(crate) info: ServerInfo,
+    pub(crate) scheduler_id: u32,
+}
+
+/// the replicated state: which nodes are members of the cluster and who owns which shard.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct Registry {
+    pub(crate) nodes: BTreeMap<u32, NodeRecord>,
+    pub(crate) shards: BTreeMap<String, u32>,
+}
+
+impl Registry {
+    /// must be deterministic, it runs on every scheduler and on every restart.
+    pub(crate) fn apply(&mut self, command: &Command) -> ApplyResult {
+        match command {
+            Command::Noop => ApplyResult::Applied,
+            Command::RegisterNode { info, scheduler_id } => {
+                if let Some(record) = self.nodes.get(&info.id) {
+                    // a node id can only be held by one endpoint until it leaves or crashes.
+                    if record.info.service_address != info.service_address {
+                        return ApplyResult::Rejected(format!(
+                            "node id {} is held by {}",
+                            info.id, record.info.service_address
+                        ));
+                    }
+                }
+                let mut info = info.clone();
+                info.status = ServerStatus::Normal;
+                info.load = None;
+                self.nodes.insert(
+                    info.id,
+                    NodeRecord {
+                        info,
+                        scheduler_id: *scheduler_id,
+                    },
+                );
+                ApplyResult::Applied
+            }
+            Command::UnregisterNode { node_id } | Command::CrashNode { node_id } => {
+                if self.nodes.remove(node_id).is_none() {
+                    return ApplyResult::Rejected(format!("node {} is not registered", node_id));
+                }
+                self.shards.retain(|_, owner| owner != node_id);
+                ApplyResult::Applied
+            }
+            Command::AssignShard { shard, node_id } => {
+                if !self.nodes.contains_key(node_id) {
+                    return ApplyResult::Rejected(format!("node {} is not registered", node_id));
+                }
+                self.shards.insert(shard.clone(), *node_id);
+                ApplyResult::Applied
+            }
+            Command::UnassignShard { shard } => {
+                self.shards.remove(shard);
+                ApplyResult::Applied
+            }
+        }
+    }
+
+    /// the commands turning this registry into `to`, so a snapshot can be handed to the apply hook
+    /// as if its entries were applied one by one.
+    pub(crate) fn diff(&self, to: &Registry) -> Vec<Command> {
+        let mut command_list = vec![];
+        for node_id in self.nodes.keys() {
+            if !to.nodes.contains_key(node_id) {
+                command_list.push(Command::UnregisterNode { node_id: *node_id });
+            }
+        }
+        for (node_id, record) in to.nodes.iter() {
+            if self.nodes.get(node_id) != Some(record) {
+                command_list.push(Command::RegisterNode {
+                    info: record.info.clone(),
+                    scheduler_id: record.scheduler_id,
+                });
+            }
+        }
+        for shard in self.shards.keys() {
+            if !to.shards.contains_key(shard) {
+                command_list.push(Command::UnassignShard {
+                    shard: shard.clone(),
+                });
+            }
+        }
+        for (shard, node_id) in to.shards.iter() {
+            if self.shards.get(shard) != Some(node_id) {
+                command_list.push(Command::AssignShard {
+                    shard: shard.clone(),
+                    node_id: *node_id,
+                });
+            }
+        }
+        command_list
+    }
+}
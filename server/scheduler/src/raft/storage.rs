@@ -0,0 +1,129 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use lib::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{Entry, Registry};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct HardState {
+    pub(super) term: u64,
+    pub(super) voted_for: Option<u32>,
+}
+
+/// the registry with every entry up to `last_index` applied, those entries are dropped from the log.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    pub(crate) last_index: u64,
+    pub(crate) last_term: u64,
+    pub(crate) registry: Registry,
+}
+
+/// durable raft state of one scheduler: term and vote in `raft-<id>.state`, the latest snapshot
+/// in `raft-<id>.snapshot` and log entries after it as json lines in `raft-<id>.log`.
+pub(super) struct Storage {
+    state_path: PathBuf,
+    snapshot_path: PathBuf,
+    log_path: PathBuf,
+}
+
+impl Storage {
+    pub(super) fn open(dir: &Path, id: u32) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            state_path: dir.join(format!("raft-{}.state", id)),
+            snapshot_path: dir.join(format!("raft-{}.snapshot", id)),
+            log_path: dir.join(format!("raft-{}.log", id)),
+        })
+    }
+
+    pub(super) fn load(&self) -> Result<(HardState, Snapshot, Vec<Entry>)> {
+        let hard_state = match fs::read(&self.state_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HardState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot = match fs::read(&self.snapshot_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut log: Vec<Entry> = vec![];
+        let file = match File::open(&self.log_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((hard_state, snapshot, log))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<Entry>(&line) {
+                // left by a crash between saving the snapshot and rewriting the log.
+                Ok(entry) if entry.index <= snapshot.last_index => continue,
+                Ok(entry) if entry.index == snapshot.last_index + log.len() as u64 + 1 => {
+                    log.push(entry)
+                }
+                // a torn write at the tail, entries after it were never acknowledged.
+                _ => {
+                    warn!("raft log {:?} is truncated at {}", self.log_path, log.len());
+                    self.rewrite(&log)?;
+                    break;
+                }
+            }
+        }
+        Ok((hard_state, snapshot, log))
+    }
+
+    pub(super) fn save_hard_state(&self, hard_state: &HardState) -> Result<()> {
+        replace(&self.state_path, &serde_json::to_vec(hard_state)?)
+    }
+
+    pub(super) fn append(&self, entries: &[Entry]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)?;
+        file.write_all(&encode(entries)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// used when a conflicting suffix is dropped.
+    pub(super) fn rewrite(&self, log: &[Entry]) -> Result<()> {
+        replace(&self.log_path, &encode(log)?)
+    }
+
+    /// keep the snapshot, then the entries after it.
+    pub(super) fn compact(&self, snapshot: &Snapshot, log: &[Entry]) -> Result<()> {
+        replace(&self.snapshot_path, &serde_json::to_vec(snapshot)?)?;
+        self.rewrite(log)
+    }
+}
+
+#[inline]
+fn encode(entries: &[Entry]) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    for entry in entries {
+        serde_json::to_writer(&mut bytes, entry)?;
+        bytes.push(b'\n');
+    }
+    Ok(bytes)
+}
+
+/// write to a temporary file and rename it, so a crash never leaves a half written file.
+#[inline]
+fn replace(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
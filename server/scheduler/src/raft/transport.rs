@@ -0,0 +1,61 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use lib::{
+    entity::{ReqwestMsg, ReqwestResourceID},
+    Result,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::cluster::get_cluster_caller_map;
+
+use super::{
+    AppendReq, AppendResp, ApplyResult, Command, SnapshotReq, Transport, VoteReq, VoteResp,
+};
+
+/// carries raft rpc over the cluster connections between schedulers, payloads are json.
+pub(super) struct ClusterTransport {}
+
+#[async_trait]
+impl Transport for ClusterTransport {
+    async fn request_vote(&self, target: u32, req: VoteReq) -> Result<VoteResp> {
+        call(target, ReqwestResourceID::RaftRequestVote, &req).await
+    }
+
+    async fn append_entries(&self, target: u32, req: AppendReq) -> Result<AppendResp> {
+        call(target, ReqwestResourceID::RaftAppendEntries, &req).await
+    }
+
+    async fn install_snapshot(&self, target: u32, req: SnapshotReq) -> Result<AppendResp> {
+        call(target, ReqwestResourceID::RaftInstallSnapshot, &req).await
+    }
+
+    async fn propose(&self, target: u32, command: Command) -> Result<ApplyResult> {
+        let resp: std::result::Result<ApplyResult, String> =
+            call(target, ReqwestResourceID::RaftPropose, &command).await?;
+        resp.map_err(|e| anyhow!("propose to scheduler {} error: {}", target, e))
+    }
+}
+
+async fn call<Req: Serialize, Resp: DeserializeOwned>(
+    target: u32,
+    resource_id: ReqwestResourceID,
+    req: &Req,
+) -> Result<Resp> {
+    let caller = match get_cluster_caller_map().get(target) {
+        Some(caller) => caller.clone(),
+        None => return Err(anyhow!("scheduler {} is not connected", target)),
+    };
+    let payload = serde_json::to_vec(req)?;
+    // the length of a frame is kept in a u16, with the 10 bytes of its head.
+    if payload.len() > u16::MAX as usize - 10 {
+        return Err(anyhow!(
+            "{:?} request of {} bytes is too large",
+            resource_id,
+            payload.len()
+        ));
+    }
+    let resp = caller
+        .call(ReqwestMsg::with_resource_id_payload(resource_id, &payload))
+        .await?;
+    Ok(serde_json::from_slice(resp.payload())?)
+}
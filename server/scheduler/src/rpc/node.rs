@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use base64::Engine;
 use lib::{
-    entity::{Msg, ReqwestMsg, ReqwestResourceID, Type},
+    entity::{Msg, ReqwestMsg, ReqwestResourceID, Type, FORWARD_REFUSED},
     Result,
 };
 
@@ -24,9 +24,9 @@ use super::{
     },
 };
 use crate::{
-    cache::{get_redis_ops, USER_NODE_MAP},
+    cache::{get_redis_ops, NODE_USER_SET, USER_NODE_MAP},
     config::config,
-    service::{get_client_caller_map, get_message_node_set, get_server_info_map},
+    service::{get_client_caller_map, get_message_node_set, get_server_info_map, message_node_of},
 };
 use crate::{
     rpc::node_proto::{WhichToConnectReq, WhichToConnectResp},
@@ -61,6 +61,37 @@ impl RpcClient {
     }
 }
 
+/// find the message node of `user_id`.
+///
+/// the placement recorded in `USER_NODE_MAP_` is kept as long as that node is still alive,
+/// so users already connected won't be moved when a new node joins. otherwise the user is
+/// (re)placed by the consistent-hash ring and the record is updated, together with
+/// the reverse index `NODE_USER_SET_` used for moving users off a crashed node.
+async fn user_node(user_id: u64) -> std::result::Result<u32, Status> {
+    let key = format!("{}{}", USER_NODE_MAP, user_id);
+    let mut redis_ops = get_redis_ops().await;
+    let recorded: Result<u32> = redis_ops.get(&key).await;
+    if let Ok(node_id) = recorded {
+        if get_message_node_set().contains(node_id) {
+            return Ok(node_id);
+        }
+        let set_key = format!("{}{}", NODE_USER_SET, node_id);
+        _ = redis_ops.remove_set(&set_key, &user_id).await;
+    }
+    let node_id = match message_node_of(user_id) {
+        Some(node_id) => node_id,
+        None => return Err(Status::internal("message cluster all crashed.")),
+    };
+    match redis_ops.set(&key, &node_id).await {
+        Ok(_) => {
+            let set_key = format!("{}{}", NODE_USER_SET, node_id);
+            _ = redis_ops.push_set(&set_key, &user_id).await;
+            Ok(node_id)
+        }
+        Err(_) => Err(Status::internal("redis set error")),
+    }
+}
+
 pub(crate) struct RpcServer {}
 
 impl RpcServer {
@@ -87,7 +118,6 @@ impl Scheduler for RpcServer {
         request: Request<CurrNodeGroupIdUserListReq>,
     ) -> std::result::Result<Response<CurrNodeGroupIdUserListResp>, Status> {
         let mut rpc_client = get_rpc_client().await;
-        let mut redis_ops = get_redis_ops().await;
         let request_inner = request.into_inner();
         let user_list = match rpc_client
             .call_group_user_list(request_inner.group_id)
@@ -100,19 +130,7 @@ impl Scheduler for RpcServer {
         };
         let mut list = vec![];
         for user_id in user_list.iter() {
-            let key = format!("{}{}", USER_NODE_MAP, user_id);
-            let node_id = match redis_ops.get::<u32>(&key).await {
-                Ok(node_id) => node_id,
-                // todo: if user not in redis, we should add it.
-                Err(_) => {
-                    let node_id = self
-                        .which_node(Request::new(WhichNodeReq { user_id: *user_id }))
-                        .await?;
-                    let node_id = node_id.into_inner().node_id;
-                    _ = redis_ops.set(&key, &node_id).await;
-                    node_id
-                }
-            };
+            let node_id = user_node(*user_id).await?;
             if node_id == request_inner.node_id {
                 list.push(*user_id);
             }
@@ -127,31 +145,7 @@ impl Scheduler for RpcServer {
         request: Request<WhichNodeReq>,
     ) -> std::result::Result<Response<WhichNodeResp>, Status> {
         let user_id = request.into_inner().user_id;
-        let key = format!("{}{}", USER_NODE_MAP, user_id);
-        // todo unsafecell optimization.
-        let mut redis_ops = get_redis_ops().await;
-        let set = get_message_node_set().0;
-        let value: Result<u32> = redis_ops.get(&key).await;
-        let node_id = match value {
-            Ok(value) => value,
-            Err(_) => {
-                let node_size = set.len();
-                if node_size == 0 {
-                    return Err(Status::internal("message cluster all crashed."));
-                }
-                let index = user_id % (node_size as u64);
-                let node_id = *match set.iter().nth(index as usize) {
-                    Some(v) => v,
-                    None => return Err(Status::internal("try again.")),
-                };
-                match redis_ops.set(&key, &node_id).await {
-                    Ok(_) => node_id as u32,
-                    Err(_) => {
-                        return Err(Status::internal("redis set error"));
-                    }
-                }
-            }
-        };
+        let node_id = user_node(user_id).await?;
         Ok(Response::new(WhichNodeResp { node_id }))
     }
 
@@ -202,20 +196,27 @@ impl Scheduler for RpcServer {
             extension.as_slice(),
         );
         msg.set_type(Type::from(req.r#type as u16));
+        msg.set_seqnum(req.seqnum);
         let req =
             ReqwestMsg::with_resource_id_payload(ReqwestResourceID::MessageForward, msg.as_slice());
         let client_map = get_client_caller_map().0;
         let sender = client_map.get(&node_id);
         match sender {
+            // a message refused is answered as unsuccessful, a failure as an error, so the caller
+            // knows which ones are worth sending again.
             Some(client) => match client.call(req).await {
-                Ok(_) => Ok(Response::new(PushMsgResp {
-                    success: true,
-                    err_msg: "".to_string(),
-                })),
-                Err(_) => Ok(Response::new(PushMsgResp {
-                    success: false,
-                    err_msg: "send msg failed".to_string(),
-                })),
+                Ok(resp) => match resp.payload().split_first() {
+                    None => Ok(Response::new(PushMsgResp {
+                        success: true,
+                        err_msg: "".to_string(),
+                    })),
+                    Some((&FORWARD_REFUSED, cause)) => Ok(Response::new(PushMsgResp {
+                        success: false,
+                        err_msg: String::from_utf8_lossy(cause).to_string(),
+                    })),
+                    Some((_, cause)) => Err(Status::unavailable(String::from_utf8_lossy(cause))),
+                },
+                Err(_) => Err(Status::unavailable("send msg failed")),
             },
             None => Err(Status::internal("node not found")),
         }
@@ -225,16 +226,7 @@ impl Scheduler for RpcServer {
         &self,
         request: Request<WhichToConnectReq>,
     ) -> std::result::Result<Response<WhichToConnectResp>, Status> {
-        let recorder_node_set = get_message_node_set().0;
-        let index = (request.into_inner().user_id % (recorder_node_set.len() as u64)) as usize;
-        let node_id;
-        {
-            let id = match recorder_node_set.iter().nth(index) {
-                Some(node_id) => node_id,
-                None => return Err(Status::internal("try again")),
-            };
-            node_id = *id;
-        }
+        let node_id = user_node(request.into_inner().user_id).await?;
         let node_info_map = get_server_info_map().0;
         let node_info = match node_info_map.get(&node_id) {
             Some(node_info) => node_info,
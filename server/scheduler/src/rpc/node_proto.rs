@@ -53,6 +53,9 @@ pub struct PushMsgReq {
     pub payload: ::prost::alloc::string::String,
     #[prost(string, tag = "7")]
     pub extension: ::prost::alloc::string::String,
+    /// the message changed by an edit or withdraw.
+    #[prost(uint64, tag = "8")]
+    pub seqnum: u64,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -139,6 +142,25 @@ pub struct GroupUserListReq {
 pub struct GroupUserListResp {
     #[prost(uint64, repeated, tag = "1")]
     pub user_list: ::prost::alloc::vec::Vec<u64>,
+    /// the admins, who are in user_list too.
+    #[prost(uint64, repeated, tag = "2")]
+    pub admin_list: ::prost::alloc::vec::Vec<u64>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MsgExistListReq {
+    /// see `lib::util::conversation_id`.
+    #[prost(string, tag = "1")]
+    pub conversation_id: ::prost::alloc::string::String,
+    #[prost(uint64, repeated, tag = "2")]
+    pub seq_num_list: ::prost::alloc::vec::Vec<u64>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MsgExistListResp {
+    /// those of seq_num_list in the conversation.
+    #[prost(uint64, repeated, tag = "1")]
+    pub seq_num_list: ::prost::alloc::vec::Vec<u64>,
 }
 /// Generated client implementations.
 pub mod scheduler_client {
@@ -558,6 +580,31 @@ pub mod api_client {
                 .insert(GrpcMethod::new("node_proto.API", "GroupUserList"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn msg_exist_list(
+            &mut self,
+            request: impl tonic::IntoRequest<super::MsgExistListReq>,
+        ) -> std::result::Result<
+            tonic::Response<super::MsgExistListResp>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/node_proto.API/MsgExistList",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("node_proto.API", "MsgExistList"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -1165,6 +1212,13 @@ pub mod api_server {
             tonic::Response<super::GroupUserListResp>,
             tonic::Status,
         >;
+        async fn msg_exist_list(
+            &self,
+            request: tonic::Request<super::MsgExistListReq>,
+        ) -> std::result::Result<
+            tonic::Response<super::MsgExistListResp>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
     pub struct ApiServer<T: Api> {
@@ -1289,6 +1343,50 @@ pub mod api_server {
                     };
                     Box::pin(fut)
                 }
+                "/node_proto.API/MsgExistList" => {
+                    #[allow(non_camel_case_types)]
+                    struct MsgExistListSvc<T: Api>(pub Arc<T>);
+                    impl<T: Api> tonic::server::UnaryService<super::MsgExistListReq>
+                    for MsgExistListSvc<T> {
+                        type Response = super::MsgExistListResp;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::MsgExistListReq>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                (*inner).msg_exist_list(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = MsgExistListSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         Ok(
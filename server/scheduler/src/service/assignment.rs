@@ -0,0 +1,289 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use lib::{
+    cache::redis_ops::RedisOps,
+    entity::{ReqwestMsg, ReqwestResourceID},
+    Result, MESSAGE_NODE_ID_BEGINNING, MSGPROCESSOR_ID_BEGINNING, SCHEDULER_NODE_ID_BEGINNING,
+};
+use tracing::{error, info};
+
+use crate::{
+    cache::{get_redis_ops, MQ_TOPIC_DRAINED, MQ_TOPIC_LAG},
+    config::config,
+    raft::{get_raft, Command, Registry},
+};
+
+use super::get_client_caller_map;
+
+const TOPIC_PREFIX: &str = "msg-";
+
+lazy_static! {
+    /// mirror of the shards in the registry, to find the previous owner of a moved topic.
+    static ref TOPIC_OWNER_MAP: Arc<DashMap<String, u32>> = Arc::new(DashMap::new());
+}
+
+/// the topic a message node publishes to.
+#[inline]
+fn topic_of(node_id: u32) -> String {
+    format!("{}{:06}", TOPIC_PREFIX, node_id)
+}
+
+/// called when `AssignShard` is applied, only the scheduler a msgprocessor connects to
+/// notifies it.
+pub(crate) fn on_assign(topic: &str, node_id: u32) {
+    if !topic.starts_with(TOPIC_PREFIX) {
+        return;
+    }
+    let previous = TOPIC_OWNER_MAP.insert(topic.to_owned(), node_id);
+    if let Some(previous) = previous {
+        if previous != node_id {
+            unassign(topic, previous);
+        }
+    }
+    assign(topic, node_id);
+}
+
+pub(crate) fn on_unassign(topic: &str) {
+    if let Some((_, node_id)) = TOPIC_OWNER_MAP.remove(topic) {
+        unassign(topic, node_id);
+    }
+}
+
+/// a msgprocessor registers again after restart, its consumers are gone.
+pub(crate) fn on_register(node_id: u32) {
+    TOPIC_OWNER_MAP
+        .iter()
+        .filter(|entry| *entry.value() == node_id)
+        .for_each(|entry| assign(entry.key(), node_id));
+}
+
+/// the registry drops shards of a leaving node, so does the mirror.
+pub(crate) fn on_leave(node_id: u32) {
+    TOPIC_OWNER_MAP.retain(|_, owner| *owner != node_id);
+}
+
+fn assign(topic: &str, node_id: u32) {
+    let message_node_id = topic
+        .trim_start_matches(TOPIC_PREFIX)
+        .parse::<u32>()
+        .unwrap_or_default();
+    let params = serde_json::json!({
+        "id": message_node_id,
+        "topic": topic,
+        "consumer_number": config().assignment.consumer_number,
+    });
+    notify(node_id, ReqwestResourceID::AssignMQProcessor, params);
+}
+
+fn unassign(topic: &str, node_id: u32) {
+    let params = serde_json::json!({ "topic": topic });
+    notify(node_id, ReqwestResourceID::UnassignMQProcessor, params);
+}
+
+fn notify(node_id: u32, resource_id: ReqwestResourceID, params: serde_json::Value) {
+    let caller = match get_client_caller_map().get(node_id) {
+        Some(caller) => caller.clone(),
+        None => return,
+    };
+    tokio::spawn(async move {
+        let req = ReqwestMsg::with_resource_id_payload(resource_id, params.to_string().as_bytes());
+        match caller.call(req).await {
+            Ok(resp) => info!(
+                "{} {} to {}: {}",
+                resource_id,
+                params["topic"],
+                node_id,
+                String::from_utf8_lossy(resp.payload())
+            ),
+            Err(e) => error!(
+                "{} {} to {} error: {}",
+                resource_id, params["topic"], node_id, e
+            ),
+        }
+    });
+}
+
+/// run on every scheduler, but only the leader makes decisions.
+pub(super) async fn balance() -> Result<()> {
+    let mut interval = tokio::time::interval(config().assignment.check_interval);
+    loop {
+        interval.tick().await;
+        if !get_raft().is_leader().await {
+            continue;
+        }
+        if let Err(e) = balance_once(&get_raft().registry().await).await {
+            error!("balance topics error: {}", e);
+        }
+    }
+}
+
+async fn balance_once(registry: &Registry) -> Result<()> {
+    let mut redis_ops = get_redis_ops().await;
+    let processor_list = registry
+        .nodes
+        .keys()
+        .copied()
+        .filter(|node_id| *node_id >= MSGPROCESSOR_ID_BEGINNING)
+        .collect::<Vec<u32>>();
+    let mut topic_list = registry
+        .nodes
+        .keys()
+        .copied()
+        .filter(|node_id| {
+            (MESSAGE_NODE_ID_BEGINNING..SCHEDULER_NODE_ID_BEGINNING).contains(node_id)
+        })
+        .map(topic_of)
+        .collect::<Vec<String>>();
+    // topics of departed message nodes are kept until they are drained.
+    topic_list.extend(
+        registry
+            .shards
+            .keys()
+            .filter(|topic| topic.starts_with(TOPIC_PREFIX) && !topic_list.contains(topic))
+            .cloned()
+            .collect::<Vec<String>>(),
+    );
+    let lag_unit = config().assignment.lag_unit.as_millis() as f64;
+    let mut weight_map = BTreeMap::new();
+    for topic in topic_list {
+        // missing if no msgprocessor has reported it lately, which tells nothing.
+        let lag = redis_ops
+            .get::<u64>(&format!("{}{}", MQ_TOPIC_LAG, topic))
+            .await
+            .ok();
+        let is_live = registry
+            .nodes
+            .contains_key(&topic.trim_start_matches(TOPIC_PREFIX).parse().unwrap_or(0));
+        if !is_live && lag.is_some() && is_drained(&mut redis_ops, &topic).await {
+            info!("topic {} is drained, unassigned", topic);
+            get_raft()
+                .propose(Command::UnassignShard { shard: topic })
+                .await?;
+            continue;
+        }
+        weight_map.insert(topic, 1.0 + lag.unwrap_or(0) as f64 / lag_unit);
+    }
+    for (topic, node_id) in plan(&weight_map, &registry.shards, &processor_list) {
+        info!("assign topic {} to msgprocessor {}", topic, node_id);
+        get_raft()
+            .propose(Command::AssignShard {
+                shard: topic,
+                node_id,
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// the owner compares the committed offsets of the topic with its end, a lag of zero only
+/// means the last batch was not full.
+async fn is_drained(redis_ops: &mut RedisOps, topic: &str) -> bool {
+    redis_ops
+        .get::<u8>(&format!("{}{}", MQ_TOPIC_DRAINED, topic))
+        .await
+        .is_ok_and(|drained| drained == 1)
+}
+
+/// topics without a live owner go to the lightest processors, then at most one topic is moved
+/// from the heaviest processor to the lightest one, so consumers are not restarted all at once.
+///
+/// a move needs the gap to exceed the topic by one more topic, so it's never moved back.
+fn plan(
+    weight_map: &BTreeMap<String, f64>,
+    owner_map: &BTreeMap<String, u32>,
+    processor_list: &[u32],
+) -> Vec<(String, u32)> {
+    if processor_list.is_empty() {
+        return vec![];
+    }
+    let mut load_map = processor_list
+        .iter()
+        .map(|node_id| (*node_id, 0.0))
+        .collect::<BTreeMap<u32, f64>>();
+    let mut orphan_list = vec![];
+    for (topic, weight) in weight_map.iter() {
+        match owner_map
+            .get(topic)
+            .and_then(|owner| load_map.get_mut(owner))
+        {
+            Some(load) => *load += weight,
+            None => orphan_list.push((topic, *weight)),
+        }
+    }
+    let lightest = |load_map: &BTreeMap<u32, f64>| {
+        load_map
+            .iter()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(node_id, load)| (*node_id, *load))
+            .unwrap()
+    };
+    let mut plan_list = vec![];
+    if !orphan_list.is_empty() {
+        orphan_list.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for (topic, weight) in orphan_list {
+            let (node_id, _) = lightest(&load_map);
+            *load_map.get_mut(&node_id).unwrap() += weight;
+            plan_list.push((topic.clone(), node_id));
+        }
+        return plan_list;
+    }
+    let (heaviest, max_load) = load_map
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(node_id, load)| (*node_id, *load))
+        .unwrap();
+    let (lightest, min_load) = lightest(&load_map);
+    let gap = max_load - min_load;
+    // the best move leaves the two processors closest to each other.
+    let candidate = weight_map
+        .iter()
+        .filter(|(topic, weight)| owner_map.get(*topic) == Some(&heaviest) && **weight + 1.0 <= gap)
+        .min_by(|a, b| (gap - 2.0 * a.1).abs().total_cmp(&(gap - 2.0 * b.1).abs()));
+    if let Some((topic, _)) = candidate {
+        plan_list.push((topic.clone(), lightest));
+    }
+    plan_list
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::plan;
+
+    #[test]
+    fn test_plan() {
+        let mut weight_map = BTreeMap::new();
+        for topic in ["msg-1", "msg-2", "msg-3", "msg-4"] {
+            weight_map.insert(topic.to_string(), 1.0);
+        }
+        let owner_map = BTreeMap::new();
+        // orphans are spread.
+        let plan_list = plan(&weight_map, &owner_map, &[1, 2]);
+        assert_eq!(
+            plan_list
+                .iter()
+                .filter(|(_, node_id)| *node_id == 1)
+                .count(),
+            2
+        );
+        let owner_map = plan_list.into_iter().collect::<BTreeMap<String, u32>>();
+        assert!(plan(&weight_map, &owner_map, &[1, 2]).is_empty());
+        // a new processor takes one topic at a time.
+        let plan_list = plan(&weight_map, &owner_map, &[1, 2, 3]);
+        assert_eq!(plan_list.len(), 1);
+        assert_eq!(plan_list[0].1, 3);
+        // a lagging topic weighs more, so others are moved away from it.
+        weight_map.insert("msg-1".to_string(), 3.0);
+        let owner_map = [("msg-1", 1), ("msg-2", 1), ("msg-3", 2), ("msg-4", 2)]
+            .into_iter()
+            .map(|(topic, node_id)| (topic.to_string(), node_id))
+            .collect::<BTreeMap<String, u32>>();
+        assert_eq!(
+            plan(&weight_map, &owner_map, &[1, 2]),
+            vec![("msg-2".to_string(), 2)]
+        );
+    }
+}
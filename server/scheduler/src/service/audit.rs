@@ -0,0 +1,40 @@
+use lib::util::timestamp;
+use tracing::{error, warn};
+
+use crate::{
+    cache::{get_redis_ops, NODE_AUTH_AUDIT},
+    config::config,
+};
+
+#[derive(serde::Serialize)]
+struct AuditRecord<'a> {
+    timestamp: u64,
+    node_id: u32,
+    service_address: &'a str,
+    reason: &'a str,
+}
+
+/// record a rejected node, both in log and in redis so that it's visible from any scheduler.
+pub(crate) async fn reject(node_id: u32, service_address: &str, reason: &str) {
+    warn!(target: "audit", "node {}({}) rejected: {}", node_id, service_address, reason);
+    let record = AuditRecord {
+        timestamp: timestamp(),
+        node_id,
+        service_address,
+        reason,
+    };
+    let record = match serde_json::to_string(&record) {
+        Ok(record) => record,
+        Err(e) => {
+            error!("serialize audit record error: {}", e);
+            return;
+        }
+    };
+    let mut redis_ops = get_redis_ops().await;
+    if let Err(e) = redis_ops
+        .push_capped_list(NODE_AUTH_AUDIT, &record, config().auth.audit_size)
+        .await
+    {
+        error!("save audit record error: {}", e);
+    }
+}
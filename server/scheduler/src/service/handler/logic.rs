@@ -1,19 +1,25 @@
+use anyhow::anyhow;
 use async_trait::async_trait;
 use lib::{
-    entity::{ReqwestMsg, ServerInfo, ServerStatus},
+    entity::{
+        NodeAuthInfo, ReqwestMsg, ReqwestResourceID, ServerInfo, ServerLoad, ServerStatus,
+        REGISTER_AGAIN,
+    },
     net::{InnerStates, InnerStatesValue},
-    Result, MESSAGE_NODE_ID_BEGINNING, MSGPROCESSOR_ID_BEGINNING, SCHEDULER_NODE_ID_BEGINNING,
-    SEQNUM_NODE_ID_BEGINNING,
+    util::jwt::verify_join_token,
+    Result,
 };
 use lib_net_tokio::net::{server::ReqwestCaller, ReqwestHandler};
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
     config::config,
-    service::{ClientCallerMap, MessageNodeSet, MsgprocessorSet, SeqnumNodeSet},
-    util::my_id,
+    service::{audit, liveness, ClientCallerMap, ServerInfoMap},
+    util::{my_id, node_kind},
 };
 
+use super::check_identity;
+
 pub(crate) struct ServerAuth {}
 
 #[async_trait]
@@ -26,27 +32,6 @@ impl ReqwestHandler for ServerAuth {
             .unwrap()
             .get_parameter::<ClientCallerMap>()
             .unwrap();
-        let message_node_set = states
-            .get("generic_map")
-            .unwrap()
-            .as_generic_parameter_map()
-            .unwrap()
-            .get_parameter::<MessageNodeSet>()
-            .unwrap();
-        let seqnum_node_set = states
-            .get("generic_map")
-            .unwrap()
-            .as_generic_parameter_map()
-            .unwrap()
-            .get_parameter::<SeqnumNodeSet>()
-            .unwrap();
-        let msgprocessor_set = states
-            .get("generic_map")
-            .unwrap()
-            .as_generic_parameter_map()
-            .unwrap()
-            .get_parameter::<MsgprocessorSet>()
-            .unwrap();
         let client_caller = states
             .get("generic_map")
             .unwrap()
@@ -54,24 +39,17 @@ impl ReqwestHandler for ServerAuth {
             .unwrap()
             .get_parameter::<ReqwestCaller>();
 
-        let server_info = ServerInfo::from(req.payload());
-        if server_info.id >= MESSAGE_NODE_ID_BEGINNING
-            && server_info.id < SCHEDULER_NODE_ID_BEGINNING
-        {
-            message_node_set.insert(server_info.id);
-        } else if server_info.id >= SCHEDULER_NODE_ID_BEGINNING
-            && server_info.id < SEQNUM_NODE_ID_BEGINNING
-        {
-        } else if server_info.id >= SEQNUM_NODE_ID_BEGINNING
-            && server_info.id < MSGPROCESSOR_ID_BEGINNING
-        {
-            seqnum_node_set.insert(server_info.id);
-        } else {
-            msgprocessor_set.insert(server_info.id);
+        let auth_info = NodeAuthInfo::from(req.payload());
+        let server_info = auth_info.info;
+        if let Err(reason) = authenticate(&server_info, &auth_info.token, states) {
+            audit::reject(server_info.id, &server_info.service_address, &reason).await;
+            // an empty reply tells the node it's rejected.
+            return Ok(ReqwestMsg::with_resource_id_payload(req.resource_id(), b""));
         }
         if let Some(client_caller) = client_caller {
             client_map.insert(server_info.id, client_caller.clone());
         }
+        liveness::watch(server_info.id);
         states.insert(
             "node_id".to_owned(),
             InnerStatesValue::Num(server_info.id as u64),
@@ -92,3 +70,126 @@ impl ReqwestHandler for ServerAuth {
         Ok(res_msg)
     }
 }
+
+pub(crate) struct LoadReport {}
+
+#[async_trait]
+impl ReqwestHandler for LoadReport {
+    async fn run(&self, req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let server_info_map = states
+            .get("generic_map")
+            .unwrap()
+            .as_generic_parameter_map()
+            .unwrap()
+            .get_parameter::<ServerInfoMap>()
+            .unwrap();
+
+        let report = ServerInfo::from(req.payload());
+        check_identity(&report, states).await?;
+        if !liveness::heartbeat(report.id) {
+            warn!("load report from unwatched server {}", report.id);
+            return Ok(ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::NodeLoadReport,
+                REGISTER_AGAIN,
+            ));
+        }
+        let load = match report.load {
+            Some(load) => load,
+            None => return Err(anyhow!("load report of server {} has no load", report.id)),
+        };
+        match server_info_map.0.get_mut(&report.id) {
+            Some(mut server_info) => {
+                // only a working node can switch between normal and overload.
+                if server_info.status == ServerStatus::Online
+                    || server_info.status == ServerStatus::Normal
+                    || server_info.status == ServerStatus::Overload
+                {
+                    let status = if is_overload(&load) {
+                        ServerStatus::Overload
+                    } else {
+                        ServerStatus::Normal
+                    };
+                    if status != server_info.status {
+                        info!(
+                            "[{}] server {} is {}: {}",
+                            server_info.typ, report.id, status, load
+                        );
+                    }
+                    server_info.status = status;
+                }
+                server_info.load = Some(load);
+            }
+            None => {
+                warn!("load report from unregistered server {}", report.id);
+            }
+        }
+        Ok(ReqwestMsg::default())
+    }
+}
+
+pub(crate) struct Heartbeat {}
+
+#[async_trait]
+impl ReqwestHandler for Heartbeat {
+    async fn run(&self, _req: &mut ReqwestMsg, states: &mut InnerStates) -> Result<ReqwestMsg> {
+        let known = match states.get("node_id") {
+            Some(node_id) => {
+                let node_id = node_id.as_num().unwrap() as u32;
+                let known = liveness::heartbeat(node_id);
+                if !known {
+                    // the node has been treated as crashed, it must register again to be routable.
+                    warn!("heartbeat from unwatched server {}", node_id);
+                }
+                known
+            }
+            None => {
+                warn!("heartbeat before auth");
+                false
+            }
+        };
+        let payload = if known { &b""[..] } else { REGISTER_AGAIN };
+        Ok(ReqwestMsg::with_resource_id_payload(
+            ReqwestResourceID::NodeHeartbeat,
+            payload,
+        ))
+    }
+}
+
+/// the join token must be valid and issued for the kind of the node id, and for the node id itself
+/// if it's bound to one. a connection can not change its node id once authenticated.
+fn authenticate(
+    server_info: &ServerInfo,
+    token: &str,
+    states: &InnerStates,
+) -> std::result::Result<(), String> {
+    if let Some(node_id) = states.get("node_id") {
+        if node_id.as_num() != Some(server_info.id as u64) {
+            return Err(format!(
+                "connection is already authenticated as {:?}",
+                node_id.as_num()
+            ));
+        }
+    }
+    let (token_kind, token_node_id) =
+        verify_join_token(token, config().auth.join_secret.as_bytes())
+            .map_err(|e| format!("invalid join token: {}", e))?;
+    let kind = node_kind(server_info.id)
+        .ok_or_else(|| format!("node id {} is not allowed to join", server_info.id))?;
+    if token_kind != kind {
+        return Err(format!(
+            "join token for {} node is presented by {} node",
+            token_kind, kind
+        ));
+    }
+    if token_node_id != 0 && token_node_id != server_info.id {
+        return Err(format!("join token is bound to node {}", token_node_id));
+    }
+    Ok(())
+}
+
+#[inline]
+fn is_overload(load: &ServerLoad) -> bool {
+    load.cpu.1 > config().load.cpu_threshold
+        || load.mem.1 > config().load.mem_threshold
+        || load.disk.1 > config().load.disk_threshold
+}
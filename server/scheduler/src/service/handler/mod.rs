@@ -2,3 +2,19 @@ pub(super) mod logic;
 pub(super) mod message;
 pub(super) mod seqnum;
 pub(super) mod msgprocessor;
+
+use anyhow::anyhow;
+use lib::{entity::ServerInfo, net::InnerStates, Result};
+
+use super::audit;
+
+/// requests about a node are only accepted from the connection authenticated as that node.
+pub(super) async fn check_identity(server_info: &ServerInfo, states: &InnerStates) -> Result<()> {
+    let node_id = states.get("node_id").and_then(|node_id| node_id.as_num());
+    if node_id == Some(server_info.id as u64) {
+        return Ok(());
+    }
+    let reason = format!("connection of node {:?} acts as node {}", node_id, server_info.id);
+    audit::reject(server_info.id, &server_info.service_address, &reason).await;
+    Err(anyhow!(reason))
+}
@@ -0,0 +1,206 @@
+use std::{sync::Arc, time::Instant};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use lib::{
+    cache::redis_ops::RedisOps, Result, MESSAGE_NODE_ID_BEGINNING, SCHEDULER_NODE_ID_BEGINNING,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    cache::{get_redis_ops, NODE_USER_SET, USER_NODE_MAP, USER_ONLINE},
+    config::config,
+    raft::{get_raft, ApplyResult, Command},
+    util::my_id,
+};
+
+use super::{get_client_caller_map, message_node_of};
+
+lazy_static! {
+    /// last heartbeat time of every node connected to this scheduler.
+    static ref NODE_HEARTBEAT_MAP: Arc<DashMap<u32, Instant>> = Arc::new(DashMap::new());
+    /// nodes of a scheduler gone, watched by this scheduler as the leader, to the scheduler
+    /// they registered through.
+    static ref TAKEN_OVER_MAP: Arc<DashMap<u32, u32>> = Arc::new(DashMap::new());
+}
+
+/// start watching a node, called when the node authenticates.
+pub(crate) fn watch(node_id: u32) {
+    NODE_HEARTBEAT_MAP.insert(node_id, Instant::now());
+}
+
+/// stop watching a node, called when the node leaves normally.
+pub(crate) fn forget(node_id: u32) {
+    NODE_HEARTBEAT_MAP.remove(&node_id);
+}
+
+/// record a heartbeat, return false if the node is not watched(never authenticated or already
+/// treated as crashed), such node should register again.
+pub(crate) fn heartbeat(node_id: u32) -> bool {
+    match NODE_HEARTBEAT_MAP.get_mut(&node_id) {
+        Some(mut last) => {
+            *last = Instant::now();
+            true
+        }
+        None => false,
+    }
+}
+
+pub(super) async fn detect() -> Result<()> {
+    let mut interval = tokio::time::interval(config().liveness.check_interval);
+    loop {
+        interval.tick().await;
+        let crashed_list = NODE_HEARTBEAT_MAP
+            .iter()
+            .filter(|entry| entry.value().elapsed() > config().liveness.heartbeat_timeout)
+            .map(|entry| *entry.key())
+            .collect::<Vec<u32>>();
+        for node_id in crashed_list {
+            if let Err(e) = node_crash(node_id).await {
+                error!("handle crash of node {} error: {}", node_id, e);
+            }
+        }
+    }
+}
+
+/// remove the crashed node from the registry, so no more traffic will be routed to it.
+/// every scheduler notifies its own message nodes when the removal is applied, and the users
+/// of a crashed message node are moved to alive nodes.
+async fn node_crash(node_id: u32) -> Result<()> {
+    forget(node_id);
+    get_client_caller_map().remove(node_id);
+    warn!("server {} crashed", node_id);
+    if let ApplyResult::Rejected(_) = get_raft().propose(Command::CrashNode { node_id }).await? {
+        // already removed, by a normal leave or by another scheduler.
+        return Ok(());
+    }
+    if (MESSAGE_NODE_ID_BEGINNING..SCHEDULER_NODE_ID_BEGINNING).contains(&node_id) {
+        reassign_users(node_id).await?;
+    }
+    Ok(())
+}
+
+/// watch the registered nodes no scheduler is watching, those not heartbeating within the
+/// timeout are treated as crashed by `detect`:
+/// - nodes registered through this scheduler before it restarted.
+/// - nodes of a scheduler the leader can't reach anymore, the leader takes them over.
+///
+/// a node of an unreachable scheduler which is still alive registers again once its scheduler
+/// comes back, or it reconnects to another one.
+pub(super) async fn sweep() -> Result<()> {
+    // give nodes of this scheduler the time to come back after a restart.
+    tokio::time::sleep(config().liveness.heartbeat_timeout).await;
+    let mut interval = tokio::time::interval(config().liveness.check_interval);
+    loop {
+        interval.tick().await;
+        let raft = get_raft();
+        let registry = raft.registry().await;
+        let mut unreachable_list = vec![];
+        // only the leader knows which schedulers it can reach.
+        let is_leader = raft.is_leader().await;
+        for record in registry.nodes.values() {
+            if is_leader
+                && record.scheduler_id != my_id()
+                && !unreachable_list.contains(&record.scheduler_id)
+                && !raft
+                    .is_reachable(record.scheduler_id, config().liveness.heartbeat_timeout)
+                    .await
+            {
+                unreachable_list.push(record.scheduler_id);
+            }
+        }
+        // given back once the node registers through another scheduler, or leaves.
+        TAKEN_OVER_MAP.retain(|node_id, scheduler_id| {
+            let keep = registry
+                .nodes
+                .get(node_id)
+                .map(|record| record.scheduler_id)
+                == Some(*scheduler_id)
+                && unreachable_list.contains(scheduler_id);
+            // a node registered through this scheduler again is watched as its own.
+            let moved_here = registry
+                .nodes
+                .get(node_id)
+                .map(|record| record.scheduler_id)
+                == Some(my_id());
+            if !keep && !moved_here {
+                forget(*node_id);
+            }
+            keep
+        });
+        for record in registry.nodes.values() {
+            let node_id = record.info.id;
+            if NODE_HEARTBEAT_MAP.contains_key(&node_id) {
+                continue;
+            }
+            if record.scheduler_id == my_id() {
+                watch(node_id);
+            } else if unreachable_list.contains(&record.scheduler_id) {
+                warn!(
+                    "scheduler {} is unreachable, node {} is taken over",
+                    record.scheduler_id, node_id
+                );
+                TAKEN_OVER_MAP.insert(node_id, record.scheduler_id);
+                watch(node_id);
+            }
+        }
+    }
+}
+
+/// move users recorded on the crashed node to the nodes chosen by the ring.
+///
+/// a user failed to move is left to `which_node`, which places again users of a node gone.
+async fn reassign_users(node_id: u32) -> Result<()> {
+    let mut redis_ops = get_redis_ops().await;
+    let set_key = format!("{}{}", NODE_USER_SET, node_id);
+    let user_list: Vec<u64> = redis_ops.peek_set(&set_key).await?;
+    let mut count = 0;
+    for user_id in user_list {
+        let new_node_id = match message_node_of(user_id) {
+            Some(new_node_id) => new_node_id,
+            None => {
+                // left to `which_node` once any message node comes back.
+                warn!(
+                    "no message node alive, users of node {} are not moved",
+                    node_id
+                );
+                return Ok(());
+            }
+        };
+        match reassign_user(&mut redis_ops, user_id, node_id, new_node_id).await {
+            Ok(true) => count += 1,
+            Ok(false) => {}
+            Err(e) => error!("move user {} of node {} error: {}", user_id, node_id, e),
+        }
+    }
+    redis_ops.del(&set_key).await?;
+    info!("{} users of crashed node {} are moved", count, node_id);
+    Ok(())
+}
+
+/// return false if the user has been moved by `which_node` already.
+async fn reassign_user(
+    redis_ops: &mut RedisOps,
+    user_id: u64,
+    node_id: u32,
+    new_node_id: u32,
+) -> Result<bool> {
+    // the user is not connected anywhere until it reconnects.
+    let online_key = format!("{}{}", USER_ONLINE, user_id);
+    if let Ok(online_node_id) = redis_ops.get::<u32>(&online_key).await {
+        if online_node_id == node_id {
+            redis_ops.del(&online_key).await?;
+        }
+    }
+    let key = format!("{}{}", USER_NODE_MAP, user_id);
+    if let Ok(recorded) = redis_ops.get::<u32>(&key).await {
+        if recorded != node_id {
+            return Ok(false);
+        }
+    }
+    redis_ops.set(&key, &new_node_id).await?;
+    redis_ops
+        .push_set(&format!("{}{}", NODE_USER_SET, new_node_id), &user_id)
+        .await?;
+    Ok(true)
+}
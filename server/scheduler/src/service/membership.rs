@@ -0,0 +1,171 @@
+use anyhow::anyhow;
+use lib::{
+    entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus},
+    Result, MESSAGE_NODE_ID_BEGINNING, MSGPROCESSOR_ID_BEGINNING, SCHEDULER_NODE_ID_BEGINNING,
+    SEQNUM_NODE_ID_BEGINNING,
+};
+use lib_net_tokio::net::server::ReqwestCaller;
+use tracing::{error, info, warn};
+
+use crate::{
+    raft::{get_raft, ApplyResult, Command},
+    util::my_id,
+};
+
+use super::{
+    assignment, get_client_caller_map, get_message_node_set, get_msgprocessor_set, get_seqnum_node_set,
+    get_server_info_map,
+};
+
+/// mirror committed registry changes into the local node sets, and notify message nodes
+/// connected to this scheduler, so every scheduler notifies its own nodes exactly once.
+pub(crate) fn on_apply(command: &Command, result: &ApplyResult) {
+    if let ApplyResult::Rejected(cause) = result {
+        warn!("registry change {:?} rejected: {}", command, cause);
+        return;
+    }
+    match command {
+        Command::RegisterNode { info, scheduler_id } => node_register(info.clone(), *scheduler_id),
+        Command::UnregisterNode { node_id } => node_unregister(*node_id, ServerStatus::Offline),
+        Command::CrashNode { node_id } => node_unregister(*node_id, ServerStatus::Crash),
+        Command::AssignShard { shard, node_id } => assignment::on_assign(shard, *node_id),
+        Command::UnassignShard { shard } => assignment::on_unassign(shard),
+        Command::Noop => {}
+    }
+}
+
+fn node_register(server_info: ServerInfo, scheduler_id: u32) {
+    let node_id = server_info.id;
+    get_server_info_map().insert(node_id, server_info.clone());
+    if (SEQNUM_NODE_ID_BEGINNING..MSGPROCESSOR_ID_BEGINNING).contains(&node_id) {
+        get_seqnum_node_set().insert(node_id);
+        return;
+    } else if node_id >= MSGPROCESSOR_ID_BEGINNING {
+        get_msgprocessor_set().insert(node_id);
+        assignment::on_register(node_id);
+        return;
+    } else if !(MESSAGE_NODE_ID_BEGINNING..SCHEDULER_NODE_ID_BEGINNING).contains(&node_id) {
+        return;
+    }
+    let message_node_set = get_message_node_set();
+    message_node_set.insert(node_id);
+
+    let client_map = get_client_caller_map();
+    let server_info_map = get_server_info_map();
+    let peer_list = local_message_nodes(node_id);
+    // the node itself learns its peers from the scheduler it connects to.
+    let self_caller = if scheduler_id == my_id() {
+        client_map.get(node_id).map(|caller| caller.clone())
+    } else {
+        None
+    };
+    let peer_info_list = message_node_set
+        .0
+        .iter()
+        .filter(|entry| *entry.key() != node_id)
+        .filter_map(|entry| server_info_map.get(*entry.key()).map(|info| info.clone()))
+        .collect::<Vec<ServerInfo>>();
+    tokio::spawn(async move {
+        let mut bytes = vec![1u8];
+        bytes.extend_from_slice(&server_info.to_bytes());
+        let notify_msg =
+            ReqwestMsg::with_resource_id_payload(ReqwestResourceID::MessageNodeRegister, &bytes);
+        for (peer_id, caller) in peer_list {
+            if let Err(e) = caller.call(notify_msg.clone()).await {
+                error!("notify register of {} to {} error: {}", node_id, peer_id, e);
+            }
+        }
+        let self_caller = match self_caller {
+            Some(self_caller) => self_caller,
+            None => return,
+        };
+        for peer_info in peer_info_list {
+            let mut bytes = vec![0u8];
+            bytes.extend_from_slice(&peer_info.to_bytes());
+            let peer_notify_msg = ReqwestMsg::with_resource_id_payload(
+                ReqwestResourceID::MessageNodeRegister,
+                &bytes,
+            );
+            if let Err(e) = self_caller.call(peer_notify_msg).await {
+                error!("notify peer {} to {} error: {}", peer_info.id, node_id, e);
+            }
+        }
+    });
+}
+
+fn node_unregister(node_id: u32, status: ServerStatus) {
+    let server_info_map = get_server_info_map();
+    let mut server_info = match server_info_map.get(node_id) {
+        Some(server_info) => server_info.clone(),
+        None => ServerInfo {
+            id: node_id,
+            ..Default::default()
+        },
+    };
+    server_info_map.remove(node_id);
+    server_info.status = status;
+    info!("[{}] server {} is {}", server_info.typ, node_id, status);
+    if (SEQNUM_NODE_ID_BEGINNING..MSGPROCESSOR_ID_BEGINNING).contains(&node_id) {
+        get_seqnum_node_set().remove(node_id);
+        return;
+    } else if node_id >= MSGPROCESSOR_ID_BEGINNING {
+        get_msgprocessor_set().remove(node_id);
+        assignment::on_leave(node_id);
+        return;
+    } else if !(MESSAGE_NODE_ID_BEGINNING..SCHEDULER_NODE_ID_BEGINNING).contains(&node_id) {
+        return;
+    }
+    get_message_node_set().remove(node_id);
+
+    let resource_id = if status == ServerStatus::Crash {
+        ReqwestResourceID::MessageNodeCrash
+    } else {
+        ReqwestResourceID::MessageNodeUnregister
+    };
+    let peer_list = local_message_nodes(node_id);
+    tokio::spawn(async move {
+        let notify_msg = ReqwestMsg::with_resource_id_payload(resource_id, &server_info.to_bytes());
+        for (peer_id, caller) in peer_list {
+            if let Err(e) = caller.call(notify_msg.clone()).await {
+                error!(
+                    "notify {} of {} to {} error: {}",
+                    status, node_id, peer_id, e
+                );
+            }
+        }
+    });
+}
+
+/// message nodes connected to this scheduler, except `node_id`.
+#[inline]
+fn local_message_nodes(node_id: u32) -> Vec<(u32, ReqwestCaller)> {
+    let message_node_set = get_message_node_set();
+    get_client_caller_map()
+        .0
+        .iter()
+        .filter(|entry| *entry.key() != node_id && message_node_set.contains(*entry.key()))
+        .map(|entry| (*entry.key(), entry.value().clone()))
+        .collect()
+}
+
+/// register a node connected to this scheduler through the replicated registry.
+pub(crate) async fn register(server_info: ServerInfo) -> Result<()> {
+    let node_id = server_info.id;
+    let command = Command::RegisterNode {
+        info: server_info,
+        scheduler_id: my_id(),
+    };
+    match get_raft().propose(command).await? {
+        ApplyResult::Applied => Ok(()),
+        ApplyResult::Rejected(cause) => Err(anyhow!("register of {} rejected: {}", node_id, cause)),
+    }
+}
+
+/// a node may have been removed as crashed before it leaves, which is not an error.
+pub(crate) async fn unregister(node_id: u32) -> Result<()> {
+    get_client_caller_map().remove(node_id);
+    get_raft()
+        .propose(Command::UnregisterNode { node_id })
+        .await?;
+    Ok(())
+}
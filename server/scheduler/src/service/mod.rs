@@ -1,12 +1,24 @@
+pub(crate) mod assignment;
+pub(crate) mod audit;
 pub(crate) mod handler;
+pub(crate) mod liveness;
+pub(crate) mod membership;
 mod server;
 
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use dashmap::{mapref::one::Ref, DashMap, DashSet};
 use lazy_static::lazy_static;
-use lib::{Result, entity::ServerInfo, net::GenericParameter};
+use lib::{
+    entity::{ServerInfo, ServerStatus},
+    net::GenericParameter,
+    util::ring::HashRing,
+    Result,
+};
 use lib_net_tokio::net::server::ReqwestCaller;
+use tracing::error;
+
+use crate::config::config;
 
 /// we choose to split set and integration map to get minimum split operation.
 pub(crate) struct ClientCallerMap(pub(crate) Arc<DashMap<u32, ReqwestCaller>>);
@@ -21,6 +33,9 @@ lazy_static! {
     static ref MESSAGE_NODE_SET: MessageNodeSet = MessageNodeSet(Arc::new(DashSet::new()));
     static ref SEQNUM_NODE_SET: SeqnumNodeSet = SeqnumNodeSet(Arc::new(DashSet::new()));
     static ref MSGPROCESSOR_SET: MsgprocessorSet = MsgprocessorSet(Arc::new(DashSet::new()));
+    /// kept in sync with `MESSAGE_NODE_SET`, decides which message node a user belongs to.
+    static ref MESSAGE_NODE_RING: RwLock<HashRing> =
+        RwLock::new(HashRing::new(config().ring.virtual_nodes));
 }
 
 pub(crate) fn get_client_caller_map() -> ClientCallerMap {
@@ -43,6 +58,18 @@ pub(crate) fn get_msgprocessor_set() -> MsgprocessorSet {
     MsgprocessorSet(MSGPROCESSOR_SET.0.clone())
 }
 
+/// the message node `user_id` should be placed on, according to the consistent-hash ring.
+///
+/// overloaded nodes are skipped unless all nodes are overloaded.
+pub(crate) fn message_node_of(user_id: u64) -> Option<u32> {
+    let ring = MESSAGE_NODE_RING.read().unwrap();
+    ring.get_filtered(user_id, |node_id| match SERVER_INFO_MAP.0.get(&node_id) {
+        Some(server_info) => server_info.status != ServerStatus::Overload,
+        None => true,
+    })
+    .or_else(|| ring.get(user_id))
+}
+
 impl GenericParameter for ClientCallerMap {
     fn as_any(&self) -> &dyn std::any::Any {
         self
@@ -122,17 +149,19 @@ impl ServerInfoMap {
 }
 
 impl MessageNodeSet {
-    #[allow(unused)]
     pub(crate) fn contains(&self, key: u32) -> bool {
         self.0.contains(&key)
     }
 
     pub(crate) fn insert(&self, key: u32) {
         self.0.insert(key);
+        let weight = config().ring.weights.get(&key).copied().unwrap_or(1);
+        MESSAGE_NODE_RING.write().unwrap().insert(key, weight);
     }
 
     pub(crate) fn remove(&self, key: u32) {
         self.0.remove(&key);
+        MESSAGE_NODE_RING.write().unwrap().remove(key);
     }
 }
 
@@ -167,6 +196,21 @@ impl MsgprocessorSet {
 }
 
 pub(crate) async fn start() -> Result<()> {
+    tokio::spawn(async move {
+        if let Err(e) = liveness::detect().await {
+            error!("liveness detector error: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = liveness::sweep().await {
+            error!("liveness sweep error: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        if let Err(e) = assignment::balance().await {
+            error!("topic assignment error: {}", e);
+        }
+    });
     server::Server::run().await?;
     Ok(())
 }
@@ -17,7 +17,7 @@ use tracing::error;
 
 use super::{
     get_client_caller_map, get_message_node_set, get_seqnum_node_set, get_server_info_map,
-    handler::{logic, message, seqnum, msgprocessor}, get_msgprocessor_set,
+    handler::{logic, message, seqnum, msgprocessor}, get_msgprocessor_set, liveness, audit,
 };
 
 pub(super) struct ClientConnectionHandler {
@@ -73,6 +73,12 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
             match recv.recv().await {
                 Some(mut req) => {
                     let resource_id = req.resource_id();
+                    if resource_id != ReqwestResourceID::NodeAuth
+                        && !self.states.contains_key("node_id")
+                    {
+                        audit::reject(0, "", &format!("{} before auth", resource_id)).await;
+                        break;
+                    }
                     match self.handler_map.get(&resource_id) {
                         Some(handler) => match handler.run(&mut req, &mut self.states).await {
                             Ok(mut resp) => {
@@ -91,7 +97,12 @@ impl NewReqwestConnectionHandler for ClientConnectionHandler {
                     };
                 }
                 None => {
-                    let node_id = self.states.get("node_id").unwrap().as_num().unwrap() as u32;
+                    let node_id = match self.states.get("node_id") {
+                        Some(node_id) => node_id.as_num().unwrap() as u32,
+                        // never authenticated, nothing to clean.
+                        None => break,
+                    };
+                    liveness::forget(node_id);
                     let mut server_info = ServerInfo::default();
                     server_info.id = node_id;
                     if node_id >= MESSAGE_NODE_ID_BEGINNING && node_id < SCHEDULER_NODE_ID_BEGINNING
@@ -185,6 +196,14 @@ impl Server {
 
         let mut handler_map: AHashMap<ReqwestResourceID, Box<dyn ReqwestHandler>> = AHashMap::new();
         handler_map.insert(ReqwestResourceID::NodeAuth, Box::new(logic::ServerAuth {}));
+        handler_map.insert(
+            ReqwestResourceID::NodeLoadReport,
+            Box::new(logic::LoadReport {}),
+        );
+        handler_map.insert(
+            ReqwestResourceID::NodeHeartbeat,
+            Box::new(logic::Heartbeat {}),
+        );
         handler_map.insert(
             ReqwestResourceID::MessageNodeRegister,
             Box::new(message::NodeRegister {}),
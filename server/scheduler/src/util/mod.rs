@@ -1,7 +1,10 @@
 use std::path::PathBuf;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use lib::{Result, SCHEDULER_NODE_ID_BEGINNING};
+use lib::{
+    Result, MESSAGE_NODE_ID_BEGINNING, MSGPROCESSOR_ID_BEGINNING, SCHEDULER_NODE_ID_BEGINNING,
+    SEQNUM_NODE_ID_BEGINNING,
+};
 
 use crate::cache::{get_redis_ops, NODE_ID};
 
@@ -37,3 +40,20 @@ pub(crate) async fn load_my_id(my_id_preload: u32) -> Result<()> {
     unsafe { MY_ID = my_id }
     Ok(())
 }
+
+/// kinds of node allowed to join through the service port, named as in join tokens.
+pub(crate) const NODE_KINDS: [&str; 3] = ["message", "seqnum", "msgprocessor"];
+
+/// kind of node a node id belongs to, schedulers join by the cluster port instead.
+#[inline]
+pub(crate) fn node_kind(node_id: u32) -> Option<&'static str> {
+    if (MESSAGE_NODE_ID_BEGINNING..SCHEDULER_NODE_ID_BEGINNING).contains(&node_id) {
+        Some(NODE_KINDS[0])
+    } else if (SEQNUM_NODE_ID_BEGINNING..MSGPROCESSOR_ID_BEGINNING).contains(&node_id) {
+        Some(NODE_KINDS[1])
+    } else if node_id >= MSGPROCESSOR_ID_BEGINNING {
+        Some(NODE_KINDS[2])
+    } else {
+        None
+    }
+}
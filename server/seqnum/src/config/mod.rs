@@ -91,6 +91,7 @@ struct Scheduler0 {
     address: Option<String>,
     domain: Option<String>,
     cert_path: Option<String>,
+    join_token: Option<String>,
 }
 
 #[derive(Debug)]
@@ -101,6 +102,8 @@ pub(crate) struct Scheduler {
     pub(crate) domain: String,
     #[allow(unused)]
     pub(crate) cert: rustls::Certificate,
+    /// issued by `scheduler --issue_join_token`, presented when connecting to it.
+    pub(crate) join_token: String,
 }
 
 impl Config {
@@ -185,6 +188,7 @@ impl Scheduler {
                 .collect::<Vec<SocketAddr>>()[0],
             domain: scheduler0.domain.take().unwrap(),
             cert: rustls::Certificate(cert),
+            join_token: scheduler0.join_token.take().unwrap_or_default(),
         }
     }
 }
@@ -1,14 +1,34 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use common::{
+    load::LoadSampler,
+    scheduler::{HEARTBEAT_INTERVAL, LOAD_REPORT_INTERVAL},
+};
 use lib::{
-    entity::{ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType},
+    entity::{
+        NodeAuthInfo, ReqwestMsg, ReqwestResourceID, ServerInfo, ServerStatus, ServerType,
+        REGISTER_AGAIN,
+    },
     net::client::ClientConfigBuilder,
     Result,
 };
 use lib_net_monoio::net::{client::ClientReqwestTcp, ReqwestOperatorManager};
+use tracing::{error, warn};
 
 use crate::{config::config, util::my_id};
 
+fn server_info() -> ServerInfo {
+    ServerInfo {
+        id: my_id(),
+        service_address: config().server.service_address.clone(),
+        cluster_address: Some(config().server.cluster_address.clone()),
+        connection_id: 0,
+        status: ServerStatus::Online,
+        typ: ServerType::SeqnumCluster,
+        load: None,
+    }
+}
+
 pub(super) struct Client {}
 
 impl Client {
@@ -25,16 +45,6 @@ impl Client {
             .with_max_bi_streams(config().transport.max_bi_streams);
         let client_config = config_builder.build().unwrap();
 
-        let server_info = ServerInfo {
-            id: my_id(),
-            service_address: config().server.service_address.clone(),
-            cluster_address: Some(config().server.cluster_address.clone()),
-            connection_id: 0,
-            status: ServerStatus::Online,
-            typ: ServerType::SeqnumCluster,
-            load: None,
-        };
-
         let mut client = ClientReqwestTcp::new(client_config, Duration::from_millis(3000));
         let operator = match client.build().await {
             Ok(operator) => operator,
@@ -45,13 +55,23 @@ impl Client {
                 ))
             }
         };
-        let mut auth_info = server_info.clone();
-        auth_info.typ = ServerType::SchedulerClient;
+        Self::register(&operator).await?;
+        Box::leak(Box::new(client));
+        Ok(operator)
+    }
+
+    /// authenticate the connection and register as a seqnum node.
+    async fn register(operator: &ReqwestOperatorManager) -> Result<()> {
+        let mut auth_info = NodeAuthInfo {
+            info: server_info(),
+            token: config().scheduler.join_token.clone(),
+        };
+        auth_info.info.typ = ServerType::SchedulerClient;
         let auth_msg = ReqwestMsg::with_resource_id_payload(
             ReqwestResourceID::NodeAuth,
             &auth_info.to_bytes(),
         );
-        let _resp = match operator.call(auth_msg).await {
+        let resp = match operator.call(auth_msg).await {
             Ok(resp) => resp,
             Err(e) => {
                 return Err(anyhow::anyhow!(
@@ -60,9 +80,12 @@ impl Client {
                 ))
             }
         };
+        if resp.payload().is_empty() {
+            return Err(anyhow::anyhow!("rejected by scheduler"));
+        }
         let register_msg = ReqwestMsg::with_resource_id_payload(
             ReqwestResourceID::SeqnumNodeRegister,
-            &server_info.to_bytes(),
+            &server_info().to_bytes(),
         );
         let _resp = match operator.call(register_msg).await {
             Ok(resp) => resp,
@@ -73,7 +96,36 @@ impl Client {
                 ))
             }
         };
-        Box::leak(Box::new(client));
-        Ok(operator)
+        Ok(())
+    }
+
+    /// the same as `common::scheduler::keep_alive`, but driven by monoio.
+    pub(super) async fn keep_alive(operator: ReqwestOperatorManager) {
+        let mut sampler = LoadSampler::new();
+        let mut info = server_info();
+        let mut last_report = Instant::now();
+        loop {
+            monoio::time::sleep(HEARTBEAT_INTERVAL).await;
+            let msg = if last_report.elapsed() >= LOAD_REPORT_INTERVAL {
+                last_report = Instant::now();
+                info.load = Some(sampler.sample());
+                ReqwestMsg::with_resource_id_payload(
+                    ReqwestResourceID::NodeLoadReport,
+                    &info.to_bytes(),
+                )
+            } else {
+                ReqwestMsg::with_resource_id_payload(ReqwestResourceID::NodeHeartbeat, b"")
+            };
+            match operator.call(msg).await {
+                Ok(resp) if resp.payload() == REGISTER_AGAIN => {
+                    warn!("scheduler asks node {} to register again", info.id);
+                    if let Err(e) = Self::register(&operator).await {
+                        error!("register to scheduler again failed: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => error!("keep alive with scheduler failed: {}", e),
+            }
+        }
     }
 }
@@ -1,8 +1,9 @@
 use lib::Result;
-use lib_net_monoio::net::ReqwestOperatorManager;
 
 pub(self) mod client;
 
-pub(crate) async fn start() -> Result<ReqwestOperatorManager> {
-    client::Client::run().await
-}
\ No newline at end of file
+pub(crate) async fn start() -> Result<()> {
+    let operator = client::Client::run().await?;
+    monoio::spawn(client::Client::keep_alive(operator));
+    Ok(())
+}
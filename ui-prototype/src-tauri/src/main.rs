@@ -3,12 +3,13 @@
     windows_subsystem = "windows"
 )]
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use config::{conf, load_config};
 use lib::{
     entity::{Msg, Type, GROUP_ID_THRESHOLD},
     net::client::ClientConfigBuilder,
+    util::timestamp,
 };
 
 use lazy_static::lazy_static;
@@ -41,6 +42,7 @@ lazy_static! {
 
 const CONNECTED: u8 = 1;
 const DISCONNECTED: u8 = 2;
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
 
 static mut LOCAL_DATA_DIR: &'static str = ".";
 
@@ -84,6 +86,10 @@ async fn main() -> tauri::Result<()> {
             get_msg,
             del_msg_list,
             latest_seq_num,
+            e2ee_keys,
+            e2ee_has_session,
+            e2ee_encrypt,
+            e2ee_decrypt,
             http_get,
             http_put,
             http_post,
@@ -252,6 +258,15 @@ async fn disconnect() -> Result<(), String> {
 }
 
 fn setup(window: Window<Wry>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = get_msg_ops().await.delete_expired(timestamp()).await {
+                error!("delete expired msg error: {}", e);
+            }
+        }
+    });
     tokio::spawn(async move {
         let mut signal_rx = SIGNAL_RX.lock().await.take().unwrap();
         loop {
@@ -590,6 +605,78 @@ fn preparse(obj: serde_json::Value) -> serde_json::Value {
     }
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct E2eeKeysParams {
+    user_id: serde_json::Value,
+    /// one-time prekeys to add, a batch if not given.
+    count: Option<usize>,
+}
+
+/// the keys to put to `/e2ee/bundle`.
+#[tauri::command]
+async fn e2ee_keys(params: E2eeKeysParams) -> std::result::Result<serde_json::Value, String> {
+    let user_id = preparse(params.user_id).as_u64().unwrap();
+    match service::e2ee::published_keys(user_id, params.count).await {
+        Ok(keys) => serde_json::to_value(keys).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct E2eeSessionParams {
+    user_id: serde_json::Value,
+    peer_id: serde_json::Value,
+}
+
+#[tauri::command]
+async fn e2ee_has_session(params: E2eeSessionParams) -> std::result::Result<bool, String> {
+    let user_id = preparse(params.user_id).as_u64().unwrap();
+    let peer_id = preparse(params.peer_id).as_u64().unwrap();
+    service::e2ee::has_session(user_id, peer_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct E2eeEncryptParams {
+    user_id: serde_json::Value,
+    peer_id: serde_json::Value,
+    plaintext: String,
+    /// got from `/e2ee/bundle` when there is no session with the peer yet.
+    bundle: Option<serde_json::Value>,
+}
+
+/// the payload to send with the encrypted flag set.
+#[tauri::command]
+async fn e2ee_encrypt(params: E2eeEncryptParams) -> std::result::Result<String, String> {
+    let user_id = preparse(params.user_id).as_u64().unwrap();
+    let peer_id = preparse(params.peer_id).as_u64().unwrap();
+    let bundle = match params.bundle {
+        Some(bundle) => Some(serde_json::from_value(bundle).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    service::e2ee::encrypt(user_id, peer_id, &params.plaintext, bundle)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct E2eeDecryptParams {
+    user_id: serde_json::Value,
+    peer_id: serde_json::Value,
+    payload: String,
+}
+
+/// the text of an encrypted message, it can be decrypted only once, so save it decrypted.
+#[tauri::command]
+async fn e2ee_decrypt(params: E2eeDecryptParams) -> std::result::Result<String, String> {
+    let user_id = preparse(params.user_id).as_u64().unwrap();
+    let peer_id = preparse(params.peer_id).as_u64().unwrap();
+    service::e2ee::decrypt(user_id, peer_id, params.payload.as_bytes())
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
 struct HttpGetParams {
     host: String,
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use lib::entity::Msg;
+use lib::entity::{Msg, MsgExtension, Type};
 use lib::Result;
 use rusqlite::params;
 use tokio_rusqlite::Connection;
@@ -17,9 +17,13 @@ const MSG_DB_CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS msg (
     type        INTEGER,
     version     INTEGER,
     payload     TEXT,
-    extension   TEXT
+    extension   TEXT,
+    expire_at   INTEGER
 )";
 
+/// databases created before messages could expire.
+const MSG_DB_ADD_EXPIRE_AT: &str = "ALTER TABLE msg ADD COLUMN expire_at INTEGER";
+
 const KV_DB_CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS kv (
     id          INTEGER PRIMARY KEY,
     key         TEXT,
@@ -40,6 +44,8 @@ impl MsgDB {
             .call(|conn| {
                 let mut stmt = conn.prepare(MSG_DB_CREATE_TABLE).unwrap();
                 stmt.execute(params![]).unwrap();
+                // fails if the column is there already.
+                let _ = conn.execute(MSG_DB_ADD_EXPIRE_AT, params![]);
                 Ok::<(), rusqlite::Error>(())
             })
             .await
@@ -52,8 +58,8 @@ impl MsgDB {
             .call(move |conn| {
                 conn
                     .execute(
-                        "INSERT INTO msg (sender, receiver, \"timestamp\", seq_num, type, version, payload, extension) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                        params![msg.sender(), msg.receiver(), msg.timestamp(), msg.seqnum(), msg.typ(), msg.version(), String::from_utf8_lossy(msg.payload()).to_string(), String::from_utf8_lossy(msg.extension()).to_string()]
+                        "INSERT INTO msg (sender, receiver, \"timestamp\", seq_num, type, version, payload, extension, expire_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        params![msg.sender(), msg.receiver(), msg.timestamp(), msg.seqnum(), msg.typ(), msg.version(), String::from_utf8_lossy(msg.payload()).to_string(), String::from_utf8_lossy(msg.extension()).to_string(), expire_at(&msg)]
                     )?;
                     Ok::<(), rusqlite::Error>(())
             })
@@ -108,6 +114,11 @@ impl MsgDB {
 
     pub(crate) async fn insert_or_update_list(&self, msg_list: Vec<Msg>) -> Result<()> {
         for msg in msg_list.into_iter() {
+            if msg.typ() == Type::Expire {
+                self.delete(msg.sender(), msg.receiver(), msg.seqnum())
+                    .await?;
+                continue;
+            }
             if let Some(_) = self
                 .select(msg.sender(), msg.receiver(), msg.seqnum())
                 .await?
@@ -120,7 +131,13 @@ impl MsgDB {
         Ok(())
     }
 
+    /// an expired message is deleted instead.
     pub(crate) async fn insert_or_update(&self, msg: Msg) -> Result<()> {
+        if msg.typ() == Type::Expire {
+            return self
+                .delete(msg.sender(), msg.receiver(), msg.seqnum())
+                .await;
+        }
         if let Some(_) = self
             .select(msg.sender(), msg.receiver(), msg.seqnum())
             .await?
@@ -229,6 +246,18 @@ impl MsgDB {
         Ok(())
     }
 
+    /// delete the messages whose own time to live passed, those expired by the time to live of
+    /// their conversation are deleted as the server tells.
+    pub(crate) async fn delete_expired(&self, now: u64) -> Result<()> {
+        self.connection
+            .call(move |conn| {
+                conn.execute("DELETE FROM msg WHERE expire_at <= ?1", params![now])?;
+                Ok::<(), rusqlite::Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+
     pub(crate) async fn latest_seq_num(&self, user_id1: u64, user_id2: u64) -> Result<Option<u64>> {
         let res = self.connection.call(move |conn| {
             let mut statement = conn.prepare("SELECT seq_num FROM msg WHERE ((sender = ?1 AND receiver = ?2) OR (sender = ?2 AND receiver = ?1)) ORDER BY seq_num DESC LIMIT 1")?;
@@ -248,6 +277,13 @@ impl MsgDB {
     }
 }
 
+/// in milliseconds, by the time to live in the extension of the message.
+fn expire_at(msg: &Msg) -> Option<u64> {
+    MsgExtension::parse(msg.extension())
+        .ttl
+        .map(|ttl| msg.timestamp().saturating_add(ttl.saturating_mul(1000)))
+}
+
 /// only accept js object in string
 pub(crate) struct KVDB {
     connection: Connection,
@@ -0,0 +1,117 @@
+use anyhow::anyhow;
+use e2ee::{
+    identity::ONE_TIME_PREKEY_BATCH, Envelope, IdentityKeyPair, PreKeyBundle, PreKeyStore,
+    PublishedKeys, Session,
+};
+use lazy_static::lazy_static;
+use lib::Result;
+use tokio::sync::Mutex;
+
+use super::get_kv_ops;
+
+lazy_static! {
+    /// sessions change with every message, one at a time.
+    static ref E2EE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+fn store_key(user_id: u64) -> String {
+    format!("e2ee_prekey_store_{}", user_id)
+}
+
+fn session_key(user_id: u64, peer_id: u64) -> String {
+    format!("e2ee_session_{}_{}", user_id, peer_id)
+}
+
+async fn load<T: serde::de::DeserializeOwned>(key: &str) -> Result<Option<T>> {
+    match get_kv_ops().await.get(key).await? {
+        Some(value) => Ok(Some(serde_json::from_value(value)?)),
+        None => Ok(None),
+    }
+}
+
+async fn save<T: serde::Serialize>(key: &str, value: &T) -> Result<()> {
+    get_kv_ops()
+        .await
+        .set(key, &serde_json::to_value(value)?)
+        .await?;
+    Ok(())
+}
+
+/// the keys to publish to the api, the identity is created the first time, and new one-time
+/// prekeys are added as the api runs low on them.
+pub(crate) async fn published_keys(user_id: u64, count: Option<usize>) -> Result<PublishedKeys> {
+    let _guard = E2EE_LOCK.lock().await;
+    let mut store = match load::<PreKeyStore>(&store_key(user_id)).await? {
+        Some(store) => store,
+        None => PreKeyStore::new(IdentityKeyPair::generate()),
+    };
+    let one_time_prekey_list =
+        store.generate_one_time_prekeys(count.unwrap_or(ONE_TIME_PREKEY_BATCH));
+    save(&store_key(user_id), &store).await?;
+    Ok(store.published_keys(one_time_prekey_list))
+}
+
+pub(crate) async fn has_session(user_id: u64, peer_id: u64) -> Result<bool> {
+    let _guard = E2EE_LOCK.lock().await;
+    Ok(load::<Session>(&session_key(user_id, peer_id))
+        .await?
+        .is_some())
+}
+
+/// the payload of an encrypted message to the peer, a session is started from the bundle of the
+/// peer if there is none yet.
+pub(crate) async fn encrypt(
+    user_id: u64,
+    peer_id: u64,
+    plaintext: &str,
+    bundle: Option<PreKeyBundle>,
+) -> Result<String> {
+    let _guard = E2EE_LOCK.lock().await;
+    let mut session = match (
+        load::<Session>(&session_key(user_id, peer_id)).await?,
+        bundle,
+    ) {
+        (_, Some(bundle)) => {
+            let store = load::<PreKeyStore>(&store_key(user_id))
+                .await?
+                .ok_or_else(|| anyhow!("keys not published yet"))?;
+            Session::initiate(store.identity(), &bundle)?
+        }
+        (Some(session), None) => session,
+        (None, None) => return Err(anyhow!("no session with {}", peer_id)),
+    };
+    let payload = session.encrypt(plaintext.as_bytes())?;
+    save(&session_key(user_id, peer_id), &session).await?;
+    Ok(payload)
+}
+
+/// a message starting a new session replaces the one kept, the peer may have lost it, one
+/// carrying the prekey header the session kept was agreed by only goes to that session.
+pub(crate) async fn decrypt(user_id: u64, peer_id: u64, payload: &[u8]) -> Result<String> {
+    let _guard = E2EE_LOCK.lock().await;
+    let envelope = Envelope::parse(payload)?;
+    let mut session = load::<Session>(&session_key(user_id, peer_id)).await?;
+    let respond = match (session.as_ref(), envelope.prekey_header.as_ref()) {
+        (None, Some(_)) => true,
+        (Some(session), Some(prekey_header)) => !session.started_from(prekey_header),
+        (_, None) => false,
+    };
+    let plaintext = if respond {
+        let mut store = load::<PreKeyStore>(&store_key(user_id))
+            .await?
+            .ok_or_else(|| anyhow!("keys not published yet"))?;
+        let (new_session, plaintext) = Session::respond(&mut store, &envelope)?;
+        save(&store_key(user_id), &store).await?;
+        session = Some(new_session);
+        plaintext
+    } else {
+        match session.as_mut() {
+            Some(session) => session.decrypt(&envelope)?,
+            None => return Err(anyhow!("no session with {}", peer_id)),
+        }
+    };
+    if let Some(session) = session.as_ref() {
+        save(&session_key(user_id, peer_id), session).await?;
+    }
+    Ok(String::from_utf8_lossy(&plaintext).to_string())
+}
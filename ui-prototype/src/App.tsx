@@ -333,8 +333,9 @@ function App() {
                 return;
             }
             let msgList = resp.data as Array<any>;
+            // each item is the message with its edit, thread and reaction state.
             msgList.forEach((item) => {
-                let arr = item as Array<number>;
+                let arr = item.msg as Array<number>;
                 let body = new Uint8Array(arr.length);
                 for (let i = 0; i < arr.length; ++i) {
                     body[i] = arr[i];
@@ -446,16 +447,21 @@ function App() {
                 if (msgList.length === 0) {
                     break;
                 }
+                let lastSeqNum = fromSeqNum - 1n;
                 for (let j = 0; j < msgList.length; ++j) {
-                    let arr = msgList[j] as Array<number>;
+                    let arr = msgList[j].msg as Array<number>;
                     let body = new Uint8Array(arr.length);
                     for (let i = 0; i < arr.length; ++i) {
                         body[i] = arr[i];
                     }
                     let msg = Msg.fromArrayBuffer(body.buffer);
+                    // the newest comes first.
+                    if (msg.head.seqnum > lastSeqNum) {
+                        lastSeqNum = msg.head.seqnum;
+                    }
                     await newMsg(msg);
                 }
-                fromSeqNum = BigInt(msgList[msgList.length - 1][0]) + 1n;
+                fromSeqNum = lastSeqNum + 1n;
                 toSeqNum = fromSeqNum + 100n;
             }
         }
@@ -573,7 +579,7 @@ function App() {
             let msgList = resp.data as Array<any>;
             console.log(msgList);
             for (let j = msgList.length - 1; j >= 0; j--) {
-                let arr = msgList[j] as Array<number>;
+                let arr = msgList[j].msg as Array<number>;
                 let body = new Uint8Array(arr.length);
                 for (let i = 0; i < arr.length; ++i) {
                     body[i] = arr[i];
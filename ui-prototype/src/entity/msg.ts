@@ -17,6 +17,8 @@ const EXTENSION_THRESHOLD = 1 << 6 - 1;
 const PAYLOAD_THRESHOLD = 1 << 14 - 1;
 // user_id lager than(also equal) this value is considered as a group
 const GROUP_ID_THRESHOLD: bigint = BigInt(1 << 36);
+// set in the version of a message whose payload is end-to-end encrypted.
+const ENCRYPTED_FLAG = 1 << 14;
 
 enum Type {
     NA = 0,
@@ -36,6 +38,9 @@ enum Type {
     /// control message part
     Edit = 64,
     Withdraw = 65,
+    Read = 66,
+    Reaction = 67,
+    Expire = 68,
 
     /// the below types are used for user and server's communication.
     ///
@@ -180,6 +185,14 @@ class Msg {
         return new TextDecoder().decode(this.extension);
     }
 
+    encrypted = (): boolean => {
+        return (this.head.version & ENCRYPTED_FLAG) !== 0;
+    }
+
+    setEncrypted = (encrypted: boolean) => {
+        this.head.version = encrypted ? this.head.version | ENCRYPTED_FLAG : this.head.version & ~ENCRYPTED_FLAG;
+    }
+
     static text = (sender: bigint, receiver: bigint, nodeId: number, text: string): Msg => {
         let payload = new TextEncoder().encode(text);
         let head = new Head(0, sender, nodeId, receiver, Type.Text, 0, timestamp(), payload.length, 0n);
@@ -200,4 +213,4 @@ class Msg {
     }
 }
 
-export { Type, Head, Msg, GROUP_ID_THRESHOLD };
\ No newline at end of file
+export { Type, Head, Msg, GROUP_ID_THRESHOLD, ENCRYPTED_FLAG };
\ No newline at end of file